@@ -1,32 +1,53 @@
 use super::{library::Library, mpd::ConnectionManager};
-use crate::listenbrainz;
+use crate::{artistinfo, coverartarchive, listenbrainz};
 use axum::{
-    body::Body,
-    extract::{Extension, FromRequestParts, Query},
-    http::{header, request::Parts, HeaderValue, Request},
+    body::{to_bytes, Body},
+    extract::{rejection::ExtensionRejection, Extension, FromRequestParts, Query},
+    http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue, Method, Request, Uri},
     middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{on_service, MethodFilter, MethodRouter, Router},
 };
 use bb8::Pool;
-use glue::{Handler, RawHandler};
+use glue::{ChunkWriter, Handler, RawHandler};
+use ring::hmac;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tower_http::cors::{Any, CorsLayer};
+use tracing::warn;
 
 mod annotation;
+mod bookmarks;
 mod browsing;
 mod common;
 mod error;
 mod glue;
+mod jukebox;
+mod lists;
 mod playlists;
 mod retrieval;
 mod scanning;
+mod searching;
 mod system;
 mod types;
 mod users;
 
+use browsing::ArtistsCache;
+use retrieval::CoverCache;
+pub(crate) use retrieval::TranscodeFormat;
+
 static VERSION: &str = "1.16.1";
+// Server implementation announced to OpenSubsonic clients unless configured otherwise
+static SERVER_TYPE: &str = env!("CARGO_PKG_NAME");
+static SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Maximum size of a single chunk of a streamed reply
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
 
 use error::Error;
 
@@ -35,25 +56,155 @@ type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Clone)]
 pub(crate) struct Authentication {
-    username: String,
+    // Credentials of the known users by username
+    users: HashMap<String, Credentials>,
+    // User the requests without a username are made by when authentication is disabled
+    default_username: String,
+    // API key and the user it authenticates requests as
+    api_key: Option<(String, String)>,
+    // Key signing URLs returned to clients, so that they don't carry the credentials of the user
+    url_key: hmac::Key,
+    disabled: bool,
+}
+
+#[derive(Clone)]
+struct Credentials {
     password: String,
     encoded_password: String,
 }
 
+impl Credentials {
+    fn new(password: &str) -> Self {
+        Credentials {
+            password: password.to_string(),
+            encoded_password: format!("enc:{}", hex::encode(password)),
+        }
+    }
+}
+
+// Config holds server settings which affect API replies
+pub(crate) struct Config {
+    // Directory with artist images
+    pub(crate) artist_image_dir: Option<PathBuf>,
+    // Do not expose song paths to clients
+    pub(crate) hide_paths: bool,
+    // Treat an album as starred if any (rather than all) of its songs is starred
+    pub(crate) album_starred_any: bool,
+    // Format to transcode to if the client doesn't ask for a specific one
+    pub(crate) default_transcode_format: TranscodeFormat,
+    // Reject requests modifying the library, playlists or stickers
+    pub(crate) read_only: bool,
+    // Maximum number of album art images kept in memory
+    pub(crate) cover_cache_size: usize,
+    // How long to keep the artists index (zero disables caching)
+    pub(crate) artists_cache_ttl: Duration,
+    // Always use https in URLs returned to clients
+    pub(crate) announce_https: bool,
+    // Server implementation announced to OpenSubsonic clients
+    pub(crate) announce_type: String,
+    // Avatar image of the main user
+    pub(crate) avatar: Option<PathBuf>,
+    // Let clients control MPD playback through the jukebox API
+    pub(crate) jukebox: bool,
+}
+
 struct State {
     pool: Pool<ConnectionManager>,
     lib: Box<dyn Library + Send + Sync>,
     listenbrainz: Option<listenbrainz::Client>,
+    artist_info: Option<artistinfo::Client>,
+    cover_art_archive: Option<coverartarchive::Client>,
+    artist_image_dir: Option<PathBuf>,
+    hide_paths: bool,
+    album_starred_any: bool,
+    default_transcode_format: TranscodeFormat,
+    read_only: bool,
+    cover_cache: CoverCache,
+    artists_cache: ArtistsCache,
+    // Play counts are read and written back, so concurrent updates are serialized
+    play_count_lock: tokio::sync::Mutex<()>,
+    announce_https: bool,
+    announce_type: String,
+    // Avatar image along with the main user it belongs to
+    avatar: Option<(String, PathBuf)>,
+    jukebox: bool,
+}
+
+impl State {
+    fn song_options(&self) -> common::SongOptions {
+        common::SongOptions {
+            hide_path: self.hide_paths,
+            transcode_format: self.default_transcode_format,
+        }
+    }
+
+    // ensure_writable fails if the server is running in read-only mode
+    fn ensure_writable(&self) -> Result<()> {
+        match self.read_only {
+            true => Err(Error::not_authorized("Server is running in read-only mode")),
+            false => Ok(()),
+        }
+    }
 }
 
 impl Authentication {
     pub(crate) fn new(username: &str, password: &str) -> Self {
         Authentication {
-            username: username.to_string(),
-            password: password.to_string(),
-            encoded_password: format!("enc:{}", hex::encode(password)),
+            users: HashMap::from([(username.to_string(), Credentials::new(password))]),
+            default_username: username.to_string(),
+            api_key: None,
+            url_key: url_key(),
+            disabled: false,
         }
     }
+
+    // with_user adds another user, replacing the password if the user already exists
+    pub(crate) fn with_user(mut self, username: &str, password: &str) -> Self {
+        self.users
+            .insert(username.to_string(), Credentials::new(password));
+        self
+    }
+
+    // with_api_key lets clients authenticate as the first user with the API key instead of
+    // a password
+    pub(crate) fn with_api_key(mut self, key: &str) -> Self {
+        self.api_key = Some((key.to_string(), self.default_username.clone()));
+        self
+    }
+
+    // disabled returns authentication that lets every request through. Requests without
+    // a username are treated as if they were made by the given user.
+    pub(crate) fn disabled(username: &str) -> Self {
+        Authentication {
+            users: HashMap::new(),
+            default_username: username.to_string(),
+            api_key: None,
+            url_key: url_key(),
+            disabled: true,
+        }
+    }
+}
+
+// url_key returns a random key for signing URLs. URLs signed by a previous run of the server
+// stop working after a restart.
+fn url_key() -> hmac::Key {
+    hmac::Key::new(hmac::HMAC_SHA256, &rand::random::<[u8; 32]>())
+}
+
+// Headers with totals of paged replies. Browsers hide response headers from cross-origin scripts
+// unless they are explicitly exposed.
+const TOTAL_COUNT_HEADERS: [&str; 4] = [
+    glue::X_TOTAL_COUNT,
+    searching::X_TOTAL_COUNT_ARTISTS,
+    searching::X_TOTAL_COUNT_ALBUMS,
+    searching::X_TOTAL_COUNT_SONGS,
+];
+
+// cors lets web clients served from other origins use the API
+fn cors() -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(Any)
+        .expose_headers(TOTAL_COUNT_HEADERS.map(HeaderName::from_static))
 }
 
 pub(crate) fn get_router(
@@ -61,27 +212,53 @@ pub(crate) fn get_router(
     pool: Pool<ConnectionManager>,
     lib: Box<dyn Library + Send + Sync>,
     listenbrainz: Option<listenbrainz::Client>,
+    artist_info: Option<artistinfo::Client>,
+    cover_art_archive: Option<coverartarchive::Client>,
+    config: Config,
 ) -> Router {
+    let avatar = config
+        .avatar
+        .map(|path| (auth.default_username.clone(), path));
+
     Router::new()
         .nest(
             "/rest",
             Router::new()
                 .merge(annotation::get_router())
+                .merge(bookmarks::get_router())
                 .merge(browsing::get_router())
+                .merge(jukebox::get_router())
+                .merge(lists::get_router())
                 .merge(playlists::get_router())
                 .merge(retrieval::get_router())
                 .merge(scanning::get_router())
+                .merge(searching::get_router())
                 .merge(system::get_router())
                 .merge(users::get_router()),
         )
         .route_layer(middleware::from_fn(move |req, next| {
             authenticate(req, next, auth.clone())
         }))
-        .layer(CorsLayer::new().allow_origin(Any))
+        .route_layer(middleware::from_fn(form_post))
+        .layer(cors())
         .layer(Extension(Arc::new(State {
             pool,
             lib,
             listenbrainz,
+            artist_info,
+            cover_art_archive,
+            artist_image_dir: config.artist_image_dir,
+            hide_paths: config.hide_paths,
+            album_starred_any: config.album_starred_any,
+            default_transcode_format: config.default_transcode_format,
+            read_only: config.read_only,
+            cover_cache: CoverCache::new(config.cover_cache_size),
+            artists_cache: ArtistsCache::new(config.artists_cache_ttl),
+            play_count_lock: Default::default(),
+            announce_https: config.announce_https,
+            announce_type: config.announce_type,
+            avatar,
+            jukebox: config.jukebox,
         })))
 }
 
@@ -111,57 +288,356 @@ where
 
 #[derive(Deserialize)]
 struct AuthenticationQuery {
-    u: String,
+    u: Option<String>,
     p: Option<String>,
     t: Option<String>,
     s: Option<String>,
+    #[serde(rename = "apiKey")]
+    api_key: Option<String>,
+    #[serde(rename = "imageToken")]
+    image_token: Option<String>,
 }
 
+// authenticate checks credentials of the request. Accepted are a clear text (p=password) or hex
+// encoded (p=enc:hex) password, or a token (t=md5(password + salt)) with its salt (s). The
+// password wins if both a password and a token are given. Alternatively, an API key (apiKey)
+// authenticates the request as the user the key belongs to, and URLs returned to clients are
+// authenticated by a token (imageToken) signed for the endpoint they point to. Requests
+// authenticated otherwise than by a token get a URLSigner to sign such URLs with.
 async fn authenticate(req: Request<Body>, next: Next, auth: Authentication) -> Response {
+    let (mut parts, body) = req.into_parts();
+
+    if auth.disabled {
+        if let Err(err) = default_username(&mut parts, &auth.default_username) {
+            return serialize_reply(err, &serialization_format(&parts));
+        }
+        return next.run(Request::from_parts(parts, body)).await;
+    }
+
+    let res = match Query::<AuthenticationQuery>::from_request_parts(&mut parts, &()).await {
+        Ok(Query(aq)) if aq.image_token.is_some() => {
+            check_image_token(&auth, &aq, parts.uri.path()).map(|_| None)
+        }
+        Ok(Query(aq)) if aq.api_key.is_some() => check_api_key(&auth, &aq).and_then(|username| {
+            default_username(&mut parts, username).map(|_| Some(username.to_string()))
+        }),
+        Ok(Query(aq)) => check_password(&auth, &aq).map(|_| aq.u),
+        Err(err) => Err(err.into()),
+    };
+    match res {
+        Ok(Some(username)) => {
+            parts.extensions.insert(URLSigner {
+                key: auth.url_key,
+                username,
+            });
+        }
+        Ok(None) => (),
+        Err(err) => return serialize_reply(err, &serialization_format(&parts)),
+    }
+
+    next.run(Request::from_parts(parts, body)).await
+}
+
+// check_password checks the password or the token of the user
+fn check_password(auth: &Authentication, aq: &AuthenticationQuery) -> Result<()> {
     use constant_time_eq::constant_time_eq;
 
+    let Some(u) = &aq.u else {
+        return Err(Error::missing_parameter(
+            "either username or password is missing",
+        ));
+    };
+    // Unknown users are checked against empty credentials, which never match
+    let (valid_user, user) = match auth.users.get(u) {
+        Some(user) => (true, user.clone()),
+        None => (false, Credentials::new("")),
+    };
+
+    match (aq.p.as_deref(), aq.t.as_deref(), aq.s.as_deref()) {
+        (Some(p), _, _)
+            if p.starts_with("enc:")
+                && constant_time_eq(p.as_bytes(), user.encoded_password.as_bytes())
+                && valid_user =>
+        {
+            Ok(())
+        }
+        (Some(p), _, _) if p.starts_with("enc:") => Err(Error::authentication_failed()),
+        (Some(p), _, _)
+            if constant_time_eq(p.as_bytes(), user.password.as_bytes()) && valid_user =>
+        {
+            Ok(())
+        }
+        (Some(_), _, _) => Err(Error::authentication_failed()),
+        (_, Some(t), Some(s))
+            if constant_time_eq(
+                t.as_bytes(),
+                format!("{:?}", md5::compute(user.password + s)).as_bytes(),
+            ) && valid_user =>
+        {
+            Ok(())
+        }
+        (_, Some(_), Some(_)) => Err(Error::authentication_failed()),
+        (_, Some(_), None) => Err(Error::missing_parameter(
+            "salt (s) is required for token authentication",
+        )),
+        (_, None, Some(_)) => Err(Error::missing_parameter(
+            "token (t) is required for token authentication",
+        )),
+        _ => Err(Error::missing_parameter(
+            "either username or password is missing",
+        )),
+    }
+}
+
+// check_api_key checks the API key of the request and returns the user it belongs to. The key
+// can't be mixed with a username, a password or a token.
+fn check_api_key<'a>(auth: &'a Authentication, aq: &AuthenticationQuery) -> Result<&'a str> {
+    use constant_time_eq::constant_time_eq;
+
+    let Some((key, username)) = &auth.api_key else {
+        return Err(Error::authentication_not_supported(
+            "API key authentication is not enabled",
+        ));
+    };
+    if aq.u.is_some() || aq.p.is_some() || aq.t.is_some() || aq.s.is_some() {
+        return Err(Error::conflicting_authentication());
+    }
+
+    match constant_time_eq(
+        aq.api_key.as_deref().unwrap_or_default().as_bytes(),
+        key.as_bytes(),
+    ) {
+        true => Ok(username),
+        false => Err(Error::invalid_api_key()),
+    }
+}
+
+// check_image_token checks the token of a URL previously returned to the client. The token is
+// only valid for the endpoint it was signed for and can't be mixed with other credentials.
+fn check_image_token(auth: &Authentication, aq: &AuthenticationQuery, path: &str) -> Result<()> {
+    if aq.p.is_some() || aq.t.is_some() || aq.s.is_some() || aq.api_key.is_some() {
+        return Err(Error::conflicting_authentication());
+    }
+    let (Some(u), Some(token)) = (&aq.u, &aq.image_token) else {
+        return Err(Error::missing_parameter(
+            "username (u) is required for image token authentication",
+        ));
+    };
+    let view = path
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(".view");
+
+    let valid = token
+        .split_once('.')
+        .and_then(|(expires, signature)| Some((expires.parse::<u64>().ok()?, signature)))
+        .filter(|&(expires, _)| expires >= unix_time())
+        .and_then(|(expires, signature)| {
+            hmac::verify(
+                &auth.url_key,
+                url_payload(view, u, expires).as_bytes(),
+                &hex::decode(signature).ok()?,
+            )
+            .ok()
+        })
+        .is_some();
+    match valid && auth.users.contains_key(u) {
+        true => Ok(()),
+        false => Err(Error::authentication_failed()),
+    }
+}
+
+// How long URLs returned to clients stay valid
+const URL_TOKEN_TTL: Duration = Duration::from_secs(60 * 60);
+
+// URLSigner signs URLs returned to the client on behalf of the user who made the request
+#[derive(Clone)]
+struct URLSigner {
+    key: hmac::Key,
+    username: String,
+}
+
+impl URLSigner {
+    // token returns the token authenticating requests to the given endpoint until expires
+    fn token(&self, view: &str, expires: u64) -> String {
+        let signature = hmac::sign(
+            &self.key,
+            url_payload(view, &self.username, expires).as_bytes(),
+        );
+        format!("{expires}.{}", hex::encode(signature))
+    }
+}
+
+// url_payload is the signed part of a URL token
+fn url_payload(view: &str, username: &str, expires: u64) -> String {
+    format!("{view}\n{username}\n{expires}")
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+// default_username adds username to the request query unless the request already has one, so
+// that handlers relying on the `u` parameter keep working with authentication disabled.
+fn default_username(parts: &mut Parts, username: &str) -> Result<()> {
+    let query = parts.uri.query().unwrap_or_default();
+    let has_username = serde_urlencoded::from_str::<Vec<(String, String)>>(query)
+        .map_err(|_| Error::generic_error(Some("invalid query")))?
+        .iter()
+        .any(|(k, _)| k == "u");
+    if has_username {
+        return Ok(());
+    }
+
+    let username =
+        serde_urlencoded::to_string([("u", username)]).map_err(|_| Error::generic_error(None))?;
+    append_query(parts, &username)
+}
+
+// Maximum size of form encoded request bodies
+const MAX_FORM_SIZE: usize = 1024 * 1024;
+
+// form_post moves parameters of POST requests with form encoded bodies to the query, so that
+// neither authentication nor handlers have to care about how the client sent them (OpenSubsonic
+// formPost extension)
+async fn form_post(req: Request<Body>, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
 
-    let aq = Query::<AuthenticationQuery>::from_request_parts(&mut parts, &()).await;
-    let err: Option<Error> = if let Ok(aq) = aq {
-        let valid_user = constant_time_eq(aq.u.as_bytes(), auth.username.as_bytes());
-
-        match (aq.p.as_deref(), aq.t.as_deref(), aq.s.as_deref()) {
-            (Some(p), _, _)
-                if p.starts_with("enc:")
-                    && constant_time_eq(p.as_bytes(), auth.encoded_password.as_bytes())
-                    && valid_user =>
-            {
-                None
-            }
-            (Some(p), _, _) if p.starts_with("enc:") => Some(Error::authentication_failed()),
-            (Some(p), _, _)
-                if constant_time_eq(p.as_bytes(), auth.password.as_bytes()) && valid_user =>
-            {
-                None
-            }
-            (Some(_), _, _) => Some(Error::authentication_failed()),
-            (_, Some(t), Some(s))
-                if constant_time_eq(
-                    t.as_bytes(),
-                    format!("{:?}", md5::compute(auth.password + s)).as_bytes(),
-                ) && valid_user =>
-            {
-                None
-            }
-            (_, Some(_), Some(_)) => Some(Error::authentication_failed()),
-            _ => Some(Error::missing_parameter(
-                "either username or password is missing",
-            )),
+    let is_form = parts.method == Method::POST
+        && parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return next.run(Request::from_parts(parts, body)).await;
+    }
+
+    let form = match to_bytes(body, MAX_FORM_SIZE).await {
+        Ok(form) => form,
+        Err(_) => {
+            return serialize_reply(
+                Error::generic_error(Some("failed to read request body")),
+                &serialization_format(&parts),
+            )
         }
-    } else {
-        aq.err().map(Into::into)
     };
-    if let Some(err) = err {
-        return serialize_reply(err, &serialization_format(&parts));
+    let form = String::from_utf8_lossy(&form);
+    let form = form.trim();
+    if !form.is_empty() {
+        if let Err(err) = append_query(&mut parts, form) {
+            return serialize_reply(err, &serialization_format(&parts));
+        }
     }
 
-    next.run(Request::from_parts(parts, body)).await
+    next.run(Request::from_parts(parts, Body::empty())).await
+}
+
+// append_query appends already encoded parameters to the request query
+fn append_query(parts: &mut Parts, params: &str) -> Result<()> {
+    let path_and_query = match parts.uri.query().unwrap_or_default() {
+        "" => format!("{}?{}", parts.uri.path(), params),
+        query => format!("{}?{}&{}", parts.uri.path(), query, params),
+    };
+
+    let mut uri = parts.uri.clone().into_parts();
+    uri.path_and_query = Some(
+        path_and_query
+            .parse()
+            .map_err(|_| Error::generic_error(None))?,
+    );
+    parts.uri = Uri::from_parts(uri).map_err(|_| Error::generic_error(None))?;
+
+    Ok(())
+}
+
+// Query parameters which are copied from the request to URLs returned to the client. Credentials
+// are never copied, the URLs are signed instead.
+const URL_PARAMETERS: &[&str] = &["v", "c"];
+
+// ServerUrl builds absolute URLs of API endpoints as seen by the client making the request
+#[derive(Clone)]
+pub(crate) struct ServerUrl {
+    base: String,
+    params: Vec<(String, String)>,
+    signer: Option<URLSigner>,
+}
+
+impl ServerUrl {
+    // endpoint returns URL of the given API endpoint with the given parameters. The URL is
+    // authenticated for a limited time as the user who made the request.
+    fn endpoint(&self, view: &str, params: &[(&str, &str)]) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query.extend_pairs(self.params.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        if let Some(signer) = &self.signer {
+            let token = signer.token(view, unix_time() + URL_TOKEN_TTL.as_secs());
+            query.extend_pairs([("u", signer.username.as_str()), ("imageToken", &token)]);
+        }
+        let query = query.extend_pairs(params).finish();
+
+        format!("{}/{}.view?{}", self.base, view, query)
+    }
+}
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for ServerUrl
+where
+    S: Send + Sync,
+{
+    type Rejection = ExtensionRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        let Extension(server) = Extension::<Arc<State>>::from_request_parts(parts, state).await?;
+        let host = header_value(&parts.headers, "x-forwarded-host")
+            .or_else(|| header_value(&parts.headers, header::HOST.as_str()))
+            .or_else(|| parts.uri.authority().map(|a| a.as_str()))
+            .unwrap_or("localhost");
+        let params =
+            serde_urlencoded::from_str::<Vec<(String, String)>>(parts.uri.query().unwrap_or(""))
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(k, _)| URL_PARAMETERS.contains(&k.as_str()))
+                .collect();
+
+        Ok(ServerUrl {
+            base: format!(
+                "{}://{}/rest",
+                url_scheme(server.announce_https, &parts.headers),
+                host
+            ),
+            params,
+            signer: parts.extensions.get::<URLSigner>().cloned(),
+        })
+    }
+}
+
+// url_scheme returns scheme of URLs returned to clients. Explicit configuration takes precedence
+// over X-Forwarded-Proto set by a reverse proxy, plain HTTP is assumed otherwise.
+fn url_scheme(announce_https: bool, headers: &HeaderMap) -> &'static str {
+    if announce_https {
+        return "https";
+    }
+    match header_value(headers, "x-forwarded-proto")
+        .and_then(|proto| proto.split(',').next())
+        .map(str::trim)
+    {
+        Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+        _ => "http",
+    }
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
 }
 
 // Trait for data that can be returned as API reply
@@ -172,63 +648,119 @@ trait Reply: yaserde::YaSerialize + serde::Serialize {
     fn field_name() -> Option<&'static str>;
 }
 
-// Optional query values controlling response serialization format.
-#[derive(Default, Deserialize)]
+// Optional query values controlling response serialization format. The server type announced
+// in the reply comes from the configuration rather than the query.
+#[derive(Clone, Default, Deserialize)]
 struct SerializationQuery {
     f: Option<String>,
     callback: Option<String>,
+    #[serde(skip)]
+    server_type: Option<String>,
+}
+
+impl SerializationQuery {
+    fn server_type(&self) -> &str {
+        self.server_type.as_deref().unwrap_or(SERVER_TYPE)
+    }
 }
 
 fn serialization_format(req: &Parts) -> SerializationQuery {
     let query = req.uri.query().unwrap_or_default();
 
     // Official Subsonic server falls back to XML if some of the parameters are invalid or not provided
-    serde_urlencoded::from_str::<SerializationQuery>(query).unwrap_or_default()
+    SerializationQuery {
+        server_type: req
+            .extensions
+            .get::<Arc<State>>()
+            .map(|state| state.announce_type.clone()),
+        ..serde_urlencoded::from_str::<SerializationQuery>(query).unwrap_or_default()
+    }
 }
 
 fn serialize_reply<T>(reply: T, format: &SerializationQuery) -> Response
 where
     T: Reply,
 {
+    let mut body = Vec::new();
+    write_reply(&reply, format, &mut body).expect("failed to serialize reply");
+
+    ([(header::CONTENT_TYPE, reply_content_type(format))], body).into_response()
+}
+
+// stream_reply serializes the reply on a blocking thread and sends it to the client in chunks
+// as it is being produced, so the whole serialized reply is never buffered in memory.
+fn stream_reply<T>(reply: T, format: SerializationQuery) -> Response
+where
+    T: Reply + Send + 'static,
+{
+    use futures::StreamExt;
+
+    let content_type = reply_content_type(&format);
+    let (tx, rx) = futures::channel::mpsc::channel(1);
+
+    tokio::task::spawn_blocking(move || {
+        let mut writer = ChunkWriter::new(tx, STREAM_CHUNK_SIZE);
+        if let Err(err) = write_reply(&reply, &format, &mut writer)
+            .and_then(|_| std::io::Write::flush(&mut writer).map_err(|err| err.to_string()))
+        {
+            warn!(action = "stream reply", err = ?err);
+        }
+    });
+
+    (
+        [(header::CONTENT_TYPE, content_type)],
+        Body::from_stream(rx.map(Ok::<_, std::convert::Infallible>)),
+    )
+        .into_response()
+}
+
+fn reply_content_type(format: &SerializationQuery) -> HeaderValue {
     match (format.f.as_deref(), &format.callback) {
-        (Some("json"), _) => (
-            [(
-                header::CONTENT_TYPE,
-                HeaderValue::from_static(mime::APPLICATION_JSON.as_ref()),
-            )],
-            json(&reply),
-        )
-            .into_response(),
-        (Some("jsonp"), Some(callback)) => (
-            [(
-                header::CONTENT_TYPE,
-                HeaderValue::from_static(mime::TEXT_JAVASCRIPT.as_ref()),
-            )],
-            format!(
-                "{callback}({data})",
-                callback = callback,
-                data = json(&reply)
-            ),
-        )
-            .into_response(),
-        _ => (
-            [(
-                header::CONTENT_TYPE,
-                HeaderValue::from_static(mime::TEXT_XML.as_ref()),
-            )],
-            xml(&reply),
-        )
-            .into_response(),
+        (Some("json"), _) => HeaderValue::from_static(mime::APPLICATION_JSON.as_ref()),
+        (Some("jsonp"), Some(_)) => HeaderValue::from_static(mime::TEXT_JAVASCRIPT.as_ref()),
+        _ => HeaderValue::from_static(mime::TEXT_XML.as_ref()),
     }
 }
 
+fn write_reply<T, W>(
+    reply: &T,
+    format: &SerializationQuery,
+    mut writer: W,
+) -> std::result::Result<(), String>
+where
+    T: Reply,
+    W: std::io::Write,
+{
+    let server_type = format.server_type();
+    match (format.f.as_deref(), &format.callback) {
+        (Some("json"), _) => write_json(reply, server_type, writer),
+        (Some("jsonp"), Some(callback)) => {
+            write!(writer, "{callback}(").map_err(|err| err.to_string())?;
+            write_json(reply, server_type, &mut writer)?;
+            write!(writer, ")").map_err(|err| err.to_string())
+        }
+        _ => write_xml(reply, server_type, writer),
+    }
+}
+
+#[cfg(test)]
 fn xml<T>(reply: &T) -> String
 where
     T: Reply,
 {
-    use yaserde::ser::{to_string_with_config, Config, Serializer};
+    let mut buf = Vec::new();
+    write_xml(reply, SERVER_TYPE, &mut buf).expect("failed to serialize XML reply");
+    String::from_utf8(buf).expect("XML reply is not valid UTF-8")
+}
+
+fn write_xml<T, W>(reply: &T, server_type: &str, writer: W) -> std::result::Result<(), String>
+where
+    T: Reply,
+    W: std::io::Write,
+{
+    use yaserde::ser::{serialize_with_writer, Config, Serializer};
 
-    struct Response<'a, T>(&'a T);
+    struct Response<'a, T>(&'a T, &'a str);
 
     impl<'a, T> yaserde::YaSerialize for Response<'a, T>
     where
@@ -252,7 +784,10 @@ where
                                 "ok"
                             },
                         )
-                        .attr("version", VERSION),
+                        .attr("version", VERSION)
+                        .attr("type", self.1)
+                        .attr("serverVersion", SERVER_VERSION)
+                        .attr("openSubsonic", "true"),
                 )
                 .map_err(|err| err.to_string())?;
             if <T as Reply>::field_name().is_some() {
@@ -279,24 +814,35 @@ where
         }
     }
 
-    to_string_with_config(
-        &Response(reply),
+    serialize_with_writer(
+        &Response(reply, server_type),
+        writer,
         &Config {
             perform_indent: true,
             ..Default::default()
         },
     )
-    .expect("failed to serialize XML reply")
+    .map(|_| ())
 }
 
+#[cfg(test)]
 fn json<T>(reply: &T) -> String
 where
     T: Reply,
 {
-    use serde::ser::{SerializeMap, Serializer};
-    use serde_json::to_string_pretty;
+    let mut buf = Vec::new();
+    write_json(reply, SERVER_TYPE, &mut buf).expect("failed to serialize JSON reply");
+    String::from_utf8(buf).expect("JSON reply is not valid UTF-8")
+}
 
-    struct InnerResponse<'a, T>(&'a T);
+fn write_json<T, W>(reply: &T, server_type: &str, writer: W) -> std::result::Result<(), String>
+where
+    T: Reply,
+    W: std::io::Write,
+{
+    use serde::ser::{SerializeMap, Serializer};
+    use serde_json::to_writer_pretty;
+    struct InnerResponse<'a, T>(&'a T, &'a str);
 
     #[derive(Serialize)]
     struct Response<'a, T: Reply> {
@@ -312,7 +858,7 @@ where
         where
             S: Serializer,
         {
-            let mut map = serializer.serialize_map(Some(3))?;
+            let mut map = serializer.serialize_map(Some(6))?;
             map.serialize_entry(
                 "status",
                 if <T as Reply>::is_error() {
@@ -322,6 +868,9 @@ where
                 },
             )?;
             map.serialize_entry("version", VERSION)?;
+            map.serialize_entry("type", self.1)?;
+            map.serialize_entry("serverVersion", SERVER_VERSION)?;
+            map.serialize_entry("openSubsonic", &true)?;
             if let Some(field) = <T as Reply>::field_name() {
                 map.serialize_entry(field, &self.0)?;
             }
@@ -329,10 +878,13 @@ where
         }
     }
 
-    to_string_pretty(&Response {
-        sr: InnerResponse(reply),
-    })
-    .expect("failed to serialize JSON reply")
+    to_writer_pretty(
+        writer,
+        &Response {
+            sr: InnerResponse(reply, server_type),
+        },
+    )
+    .map_err(|err| err.to_string())
 }
 
 #[cfg(test)]
@@ -340,18 +892,22 @@ fn expect_xml(inner: Option<&str>, status: &str) -> String {
     match inner {
         Some(inner) => format!(
             r#"<?xml version="1.0" encoding="utf-8"?>
-<subsonic-response xmlns="http://subsonic.org/restapi" status="{status}" version="{version}">
+<subsonic-response xmlns="http://subsonic.org/restapi" status="{status}" version="{version}" type="{server_type}" serverVersion="{server_version}" openSubsonic="true">
   {inner}
 </subsonic-response>"#,
             status = status,
             version = VERSION,
+            server_type = SERVER_TYPE,
+            server_version = SERVER_VERSION,
             inner = inner
         ),
         None => format!(
             r#"<?xml version="1.0" encoding="utf-8"?>
-<subsonic-response xmlns="http://subsonic.org/restapi" status="{status}" version="{version}" />"#,
+<subsonic-response xmlns="http://subsonic.org/restapi" status="{status}" version="{version}" type="{server_type}" serverVersion="{server_version}" openSubsonic="true" />"#,
             status = status,
             version = VERSION,
+            server_type = SERVER_TYPE,
+            server_version = SERVER_VERSION,
         ),
     }
 }
@@ -368,6 +924,9 @@ fn expect_json(inner: Option<serde_json::Value>, status: &str) -> String {
     "subsonic-response": {
     "status": status,
     "version": VERSION,
+    "type": SERVER_TYPE,
+    "serverVersion": SERVER_VERSION,
+    "openSubsonic": true,
     }});
 
     if let Some(inner) = inner {
@@ -382,7 +941,450 @@ fn expect_json(inner: Option<serde_json::Value>, status: &str) -> String {
     to_string_pretty(&exp).unwrap()
 }
 
+// test_state returns API state which never connects to MPD, suitable for testing handlers
+// that fail before talking to MPD
+#[cfg(test)]
+async fn test_state() -> Arc<State> {
+    test_state_with_mpd(([127, 0, 0, 1], 0).into()).await
+}
+
+// test_state_with_mpd returns API state which connects to MPD at the given address
+#[cfg(test)]
+async fn test_state_with_mpd(address: std::net::SocketAddr) -> Arc<State> {
+    let manager = ConnectionManager::new(
+        &crate::mpd::Address::Tcp(address),
+        &None,
+        crate::mpd::testing::TIMEOUT,
+    );
+
+    Arc::new(State {
+        pool: Pool::builder().build_unchecked(manager),
+        lib: super::library::get_library("/").await.unwrap(),
+        listenbrainz: None,
+        artist_info: None,
+        cover_art_archive: None,
+        artist_image_dir: None,
+        hide_paths: false,
+        album_starred_any: false,
+        default_transcode_format: TranscodeFormat::default(),
+        read_only: false,
+        cover_cache: CoverCache::new(0),
+        artists_cache: ArtistsCache::new(Duration::ZERO),
+        play_count_lock: Default::default(),
+        announce_https: false,
+        announce_type: SERVER_TYPE.to_string(),
+        avatar: None,
+        jukebox: false,
+    })
+}
+
+// test_server_url returns server URL of a client talking to the server over plain HTTP
+#[cfg(test)]
+fn test_server_url() -> ServerUrl {
+    ServerUrl {
+        base: "http://localhost/rest".to_string(),
+        params: vec![],
+        signer: None,
+    }
+}
+
 #[cfg(test)]
 fn expect_ok_json(inner: Option<serde_json::Value>) -> String {
     expect_json(inner, "ok")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        authenticate, cors, form_post, unix_time, url_scheme, xml, Authentication, Error,
+        ServerUrl, URLSigner,
+    };
+    use axum::{
+        body::{to_bytes, Body},
+        extract::{Extension, FromRequestParts, Query},
+        http::{header, HeaderMap, HeaderValue, Request},
+        middleware,
+        routing::{any, Router},
+    };
+    use std::collections::HashMap;
+    use tower_service::Service;
+
+    async fn request(auth: Authentication, uri: &str) -> String {
+        send(auth, Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    async fn post(auth: Authentication, uri: &str, form: &str) -> String {
+        let req = Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(form.to_string()))
+            .unwrap();
+        send(auth, req).await
+    }
+
+    async fn send(auth: Authentication, req: Request<Body>) -> String {
+        let mut router = Router::new()
+            .route(
+                "/rest/whoami.view",
+                any(|Query(q): Query<HashMap<String, String>>| async move {
+                    q.get("u").cloned().unwrap_or_default()
+                }),
+            )
+            .route(
+                "/rest/signer.view",
+                any(|signer: Option<Extension<URLSigner>>| async move {
+                    signer.map(|Extension(s)| s.username).unwrap_or_default()
+                }),
+            )
+            .route_layer(middleware::from_fn(move |req, next| {
+                authenticate(req, next, auth.clone())
+            }))
+            .route_layer(middleware::from_fn(form_post));
+
+        let resp = router.call(req).await.unwrap();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn cors_headers() {
+        let mut router = Router::new()
+            .route("/rest/ping.view", any(|| async { "" }))
+            .layer(cors());
+        let req = Request::get("/rest/ping.view")
+            .header(header::ORIGIN, "http://example.com")
+            .body(Body::empty())
+            .unwrap();
+
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        let exposed = resp.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(exposed.contains("x-total-count"), "{exposed}");
+        assert!(exposed.contains("x-total-count-songs"), "{exposed}");
+    }
+
+    #[tokio::test]
+    async fn authentication_enabled() {
+        let auth = Authentication::new("alice", "secret");
+
+        assert!(request(auth.clone(), "/rest/whoami.view")
+            .await
+            .contains(r#"status="failed""#));
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?u=alice").await,
+            xml(&Error::missing_parameter(
+                "either username or password is missing"
+            ))
+        );
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?u=alice&p=wrong").await,
+            xml(&Error::authentication_failed())
+        );
+        assert_eq!(
+            request(auth, "/rest/whoami.view?u=alice&p=secret").await,
+            "alice"
+        );
+    }
+
+    #[tokio::test]
+    async fn authentication_modes() {
+        let auth = Authentication::new("alice", "secret");
+        let token = format!("{:?}", md5::compute("secretsalt"));
+        let missing = |msg| xml(&Error::missing_parameter(msg));
+
+        let cases = [
+            ("", missing("either username or password is missing")),
+            ("&p=secret", "alice".to_string()),
+            ("&p=wrong", xml(&Error::authentication_failed())),
+            (
+                &format!("&t={token}"),
+                missing("salt (s) is required for token authentication"),
+            ),
+            (
+                "&s=salt",
+                missing("token (t) is required for token authentication"),
+            ),
+            (&format!("&t={token}&s=salt"), "alice".to_string()),
+            (
+                &format!("&t={token}&s=pepper"),
+                xml(&Error::authentication_failed()),
+            ),
+            (&format!("&p=secret&t={token}"), "alice".to_string()),
+            ("&p=secret&s=salt", "alice".to_string()),
+            (
+                "&p=wrong&t=wrong&s=salt",
+                xml(&Error::authentication_failed()),
+            ),
+            (
+                &format!("&p=wrong&t={token}&s=salt"),
+                xml(&Error::authentication_failed()),
+            ),
+        ];
+        for (params, expected) in cases {
+            assert_eq!(
+                request(auth.clone(), &format!("/rest/whoami.view?u=alice{params}")).await,
+                expected,
+                "{params}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn api_key() {
+        let auth = Authentication::new("alice", "secret").with_user("bob", "hunter2");
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?apiKey=key").await,
+            xml(&Error::authentication_not_supported(
+                "API key authentication is not enabled"
+            ))
+        );
+
+        let auth = auth.with_api_key("key");
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?apiKey=key").await,
+            "alice"
+        );
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?apiKey=wrong").await,
+            xml(&Error::invalid_api_key())
+        );
+        for params in [
+            "u=alice",
+            "u=bob",
+            "p=secret",
+            "t=token&s=salt",
+            "u=alice&p=secret",
+        ] {
+            assert_eq!(
+                request(
+                    auth.clone(),
+                    &format!("/rest/whoami.view?apiKey=key&{params}")
+                )
+                .await,
+                xml(&Error::conflicting_authentication()),
+                "{params}"
+            );
+        }
+        assert_eq!(
+            request(auth, "/rest/whoami.view?u=bob&p=hunter2").await,
+            "bob"
+        );
+    }
+
+    #[tokio::test]
+    async fn multiple_users() {
+        let auth = Authentication::new("alice", "secret").with_user("bob", "hunter2");
+
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?u=alice&p=secret").await,
+            "alice"
+        );
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?u=bob&p=hunter2").await,
+            "bob"
+        );
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?u=bob&p=enc:68756e74657232").await,
+            "bob"
+        );
+        assert_eq!(
+            request(
+                auth.clone(),
+                &format!(
+                    "/rest/whoami.view?u=bob&t={:?}&s=salt",
+                    md5::compute("hunter2salt")
+                )
+            )
+            .await,
+            "bob"
+        );
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?u=bob&p=secret").await,
+            xml(&Error::authentication_failed())
+        );
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?u=alice&p=hunter2").await,
+            xml(&Error::authentication_failed())
+        );
+        assert_eq!(
+            request(auth, "/rest/whoami.view?u=carol&p=").await,
+            xml(&Error::authentication_failed())
+        );
+    }
+
+    #[tokio::test]
+    async fn form_post_parameters() {
+        let auth = Authentication::new("alice", "secret");
+
+        assert_eq!(
+            post(auth.clone(), "/rest/whoami.view", "u=alice&p=secret").await,
+            "alice"
+        );
+        assert_eq!(
+            post(auth.clone(), "/rest/whoami.view", "u=alice&p=wrong").await,
+            xml(&Error::authentication_failed())
+        );
+        assert_eq!(
+            post(auth.clone(), "/rest/whoami.view?u=alice", "p=secret").await,
+            "alice"
+        );
+        assert_eq!(
+            post(auth, "/rest/whoami.view?u=alice&p=secret", "").await,
+            "alice"
+        );
+    }
+
+    #[tokio::test]
+    async fn authentication_disabled() {
+        let auth = Authentication::disabled("alice");
+
+        assert_eq!(request(auth.clone(), "/rest/whoami.view").await, "alice");
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?f=json").await,
+            "alice"
+        );
+        assert_eq!(
+            request(auth.clone(), "/rest/whoami.view?u=bob&p=wrong").await,
+            "bob"
+        );
+        assert_eq!(request(auth, "/rest/whoami.view?u=b%20b").await, "b b");
+    }
+
+    #[test]
+    fn scheme() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(url_scheme(false, &headers), "http");
+        assert_eq!(url_scheme(true, &headers), "https");
+
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("https"));
+        assert_eq!(url_scheme(false, &headers), "https");
+
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("http"));
+        assert_eq!(url_scheme(false, &headers), "http");
+        assert_eq!(url_scheme(true, &headers), "https");
+    }
+
+    #[tokio::test]
+    async fn server_url() {
+        let state = super::test_state().await;
+        let signer = URLSigner {
+            key: Authentication::new("alice", "secret").url_key,
+            username: "alice".to_string(),
+        };
+        let (mut parts, _) =
+            Request::get("/rest/getArtistInfo2.view?u=alice&t=abc&s=def&v=1.16.1&f=json&id=1")
+                .header("host", "music.example.com")
+                .header("x-forwarded-proto", "https")
+                .extension(state)
+                .extension(signer)
+                .body(Body::empty())
+                .unwrap()
+                .into_parts();
+
+        let Ok(url) = ServerUrl::from_request_parts(&mut parts, &()).await else {
+            panic!("failed to build server URL");
+        };
+        let url = url.endpoint("getCoverArt", &[("id", "a b")]);
+        let (base, query) = url.split_once('?').unwrap();
+        assert_eq!(base, "https://music.example.com/rest/getCoverArt.view");
+
+        let query = serde_urlencoded::from_str::<HashMap<String, String>>(query).unwrap();
+        assert_eq!(query["v"], "1.16.1");
+        assert_eq!(query["u"], "alice");
+        assert_eq!(query["id"], "a b");
+        assert!(query.contains_key("imageToken"));
+        for credential in ["p", "t", "s"] {
+            assert!(!query.contains_key(credential), "{credential}");
+        }
+    }
+
+    #[tokio::test]
+    async fn image_token() {
+        let auth = Authentication::new("alice", "secret").with_api_key("key");
+        let signer = |username: &str| URLSigner {
+            key: auth.url_key.clone(),
+            username: username.to_string(),
+        };
+        let expires = unix_time() + 60;
+        let token = signer("alice").token("whoami", expires);
+
+        assert_eq!(
+            request(
+                auth.clone(),
+                &format!("/rest/whoami.view?u=alice&imageToken={token}")
+            )
+            .await,
+            "alice"
+        );
+
+        let failed = xml(&Error::authentication_failed());
+        let cases = [
+            format!("u=bob&imageToken={token}"),
+            format!(
+                "u=alice&imageToken={}",
+                signer("alice").token("getCoverArt", expires)
+            ),
+            format!(
+                "u=alice&imageToken={}",
+                signer("alice").token("whoami", unix_time() - 1)
+            ),
+            format!(
+                "u=bob&imageToken={}",
+                signer("bob").token("whoami", expires)
+            ),
+            format!(
+                "u=alice&imageToken={}",
+                URLSigner {
+                    key: Authentication::new("alice", "secret").url_key,
+                    username: "alice".to_string(),
+                }
+                .token("whoami", expires)
+            ),
+            "u=alice&imageToken=garbage".to_string(),
+        ];
+        for params in cases {
+            assert_eq!(
+                request(auth.clone(), &format!("/rest/whoami.view?{params}")).await,
+                failed,
+                "{params}"
+            );
+        }
+
+        assert_eq!(
+            request(
+                auth.clone(),
+                &format!("/rest/whoami.view?u=alice&p=secret&imageToken={token}")
+            )
+            .await,
+            xml(&Error::conflicting_authentication())
+        );
+    }
+
+    #[tokio::test]
+    async fn url_signer() {
+        let auth = Authentication::new("alice", "secret")
+            .with_user("bob", "hunter2")
+            .with_api_key("key");
+
+        assert_eq!(
+            request(auth.clone(), "/rest/signer.view?u=bob&p=hunter2").await,
+            "bob"
+        );
+        assert_eq!(
+            request(auth.clone(), "/rest/signer.view?apiKey=key").await,
+            "alice"
+        );
+
+        let token = URLSigner {
+            key: auth.url_key.clone(),
+            username: "bob".to_string(),
+        }
+        .token("signer", unix_time() + 60);
+        assert_eq!(
+            request(auth, &format!("/rest/signer.view?u=bob&imageToken={token}")).await,
+            ""
+        );
+    }
+}
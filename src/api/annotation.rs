@@ -1,18 +1,23 @@
-use crate::listenbrainz;
+use crate::{listenbrainz, mpd::Connection};
 
 use super::{
-    common::{STICKER_RATING, STICKER_STARRED},
-    types::SongID,
+    common::{
+        find_albums_songs, get_songs_annotations, STICKER_PLAY_COUNT, STICKER_RATING,
+        STICKER_STARRED,
+    },
+    glue::RawQuery,
+    types::{AlbumID, ArtistID, SongID},
     Error,
 };
 use axum::{extract::Query, routing::Router, Extension};
 use mpd_client::{
-    commands::{Find, StickerDelete, StickerSet},
+    commands::{Find, StickerDelete, StickerGet, StickerSet},
     filter::Filter,
+    responses,
     tag::Tag,
 };
 use serde::Deserialize;
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 use time::{format_description::well_known, OffsetDateTime};
 
 pub(crate) fn get_router() -> Router {
@@ -36,15 +41,8 @@ async fn scrobble(
     Extension(state): Extension<Arc<super::State>>,
     Query(param): Query<ScrobbleQuery>,
 ) -> super::Result<()> {
-    let listenbrainz = state
-        .listenbrainz
-        .as_ref()
-        .ok_or_else(|| Error::generic_error(Some("ListenBrainz client is not configured")))?;
-
-    let songs = state
-        .pool
-        .get()
-        .await?
+    let conn = state.pool.get().await?;
+    let songs = conn
         .command(Find::new(Filter::tag(
             Tag::Other("file".into()),
             param.song.path,
@@ -54,44 +52,114 @@ async fn scrobble(
 
     match param.submission {
         Some(true) => {
-            listenbrainz
-                .listen(
-                    song,
-                    param
-                        .time
-                        .unwrap_or_else(|| OffsetDateTime::now_utc().unix_timestamp()),
-                )
-                .await?
+            if !state.read_only {
+                let _guard = state.play_count_lock.lock().await;
+                increment_play_count(&conn, &song.url).await?;
+            }
+            if let Some(listenbrainz) = &state.listenbrainz {
+                listenbrainz
+                    .listen(
+                        song,
+                        param
+                            .time
+                            .map(scrobble_timestamp)
+                            .unwrap_or_else(|| OffsetDateTime::now_utc().unix_timestamp()),
+                    )
+                    .await?
+            }
+        }
+        _ => {
+            if let Some(listenbrainz) = &state.listenbrainz {
+                listenbrainz.playing_now(song).await?
+            }
         }
-        _ => listenbrainz.playing_now(song).await?,
     }
 
     Ok(())
 }
 
+// increment_play_count increments the play count sticker of the song. MPD can't increment it
+// atomically, so callers must not update play counts concurrently. Updates made by other MPD
+// clients at the same time can still be lost.
+async fn increment_play_count(conn: &Connection, path: &str) -> super::Result<()> {
+    // MPD fails the request if the song doesn't have the sticker yet
+    let count = conn
+        .command(StickerGet::new(path, STICKER_PLAY_COUNT))
+        .await
+        .ok()
+        .and_then(|s| s.value.parse::<u64>().ok())
+        .unwrap_or(0);
+    conn.command(StickerSet::new(
+        path,
+        STICKER_PLAY_COUNT,
+        &(count + 1).to_string(),
+    ))
+    .await?;
+
+    Ok(())
+}
+
+// Timestamps above this are in milliseconds. In seconds, it is far in the future, while in
+// milliseconds it is early 1973.
+const MAX_SCROBBLE_TIMESTAMP_SECS: i64 = 100_000_000_000;
+
+// scrobble_timestamp converts scrobble time into a unix timestamp in seconds. Subsonic specifies
+// the time in milliseconds, but some clients send seconds.
+fn scrobble_timestamp(time: i64) -> i64 {
+    match time > MAX_SCROBBLE_TIMESTAMP_SECS {
+        true => time / 1000,
+        false => time,
+    }
+}
+
 #[derive(Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct SetRatingQuery {
-    #[serde(rename = "id")]
-    song: SongID,
+    id: RatingID,
     rating: u8,
 }
 
+// RatingID identifies what is being rated. Rating an album or an artist rates all of their
+// songs. Album IDs also decode as artist IDs, so albums must be tried first.
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+enum RatingID {
+    Song(SongID),
+    Album(AlbumID),
+    Artist(ArtistID),
+}
+
+const MAX_RATING: u8 = 5;
+
 async fn set_rating(
     Extension(state): Extension<Arc<super::State>>,
     Query(param): Query<SetRatingQuery>,
 ) -> super::Result<()> {
+    state.ensure_writable()?;
+    validate_rating(param.rating)?;
+
     let conn = state.pool.get().await?;
+    let song = match param.id {
+        RatingID::Song(song) => song,
+        RatingID::Album(album) => {
+            let songs = find_albums_songs(&conn, &[&album]).await?.concat();
+            return set_songs_rating(&conn, &songs, param.rating).await;
+        }
+        RatingID::Artist(artist) => {
+            let songs = conn.command(Find::new(artist_filter(&artist))).await?;
+            return set_songs_rating(&conn, &songs, param.rating).await;
+        }
+    };
+
     if param.rating > 0 {
         conn.command(StickerSet::new(
-            &param.song.path,
+            &song.path,
             STICKER_RATING,
             &param.rating.to_string(),
         ))
         .await?;
     } else {
-        conn.command(StickerDelete::new(&param.song.path, "rating"))
-            .await?;
+        delete_sticker(&conn, &song.path, STICKER_RATING).await?;
     };
 
     let listenbrainz = if let Some(ref client) = state.listenbrainz {
@@ -103,67 +171,648 @@ async fn set_rating(
     let songs = conn
         .command(Find::new(Filter::tag(
             Tag::Other("file".into()),
-            &param.song.path,
+            &song.path,
         )))
         .await?;
     let song = songs.first().ok_or_else(Error::not_found)?;
 
-    if let Some(score) = match param.rating {
+    if let Some(score) = rating_feedback(param.rating) {
+        listenbrainz.feedback(song, score).await?;
+    }
+
+    Ok(())
+}
+
+// delete_sticker deletes the sticker of the song. MPD fails to delete a sticker that doesn't
+// exist, but there is nothing to delete then, so it is not an error.
+async fn delete_sticker(conn: &Connection, path: &str, name: &str) -> super::Result<()> {
+    match conn.command(StickerDelete::new(path, name)).await {
+        Err(err) if !err.is_no_exist() => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+// set_songs_rating rates all songs of an album or an artist. ListenBrainz feedback is per
+// recording, so it is not submitted for albums and artists.
+async fn set_songs_rating(
+    conn: &Connection,
+    songs: &[responses::Song],
+    rating: u8,
+) -> super::Result<()> {
+    if songs.is_empty() {
+        return Err(Error::not_found());
+    }
+
+    if rating > 0 {
+        let rating = rating.to_string();
+        conn.command_list(
+            songs
+                .iter()
+                .map(|s| StickerSet::new(&s.url, STICKER_RATING, &rating))
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+    } else {
+        let annotations = get_songs_annotations(conn, songs).await?;
+        let rated = songs
+            .iter()
+            .filter(|s| annotations.ratings.contains_key(&s.url))
+            .map(|s| StickerDelete::new(&s.url, STICKER_RATING))
+            .collect::<Vec<_>>();
+        if !rated.is_empty() {
+            conn.command_list(rated).await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_rating(rating: u8) -> super::Result<()> {
+    match rating {
+        0..=MAX_RATING => Ok(()),
+        _ => Err(Error::generic_error(Some(&format!(
+            "rating must be between 0 and {MAX_RATING}"
+        )))),
+    }
+}
+
+// rating_feedback maps canonical ratings to ListenBrainz feedback
+fn rating_feedback(rating: u8) -> Option<listenbrainz::Score> {
+    match rating {
         0 => Some(listenbrainz::Score::Remove),
         1 => Some(listenbrainz::Score::Hate),
-        5 => Some(listenbrainz::Score::Love),
+        MAX_RATING => Some(listenbrainz::Score::Love),
         _ => None,
-    } {
-        listenbrainz.feedback(song, score).await?;
     }
+}
 
-    Ok(())
+fn artist_filter(artist: &ArtistID) -> Filter {
+    Filter::tag(Tag::AlbumArtist, &artist.name)
 }
 
-#[derive(Clone, Deserialize)]
-#[serde(rename_all = "camelCase")]
+// StarQuery identifies songs to star or unstar. An album or an artist stands for all of
+// their songs. Each kind of ID can be repeated, so the query is parsed manually.
+#[derive(Clone, Default)]
 struct StarQuery {
-    #[serde(rename = "id")]
-    song: SongID,
+    songs: Vec<SongID>,
+    album_ids: Vec<AlbumID>,
+    artist_ids: Vec<ArtistID>,
+}
+
+impl StarQuery {
+    fn parse(query: Option<String>) -> super::Result<Self> {
+        let query = query.ok_or_else(|| Error::missing_parameter("failed to parse URL query"))?;
+
+        let mut param = StarQuery::default();
+        for (k, v) in url::form_urlencoded::parse(query.as_bytes()) {
+            let invalid = |err| Error::missing_parameter(&format!("{k}: {err}"));
+            match k.as_ref() {
+                "id" => param
+                    .songs
+                    .push(SongID::try_from(v.as_ref()).map_err(invalid)?),
+                "albumId" => param
+                    .album_ids
+                    .push(AlbumID::try_from(v.as_ref()).map_err(invalid)?),
+                "artistId" => param
+                    .artist_ids
+                    .push(ArtistID::try_from(v.as_ref()).map_err(invalid)?),
+                _ => {}
+            }
+        }
+        if param.songs.is_empty() && param.album_ids.is_empty() && param.artist_ids.is_empty() {
+            return Err(Error::missing_parameter("either id, albumId or artistId"));
+        }
+
+        Ok(param)
+    }
+}
+
+// find_star_songs finds all songs of the albums and the artists from the query
+async fn find_star_songs(
+    conn: &Connection,
+    param: &StarQuery,
+) -> super::Result<Vec<responses::Song>> {
+    if param.album_ids.is_empty() && param.artist_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let albums = param.album_ids.iter().collect::<Vec<_>>();
+    let mut songs = find_albums_songs(conn, &albums).await?.concat();
+    if !param.artist_ids.is_empty() {
+        songs.extend(
+            conn.command_list(
+                param
+                    .artist_ids
+                    .iter()
+                    .map(|artist| Find::new(artist_filter(artist)))
+                    .collect::<Vec<_>>(),
+            )
+            .await?
+            .into_iter()
+            .flatten(),
+        );
+    }
+    match songs.is_empty() {
+        true => Err(Error::not_found()),
+        false => Ok(songs),
+    }
 }
 
 async fn star(
     Extension(state): Extension<Arc<super::State>>,
-    Query(param): Query<StarQuery>,
+    RawQuery(query): RawQuery,
 ) -> super::Result<()> {
-    state
-        .pool
-        .get()
-        .await?
-        .command(StickerSet::new(
-            &param.song.path,
-            STICKER_STARRED,
-            &OffsetDateTime::now_utc()
-                .format(&well_known::Rfc3339)
-                .map_err(|_| super::Error::generic_error(None))?,
-        ))
-        .await?;
+    state.ensure_writable()?;
+    let param = StarQuery::parse(query)?;
+    let conn = state.pool.get().await?;
 
-    Ok(())
-}
+    let songs = find_star_songs(&conn, &param).await?;
+    let paths = param
+        .songs
+        .iter()
+        .map(|s| s.path.as_str())
+        .chain(songs.iter().map(|s| s.url.as_str()))
+        .collect::<HashSet<_>>();
+    let now = OffsetDateTime::now_utc()
+        .format(&well_known::Rfc3339)
+        .map_err(|_| super::Error::generic_error(None))?;
 
-#[derive(Clone, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct UnstarQuery {
-    #[serde(rename = "id")]
-    song: SongID,
+    conn.command_list(
+        paths
+            .into_iter()
+            .map(|p| StickerSet::new(p, STICKER_STARRED, &now))
+            .collect::<Vec<_>>(),
+    )
+    .await?;
+
+    Ok(())
 }
 
 async fn unstar(
     Extension(state): Extension<Arc<super::State>>,
-    Query(param): Query<UnstarQuery>,
+    RawQuery(query): RawQuery,
 ) -> super::Result<()> {
-    state
-        .pool
-        .get()
-        .await?
-        .command(StickerDelete::new(&param.song.path, STICKER_STARRED))
-        .await?;
+    state.ensure_writable()?;
+    let param = StarQuery::parse(query)?;
+    let conn = state.pool.get().await?;
+
+    let songs = find_star_songs(&conn, &param).await?;
+    let paths = param
+        .songs
+        .iter()
+        .map(|s| s.path.as_str())
+        .chain(songs.iter().map(|s| s.url.as_str()))
+        .collect::<HashSet<_>>();
+    // Unstarring a song which is not starred is a no-op. MPD stops a command list at the first
+    // failed command, so songs are unstarred one by one.
+    for path in paths {
+        delete_sticker(&conn, path, STICKER_STARRED).await?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        rating_feedback, scrobble, scrobble_timestamp, set_rating, star, unstar, validate_rating,
+        RatingID, ScrobbleQuery, SetRatingQuery, STICKER_RATING,
+    };
+    use crate::{
+        api::{
+            error::Error,
+            glue::RawQuery,
+            test_state, test_state_with_mpd,
+            types::{AlbumID, ArtistID, SongID},
+            xml,
+        },
+        listenbrainz::Score,
+        mpd::testing::fake_server,
+    };
+    use axum::extract::{Extension, Query};
+    use std::sync::{Arc, Mutex};
+
+    // star_server starts a fake MPD server with an album of two songs, only the first of which
+    // is starred. It records all successful sticker modifications.
+    async fn star_server() -> (std::net::SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let stickers = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let stickers = stickers.clone();
+            move |command| {
+                if command.starts_with("find") {
+                    "file: alpha/beta/1.flac\nfile: alpha/beta/2.flac\n".to_string()
+                } else if command.starts_with("sticker find") && command.ends_with("starred") {
+                    "file: alpha/beta/1.flac\nsticker: starred=2023-01-02T03:04:05Z\n".to_string()
+                } else if command.starts_with("sticker delete")
+                    && !command.contains("alpha/beta/1.flac")
+                {
+                    "ACK [50@0] {sticker} no such sticker\n".to_string()
+                } else if command.starts_with("sticker set")
+                    || command.starts_with("sticker delete")
+                {
+                    stickers.lock().unwrap().push(command.to_string());
+                    String::new()
+                } else {
+                    String::new()
+                }
+            }
+        })
+        .await;
+
+        (mpd, stickers)
+    }
+
+    fn query(songs: &[&str], albums: &[(&str, &str)], artists: &[&str]) -> RawQuery {
+        let id = |id: serde_json::Value| id.as_str().unwrap().to_string();
+        let params = songs
+            .iter()
+            .map(|&s| ("id", id(serde_json::to_value(SongID::new(s)).unwrap())))
+            .chain(albums.iter().map(|&(name, artist)| {
+                let album = AlbumID::new(name, artist);
+                ("albumId", id(serde_json::to_value(album).unwrap()))
+            }))
+            .chain(artists.iter().map(|&a| {
+                (
+                    "artistId",
+                    id(serde_json::to_value(ArtistID::new(a)).unwrap()),
+                )
+            }))
+            .collect::<Vec<_>>();
+        RawQuery(Some(serde_urlencoded::to_string(params).unwrap()))
+    }
+
+    #[test]
+    fn set_rating_validation() {
+        assert!(validate_rating(0).is_ok());
+        assert!(validate_rating(3).is_ok());
+        assert!(validate_rating(5).is_ok());
+        assert!(validate_rating(6).is_err());
+        assert!(validate_rating(255).is_err());
+    }
+
+    #[test]
+    fn set_rating_feedback() {
+        assert!(matches!(rating_feedback(0), Some(Score::Remove)));
+        assert!(matches!(rating_feedback(1), Some(Score::Hate)));
+        assert!(rating_feedback(3).is_none());
+        assert!(matches!(rating_feedback(5), Some(Score::Love)));
+        assert!(rating_feedback(7).is_none());
+    }
+
+    #[tokio::test]
+    async fn star_album() {
+        let (mpd, stickers) = star_server().await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let res = star(
+            Extension(state.clone()),
+            query(&[], &[("beta", "alpha")], &[]),
+        )
+        .await;
+        assert!(res.is_ok());
+
+        let mut stickers = std::mem::take(&mut *stickers.lock().unwrap());
+        stickers.sort();
+        assert_eq!(stickers.len(), 2);
+        assert!(stickers[0].starts_with("sticker set song alpha/beta/1.flac starred"));
+        assert!(stickers[1].starts_with("sticker set song alpha/beta/2.flac starred"));
+    }
+
+    #[tokio::test]
+    async fn star_mixed() {
+        let (mpd, stickers) = star_server().await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let res = star(
+            Extension(state.clone()),
+            query(
+                &["gamma/1.flac", "alpha/beta/1.flac"],
+                &[("beta", "alpha"), ("delta", "gamma")],
+                &["alpha"],
+            ),
+        )
+        .await;
+        assert!(res.is_ok());
+
+        let mut stickers = std::mem::take(&mut *stickers.lock().unwrap());
+        stickers.sort();
+        assert_eq!(stickers.len(), 3);
+        assert!(stickers[0].starts_with("sticker set song alpha/beta/1.flac starred"));
+        assert!(stickers[1].starts_with("sticker set song alpha/beta/2.flac starred"));
+        assert!(stickers[2].starts_with("sticker set song gamma/1.flac starred"));
+    }
+
+    #[tokio::test]
+    async fn unstar_artist() {
+        let (mpd, stickers) = star_server().await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let res = unstar(Extension(state.clone()), query(&[], &[], &["alpha"])).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            *stickers.lock().unwrap(),
+            vec!["sticker delete song alpha/beta/1.flac starred"]
+        );
+    }
+
+    #[tokio::test]
+    async fn star_song() {
+        let (mpd, stickers) = star_server().await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let res = star(
+            Extension(state.clone()),
+            query(&["alpha/beta/2.flac"], &[], &[]),
+        )
+        .await;
+        assert!(res.is_ok());
+        let set = std::mem::take(&mut *stickers.lock().unwrap());
+        assert_eq!(set.len(), 1);
+        assert!(set[0].starts_with("sticker set song alpha/beta/2.flac starred"));
+
+        let res = unstar(
+            Extension(state.clone()),
+            query(&["alpha/beta/1.flac"], &[], &[]),
+        )
+        .await;
+        assert!(res.is_ok());
+        assert_eq!(
+            std::mem::take(&mut *stickers.lock().unwrap()),
+            vec!["sticker delete song alpha/beta/1.flac starred"]
+        );
+
+        // Unstarring a song which is not starred succeeds without touching its stickers
+        let res = unstar(
+            Extension(state.clone()),
+            query(&["alpha/beta/2.flac"], &[], &[]),
+        )
+        .await;
+        assert!(res.is_ok());
+        assert!(stickers.lock().unwrap().is_empty());
+
+        let res = star(Extension(state.clone()), query(&[], &[], &[])).await;
+        assert!(res.is_err());
+
+        // Invalid IDs fail the request instead of being skipped
+        let RawQuery(Some(valid)) = query(&["alpha/beta/2.flac"], &[], &[]) else {
+            unreachable!();
+        };
+        for id in ["id", "albumId", "artistId"] {
+            let query = RawQuery(Some(format!("{valid}&{id}=garbage")));
+            assert!(star(Extension(state.clone()), query).await.is_err(), "{id}");
+        }
+        assert!(stickers.lock().unwrap().is_empty());
+    }
+
+    fn rating_query<T: serde::Serialize>(id: T, rating: u8) -> SetRatingQuery {
+        let id = serde_json::to_value(id)
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string();
+        serde_urlencoded::from_str(
+            &serde_urlencoded::to_string([("id", id), ("rating", rating.to_string())]).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn rating_id() {
+        assert!(matches!(
+            rating_query(SongID::new("alpha/beta/1.flac"), 3).id,
+            RatingID::Song(song) if song.path == "alpha/beta/1.flac"
+        ));
+
+        assert!(matches!(
+            rating_query(AlbumID::new("beta", "alpha"), 3).id,
+            RatingID::Album(album) if album.name == "beta" && album.artist == "alpha"
+        ));
+
+        assert!(matches!(
+            rating_query(ArtistID::new("alpha"), 3).id,
+            RatingID::Artist(artist) if artist.name == "alpha"
+        ));
+    }
+
+    #[tokio::test]
+    async fn set_album_rating() {
+        let (mpd, stickers) = star_server().await;
+        let state = test_state_with_mpd(mpd).await;
+        let album = || AlbumID::new("beta", "alpha");
+
+        let res = set_rating(Extension(state.clone()), Query(rating_query(album(), 4))).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            std::mem::take(&mut *stickers.lock().unwrap()),
+            vec![
+                "sticker set song alpha/beta/1.flac rating 4",
+                "sticker set song alpha/beta/2.flac rating 4"
+            ]
+        );
+
+        let res = set_rating(Extension(state), Query(rating_query(album(), 0))).await;
+        assert!(res.is_ok());
+        assert!(stickers.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn album_without_album_artist() {
+        let stickers = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let stickers = stickers.clone();
+            move |command| {
+                if command.starts_with("find") && command.contains("(Artist ==") {
+                    "file: alpha/beta/1.flac\n".to_string()
+                } else if command.starts_with("sticker set") {
+                    stickers.lock().unwrap().push(command.to_string());
+                    String::new()
+                } else {
+                    String::new()
+                }
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let album = || AlbumID::new("beta", "alpha");
+
+        let res = set_rating(Extension(state.clone()), Query(rating_query(album(), 4))).await;
+        assert!(res.is_ok());
+        let res = star(Extension(state), query(&[], &[("beta", "alpha")], &[])).await;
+        assert!(res.is_ok());
+
+        let stickers = std::mem::take(&mut *stickers.lock().unwrap());
+        assert_eq!(stickers.len(), 2);
+        assert_eq!(stickers[0], "sticker set song alpha/beta/1.flac rating 4");
+        assert!(stickers[1].starts_with("sticker set song alpha/beta/1.flac starred"));
+    }
+
+    #[tokio::test]
+    async fn set_song_rating() {
+        let (mpd, stickers) = star_server().await;
+        let state = test_state_with_mpd(mpd).await;
+        let song = || SongID::new("alpha/beta/1.flac");
+
+        let res = set_rating(Extension(state.clone()), Query(rating_query(song(), 4))).await;
+        assert!(res.is_ok());
+        let res = set_rating(Extension(state), Query(rating_query(song(), 0))).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            *stickers.lock().unwrap(),
+            vec![
+                format!("sticker set song alpha/beta/1.flac {STICKER_RATING} 4"),
+                format!("sticker delete song alpha/beta/1.flac {STICKER_RATING}"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_missing_stickers() {
+        let deletes = Arc::new(Mutex::new(0));
+        let mpd = fake_server({
+            let deletes = deletes.clone();
+            move |command| {
+                if command.starts_with("sticker delete") {
+                    *deletes.lock().unwrap() += 1;
+                    "ACK [50@0] {sticker} no such sticker\n".to_string()
+                } else {
+                    String::new()
+                }
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let res = set_rating(
+            Extension(state.clone()),
+            Query(rating_query(SongID::new("alpha/beta/1.flac"), 0)),
+        )
+        .await;
+        assert!(res.is_ok());
+
+        let res = unstar(Extension(state), query(&["alpha/beta/1.flac"], &[], &[])).await;
+        assert!(res.is_ok());
+        assert_eq!(*deletes.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn read_only() {
+        let mut state = Arc::into_inner(test_state().await).unwrap();
+        state.read_only = true;
+        let state = Arc::new(state);
+
+        let Err(err) = star(
+            Extension(state.clone()),
+            query(&["alpha/beta/1.flac"], &[], &[]),
+        )
+        .await
+        else {
+            panic!("star succeeded in read-only mode");
+        };
+        assert_eq!(
+            xml(&err),
+            xml(&Error::not_authorized(
+                "Server is running in read-only mode"
+            ))
+        );
+
+        let res = set_rating(
+            Extension(state),
+            Query(rating_query(SongID::new("alpha/beta/1.flac"), 3)),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn scrobble_time() {
+        assert_eq!(scrobble_timestamp(1_700_000_000_123), 1_700_000_000);
+        assert_eq!(scrobble_timestamp(1_700_000_000_999), 1_700_000_000);
+        assert_eq!(scrobble_timestamp(1_700_000_000), 1_700_000_000);
+        assert_eq!(scrobble_timestamp(0), 0);
+    }
+
+    #[tokio::test]
+    async fn scrobble_play_count() {
+        let play_count = |current: &'static str| async move {
+            let stickers = Arc::new(Mutex::new(Vec::new()));
+            let mpd = fake_server({
+                let stickers = stickers.clone();
+                move |command| {
+                    if command.starts_with("find") {
+                        "file: alpha/beta/1.flac\n".to_string()
+                    } else if command.starts_with("sticker get") {
+                        current.to_string()
+                    } else if command.starts_with("sticker set") {
+                        stickers.lock().unwrap().push(command.to_string());
+                        String::new()
+                    } else {
+                        String::new()
+                    }
+                }
+            })
+            .await;
+
+            let res = scrobble(
+                Extension(test_state_with_mpd(mpd).await),
+                Query(ScrobbleQuery {
+                    song: SongID::new("alpha/beta/1.flac"),
+                    time: None,
+                    submission: Some(true),
+                }),
+            )
+            .await;
+            assert!(res.is_ok());
+
+            let stickers = stickers.lock().unwrap().clone();
+            stickers
+        };
+
+        assert_eq!(
+            play_count("sticker: playcount=4\n").await,
+            vec!["sticker set song alpha/beta/1.flac playcount 5"]
+        );
+        assert_eq!(
+            play_count("ACK [50@0] {sticker} no such sticker\n").await,
+            vec!["sticker set song alpha/beta/1.flac playcount 1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn scrobble_concurrently() {
+        let count = Arc::new(Mutex::new(0));
+        let mpd = fake_server({
+            let count = count.clone();
+            move |command| {
+                if command.starts_with("find") {
+                    "file: alpha/beta/1.flac\n".to_string()
+                } else if command.starts_with("sticker get") {
+                    format!("sticker: playcount={}\n", count.lock().unwrap())
+                } else if let Some(value) =
+                    command.strip_prefix("sticker set song alpha/beta/1.flac playcount ")
+                {
+                    *count.lock().unwrap() = value.parse().unwrap();
+                    String::new()
+                } else {
+                    String::new()
+                }
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let scrobbles = (0..10).map(|_| {
+            scrobble(
+                Extension(state.clone()),
+                Query(ScrobbleQuery {
+                    song: SongID::new("alpha/beta/1.flac"),
+                    time: None,
+                    submission: Some(true),
+                }),
+            )
+        });
+        for res in futures::future::join_all(scrobbles).await {
+            assert!(res.is_ok());
+        }
+        assert_eq!(*count.lock().unwrap(), 10);
+    }
+}
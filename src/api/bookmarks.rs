@@ -0,0 +1,696 @@
+use super::{
+    browsing::ROOT_FOLDER,
+    common::{get_songs_annotations, get_songs_by_path, mpd_song_to_subsonic},
+    glue::RawQuery,
+    playlists::HIDDEN_PLAYLIST_PREFIX,
+    types::{Song, SongID},
+    Error,
+};
+use axum::{
+    extract::{Extension, Query},
+    routing::Router,
+};
+use mpd_client::{
+    commands::{
+        AddToPlaylist, DeletePlaylist, Find, GetPlaylist, GetPlaylists, StickerDelete, StickerFind,
+        StickerGet, StickerSet,
+    },
+    filter::Filter,
+    tag::Tag,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use time::{format_description::well_known, OffsetDateTime};
+use yaserde_derive::YaSerialize;
+
+// Bookmarks are stored as JSON encoded stickers of the bookmarked songs
+const STICKER_BOOKMARK: &str = "bookmark";
+
+// Saved play queues are stored as MPD playlists, one per user. The current song, position and
+// modification details are stored as a JSON encoded sticker of the current song (or the first
+// song if there is no current one).
+const PLAY_QUEUE_PLAYLIST: &str = "queue_";
+const STICKER_PLAY_QUEUE: &str = "playqueue_";
+
+pub(crate) fn get_router() -> Router {
+    Router::new()
+        .route("/createBookmark.view", super::handler(create_bookmark))
+        .route("/getBookmarks.view", super::handler(get_bookmarks))
+        .route("/deleteBookmark.view", super::handler(delete_bookmark))
+        .route("/savePlayQueue.view", super::handler(save_play_queue))
+        .route("/getPlayQueue.view", super::handler(get_play_queue))
+}
+
+// StoredBookmark is a bookmark as stored in the song sticker
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+struct StoredBookmark {
+    position: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    created: String,
+    changed: String,
+}
+
+#[derive(Clone, Deserialize)]
+struct CreateBookmarkQuery {
+    #[serde(rename = "id")]
+    song: SongID,
+    position: u64,
+    comment: Option<String>,
+}
+
+async fn create_bookmark(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<CreateBookmarkQuery>,
+) -> super::Result<()> {
+    state.ensure_writable()?;
+
+    let conn = state.pool.get().await?;
+    let songs = conn
+        .command(Find::new(Filter::tag(
+            Tag::Other("file".into()),
+            &param.song.path,
+        )))
+        .await?;
+    let song = songs.first().ok_or_else(Error::not_found)?;
+    validate_position(param.position, song.duration)?;
+
+    let now = OffsetDateTime::now_utc()
+        .format(&well_known::Rfc3339)
+        .map_err(|_| Error::generic_error(None))?;
+    let created = conn
+        .command(StickerGet::new(&song.url, STICKER_BOOKMARK))
+        .await
+        .ok()
+        .and_then(|s| parse_bookmark(&s.value))
+        .map_or_else(|| now.clone(), |b| b.created);
+    let bookmark = serde_json::to_string(&StoredBookmark {
+        position: param.position,
+        comment: param.comment,
+        created,
+        changed: now,
+    })
+    .map_err(|_| Error::generic_error(None))?;
+
+    conn.command(StickerSet::new(&song.url, STICKER_BOOKMARK, &bookmark))
+        .await?;
+
+    Ok(())
+}
+
+// validate_position fails if the position (in milliseconds) is past the end of the song. Songs
+// of unknown duration accept any position.
+fn validate_position(position: u64, duration: Option<Duration>) -> super::Result<()> {
+    match duration {
+        Some(duration) if u128::from(position) > duration.as_millis() => {
+            Err(Error::generic_error(Some(&format!(
+                "bookmark position {position}ms is past the end of the song ({}ms)",
+                duration.as_millis()
+            ))))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn parse_bookmark(value: &str) -> Option<StoredBookmark> {
+    serde_json::from_str(value).ok()
+}
+
+#[derive(Clone, Deserialize)]
+struct GetBookmarksQuery {
+    u: String,
+}
+
+async fn get_bookmarks(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetBookmarksQuery>,
+) -> super::Result<Bookmarks> {
+    let conn = state.pool.get().await?;
+    let mut bookmarks = conn
+        .command(StickerFind::new(ROOT_FOLDER, STICKER_BOOKMARK))
+        .await?
+        .value
+        .into_iter()
+        .filter_map(|(path, value)| Some((path, parse_bookmark(&value)?)))
+        .collect::<Vec<_>>();
+    bookmarks.sort_by(|(p1, b1), (p2, b2)| b2.changed.cmp(&b1.changed).then_with(|| p1.cmp(p2)));
+
+    let paths = bookmarks.iter().map(|(p, _)| p).collect::<Vec<_>>();
+    let songs = get_songs_by_path(&conn, &paths).await?;
+    let annotations = get_songs_annotations(&conn, &songs).await?;
+
+    let mut songs = songs
+        .into_iter()
+        .map(|s| (s.url.clone(), s))
+        .collect::<HashMap<_, _>>();
+
+    Ok(Bookmarks {
+        bookmarks: bookmarks
+            .into_iter()
+            .filter_map(|(path, bookmark)| {
+                let song = songs.remove(&path)?;
+
+                Some(Bookmark {
+                    position: bookmark.position,
+                    username: param.u.clone(),
+                    comment: bookmark.comment,
+                    created: bookmark.created,
+                    changed: bookmark.changed,
+                    entry: mpd_song_to_subsonic(song, &annotations, state.song_options()),
+                })
+            })
+            .collect(),
+    })
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "bookmarks")]
+struct Bookmarks {
+    #[yaserde(child, rename = "bookmark")]
+    #[serde(rename = "bookmark")]
+    bookmarks: Vec<Bookmark>,
+}
+
+impl super::Reply for Bookmarks {
+    fn field_name() -> Option<&'static str> {
+        Some("bookmarks")
+    }
+}
+
+#[derive(Serialize, YaSerialize)]
+struct Bookmark {
+    #[yaserde(attribute)]
+    position: u64,
+    #[yaserde(attribute)]
+    username: String,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[yaserde(attribute)]
+    created: String,
+    #[yaserde(attribute)]
+    changed: String,
+    #[yaserde(child)]
+    entry: Song,
+}
+
+#[derive(Clone, Deserialize)]
+struct DeleteBookmarkQuery {
+    #[serde(rename = "id")]
+    song: SongID,
+}
+
+async fn delete_bookmark(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<DeleteBookmarkQuery>,
+) -> super::Result<()> {
+    state.ensure_writable()?;
+
+    state
+        .pool
+        .get()
+        .await?
+        .command(StickerDelete::new(&param.song.path, STICKER_BOOKMARK))
+        .await?;
+
+    Ok(())
+}
+
+// StoredPlayQueue is the state of a saved play queue as stored in the song sticker
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+struct StoredPlayQueue {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    current: Option<String>,
+    #[serde(default)]
+    position: u64,
+    changed: String,
+    changed_by: String,
+}
+
+fn play_queue_playlist(user: &str) -> String {
+    format!("{HIDDEN_PLAYLIST_PREFIX}{PLAY_QUEUE_PLAYLIST}{user}")
+}
+
+fn play_queue_sticker(user: &str) -> String {
+    format!("{STICKER_PLAY_QUEUE}{user}")
+}
+
+#[derive(Clone, Deserialize)]
+struct SavePlayQueueQuery {
+    u: String,
+    #[serde(default)]
+    c: String,
+    current: Option<SongID>,
+    position: Option<u64>,
+}
+
+// save_play_queue replaces the saved play queue of the user. Saving an empty queue removes the
+// saved one.
+async fn save_play_queue(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<SavePlayQueueQuery>,
+    RawQuery(query): RawQuery,
+) -> super::Result<()> {
+    state.ensure_writable()?;
+
+    let songs = url::form_urlencoded::parse(
+        &query
+            .ok_or_else(|| Error::missing_parameter("failed to parse URL query"))?
+            .into_bytes(),
+    )
+    .filter_map(|(k, v)| match k.as_ref() {
+        "id" => SongID::try_from(v.as_ref()).ok().map(|s| s.path),
+        _ => None,
+    })
+    .collect::<Vec<_>>();
+    let current = param.current.map(|s| s.path);
+    if current.as_ref().is_some_and(|c| !songs.contains(c)) {
+        return Err(Error::generic_error(Some(
+            "current song is not in the play queue",
+        )));
+    }
+
+    let playlist = play_queue_playlist(&param.u);
+    let sticker = play_queue_sticker(&param.u);
+    let conn = state.pool.get().await?;
+
+    // Neither the playlist nor the sticker exist until the queue is saved for the first time
+    conn.command(DeletePlaylist(&playlist)).await.ok();
+    let stale = conn
+        .command(StickerFind::new(ROOT_FOLDER, &sticker))
+        .await
+        .map(|s| s.value)
+        .unwrap_or_default();
+    if !stale.is_empty() {
+        conn.command_list(
+            stale
+                .keys()
+                .map(|path| StickerDelete::new(path, &sticker))
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+    }
+    let Some(holder) = current.as_ref().or(songs.first()).cloned() else {
+        return Ok(());
+    };
+
+    conn.command_list(
+        songs
+            .iter()
+            .map(|s| AddToPlaylist::new(&playlist, s))
+            .collect::<Vec<_>>(),
+    )
+    .await?;
+
+    let stored = serde_json::to_string(&StoredPlayQueue {
+        current,
+        position: param.position.unwrap_or(0),
+        changed: OffsetDateTime::now_utc()
+            .format(&well_known::Rfc3339)
+            .map_err(|_| Error::generic_error(None))?,
+        changed_by: param.c,
+    })
+    .map_err(|_| Error::generic_error(None))?;
+    conn.command(StickerSet::new(&holder, &sticker, &stored))
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Clone, Deserialize)]
+struct GetPlayQueueQuery {
+    u: String,
+}
+
+// get_play_queue returns the saved play queue of the user. Songs removed from the library since
+// the queue was saved are silently dropped from it. If the current song is among them, or if the
+// sticker with the queue state was lost along with its song, the queue is returned without
+// current song and position, and with the modification time of the playlist.
+async fn get_play_queue(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetPlayQueueQuery>,
+) -> super::Result<PlayQueue> {
+    let playlist = play_queue_playlist(&param.u);
+    let conn = state.pool.get().await?;
+
+    let last_modified = conn
+        .command(GetPlaylists)
+        .await?
+        .into_iter()
+        .find(|p| p.name == playlist)
+        .ok_or_else(Error::not_found)?
+        .last_modified
+        .raw()
+        .to_owned();
+    let paths = conn
+        .command(GetPlaylist(&playlist))
+        .await?
+        .into_iter()
+        .map(|s| s.url)
+        .collect::<Vec<_>>();
+    let stored = conn
+        .command(StickerFind::new(ROOT_FOLDER, &play_queue_sticker(&param.u)))
+        .await
+        .map(|s| s.value)
+        .unwrap_or_default()
+        .into_iter()
+        .find_map(|(_, value)| serde_json::from_str::<StoredPlayQueue>(&value).ok());
+
+    // Songs missing from the library are not found and thus dropped
+    let songs = get_songs_by_path(&conn, &paths).await?;
+    let annotations = get_songs_annotations(&conn, &songs).await?;
+
+    let (current, position, changed, changed_by) = match stored {
+        Some(stored) => {
+            let current = stored.current.filter(|c| songs.iter().any(|s| &s.url == c));
+            let position = current.as_ref().map(|_| stored.position);
+            (current, position, stored.changed, stored.changed_by)
+        }
+        None => (None, None, last_modified, String::new()),
+    };
+
+    Ok(PlayQueue {
+        current: current.map(|c| SongID::new(&c)),
+        position,
+        username: param.u,
+        changed,
+        changed_by,
+        entries: songs
+            .into_iter()
+            .map(|s| mpd_song_to_subsonic(s, &annotations, state.song_options()))
+            .collect(),
+    })
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "playQueue")]
+#[serde(rename_all = "camelCase")]
+struct PlayQueue {
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current: Option<SongID>,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<u64>,
+    #[yaserde(attribute)]
+    username: String,
+    #[yaserde(attribute)]
+    changed: String,
+    #[yaserde(attribute, rename = "changedBy")]
+    changed_by: String,
+    #[yaserde(child, rename = "entry")]
+    #[serde(rename = "entry")]
+    entries: Vec<Song>,
+}
+
+impl super::Reply for PlayQueue {
+    fn field_name() -> Option<&'static str> {
+        Some("playQueue")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        create_bookmark, get_play_queue, save_play_queue, validate_position, Bookmark, Bookmarks,
+        CreateBookmarkQuery, GetPlayQueueQuery, PlayQueue, SavePlayQueueQuery, StoredBookmark,
+    };
+    use crate::{
+        api::{
+            expect_ok_json, expect_ok_xml,
+            glue::RawQuery,
+            json, test_state_with_mpd,
+            types::{ArtistID, CoverArtID, Song, SongID},
+            xml,
+        },
+        mpd::testing::fake_server,
+    };
+    use axum::extract::{Extension, Query};
+    use serde_json::json;
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[test]
+    fn bookmarks() {
+        let bookmarks = Bookmarks {
+            bookmarks: vec![Bookmark {
+                position: 12000,
+                username: "me".to_string(),
+                comment: None,
+                created: "2023-01-02T03:04:05Z".to_string(),
+                changed: "2023-01-02T03:04:06Z".to_string(),
+                entry: Song {
+                    id: SongID::new("song1"),
+                    artist: "alpha".to_string(),
+                    cover_art: CoverArtID::new("song1"),
+                    artist_id: ArtistID::new("alpha"),
+                    ..Default::default()
+                },
+            }],
+        };
+        assert_eq!(
+            xml(&bookmarks),
+            expect_ok_xml(Some(
+                r#"<bookmarks>
+    <bookmark position="12000" username="me" created="2023-01-02T03:04:05Z" changed="2023-01-02T03:04:06Z">
+      <entry id="eyJwYXRoIjoic29uZzEifQ==" artist="alpha" coverArt="eyJwYXRoIjoic29uZzEifQ==" artistId="eyJuYW1lIjoiYWxwaGEifQ==" />
+    </bookmark>
+  </bookmarks>"#
+            ))
+        );
+        assert_eq!(
+            json(&bookmarks),
+            expect_ok_json(Some(json!({"bookmarks": {"bookmark": [{
+                "position": 12000,
+                "username": "me",
+                "created": "2023-01-02T03:04:05Z",
+                "changed": "2023-01-02T03:04:06Z",
+                "entry": {
+                    "id": "eyJwYXRoIjoic29uZzEifQ==",
+                    "artist": "alpha",
+                    "coverArt": "eyJwYXRoIjoic29uZzEifQ==",
+                    "albumId": null,
+                    "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                },
+            }]}})))
+        );
+    }
+
+    #[test]
+    fn play_queue_reply() {
+        let queue = PlayQueue {
+            current: Some(SongID::new("song1")),
+            position: Some(1500),
+            username: "me".to_string(),
+            changed: "2023-01-02T03:04:05Z".to_string(),
+            changed_by: "dsub".to_string(),
+            entries: vec![Song {
+                id: SongID::new("song1"),
+                artist: "alpha".to_string(),
+                cover_art: CoverArtID::new("song1"),
+                artist_id: ArtistID::new("alpha"),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(
+            xml(&queue),
+            expect_ok_xml(Some(
+                r#"<playQueue current="eyJwYXRoIjoic29uZzEifQ==" position="1500" username="me" changed="2023-01-02T03:04:05Z" changedBy="dsub">
+    <entry id="eyJwYXRoIjoic29uZzEifQ==" artist="alpha" coverArt="eyJwYXRoIjoic29uZzEifQ==" artistId="eyJuYW1lIjoiYWxwaGEifQ==" />
+  </playQueue>"#
+            ))
+        );
+        assert_eq!(
+            json(&queue),
+            expect_ok_json(Some(json!({"playQueue": {
+                "current": "eyJwYXRoIjoic29uZzEifQ==",
+                "position": 1500,
+                "username": "me",
+                "changed": "2023-01-02T03:04:05Z",
+                "changedBy": "dsub",
+                "entry": [{
+                    "id": "eyJwYXRoIjoic29uZzEifQ==",
+                    "artist": "alpha",
+                    "coverArt": "eyJwYXRoIjoic29uZzEifQ==",
+                    "albumId": null,
+                    "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                }],
+            }})))
+        );
+    }
+
+    #[test]
+    fn position() {
+        let duration = Some(Duration::from_secs(180));
+
+        assert!(validate_position(0, duration).is_ok());
+        assert!(validate_position(90_000, duration).is_ok());
+        assert!(validate_position(180_000, duration).is_ok());
+        assert!(validate_position(180_001, duration).is_err());
+        assert!(validate_position(1_000_000, None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn create() {
+        let stickers = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let stickers = stickers.clone();
+            move |command| {
+                if command.starts_with("find") {
+                    "file: alpha/1.flac\nduration: 180.000\n".to_string()
+                } else if command.starts_with("sticker set") {
+                    stickers.lock().unwrap().push(command.to_string());
+                    String::new()
+                } else {
+                    String::new()
+                }
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let create = |position| {
+            create_bookmark(
+                Extension(state.clone()),
+                Query(CreateBookmarkQuery {
+                    song: SongID::new("alpha/1.flac"),
+                    position,
+                    comment: Some("halfway".to_string()),
+                }),
+            )
+        };
+
+        assert!(create(0).await.is_ok());
+        assert!(create(90_000).await.is_ok());
+        assert!(create(200_000).await.is_err());
+
+        let stickers = stickers.lock().unwrap();
+        assert_eq!(stickers.len(), 2);
+        let bookmark = stickers[1]
+            .strip_prefix("sticker set song alpha/1.flac bookmark ")
+            .unwrap();
+        let bookmark: StoredBookmark =
+            serde_json::from_str(&bookmark.trim_matches('"').replace("\\\"", "\"")).unwrap();
+        assert_eq!(bookmark.position, 90_000);
+        assert_eq!(bookmark.comment.as_deref(), Some("halfway"));
+    }
+
+    #[tokio::test]
+    async fn play_queue() {
+        #[derive(Default)]
+        struct Mpd {
+            playlist: Vec<String>,
+            sticker: Option<(String, String)>,
+        }
+
+        let mpd = Arc::new(Mutex::new(Mpd::default()));
+        let server = fake_server({
+            let mpd = mpd.clone();
+            move |command| {
+                let mut mpd = mpd.lock().unwrap();
+                let args = command.splitn(5, ' ').collect::<Vec<_>>();
+                match args[..] {
+                    ["listplaylists"] if !mpd.playlist.is_empty() => {
+                        "playlist: __mpdsonic_queue_me\nLast-Modified: 2023-01-02T03:04:05Z\n"
+                            .to_string()
+                    }
+                    ["rm", ..] => {
+                        mpd.playlist.clear();
+                        String::new()
+                    }
+                    ["playlistadd", _, song] => {
+                        mpd.playlist.push(song.to_string());
+                        String::new()
+                    }
+                    ["listplaylistinfo", ..] => mpd
+                        .playlist
+                        .iter()
+                        .map(|s| format!("file: {s}"))
+                        .chain([String::new()])
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    ["sticker", "find", ..] => match &mpd.sticker {
+                        Some((path, value)) => {
+                            format!("file: {path}\nsticker: playqueue_me={value}\n")
+                        }
+                        None => String::new(),
+                    },
+                    ["sticker", "delete", ..] => {
+                        mpd.sticker = None;
+                        String::new()
+                    }
+                    ["sticker", "set", "song", path, rest] => {
+                        let value = rest.strip_prefix("playqueue_me ").unwrap();
+                        let value = value.trim_matches('"').replace("\\\"", "\"");
+                        mpd.sticker = Some((path.to_string(), value));
+                        String::new()
+                    }
+                    // alpha/3.flac was removed from the library
+                    _ if command.starts_with("find") && !command.contains("3.flac") => {
+                        let path = ["alpha/1.flac", "alpha/2.flac"]
+                            .into_iter()
+                            .find(|p| command.contains(p))
+                            .unwrap();
+                        format!("file: {path}\n")
+                    }
+                    _ => String::new(),
+                }
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(server).await;
+        let get = || {
+            get_play_queue(
+                Extension(state.clone()),
+                Query(GetPlayQueueQuery {
+                    u: "me".to_string(),
+                }),
+            )
+        };
+
+        assert!(get().await.is_err());
+
+        let save = |current: &str| {
+            let query = ["alpha/1.flac", "alpha/2.flac", "alpha/3.flac"]
+                .into_iter()
+                .map(|s| {
+                    let id = serde_json::to_value(SongID::new(s)).unwrap();
+                    format!("id={}", id.as_str().unwrap())
+                })
+                .collect::<Vec<_>>()
+                .join("&");
+            save_play_queue(
+                Extension(state.clone()),
+                Query(SavePlayQueueQuery {
+                    u: "me".to_string(),
+                    c: "dsub".to_string(),
+                    current: Some(SongID::new(current)),
+                    position: Some(1500),
+                }),
+                RawQuery(Some(query)),
+            )
+        };
+
+        assert!(save("alpha/2.flac").await.is_ok());
+        let Ok(queue) = get().await else {
+            panic!("getPlayQueue failed");
+        };
+        assert_eq!(queue.entries.len(), 2);
+        assert_eq!(
+            queue.current.map(|c| c.path).as_deref(),
+            Some("alpha/2.flac")
+        );
+        assert_eq!(queue.position, Some(1500));
+        assert_eq!(queue.changed_by, "dsub");
+
+        // The current song is gone from the library
+        assert!(save("alpha/3.flac").await.is_ok());
+        let Ok(queue) = get().await else {
+            panic!("getPlayQueue failed");
+        };
+        assert_eq!(queue.entries.len(), 2);
+        assert!(queue.current.is_none());
+        assert_eq!(queue.position, None);
+    }
+}
@@ -1,44 +1,107 @@
 use super::{
-    common::{get_song_year, get_songs_ratings_starred, mpd_song_to_subsonic},
-    types::{AlbumID, ArtistID, CoverArtID, Song},
+    common::{
+        album_filter, album_model, artist_spellings, fill_songs_sizes, find_albums_songs,
+        get_album_rating, get_single_tag, get_songs_annotations, get_songs_by_path, merge_artists,
+        mpd_song_to_subsonic,
+    },
+    glue::Paged,
+    retrieval::artist_image_path,
+    types::{
+        Album, AlbumID, AlbumModel, Artist, ArtistID, Child, CoverArtID, DirectoryID, Song, SongID,
+    },
     Error,
 };
+use crate::{
+    listenbrainz,
+    mpd::{Connection, LsInfo},
+};
 use axum::{
     extract::{Extension, Query},
+    response::Response,
     routing::Router,
 };
 use itertools::Itertools;
 use mpd_client::{
-    commands::{Count, Find, List},
+    commands::{Count, CountGrouped, Find, List, Stats},
     filter::Filter,
+    responses,
     tag::Tag,
 };
+use rand::seq::SliceRandom;
 
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::warn;
 use yaserde_derive::YaSerialize;
 
-const ROOT_FOLDER: &str = "/";
+pub(crate) const ROOT_FOLDER: &str = "/";
+
+const SIMILAR_SONGS_DEFAULT_COUNT: usize = 50;
+const SIMILAR_SONGS_MAX_COUNT: usize = 500;
+// Maximum number of similar recordings or artists looked up in the library
+const SIMILAR_MBIDS_MAX_COUNT: usize = 100;
+const TOP_SONGS_DEFAULT_COUNT: usize = 50;
+const TOP_SONGS_MAX_COUNT: usize = 500;
+
+// Music folders exposed to clients as (name, MPD directory) pairs
+const MUSIC_FOLDERS: &[(&str, &str)] = &[("Music", ROOT_FOLDER)];
 
 pub(crate) fn get_router() -> Router {
     Router::new()
         .route("/getMusicFolders.view", super::handler(get_music_folders))
-        .route("/getArtists.view", super::handler(get_artists))
+        .route("/getArtists.view", super::raw_handler(get_artists))
         .route("/getArtist.view", super::handler(get_artist))
         .route("/getArtistInfo2.view", super::handler(get_artist_info2))
+        .route("/getAlbumInfo2.view", super::handler(get_album_info2))
         .route("/getAlbum.view", super::handler(get_album))
+        .route("/getGenres.view", super::handler(get_genres))
+        .route("/getSimilarSongs.view", super::handler(get_similar_songs))
+        .route("/getSimilarSongs2.view", super::handler(get_similar_songs2))
+        .route("/getTopSongs.view", super::handler(get_top_songs))
+        .route("/getIndexes.view", super::handler(get_indexes))
+        .route(
+            "/getMusicDirectory.view",
+            super::handler(get_music_directory),
+        )
 }
 
 async fn get_music_folders() -> super::Result<GetMusicFolders> {
     Ok(GetMusicFolders {
-        music_folders: vec![MusicFolder {
-            id: ROOT_FOLDER.to_string(),
-            name: "Music".to_string(),
-        }],
+        music_folders: music_folders(MUSIC_FOLDERS),
     })
 }
 
-#[derive(Serialize, YaSerialize)]
+// music_folders builds music folders from their configuration. Folder IDs are derived from
+// the MPD directory of the folder, so they stay the same across restarts and clients can
+// safely cache them. Folders are sorted by name, so clients always see them in the same order.
+fn music_folders(folders: &[(&str, &str)]) -> Vec<MusicFolder> {
+    let mut folders = folders
+        .iter()
+        .map(|&(name, dir)| MusicFolder {
+            id: dir.to_string(),
+            name: name.to_string(),
+        })
+        .collect::<Vec<_>>();
+    folders.sort_by_cached_key(|f| (f.name.to_uppercase(), f.id.clone()));
+    folders
+}
+
+// validate_music_folder checks that the music folder ID is either absent (which means all
+// folders) or refers to one of the known music folders
+pub(crate) fn validate_music_folder(id: Option<&str>) -> super::Result<()> {
+    match id {
+        None => Ok(()),
+        Some(id) if MUSIC_FOLDERS.iter().any(|&(_, dir)| dir == id) => Ok(()),
+        Some(_) => Err(Error::generic_error(Some("unknown music folder"))),
+    }
+}
+
+#[derive(Serialize, YaSerialize, Debug, PartialEq)]
 struct MusicFolder {
     #[yaserde(attribute)]
     id: String,
@@ -64,60 +127,133 @@ impl super::Reply for GetMusicFolders {
 #[serde(rename_all = "camelCase")]
 struct GetArtistsQuery {
     music_folder_id: Option<String>,
+    offset: Option<usize>,
+    size: Option<usize>,
 }
 
+// get_artists streams the reply, as it can be huge for large libraries. Optional offset and size
+// page through the artists as if they were a flat list, the page is then split into indexes. The
+// total number of artists is sent in X-Total-Count header.
 async fn get_artists(
     Extension(state): Extension<Arc<super::State>>,
     Query(param): Query<GetArtistsQuery>,
-) -> super::Result<GetArtists> {
-    match param.music_folder_id.as_deref() {
-        Some(ROOT_FOLDER) | None => (),
-        _ => return Err(Error::generic_error(None)),
-    };
+    format: super::SerializationQuery,
+) -> super::Result<Response> {
+    validate_music_folder(param.music_folder_id.as_deref())?;
 
-    let reply = state
-        .pool
-        .get()
-        .await?
-        .command(List::new(Tag::Album).group_by([Tag::AlbumArtist]))
+    let artists = state
+        .artists_cache
+        .get_or_fetch(|| async {
+            let reply = state
+                .pool
+                .get()
+                .await?
+                .command(List::new(Tag::Album).group_by([Tag::AlbumArtist]))
+                .await?;
+
+            Ok(
+                merge_artists(reply.grouped_values().map(|(_, [artist])| artist))
+                    .into_iter()
+                    .map(|(count, artist)| (count, artist.to_string()))
+                    .collect(),
+            )
+        })
         .await?;
 
-    let index = reply
-        .grouped_values()
-        .map(|(_, [artist])| artist)
-        .dedup_with_count()
+    let index = artists_index(&artists, param.offset, param.size);
+
+    Ok(Paged::new(GetArtists { index }, artists.len()).stream(format))
+}
+
+// artists_index groups a page of the artists by their first letters. Without offset and size all
+// artists are returned.
+fn artists_index(
+    artists: &CachedArtists,
+    offset: Option<usize>,
+    size: Option<usize>,
+) -> Vec<Index> {
+    let artists = artists
+        .iter()
+        .skip(offset.unwrap_or(0))
+        .take(size.unwrap_or(usize::MAX))
         .map(|(count, artist)| Artist {
             id: ArtistID::new(artist),
-            name: artist.to_string(),
-            album_count: count,
-        })
-        .chunk_by(|artist| {
-            artist
-                .name
+            name: artist.clone(),
+            album_count: *count,
+        });
+    index_by_first_letter(artists, |artist| &artist.name)
+        .into_iter()
+        .map(|(name, artists)| Index { name, artists })
+        .collect()
+}
+
+// ArtistsCache keeps the album artists (with their album counts) getArtists is built from, as
+// listing them requires going through all albums of the library. There is a single music folder,
+// so there is a single entry. It expires after the ttl and is dropped when a scan is started.
+pub(crate) struct ArtistsCache {
+    ttl: Duration,
+    entry: Mutex<Option<ArtistsCacheEntry>>,
+}
+
+// CachedArtists are album artists with their album counts
+type CachedArtists = Vec<(usize, String)>;
+// ArtistsCacheEntry is cached artists along with the time they were fetched at
+type ArtistsCacheEntry = (Instant, Arc<CachedArtists>);
+
+impl ArtistsCache {
+    // new returns a cache keeping entries for ttl. Zero ttl disables caching.
+    pub(crate) fn new(ttl: Duration) -> Self {
+        ArtistsCache {
+            ttl,
+            entry: Mutex::new(None),
+        }
+    }
+
+    // get_or_fetch returns the cached artists or fetches them. Failed fetches are not cached.
+    async fn get_or_fetch<F, Fut>(&self, fetch: F) -> super::Result<Arc<CachedArtists>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = super::Result<CachedArtists>>,
+    {
+        if self.ttl.is_zero() {
+            return fetch().await.map(Arc::new);
+        }
+
+        if let Some((fetched, artists)) = &*self.entry.lock().unwrap() {
+            if fetched.elapsed() < self.ttl {
+                return Ok(artists.clone());
+            }
+        }
+
+        let artists = Arc::new(fetch().await?);
+        *self.entry.lock().unwrap() = Some((Instant::now(), artists.clone()));
+
+        Ok(artists)
+    }
+
+    // clear drops the cached artists, e.g. when the library is about to change
+    pub(crate) fn clear(&self) {
+        *self.entry.lock().unwrap() = None;
+    }
+}
+
+// index_by_first_letter groups items by the uppercased first letter of their names. Items are
+// expected to be sorted by name already.
+fn index_by_first_letter<T>(
+    items: impl Iterator<Item = T>,
+    name: impl Fn(&T) -> &str,
+) -> Vec<(String, Vec<T>)> {
+    items
+        .chunk_by(|item| {
+            name(item)
                 .chars()
                 .next()
                 .map(|c| c.to_uppercase().to_string())
                 .unwrap_or_default()
         })
         .into_iter()
-        .map(|(idx, group)| Index {
-            name: idx,
-            artists: group.collect(),
-        })
-        .collect();
-
-    Ok(GetArtists { index })
-}
-
-#[derive(Serialize, YaSerialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct Artist {
-    #[yaserde(attribute)]
-    id: ArtistID,
-    #[yaserde(attribute)]
-    name: String,
-    #[yaserde(attribute, rename = "albumCount")]
-    album_count: usize,
+        .map(|(idx, group)| (idx, group.collect()))
+        .collect()
 }
 
 #[derive(Serialize, YaSerialize, Debug)]
@@ -154,73 +290,62 @@ async fn get_artist(
 ) -> super::Result<GetArtist> {
     let conn = state.pool.get().await?;
 
+    let spellings = artist_spellings(&conn, &param.artist.name).await?;
     let reply = conn
-        .command(Count::new(Filter::tag(Tag::AlbumArtist, &param.artist.name)).group_by(Tag::Album))
-        .await?;
-
-    let mut albums = reply
-        .iter()
-        .map(|(album, count)| Album {
-            id: AlbumID::new(album, &param.artist.name),
-            name: album.to_string(),
-            artist: param.artist.name.clone(),
-            artist_id: param.artist.clone(),
-            song_count: count.songs,
-            duration: count.playtime.as_secs(),
-            ..Default::default()
+        .command_list(
+            spellings
+                .iter()
+                .map(|artist| {
+                    Count::new(Filter::tag(Tag::AlbumArtist, artist)).group_by(Tag::Album)
+                })
+                .collect::<Vec<_>>(),
+        )
+        .await?
+        .into_iter()
+        .zip(&spellings)
+        .flat_map(|(albums, artist)| {
+            albums
+                .into_iter()
+                .map(move |(album, count)| (AlbumID::new(&album, artist), count))
         })
         .collect::<Vec<_>>();
 
-    let songs = albums
-        .iter()
-        .map(|a| {
-            let filter =
-                Filter::tag(Tag::AlbumArtist, &a.artist).and(Filter::tag(Tag::Album, &a.name));
+    let albums = reply.iter().map(|(album, _)| album).collect::<Vec<_>>();
+    let songs = find_albums_songs(&conn, &albums).await?;
+    let annotations = get_songs_annotations(&conn, &songs.concat()).await?;
 
-            Find::new(filter).window(0..1)
-        })
-        .collect::<Vec<_>>();
-    let reply = conn.command_list(songs).await?;
+    let mut albums = reply
+        .into_iter()
+        .zip(songs)
+        .map(|((album, count), songs)| {
+            let rating = get_album_rating(&songs, &annotations, state.album_starred_any);
 
-    for (album, songs) in albums.iter_mut().zip(reply) {
-        if let Some(song) = songs.first() {
-            album.year = get_song_year(song);
-            album.genre = song.tags.get(&Tag::Genre).map(|v| v.join(", "));
-            album.cover_art = CoverArtID::new(&song.file_path().display().to_string());
-        }
-    }
+            AlbumModel {
+                user_rating: rating.user_rating,
+                average_rating: rating.average_rating,
+                starred: rating.starred,
+                ..album_model(album, &count, songs.first())
+            }
+            .into()
+        })
+        .collect::<Vec<Album>>();
+    sort_artist_albums(&mut albums);
 
     Ok(GetArtist {
         id: param.artist.clone(),
         name: param.artist.name.clone(),
         album_count: albums.len(),
+        cover_art: CoverArtID::Artist {
+            artist: param.artist.name.clone(),
+        },
         albums,
     })
 }
 
-#[derive(Serialize, YaSerialize, Debug, Default)]
-#[serde(rename_all = "camelCase")]
-struct Album {
-    #[yaserde(attribute)]
-    id: AlbumID,
-    #[yaserde(attribute)]
-    name: String,
-    #[yaserde(attribute)]
-    artist: String,
-    #[yaserde(attribute, rename = "artistId")]
-    artist_id: ArtistID,
-    #[yaserde(attribute, rename = "songCount")]
-    song_count: u64,
-    #[yaserde(attribute)]
-    duration: u64,
-    #[yaserde(attribute)]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    year: Option<i32>,
-    #[yaserde(attribute)]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    genre: Option<String>,
-    #[yaserde(attribute, rename = "coverArt")]
-    cover_art: CoverArtID,
+// sort_artist_albums orders albums of an artist chronologically. Albums without a year come last,
+// albums of the same year are ordered by name.
+fn sort_artist_albums(albums: &mut [Album]) {
+    albums.sort_by_cached_key(|a| (a.year.is_none(), a.year, a.name.to_lowercase()));
 }
 
 #[derive(Serialize, YaSerialize, Debug)]
@@ -233,6 +358,8 @@ struct GetArtist {
     name: String,
     #[yaserde(attribute, rename = "albumCount")]
     album_count: usize,
+    #[yaserde(attribute, rename = "coverArt")]
+    cover_art: CoverArtID,
     #[yaserde(child, rename = "album")]
     #[serde(rename = "album")]
     albums: Vec<Album>,
@@ -253,6 +380,7 @@ struct GetArtistInfo2Query {
 
 async fn get_artist_info2(
     Extension(state): Extension<Arc<super::State>>,
+    url: super::ServerUrl,
     Query(param): Query<GetArtistInfo2Query>,
 ) -> super::Result<ArtistInfo2> {
     let reply = state
@@ -265,9 +393,40 @@ async fn get_artist_info2(
         )
         .await?;
 
-    // TODO: artwork, similar artists
+    let has_image =
+        artist_image_path(state.artist_image_dir.as_deref(), &param.artist.name).is_some();
+    let cover: String = CoverArtID::Artist {
+        artist: param.artist.name,
+    }
+    .try_into()
+    .map_err(|_| Error::generic_error(None))?;
+    let image_url = url.endpoint("getCoverArt", &[("id", &cover)]);
+    let music_brainz_id = reply.values().next().map(str::to_string);
+
+    // External lookups are best effort, the client still gets whatever is known locally
+    let info = match (&state.artist_info, &music_brainz_id) {
+        (Some(client), Some(mbid)) => client.artist_info(mbid).await.unwrap_or_else(|err| {
+            warn!(artist = ?mbid, action = "artist info", err = ?err);
+            Default::default()
+        }),
+        _ => Default::default(),
+    };
+    // Images picked by the user win over whatever Wikipedia has
+    let (small_image_url, large_image_url) = if has_image {
+        (None, None)
+    } else {
+        (info.small_image_url, info.large_image_url)
+    };
+    let small_image_url = small_image_url.or(large_image_url.clone());
+    let large_image_url = large_image_url.or(small_image_url.clone());
+
+    // TODO: similar artists
     Ok(ArtistInfo2 {
-        music_brainz_id: reply.values().next().map(str::to_string),
+        biography: info.biography,
+        music_brainz_id,
+        medium_image_url: small_image_url.clone().unwrap_or_else(|| image_url.clone()),
+        small_image_url: small_image_url.unwrap_or_else(|| image_url.clone()),
+        large_image_url: large_image_url.unwrap_or(image_url),
     })
 }
 
@@ -275,8 +434,17 @@ async fn get_artist_info2(
 #[yaserde(rename = "artistInfo2")]
 #[serde(rename_all = "camelCase")]
 struct ArtistInfo2 {
+    #[yaserde(child)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    biography: Option<String>,
     #[yaserde(child, rename = "musicBrainzId")]
     music_brainz_id: Option<String>,
+    #[yaserde(child, rename = "smallImageUrl")]
+    small_image_url: String,
+    #[yaserde(child, rename = "mediumImageUrl")]
+    medium_image_url: String,
+    #[yaserde(child, rename = "largeImageUrl")]
+    large_image_url: String,
 }
 
 impl super::Reply for ArtistInfo2 {
@@ -285,118 +453,890 @@ impl super::Reply for ArtistInfo2 {
     }
 }
 
+// SimilarSongsID identifies the song or the artist to find similar songs for
 #[derive(Clone, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct GetAlbumQuery {
-    #[serde(rename = "id")]
-    album: AlbumID,
+#[serde(untagged)]
+enum SimilarSongsID {
+    Song(SongID),
+    Artist(ArtistID),
 }
 
-async fn get_album(
+#[derive(Clone, Deserialize)]
+struct GetSimilarSongsQuery {
+    id: SimilarSongsID,
+    count: Option<usize>,
+}
+
+async fn get_similar_songs(
     Extension(state): Extension<Arc<super::State>>,
-    Query(param): Query<GetAlbumQuery>,
-) -> super::Result<GetAlbum> {
+    Query(param): Query<GetSimilarSongsQuery>,
+) -> super::Result<SimilarSongs> {
+    Ok(SimilarSongs {
+        songs: similar_songs(&state, param).await?,
+    })
+}
+
+async fn get_similar_songs2(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetSimilarSongsQuery>,
+) -> super::Result<SimilarSongs2> {
+    Ok(SimilarSongs2 {
+        songs: similar_songs(&state, param).await?,
+    })
+}
+
+// Seed to ask ListenBrainz recommendations for
+enum Recommendation {
+    Recording(String),
+    Artist(String),
+}
+
+impl Recommendation {
+    fn mbid(&self) -> &str {
+        match self {
+            Recommendation::Recording(mbid) | Recommendation::Artist(mbid) => mbid,
+        }
+    }
+}
+
+// similar_songs returns songs ListenBrainz finds similar to the seed song or artist, if the
+// library has any. Otherwise, it returns random songs sharing a genre or an album artist with the
+// seed. The seed song itself is never returned.
+async fn similar_songs(
+    state: &super::State,
+    param: GetSimilarSongsQuery,
+) -> super::Result<Vec<Song>> {
+    let count = param
+        .count
+        .unwrap_or(SIMILAR_SONGS_DEFAULT_COUNT)
+        .min(SIMILAR_SONGS_MAX_COUNT);
+
     let conn = state.pool.get().await?;
+    let (seed, recommendation, artists, genres) = match param.id {
+        SimilarSongsID::Song(song) => {
+            let songs = conn
+                .command(Find::new(Filter::tag(Tag::Other("file".into()), song.path)).window(0..1))
+                .await?;
+            let song = songs.first().ok_or_else(Error::not_found)?;
+            let artists = match song.album_artists() {
+                [] => song.artists(),
+                artists => artists,
+            };
 
-    let (songs, count) = conn
-        .command_list((
-            Find::new(
-                Filter::tag(Tag::AlbumArtist, &param.album.artist)
-                    .and(Filter::tag(Tag::Album, &param.album.name)),
-            ),
-            Count::new(
-                Filter::tag(Tag::AlbumArtist, &param.album.artist)
-                    .and(Filter::tag(Tag::Album, &param.album.name)),
-            ),
-        ))
+            (
+                Some(song.url.clone()),
+                song.tags
+                    .get(&Tag::MusicBrainzRecordingId)
+                    .and_then(|mbids| mbids.first())
+                    .map(|m| Recommendation::Recording(m.to_string())),
+                artists.to_vec(),
+                song.tags.get(&Tag::Genre).cloned().unwrap_or_default(),
+            )
+        }
+        SimilarSongsID::Artist(artist) => {
+            let filter = Filter::tag(Tag::AlbumArtist, &artist.name);
+            let (genres, mbid) = conn
+                .command_list((
+                    List::new(Tag::Genre).filter(filter.clone()),
+                    List::new(Tag::MusicBrainzArtistId).filter(filter),
+                ))
+                .await?;
+
+            (
+                None,
+                mbid.values()
+                    .next()
+                    .map(|m| Recommendation::Artist(m.to_string())),
+                vec![artist.name],
+                genres.values().map(str::to_string).collect(),
+            )
+        }
+    };
+
+    let mut seen = seed.into_iter().collect::<HashSet<_>>();
+    let mut songs = match (&state.listenbrainz, recommendation) {
+        (Some(client), Some(recommendation)) => {
+            recommended_songs(&conn, client, recommendation, &mut seen).await?
+        }
+        _ => Vec::new(),
+    };
+    if songs.is_empty() {
+        songs = genre_songs(&conn, &artists, &genres, &mut seen).await?;
+    }
+    songs.truncate(count);
+
+    let annotations = get_songs_annotations(&conn, &songs).await?;
+
+    Ok(songs
+        .into_iter()
+        .map(|s| mpd_song_to_subsonic(s, &annotations, state.song_options()))
+        .collect())
+}
+
+// recommended_songs returns local songs ListenBrainz finds similar to the seed. Similar
+// recordings are returned most similar first, songs of similar artists are shuffled. ListenBrainz
+// is best effort, nothing is returned if it can't be reached.
+async fn recommended_songs(
+    conn: &Connection,
+    client: &listenbrainz::Client,
+    recommendation: Recommendation,
+    seen: &mut HashSet<String>,
+) -> super::Result<Vec<responses::Song>> {
+    let (similar, tag, window) = match &recommendation {
+        Recommendation::Recording(mbid) => (
+            client.similar_recordings(mbid).await,
+            Tag::MusicBrainzRecordingId,
+            Some(0..1),
+        ),
+        Recommendation::Artist(mbid) => (
+            client.similar_artists(mbid).await,
+            Tag::MusicBrainzArtistId,
+            None,
+        ),
+    };
+    let similar = similar.unwrap_or_else(|err| {
+        warn!(seed = ?recommendation.mbid(), action = "similar songs", err = ?err);
+        Vec::new()
+    });
+    if similar.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let finds = similar
+        .iter()
+        .take(SIMILAR_MBIDS_MAX_COUNT)
+        .map(|mbid| {
+            let find = Find::new(Filter::tag(tag.clone(), mbid));
+            match &window {
+                Some(window) => find.window(window.clone()),
+                None => find,
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut songs = conn
+        .command_list(finds)
+        .await?
+        .into_iter()
+        .flatten()
+        .filter(|s| seen.insert(s.url.clone()))
+        .collect::<Vec<_>>();
+    if let Recommendation::Artist(_) = recommendation {
+        songs.shuffle(&mut rand::thread_rng());
+    }
+
+    Ok(songs)
+}
+
+// genre_songs returns random songs sharing a genre or an album artist with the seed
+async fn genre_songs(
+    conn: &Connection,
+    artists: &[String],
+    genres: &[String],
+    seen: &mut HashSet<String>,
+) -> super::Result<Vec<responses::Song>> {
+    let finds = artists
+        .iter()
+        .map(|a| Filter::tag(Tag::AlbumArtist, a))
+        .chain(
+            genres
+                .iter()
+                .filter(|g| !g.is_empty())
+                .map(|g| Filter::tag(Tag::Genre, g)),
+        )
+        .map(Find::new)
+        .collect::<Vec<_>>();
+    let mut songs = conn
+        .command_list(finds)
+        .await?
+        .into_iter()
+        .flatten()
+        .filter(|s| seen.insert(s.url.clone()))
+        .collect::<Vec<_>>();
+    songs.shuffle(&mut rand::thread_rng());
+
+    Ok(songs)
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "similarSongs")]
+struct SimilarSongs {
+    #[yaserde(child, rename = "song")]
+    #[serde(rename = "song")]
+    songs: Vec<Song>,
+}
+
+impl super::Reply for SimilarSongs {
+    fn field_name() -> Option<&'static str> {
+        Some("similarSongs")
+    }
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "similarSongs2")]
+struct SimilarSongs2 {
+    #[yaserde(child, rename = "song")]
+    #[serde(rename = "song")]
+    songs: Vec<Song>,
+}
+
+impl super::Reply for SimilarSongs2 {
+    fn field_name() -> Option<&'static str> {
+        Some("similarSongs2")
+    }
+}
+
+#[derive(Clone, Deserialize)]
+struct GetTopSongsQuery {
+    artist: String,
+    count: Option<usize>,
+}
+
+async fn get_top_songs(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetTopSongsQuery>,
+) -> super::Result<TopSongs> {
+    let count = param
+        .count
+        .unwrap_or(TOP_SONGS_DEFAULT_COUNT)
+        .min(TOP_SONGS_MAX_COUNT);
+
+    let conn = state.pool.get().await?;
+    let mut songs = conn
+        .command(Find::new(Filter::tag(Tag::AlbumArtist, &param.artist)))
         .await?;
-    let (ratings, starred) = get_songs_ratings_starred(&conn, &songs).await?;
-
-    Ok(GetAlbum {
-        id: param.album.clone(),
-        name: param.album.name.clone(),
-        artist: param.album.artist.clone(),
-        artist_id: ArtistID::new(&param.album.artist),
-        year: songs.first().and_then(get_song_year),
-        genre: songs
-            .first()
-            .and_then(|s| s.tags.get(&Tag::Genre).map(|v| v.join(", "))),
-        cover_art: songs
-            .first()
-            .map(|s| CoverArtID::new(&s.file_path().display().to_string()))
-            .unwrap_or_default(),
+    let annotations = get_songs_annotations(&conn, &songs).await?;
+
+    // Most played songs go first, the rest keep album and track order
+    songs.sort_by_cached_key(|s| {
+        (
+            std::cmp::Reverse(annotations.play_counts.get(&s.url).copied().unwrap_or(0)),
+            s.album().map(str::to_lowercase),
+            s.number(),
+        )
+    });
+    songs.truncate(count);
+
+    Ok(TopSongs {
         songs: songs
             .into_iter()
-            .map(|s| mpd_song_to_subsonic(s, &ratings, &starred))
+            .map(|s| mpd_song_to_subsonic(s, &annotations, state.song_options()))
             .collect(),
-        song_count: count.songs,
-        duration: count.playtime.as_secs(),
     })
 }
 
-#[derive(Default, Serialize, YaSerialize)]
-#[yaserde(rename = "album")]
-#[serde(rename_all = "camelCase")]
-struct GetAlbum {
-    #[yaserde(attribute)]
-    id: AlbumID,
-    #[yaserde(attribute)]
-    name: String,
-    #[yaserde(attribute)]
-    artist: String,
-    #[yaserde(attribute, rename = "artistId")]
-    artist_id: ArtistID,
-    #[yaserde(attribute, rename = "songCount")]
-    song_count: u64,
-    #[yaserde(attribute)]
-    duration: u64,
-    #[yaserde(attribute)]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    year: Option<i32>,
-    #[yaserde(attribute)]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    genre: Option<String>,
-    #[yaserde(attribute, rename = "coverArt")]
-    cover_art: CoverArtID,
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "topSongs")]
+struct TopSongs {
     #[yaserde(child, rename = "song")]
     #[serde(rename = "song")]
     songs: Vec<Song>,
 }
 
-impl super::Reply for GetAlbum {
+impl super::Reply for TopSongs {
     fn field_name() -> Option<&'static str> {
-        Some("album")
+        Some("topSongs")
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{
-        Album, Artist, ArtistInfo2, GetAlbum, GetArtist, GetArtists, GetMusicFolders, Index,
-        MusicFolder, ROOT_FOLDER,
-    };
-    use crate::api::{
-        expect_ok_json, expect_ok_xml, json,
-        types::{AlbumID, ArtistID, CoverArtID, Song, SongID},
-        xml,
-    };
-    use serde_json::json;
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetAlbumInfo2Query {
+    #[serde(rename = "id")]
+    album: AlbumID,
+}
 
-    #[test]
-    fn get_user() {
-        let get_user = GetMusicFolders {
-            music_folders: vec![MusicFolder {
-                id: ROOT_FOLDER.to_string(),
-                name: "Music".to_string(),
-            }],
-        };
-        assert_eq!(
-            xml(&get_user),
-            expect_ok_xml(Some(
-                r#"<musicFolders>
-    <musicFolder id="/" name="Music" />
-  </musicFolders>"#
-            ),)
+async fn get_album_info2(
+    Extension(state): Extension<Arc<super::State>>,
+    url: super::ServerUrl,
+    Query(param): Query<GetAlbumInfo2Query>,
+) -> super::Result<AlbumInfo> {
+    let filter = Filter::tag(Tag::AlbumArtist, &param.album.artist)
+        .and(Filter::tag(Tag::Album, &param.album.name));
+    let (songs, mbid) = state
+        .pool
+        .get()
+        .await?
+        .command_list((
+            Find::new(filter.clone()).window(0..1),
+            List::new(Tag::MusicBrainzReleaseId).filter(filter),
+        ))
+        .await?;
+    let song = songs.first().ok_or_else(Error::not_found)?;
+
+    let cover: String = CoverArtID::new(&song.file_path().display().to_string())
+        .try_into()
+        .map_err(|_| Error::generic_error(None))?;
+    let image_url = url.endpoint("getCoverArt", &[("id", &cover)]);
+
+    Ok(AlbumInfo {
+        music_brainz_id: mbid.values().next().map(str::to_string),
+        small_image_url: image_url.clone(),
+        medium_image_url: image_url.clone(),
+        large_image_url: image_url,
+    })
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "albumInfo")]
+#[serde(rename_all = "camelCase")]
+struct AlbumInfo {
+    #[yaserde(child, rename = "musicBrainzId")]
+    music_brainz_id: Option<String>,
+    #[yaserde(child, rename = "smallImageUrl")]
+    small_image_url: String,
+    #[yaserde(child, rename = "mediumImageUrl")]
+    medium_image_url: String,
+    #[yaserde(child, rename = "largeImageUrl")]
+    large_image_url: String,
+}
+
+impl super::Reply for AlbumInfo {
+    fn field_name() -> Option<&'static str> {
+        Some("albumInfo")
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetAlbumQuery {
+    #[serde(rename = "id")]
+    album: AlbumID,
+}
+
+async fn get_album(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetAlbumQuery>,
+) -> super::Result<GetAlbum> {
+    let conn = state.pool.get().await?;
+
+    let (mut songs, mut count) = conn
+        .command_list((
+            Find::new(album_filter(Tag::AlbumArtist, &param.album)),
+            Count::new(album_filter(Tag::AlbumArtist, &param.album)),
+        ))
+        .await?;
+    // Songs may lack album artist, see find_albums_songs
+    if songs.is_empty() {
+        (songs, count) = conn
+            .command_list((
+                Find::new(album_filter(Tag::Artist, &param.album)),
+                Count::new(album_filter(Tag::Artist, &param.album)),
+            ))
+            .await?;
+    }
+    let annotations = get_songs_annotations(&conn, &songs).await?;
+    let rating = get_album_rating(&songs, &annotations, state.album_starred_any);
+    let album = AlbumModel {
+        user_rating: rating.user_rating,
+        average_rating: rating.average_rating,
+        starred: rating.starred,
+        ..album_model(param.album, &count, songs.first())
+    };
+
+    let disc_titles = disc_titles(&songs);
+
+    let mut songs = songs
+        .into_iter()
+        .map(|s| mpd_song_to_subsonic(s, &annotations, state.song_options()))
+        .collect::<Vec<_>>();
+    sort_album_songs(&mut songs);
+    fill_songs_sizes(&*state.lib, &mut songs).await;
+
+    Ok(GetAlbum::new(album, disc_titles, songs))
+}
+
+// sort_album_songs orders songs of an album by disc and track number. MPD returns them in
+// the order they are stored in the database, which doesn't have to follow the tags. Clients also
+// group songs by disc, so songs of a disc must come together.
+fn sort_album_songs(songs: &mut [Song]) {
+    songs.sort_by(|a, b| {
+        (a.disc_number, a.track, &a.id.path).cmp(&(b.disc_number, b.track, &b.id.path))
+    });
+}
+
+// disc_titles returns titles of the discs of the album, ordered by disc number. MPD has no tag
+// for titles of discs, so discs are named after their numbers.
+fn disc_titles(songs: &[responses::Song]) -> Vec<DiscTitle> {
+    songs
+        .iter()
+        .filter_map(|song| get_single_tag(&song.tags, &Tag::Disc))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|disc| DiscTitle {
+            disc,
+            title: format!("Disc {disc}"),
+        })
+        .collect()
+}
+
+#[derive(Serialize, YaSerialize, Debug, PartialEq)]
+struct DiscTitle {
+    #[yaserde(attribute)]
+    disc: u32,
+    #[yaserde(attribute)]
+    title: String,
+}
+
+#[derive(Default, Serialize, YaSerialize)]
+#[yaserde(rename = "album")]
+#[serde(rename_all = "camelCase")]
+struct GetAlbum {
+    #[yaserde(attribute)]
+    id: AlbumID,
+    #[yaserde(attribute)]
+    name: String,
+    #[yaserde(attribute)]
+    artist: String,
+    #[yaserde(attribute, rename = "artistId")]
+    artist_id: ArtistID,
+    #[yaserde(attribute, rename = "songCount")]
+    song_count: u64,
+    #[yaserde(attribute)]
+    duration: u64,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    year: Option<i32>,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    genre: Option<String>,
+    #[yaserde(attribute, rename = "coverArt")]
+    cover_art: CoverArtID,
+    #[yaserde(attribute, rename = "userRating")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_rating: Option<u8>,
+    #[yaserde(attribute, rename = "averageRating")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    average_rating: Option<f64>,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    starred: Option<String>,
+    #[yaserde(attribute, rename = "isCompilation")]
+    is_compilation: bool,
+    #[yaserde(child, rename = "discTitles")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    disc_titles: Vec<DiscTitle>,
+    #[yaserde(child, rename = "song")]
+    #[serde(rename = "song")]
+    songs: Vec<Song>,
+}
+
+impl GetAlbum {
+    fn new(album: AlbumModel, disc_titles: Vec<DiscTitle>, songs: Vec<Song>) -> Self {
+        let Album {
+            id,
+            name,
+            artist,
+            artist_id,
+            song_count,
+            duration,
+            year,
+            genre,
+            cover_art,
+            user_rating,
+            average_rating,
+            starred,
+            is_compilation,
+        } = album.into();
+
+        GetAlbum {
+            id,
+            name,
+            artist,
+            artist_id,
+            song_count,
+            duration,
+            year,
+            genre,
+            cover_art,
+            user_rating,
+            average_rating,
+            starred,
+            is_compilation,
+            disc_titles,
+            songs,
+        }
+    }
+}
+
+impl super::Reply for GetAlbum {
+    fn field_name() -> Option<&'static str> {
+        Some("album")
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetIndexesQuery {
+    music_folder_id: Option<String>,
+    if_modified_since: Option<u64>,
+}
+
+async fn get_indexes(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetIndexesQuery>,
+) -> super::Result<Indexes> {
+    validate_music_folder(param.music_folder_id.as_deref())?;
+
+    let conn = state.pool.get().await?;
+
+    let last_modified = conn.command(Stats).await?.db_last_update * 1000;
+    if param
+        .if_modified_since
+        .map_or(false, |since| last_modified <= since)
+    {
+        return Ok(Indexes {
+            last_modified,
+            ..Default::default()
+        });
+    }
+
+    let listing = conn.command(LsInfo::new(ROOT_FOLDER)).await?;
+    let songs = get_songs_by_path(&conn, &listing.songs).await?;
+    let annotations = get_songs_annotations(&conn, &songs).await?;
+
+    let mut directories = listing
+        .directories
+        .into_iter()
+        .map(|dir| IndexArtist {
+            id: DirectoryID::new(&dir),
+            name: dir,
+        })
+        .collect::<Vec<_>>();
+    directories.sort_by_cached_key(|dir| dir.name.to_uppercase());
+
+    Ok(Indexes {
+        last_modified,
+        ignored_articles: String::new(),
+        index: index_by_first_letter(directories.into_iter(), |dir| &dir.name)
+            .into_iter()
+            .map(|(name, artists)| DirectoryIndex { name, artists })
+            .collect(),
+        children: songs
+            .into_iter()
+            .map(|s| {
+                Child::song(
+                    mpd_song_to_subsonic(s, &annotations, state.song_options()),
+                    DirectoryID::new(ROOT_FOLDER),
+                )
+            })
+            .collect(),
+    })
+}
+
+// Top-level directory as shown in the indexes
+#[derive(Serialize, YaSerialize, Debug)]
+struct IndexArtist {
+    #[yaserde(attribute)]
+    id: DirectoryID,
+    #[yaserde(attribute)]
+    name: String,
+}
+
+#[derive(Serialize, YaSerialize, Debug)]
+struct DirectoryIndex {
+    #[yaserde(attribute)]
+    name: String,
+    #[yaserde(child, rename = "artist")]
+    #[serde(rename = "artist")]
+    artists: Vec<IndexArtist>,
+}
+
+// Indexes never have shortcuts, as there is no way to configure them
+#[derive(Serialize, YaSerialize, Debug, Default)]
+#[yaserde(rename = "indexes")]
+#[serde(rename_all = "camelCase")]
+struct Indexes {
+    #[yaserde(attribute, rename = "lastModified")]
+    last_modified: u64,
+    #[yaserde(attribute, rename = "ignoredArticles")]
+    ignored_articles: String,
+    index: Vec<DirectoryIndex>,
+    #[yaserde(child, rename = "child")]
+    #[serde(rename = "child")]
+    children: Vec<Child>,
+}
+
+impl super::Reply for Indexes {
+    fn field_name() -> Option<&'static str> {
+        Some("indexes")
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetMusicDirectoryQuery {
+    #[serde(rename = "id")]
+    directory: DirectoryID,
+}
+
+async fn get_music_directory(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetMusicDirectoryQuery>,
+) -> super::Result<MusicDirectory> {
+    let path = normalize_directory(&param.directory.directory);
+    let id = DirectoryID::new(path);
+
+    let conn = state.pool.get().await?;
+    let mut listing = conn.command(LsInfo::new(path)).await?;
+    listing
+        .directories
+        .sort_by_cached_key(|dir| (directory_name(dir).to_uppercase(), dir.clone()));
+
+    let songs = get_songs_by_path(&conn, &listing.songs).await?;
+    let annotations = get_songs_annotations(&conn, &songs).await?;
+
+    let children = listing
+        .directories
+        .iter()
+        .map(|dir| Child::directory(dir, directory_name(dir), id.clone()))
+        .chain(songs.into_iter().map(|s| {
+            Child::song(
+                mpd_song_to_subsonic(s, &annotations, state.song_options()),
+                id.clone(),
+            )
+        }))
+        .collect();
+
+    Ok(MusicDirectory {
+        parent: parent_directory(path).map(DirectoryID::new),
+        name: directory_name(path).to_string(),
+        id,
+        children,
+    })
+}
+
+// normalize_directory converts directory path to the form used in IDs: MPD-relative path
+// without leading and trailing slashes or ROOT_FOLDER for the root directory
+fn normalize_directory(path: &str) -> &str {
+    match path.trim_matches('/') {
+        "" => ROOT_FOLDER,
+        path => path,
+    }
+}
+
+// parent_directory returns parent of the (normalized) directory, or None for the root directory
+fn parent_directory(path: &str) -> Option<&str> {
+    match path {
+        ROOT_FOLDER => None,
+        path => Some(
+            path.rsplit_once('/')
+                .map_or(ROOT_FOLDER, |(parent, _)| parent),
+        ),
+    }
+}
+
+// directory_name returns name of the (normalized) directory as shown to the user
+fn directory_name(path: &str) -> &str {
+    match path {
+        ROOT_FOLDER => MUSIC_FOLDERS
+            .iter()
+            .find(|&&(_, dir)| dir == ROOT_FOLDER)
+            .map_or(ROOT_FOLDER, |&(name, _)| name),
+        path => path.rsplit('/').next().unwrap_or(path),
+    }
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "directory")]
+struct MusicDirectory {
+    #[yaserde(attribute)]
+    id: DirectoryID,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<DirectoryID>,
+    #[yaserde(attribute)]
+    name: String,
+    #[yaserde(child, rename = "child")]
+    #[serde(rename = "child")]
+    children: Vec<Child>,
+}
+
+impl super::Reply for MusicDirectory {
+    fn field_name() -> Option<&'static str> {
+        Some("directory")
+    }
+}
+
+// GenreSort is the order genres are returned in
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum GenreSort {
+    #[default]
+    Name,
+    SongCount,
+    AlbumCount,
+}
+
+#[derive(Clone, Deserialize)]
+struct GetGenresQuery {
+    #[serde(default)]
+    sort: GenreSort,
+}
+
+// get_genres returns genres exactly as MPD reports them. Multi-valued genres are expected to be
+// tagged as separate values (which MPD already splits), values like "Rock;Metal" are not split
+// any further. This keeps the counts in line with a plain `Genre == <genre>` filter.
+async fn get_genres(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetGenresQuery>,
+) -> super::Result<GetGenres> {
+    let (songs, albums) = state
+        .pool
+        .get()
+        .await?
+        .command_list((
+            CountGrouped::new(Tag::Genre),
+            List::new(Tag::Album).group_by([Tag::Genre, Tag::AlbumArtist]),
+        ))
+        .await?;
+
+    let albums = albums.grouped_values().fold(
+        HashMap::<&str, HashSet<(&str, &str)>>::new(),
+        |mut acc, (album, [genre, artist])| {
+            acc.entry(genre).or_default().insert((album, artist));
+            acc
+        },
+    );
+
+    let mut genres = songs
+        .iter()
+        .filter(|(genre, _)| !genre.is_empty())
+        .map(|(genre, count)| Genre {
+            name: genre.clone(),
+            song_count: count.songs,
+            album_count: albums.get(genre.as_str()).map_or(0, HashSet::len),
+        })
+        .collect::<Vec<_>>();
+    sort_genres(&mut genres, param.sort);
+
+    Ok(GetGenres { genres })
+}
+
+// sort_genres sorts genres by name, or by song or album count with the most populated genres
+// first. Genres with equal counts are sorted by name.
+fn sort_genres(genres: &mut [Genre], sort: GenreSort) {
+    genres.sort_by(|a, b| {
+        let by_count = match sort {
+            GenreSort::Name => std::cmp::Ordering::Equal,
+            GenreSort::SongCount => b.song_count.cmp(&a.song_count),
+            GenreSort::AlbumCount => b.album_count.cmp(&a.album_count),
+        };
+        by_count.then_with(|| a.name.cmp(&b.name))
+    });
+}
+
+#[derive(Serialize, YaSerialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Genre {
+    #[yaserde(text)]
+    #[serde(rename = "value")]
+    name: String,
+    #[yaserde(attribute, rename = "songCount")]
+    song_count: u64,
+    #[yaserde(attribute, rename = "albumCount")]
+    album_count: usize,
+}
+
+#[derive(Serialize, YaSerialize, Debug)]
+#[yaserde(rename = "genres")]
+struct GetGenres {
+    #[yaserde(child, rename = "genre")]
+    #[serde(rename = "genre")]
+    genres: Vec<Genre>,
+}
+
+impl super::Reply for GetGenres {
+    fn field_name() -> Option<&'static str> {
+        Some("genres")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        artists_index, directory_name, disc_titles, get_indexes, index_by_first_letter,
+        music_folders, normalize_directory, parent_directory, sort_album_songs, sort_artist_albums,
+        sort_genres, AlbumInfo, ArtistInfo2, ArtistsCache, DirectoryIndex, DiscTitle, Genre,
+        GenreSort, GetAlbum, GetAlbumInfo2Query, GetAlbumQuery, GetArtist, GetArtistInfo2Query,
+        GetArtistQuery, GetArtists, GetArtistsQuery, GetGenres, GetIndexesQuery, GetMusicFolders,
+        GetSimilarSongsQuery, GetTopSongsQuery, Index, IndexArtist, Indexes, MusicDirectory,
+        MusicFolder, MUSIC_FOLDERS, ROOT_FOLDER,
+    };
+    use crate::api::{
+        common::{mpd_song_to_subsonic, Annotations},
+        expect_ok_json, expect_ok_xml,
+        glue::X_TOTAL_COUNT,
+        json, stream_reply, test_server_url, test_state_with_mpd,
+        types::{
+            Album, AlbumID, Artist, ArtistID, Child, CoverArtID, DirectoryID, Song, SongArtist,
+            SongID,
+        },
+        xml, SerializationQuery, STREAM_CHUNK_SIZE,
+    };
+    use crate::{
+        artistinfo, listenbrainz,
+        mpd::testing::{fake_client, fake_server},
+    };
+    use axum::extract::{Extension, Query};
+    use futures::StreamExt;
+    use mpd_client::{commands::Find, filter::Filter, tag::Tag};
+    use serde_json::json;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn artists_cache() {
+        let cache = ArtistsCache::new(Duration::from_secs(60));
+        let fetches = AtomicUsize::new(0);
+        let get = || async {
+            let artists = cache
+                .get_or_fetch(|| async {
+                    let n = fetches.fetch_add(1, Ordering::SeqCst);
+                    Ok(vec![(1, format!("artist{n}"))])
+                })
+                .await;
+            let Ok(artists) = artists else {
+                panic!("fetching artists failed");
+            };
+            artists[0].1.clone()
+        };
+
+        assert_eq!(get().await, "artist0");
+        assert_eq!(get().await, "artist0");
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        cache.clear();
+        assert_eq!(get().await, "artist1");
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        let cache = ArtistsCache::new(Duration::ZERO);
+        let artists = cache
+            .get_or_fetch(|| async { Ok(vec![(1, "alpha".to_string())]) })
+            .await;
+        assert!(artists.is_ok());
+        assert!(cache.entry.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn get_user() {
+        let get_user = GetMusicFolders {
+            music_folders: vec![MusicFolder {
+                id: ROOT_FOLDER.to_string(),
+                name: "Music".to_string(),
+            }],
+        };
+        assert_eq!(
+            xml(&get_user),
+            expect_ok_xml(Some(
+                r#"<musicFolders>
+    <musicFolder id="/" name="Music" />
+  </musicFolders>"#
+            ),)
         );
 
         assert_eq!(
@@ -413,6 +1353,55 @@ mod tests {
         );
     }
 
+    #[test]
+    fn music_folders_ids() {
+        let ids = |folders: &[(&str, &str)]| {
+            music_folders(folders)
+                .into_iter()
+                .map(|f| f.id)
+                .collect::<Vec<_>>()
+        };
+
+        // IDs are the configured directories, whatever the order of the folders
+        assert_eq!(
+            ids(&[("Music", "music"), ("Audiobooks", "books")]),
+            ["books", "music"]
+        );
+        assert_eq!(
+            ids(&[("Audiobooks", "books"), ("Music", "music")]),
+            ["books", "music"]
+        );
+
+        let mut configured = ids(MUSIC_FOLDERS);
+        configured.sort();
+        let mut dirs = MUSIC_FOLDERS
+            .iter()
+            .map(|&(_, dir)| dir)
+            .collect::<Vec<_>>();
+        dirs.sort();
+        assert_eq!(configured, dirs);
+    }
+
+    #[test]
+    fn music_folders_order() {
+        let folders = [
+            ("Music", "music"),
+            ("Audiobooks", "books"),
+            ("live", "live"),
+        ];
+        let names =
+            |folders: Vec<MusicFolder>| folders.into_iter().map(|f| f.name).collect::<Vec<_>>();
+
+        assert_eq!(
+            names(music_folders(&folders)),
+            ["Audiobooks", "live", "Music"]
+        );
+        assert_eq!(
+            names(music_folders(&folders)),
+            names(music_folders(&folders))
+        );
+    }
+
     #[test]
     fn get_artists() {
         let get_artists = GetArtists {
@@ -492,12 +1481,286 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn get_artists_streaming() {
+        let get_artists = || GetArtists {
+            index: (0..10_000)
+                .map(|i| Index {
+                    name: format!("{i}"),
+                    artists: vec![Artist {
+                        id: ArtistID::new(&format!("artist{i}")),
+                        name: format!("artist{i}"),
+                        album_count: 1,
+                    }],
+                })
+                .collect(),
+        };
+        let expected = json(&get_artists());
+
+        let mut chunks = stream_reply(
+            get_artists(),
+            SerializationQuery {
+                f: Some("json".to_string()),
+                ..Default::default()
+            },
+        )
+        .into_body()
+        .into_data_stream();
+
+        let mut body = Vec::new();
+        let mut count = 0;
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.unwrap();
+            assert!(chunk.len() <= STREAM_CHUNK_SIZE);
+            body.extend_from_slice(&chunk);
+            count += 1;
+        }
+        assert!(count > 1);
+        assert_eq!(String::from_utf8(body).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn artists_paging() {
+        let artists = ["Abba", "Air", "Beck", "Blur", "Cream"]
+            .into_iter()
+            .map(|artist| (1, artist.to_string()))
+            .collect::<Vec<_>>();
+        let names = |index: Vec<Index>| {
+            index
+                .into_iter()
+                .flat_map(|idx| {
+                    idx.artists
+                        .into_iter()
+                        .map(move |artist| format!("{}/{}", idx.name, artist.name))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(artists_index(&artists, None, None)),
+            ["A/Abba", "A/Air", "B/Beck", "B/Blur", "C/Cream"]
+        );
+        assert_eq!(
+            names(artists_index(&artists, Some(1), Some(2))),
+            ["A/Air", "B/Beck"]
+        );
+        assert_eq!(
+            names(artists_index(&artists, Some(3), None)),
+            ["B/Blur", "C/Cream"]
+        );
+        assert!(artists_index(&artists, Some(5), Some(2)).is_empty());
+
+        // The reply tells the total number of artists, so clients know how many pages there are
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("list") => "AlbumArtist: Abba\nAlbum: alpha\nAlbumArtist: Air\nAlbum: beta\n\
+                             AlbumArtist: Beck\nAlbum: gamma\n"
+                .to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let Ok(res) = super::get_artists(
+            Extension(test_state_with_mpd(mpd).await),
+            Query(GetArtistsQuery {
+                music_folder_id: None,
+                offset: Some(1),
+                size: Some(1),
+            }),
+            SerializationQuery {
+                f: Some("json".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        else {
+            panic!("getArtists failed");
+        };
+        assert_eq!(res.headers()[X_TOTAL_COUNT], "3");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["subsonic-response"]["artists"]["index"],
+            json!([{"name": "A", "artist": [{
+                "id": ArtistID::new("Air"),
+                "name": "Air",
+                "albumCount": 1,
+            }]}])
+        );
+    }
+
+    #[tokio::test]
+    async fn artist_case_variants() {
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("list") => {
+                "AlbumArtist: Beatles\nAlbumArtist: Blur\nAlbumArtist: beatles\n".to_string()
+            }
+            Some("count") if command.contains("Beatles") => {
+                "Album: Abbey Road\nsongs: 10\nplaytime: 600\n".to_string()
+            }
+            Some("count") if command.contains("beatles") => {
+                "Album: Help\nsongs: 5\nplaytime: 300\n".to_string()
+            }
+            Some("find") if command.contains("Help") => "file: beatles/help/1.flac\n".to_string(),
+            Some("find") => "file: beatles/abbey road/1.flac\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let Ok(artist) = super::get_artist(
+            Extension(state),
+            Query(GetArtistQuery {
+                artist: ArtistID::new("BEATLES"),
+            }),
+        )
+        .await
+        else {
+            panic!("getArtist failed");
+        };
+        assert_eq!(artist.album_count, 2);
+        assert_eq!(
+            artist
+                .albums
+                .iter()
+                .map(|a| (a.name.as_str(), a.artist.as_str(), a.song_count))
+                .collect::<Vec<_>>(),
+            [("Abbey Road", "Beatles", 10), ("Help", "beatles", 5)]
+        );
+    }
+
+    #[test]
+    fn artist_albums_order() {
+        let album = |name: &str, year| Album {
+            name: name.to_string(),
+            year,
+            ..Default::default()
+        };
+        let mut albums = vec![
+            album("zeta", None),
+            album("gamma", Some(2001)),
+            album("Beta", Some(1999)),
+            album("alpha", None),
+            album("delta", Some(2001)),
+        ];
+        sort_artist_albums(&mut albums);
+        assert_eq!(
+            albums.iter().map(|a| a.name.as_str()).collect::<Vec<_>>(),
+            ["Beta", "delta", "gamma", "alpha", "zeta"]
+        );
+    }
+
+    #[test]
+    fn album_songs_order() {
+        let song = |path: &str, disc_number, track| Song {
+            id: SongID::new(path),
+            disc_number,
+            track,
+            ..Default::default()
+        };
+        let mut songs = vec![
+            song("alpha/10.flac", Some(1), Some(10)),
+            song("alpha/b.flac", Some(2), None),
+            song("alpha/2.flac", Some(1), Some(2)),
+            song("alpha/a.flac", Some(2), None),
+            song("alpha/1.flac", Some(2), Some(1)),
+            song("alpha/bonus.flac", None, None),
+        ];
+        sort_album_songs(&mut songs);
+        assert_eq!(
+            songs.iter().map(|s| s.id.path.as_str()).collect::<Vec<_>>(),
+            [
+                "alpha/bonus.flac",
+                "alpha/2.flac",
+                "alpha/10.flac",
+                "alpha/a.flac",
+                "alpha/b.flac",
+                "alpha/1.flac",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn album_disc_titles() {
+        let client = fake_client(|_| {
+            "file: alpha/2-1.flac\nDisc: 2\n\
+             file: alpha/1-1.flac\nDisc: 1\n\
+             file: alpha/1-2.flac\nDisc: 1\n\
+             file: alpha/bonus.flac\n"
+                .to_string()
+        })
+        .await;
+        let songs = client
+            .command(Find::new(Filter::tag(Tag::Album, "beta")))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            disc_titles(&songs),
+            [
+                DiscTitle {
+                    disc: 1,
+                    title: "Disc 1".to_string()
+                },
+                DiscTitle {
+                    disc: 2,
+                    title: "Disc 2".to_string()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn song_album_id() {
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("list") => "AlbumArtist: alpha\n".to_string(),
+            Some("count") => "Album: beta\nsongs: 1\nplaytime: 300\n".to_string(),
+            Some("find") => "file: alpha/beta/1.flac\nArtist: gamma\nArtist: delta\n\
+                             AlbumArtist: alpha\nAlbum: beta\n"
+                .to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let Ok(artist) = super::get_artist(
+            Extension(state.clone()),
+            Query(GetArtistQuery {
+                artist: ArtistID::new("alpha"),
+            }),
+        )
+        .await
+        else {
+            panic!("getArtist failed");
+        };
+        let album = &artist.albums[0].id;
+
+        let song = state
+            .pool
+            .get()
+            .await
+            .unwrap()
+            .command(Find::new(Filter::tag(Tag::Album, "beta")))
+            .await
+            .unwrap()
+            .remove(0);
+        let song = mpd_song_to_subsonic(song, &Annotations::default(), state.song_options());
+        let song_album = song.album_id.unwrap();
+        assert_eq!(
+            (song_album.name, song_album.artist),
+            (album.name.clone(), album.artist.clone())
+        );
+    }
+
     #[test]
     fn get_artist() {
         let get_artist = GetArtist {
             id: ArtistID::new("alpha"),
             name: "alpha".to_string(),
             album_count: 2,
+            cover_art: CoverArtID::Artist {
+                artist: "alpha".to_string(),
+            },
             albums: vec![
                 Album {
                     id: AlbumID::new("album1", "alpha"),
@@ -509,6 +1772,10 @@ mod tests {
                     year: Some(2000),
                     genre: Some("rock".to_string()),
                     cover_art: CoverArtID::new("artwork1"),
+                    user_rating: Some(4),
+                    average_rating: Some(3.5),
+                    starred: Some("2023-08-05T21:56:13Z".into()),
+                    is_compilation: true,
                 },
                 Album {
                     id: AlbumID::new("album2", "alpha"),
@@ -525,9 +1792,9 @@ mod tests {
         assert_eq!(
             xml(&get_artist),
             expect_ok_xml(Some(
-                r#"<artist id="eyJuYW1lIjoiYWxwaGEifQ==" name="alpha" albumCount="2">
-    <album id="eyJuYW1lIjoiYWxidW0xIiwiYXJ0aXN0IjoiYWxwaGEifQ==" name="album1" artist="alpha" artistId="eyJuYW1lIjoiYWxwaGEifQ==" songCount="10" duration="300" year="2000" genre="rock" coverArt="eyJwYXRoIjoiYXJ0d29yazEifQ==" />
-    <album id="eyJuYW1lIjoiYWxidW0yIiwiYXJ0aXN0IjoiYWxwaGEifQ==" name="album2" artist="alpha" artistId="eyJuYW1lIjoiYWxwaGEifQ==" songCount="20" duration="450" coverArt="eyJwYXRoIjoiYXJ0d29yazIifQ==" />
+                r#"<artist id="eyJuYW1lIjoiYWxwaGEifQ==" name="alpha" albumCount="2" coverArt="eyJhcnRpc3QiOiJhbHBoYSJ9">
+    <album id="eyJuYW1lIjoiYWxidW0xIiwiYXJ0aXN0IjoiYWxwaGEifQ==" name="album1" artist="alpha" artistId="eyJuYW1lIjoiYWxwaGEifQ==" songCount="10" duration="300" year="2000" genre="rock" coverArt="eyJwYXRoIjoiYXJ0d29yazEifQ==" userRating="4" averageRating="3.5" starred="2023-08-05T21:56:13Z" isCompilation="true" />
+    <album id="eyJuYW1lIjoiYWxidW0yIiwiYXJ0aXN0IjoiYWxwaGEifQ==" name="album2" artist="alpha" artistId="eyJuYW1lIjoiYWxwaGEifQ==" songCount="20" duration="450" coverArt="eyJwYXRoIjoiYXJ0d29yazIifQ==" isCompilation="false" />
   </artist>"#
             ),)
         );
@@ -538,6 +1805,7 @@ mod tests {
                 "id": "eyJuYW1lIjoiYWxwaGEifQ==",
                 "name": "alpha",
                 "albumCount": 2,
+                "coverArt": "eyJhcnRpc3QiOiJhbHBoYSJ9",
                 "album": [
                     {
                         "id": "eyJuYW1lIjoiYWxidW0xIiwiYXJ0aXN0IjoiYWxwaGEifQ==",
@@ -549,6 +1817,10 @@ mod tests {
                         "year": 2000,
                         "genre": "rock",
                         "coverArt": "eyJwYXRoIjoiYXJ0d29yazEifQ==",
+                        "userRating": 4,
+                        "averageRating": 3.5,
+                        "starred": "2023-08-05T21:56:13Z",
+                        "isCompilation": true,
                     },
                     {
                         "id": "eyJuYW1lIjoiYWxidW0yIiwiYXJ0aXN0IjoiYWxwaGEifQ==",
@@ -558,6 +1830,7 @@ mod tests {
                         "songCount": 20,
                         "duration": 450,
                         "coverArt": "eyJwYXRoIjoiYXJ0d29yazIifQ==",
+                        "isCompilation": false,
                     },
                 ]
             }
@@ -568,13 +1841,21 @@ mod tests {
     #[test]
     fn get_artist_info2() {
         let get_artist_info2 = ArtistInfo2 {
+            biography: Some("alpha is a band".to_string()),
             music_brainz_id: Some("788ad31c-bf0c-4a31-83f8-b8b130d79c76".to_string()),
+            small_image_url: "https://example.com/rest/getCoverArt.view?id=a&size=34".to_string(),
+            medium_image_url: "https://example.com/rest/getCoverArt.view?id=a".to_string(),
+            large_image_url: "https://example.com/rest/getCoverArt.view?id=a".to_string(),
         };
         assert_eq!(
             xml(&get_artist_info2),
             expect_ok_xml(Some(
                 r#"<artistInfo2>
+    <biography>alpha is a band</biography>
     <musicBrainzId>788ad31c-bf0c-4a31-83f8-b8b130d79c76</musicBrainzId>
+    <smallImageUrl>https://example.com/rest/getCoverArt.view?id=a&amp;size=34</smallImageUrl>
+    <mediumImageUrl>https://example.com/rest/getCoverArt.view?id=a</mediumImageUrl>
+    <largeImageUrl>https://example.com/rest/getCoverArt.view?id=a</largeImageUrl>
   </artistInfo2>"#
             ),)
         );
@@ -582,7 +1863,11 @@ mod tests {
         assert_eq!(
             json(&get_artist_info2),
             expect_ok_json(Some(json!({"artistInfo2": {
+                "biography": "alpha is a band",
                 "musicBrainzId": "788ad31c-bf0c-4a31-83f8-b8b130d79c76",
+                "smallImageUrl": "https://example.com/rest/getCoverArt.view?id=a&size=34",
+                "mediumImageUrl": "https://example.com/rest/getCoverArt.view?id=a",
+                "largeImageUrl": "https://example.com/rest/getCoverArt.view?id=a",
             }
             })),),
         );
@@ -600,6 +1885,14 @@ mod tests {
             year: Some(2020),
             genre: Some("rock".to_string()),
             cover_art: CoverArtID::new("artwork"),
+            user_rating: Some(3),
+            average_rating: Some(3.0),
+            starred: None,
+            is_compilation: true,
+            disc_titles: vec![DiscTitle {
+                disc: 1,
+                title: "Disc 1".to_string(),
+            }],
             songs: vec![
                 Song {
                     id: SongID::new("song1"),
@@ -611,19 +1904,35 @@ mod tests {
                     year: Some(2020),
                     genre: Some("rock".to_string()),
                     cover_art: CoverArtID::new("artwork"),
+                    size: None,
+                    content_type: None,
+                    suffix: None,
+                    transcoded_content_type: None,
+                    transcoded_suffix: None,
                     duration: Some(300),
-                    path: "path1".to_string(),
+                    bit_rate: None,
+                    bit_depth: None,
+                    sampling_rate: None,
+                    path: Some("path1".to_string()),
                     album_id: Some(AlbumID::new("alpha", "beta")),
                     artist_id: ArtistID::new("alpha"),
                     user_rating: Some(3),
                     starred: Some("2023-08-05T21:56:13Z".into()),
+                    display_artist: Some("alpha".to_string()),
+                    play_count: Some(5),
+                    music_brainz_id: Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae".to_string()),
+                    artists: vec![SongArtist {
+                        id: ArtistID::new("alpha"),
+                        name: "alpha".to_string(),
+                    }],
+                    contributors: Vec::new(),
                 },
                 Song {
                     id: SongID::new("song2"),
                     album: Some("beta".to_string()),
                     artist: "alpha".to_string(),
                     cover_art: CoverArtID::new("artwork"),
-                    path: "path2".to_string(),
+                    path: Some("path2".to_string()),
                     album_id: Some(AlbumID::new("alpha", "beta")),
                     artist_id: ArtistID::new("alpha"),
                     ..Default::default()
@@ -633,13 +1942,38 @@ mod tests {
         assert_eq!(
             xml(&get_album),
             expect_ok_xml(Some(
-                r#"<album id="eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=" name="beta" artist="alpha" artistId="eyJuYW1lIjoiYWxwaGEifQ==" songCount="2" duration="300" year="2020" genre="rock" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9">
-    <song id="eyJwYXRoIjoic29uZzEifQ==" title="song1" album="beta" artist="alpha" track="1" discNumber="1" year="2020" genre="rock" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" duration="300" path="path1" albumId="eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" userRating="3" starred="2023-08-05T21:56:13Z" />
+                r#"<album id="eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=" name="beta" artist="alpha" artistId="eyJuYW1lIjoiYWxwaGEifQ==" songCount="2" duration="300" year="2020" genre="rock" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" userRating="3" averageRating="3" isCompilation="true">
+    <discTitles disc="1" title="Disc 1" />
+    <song id="eyJwYXRoIjoic29uZzEifQ==" title="song1" album="beta" artist="alpha" displayArtist="alpha" track="1" discNumber="1" year="2020" genre="rock" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" duration="300" path="path1" albumId="eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" userRating="3" starred="2023-08-05T21:56:13Z" playCount="5" musicBrainzId="8f3471b5-7e6a-48da-86a9-c1c07a0f47ae">
+      <artists id="eyJuYW1lIjoiYWxwaGEifQ==" name="alpha" />
+    </song>
     <song id="eyJwYXRoIjoic29uZzIifQ==" album="beta" artist="alpha" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" path="path2" albumId="eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" />
   </album>"#
             ),)
         );
 
+        let song1 = json!({
+            "id": "eyJwYXRoIjoic29uZzEifQ==",
+            "title": "song1",
+            "album": "beta",
+            "artist": "alpha",
+            "displayArtist": "alpha",
+            "track": 1,
+            "discNumber": 1,
+            "year": 2020,
+            "genre": "rock",
+            "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+            "duration": 300,
+            "path": "path1",
+            "albumId": "eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=",
+            "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+            "userRating": 3,
+            "starred": "2023-08-05T21:56:13Z",
+            "playCount": 5,
+            "musicBrainzId": "8f3471b5-7e6a-48da-86a9-c1c07a0f47ae",
+            "artists": [{"id": "eyJuYW1lIjoiYWxwaGEifQ==", "name": "alpha"}],
+        });
+
         assert_eq!(
             json(&get_album),
             expect_ok_json(Some(json!({"album": {
@@ -652,31 +1986,185 @@ mod tests {
                 "year": 2020,
                 "genre": "rock",
                 "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+                "userRating": 3,
+                "averageRating": 3.0,
+                "isCompilation": true,
+                "discTitles": [{"disc": 1, "title": "Disc 1"}],
                 "song": [
+                    song1,
                     {
-                        "id": "eyJwYXRoIjoic29uZzEifQ==",
-                        "title": "song1",
+                        "id": "eyJwYXRoIjoic29uZzIifQ==",
                         "album": "beta",
                         "artist": "alpha",
-                        "track": 1,
-                        "discNumber": 1,
-                        "year": 2020,
-                        "genre": "rock",
                         "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
-                        "duration": 300,
-                        "path": "path1",
+                        "path": "path2",
                         "albumId": "eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=",
                         "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
-                        "userRating": 3,
-                        "starred": "2023-08-05T21:56:13Z",
+                    },
+                ]
+            }
+            })),),
+        );
+    }
+
+    #[test]
+    fn genres_order() {
+        let genre = |name: &str, song_count, album_count| Genre {
+            name: name.to_string(),
+            song_count,
+            album_count,
+        };
+        let sorted = |sort| {
+            let mut genres = vec![
+                genre("Rock", 10, 1),
+                genre("Jazz", 5, 3),
+                genre("Blues", 10, 2),
+                genre("Ambient", 1, 3),
+            ];
+            sort_genres(&mut genres, sort);
+            genres.into_iter().map(|g| g.name).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            sorted(GenreSort::default()),
+            ["Ambient", "Blues", "Jazz", "Rock"]
+        );
+        assert_eq!(
+            sorted(GenreSort::SongCount),
+            ["Blues", "Rock", "Jazz", "Ambient"]
+        );
+        assert_eq!(
+            sorted(GenreSort::AlbumCount),
+            ["Ambient", "Jazz", "Blues", "Rock"]
+        );
+    }
+
+    #[test]
+    fn get_genres() {
+        let get_genres = GetGenres {
+            genres: vec![
+                Genre {
+                    name: "Electronic".to_string(),
+                    song_count: 28,
+                    album_count: 6,
+                },
+                Genre {
+                    name: "Rock".to_string(),
+                    song_count: 10,
+                    album_count: 1,
+                },
+            ],
+        };
+        assert_eq!(
+            xml(&get_genres),
+            expect_ok_xml(Some(
+                r#"<genres>
+    <genre songCount="28" albumCount="6">Electronic</genre>
+    <genre songCount="10" albumCount="1">Rock</genre>
+  </genres>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&get_genres),
+            expect_ok_json(Some(json!({"genres": {
+                "genre": [
+                    {
+                        "value": "Electronic",
+                        "songCount": 28,
+                        "albumCount": 6,
                     },
                     {
-                        "id": "eyJwYXRoIjoic29uZzIifQ==",
-                        "album": "beta",
+                        "value": "Rock",
+                        "songCount": 10,
+                        "albumCount": 1,
+                    },
+                ]
+            }
+            })),),
+        );
+    }
+
+    #[test]
+    fn directory_paths() {
+        assert_eq!(normalize_directory(""), ROOT_FOLDER);
+        assert_eq!(normalize_directory("/"), ROOT_FOLDER);
+        assert_eq!(normalize_directory("/alpha/beta/"), "alpha/beta");
+
+        assert_eq!(parent_directory(ROOT_FOLDER), None);
+        assert_eq!(parent_directory("alpha"), Some(ROOT_FOLDER));
+        assert_eq!(parent_directory("alpha/beta"), Some("alpha"));
+
+        assert_eq!(directory_name(ROOT_FOLDER), "Music");
+        assert_eq!(directory_name("alpha"), "alpha");
+        assert_eq!(directory_name("alpha/beta"), "beta");
+    }
+
+    #[test]
+    fn get_music_directory() {
+        let get_music_directory = MusicDirectory {
+            id: DirectoryID::new("alpha"),
+            parent: Some(DirectoryID::new(ROOT_FOLDER)),
+            name: "alpha".to_string(),
+            children: vec![
+                Child::directory("alpha/beta", "beta", DirectoryID::new("alpha")),
+                Child::song(
+                    Song {
+                        id: SongID::new("alpha/song1.flac"),
+                        artist: "alpha".to_string(),
+                        track: Some(1),
+                        cover_art: CoverArtID::new("alpha/song1.flac"),
+                        content_type: Some("audio/flac".to_string()),
+                        suffix: Some("flac".to_string()),
+                        transcoded_content_type: Some("audio/ogg".to_string()),
+                        transcoded_suffix: Some("opus".to_string()),
+                        duration: Some(300),
+                        path: Some("alpha/song1.flac".to_string()),
+                        artist_id: ArtistID::new("alpha"),
+                        ..Default::default()
+                    },
+                    DirectoryID::new("alpha"),
+                ),
+            ],
+        };
+        assert_eq!(
+            xml(&get_music_directory),
+            expect_ok_xml(Some(
+                r#"<directory id="eyJkaXJlY3RvcnkiOiJhbHBoYSJ9" parent="eyJkaXJlY3RvcnkiOiIvIn0=" name="alpha">
+    <child id="eyJkaXJlY3RvcnkiOiJhbHBoYS9iZXRhIn0=" parent="eyJkaXJlY3RvcnkiOiJhbHBoYSJ9" isDir="true" title="beta" />
+    <child parent="eyJkaXJlY3RvcnkiOiJhbHBoYSJ9" isDir="false" id="eyJwYXRoIjoiYWxwaGEvc29uZzEuZmxhYyJ9" title="song1.flac" artist="alpha" track="1" coverArt="eyJwYXRoIjoiYWxwaGEvc29uZzEuZmxhYyJ9" contentType="audio/flac" suffix="flac" transcodedContentType="audio/ogg" transcodedSuffix="opus" duration="300" path="alpha/song1.flac" artistId="eyJuYW1lIjoiYWxwaGEifQ==" />
+  </directory>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&get_music_directory),
+            expect_ok_json(Some(json!({"directory": {
+                "id": "eyJkaXJlY3RvcnkiOiJhbHBoYSJ9",
+                "parent": "eyJkaXJlY3RvcnkiOiIvIn0=",
+                "name": "alpha",
+                "child": [
+                    {
+                        "id": "eyJkaXJlY3RvcnkiOiJhbHBoYS9iZXRhIn0=",
+                        "parent": "eyJkaXJlY3RvcnkiOiJhbHBoYSJ9",
+                        "isDir": true,
+                        "title": "beta",
+                    },
+                    {
+                        "parent": "eyJkaXJlY3RvcnkiOiJhbHBoYSJ9",
+                        "isDir": false,
+                        "id": "eyJwYXRoIjoiYWxwaGEvc29uZzEuZmxhYyJ9",
+                        "title": "song1.flac",
                         "artist": "alpha",
-                        "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
-                        "path": "path2",
-                        "albumId": "eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=",
+                        "track": 1,
+                        "coverArt": "eyJwYXRoIjoiYWxwaGEvc29uZzEuZmxhYyJ9",
+                        "contentType": "audio/flac",
+                        "suffix": "flac",
+                        "transcodedContentType": "audio/ogg",
+                        "transcodedSuffix": "opus",
+                        "duration": 300,
+                        "path": "alpha/song1.flac",
+                        "albumId": null,
                         "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
                     },
                 ]
@@ -684,4 +2172,538 @@ mod tests {
             })),),
         );
     }
+
+    #[test]
+    fn first_letter_index() {
+        let index =
+            index_by_first_letter(["Acid", "alpha", "beta", "", "ölpha"].into_iter(), |s| s);
+        assert_eq!(
+            index,
+            vec![
+                ("A".to_string(), vec!["Acid", "alpha"]),
+                ("B".to_string(), vec!["beta"]),
+                ("".to_string(), vec![""]),
+                ("Ö".to_string(), vec!["ölpha"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn indexes() {
+        let indexes = Indexes {
+            last_modified: 1700000000000,
+            ignored_articles: String::new(),
+            index: vec![DirectoryIndex {
+                name: "A".to_string(),
+                artists: vec![IndexArtist {
+                    id: DirectoryID::new("alpha"),
+                    name: "alpha".to_string(),
+                }],
+            }],
+            children: vec![Child::song(
+                Song {
+                    id: SongID::new("song1.flac"),
+                    title: Some("song1".to_string()),
+                    artist: "alpha".to_string(),
+                    cover_art: CoverArtID::new("song1.flac"),
+                    artist_id: ArtistID::new("alpha"),
+                    ..Default::default()
+                },
+                DirectoryID::new(ROOT_FOLDER),
+            )],
+        };
+        assert_eq!(
+            xml(&indexes),
+            expect_ok_xml(Some(
+                r#"<indexes lastModified="1700000000000" ignoredArticles="">
+    <index name="A">
+      <artist id="eyJkaXJlY3RvcnkiOiJhbHBoYSJ9" name="alpha" />
+    </index>
+    <child parent="eyJkaXJlY3RvcnkiOiIvIn0=" isDir="false" id="eyJwYXRoIjoic29uZzEuZmxhYyJ9" title="song1" artist="alpha" coverArt="eyJwYXRoIjoic29uZzEuZmxhYyJ9" artistId="eyJuYW1lIjoiYWxwaGEifQ==" />
+  </indexes>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&indexes),
+            expect_ok_json(Some(json!({"indexes": {
+                "lastModified": 1700000000000u64,
+                "ignoredArticles": "",
+                "index": [
+                    {
+                        "name": "A",
+                        "artist": [
+                            {
+                                "id": "eyJkaXJlY3RvcnkiOiJhbHBoYSJ9",
+                                "name": "alpha",
+                            }
+                        ]
+                    }
+                ],
+                "child": [
+                    {
+                        "parent": "eyJkaXJlY3RvcnkiOiIvIn0=",
+                        "isDir": false,
+                        "id": "eyJwYXRoIjoic29uZzEuZmxhYyJ9",
+                        "title": "song1",
+                        "artist": "alpha",
+                        "coverArt": "eyJwYXRoIjoic29uZzEuZmxhYyJ9",
+                        "albumId": null,
+                        "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                    }
+                ]
+            }
+            })),),
+        );
+    }
+
+    #[tokio::test]
+    async fn get_indexes_modified_since() {
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("stats") => "artists: 1\nalbums: 1\nsongs: 1\nuptime: 1\nplaytime: 0\n\
+                              db_playtime: 1\ndb_update: 1700000000\n"
+                .to_string(),
+            Some("lsinfo") => "directory: beta\ndirectory: alpha\ndirectory: Acid\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let query = |since| GetIndexesQuery {
+            music_folder_id: None,
+            if_modified_since: since,
+        };
+        let names = |indexes: &Indexes| {
+            indexes
+                .index
+                .iter()
+                .map(|i| {
+                    let artists = i.artists.iter().map(|a| a.name.as_str());
+                    (i.name.clone(), artists.collect::<Vec<_>>().join(","))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for since in [None, Some(1699999999999)] {
+            let Ok(indexes) = get_indexes(Extension(state.clone()), Query(query(since))).await
+            else {
+                panic!("getIndexes failed");
+            };
+            assert_eq!(indexes.last_modified, 1700000000000);
+            assert_eq!(
+                names(&indexes),
+                vec![
+                    ("A".to_string(), "Acid,alpha".to_string()),
+                    ("B".to_string(), "beta".to_string())
+                ]
+            );
+        }
+
+        for since in [Some(1700000000000), Some(1800000000000)] {
+            let Ok(indexes) = get_indexes(Extension(state.clone()), Query(query(since))).await
+            else {
+                panic!("getIndexes failed");
+            };
+            assert_eq!(indexes.last_modified, 1700000000000);
+            assert!(indexes.index.is_empty());
+            assert!(indexes.children.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn album_without_album_artist() {
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("count") if command.contains("group") => {
+                "Album: beta\nsongs: 2\nplaytime: 600\n".to_string()
+            }
+            Some("count") if command.contains("(Artist ") => {
+                "songs: 2\nplaytime: 600\n".to_string()
+            }
+            Some("count") => "songs: 0\nplaytime: 0\n".to_string(),
+            Some("find") if command.contains("(Artist ") => {
+                "file: alpha/beta/1.flac\nOriginalDate: 2001\nGenre: Rock\n".to_string()
+            }
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let Ok(artist) = super::get_artist(
+            Extension(state.clone()),
+            Query(GetArtistQuery {
+                artist: ArtistID::new("alpha"),
+            }),
+        )
+        .await
+        else {
+            panic!("getArtist failed");
+        };
+        assert_eq!(artist.albums.len(), 1);
+        assert_eq!(artist.albums[0].year, Some(2001));
+        assert_eq!(artist.albums[0].genre.as_deref(), Some("Rock"));
+
+        let Ok(album) = super::get_album(
+            Extension(state),
+            Query(GetAlbumQuery {
+                album: AlbumID::new("beta", "alpha"),
+            }),
+        )
+        .await
+        else {
+            panic!("getAlbum failed");
+        };
+        assert_eq!(album.song_count, 2);
+        assert_eq!(album.year, Some(2001));
+        assert_eq!(album.songs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn get_artist_without_songs() {
+        let mpd = fake_server(|_| String::new()).await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let Ok(artist) = super::get_artist(
+            Extension(state.clone()),
+            Query(GetArtistQuery {
+                artist: ArtistID::new("alpha"),
+            }),
+        )
+        .await
+        else {
+            panic!("getArtist failed");
+        };
+        assert_eq!(artist.name, "alpha");
+        assert_eq!(artist.album_count, 0);
+        assert!(artist.albums.is_empty());
+
+        let Ok(info) = super::get_artist_info2(
+            Extension(state),
+            test_server_url(),
+            Query(GetArtistInfo2Query {
+                artist: ArtistID::new("alpha"),
+            }),
+        )
+        .await
+        else {
+            panic!("getArtistInfo2 failed");
+        };
+        assert_eq!(info.music_brainz_id, None);
+    }
+
+    #[test]
+    fn album_info() {
+        let album_info = AlbumInfo {
+            music_brainz_id: Some("1fd1ca3a-2b21-4f0f-9c35-9cc7ac4e8bc4".to_string()),
+            small_image_url: "https://example.com/rest/getCoverArt.view?id=a".to_string(),
+            medium_image_url: "https://example.com/rest/getCoverArt.view?id=a".to_string(),
+            large_image_url: "https://example.com/rest/getCoverArt.view?id=a".to_string(),
+        };
+        assert_eq!(
+            xml(&album_info),
+            expect_ok_xml(Some(
+                r#"<albumInfo>
+    <musicBrainzId>1fd1ca3a-2b21-4f0f-9c35-9cc7ac4e8bc4</musicBrainzId>
+    <smallImageUrl>https://example.com/rest/getCoverArt.view?id=a</smallImageUrl>
+    <mediumImageUrl>https://example.com/rest/getCoverArt.view?id=a</mediumImageUrl>
+    <largeImageUrl>https://example.com/rest/getCoverArt.view?id=a</largeImageUrl>
+  </albumInfo>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&album_info),
+            expect_ok_json(Some(json!({"albumInfo": {
+                "musicBrainzId": "1fd1ca3a-2b21-4f0f-9c35-9cc7ac4e8bc4",
+                "smallImageUrl": "https://example.com/rest/getCoverArt.view?id=a",
+                "mediumImageUrl": "https://example.com/rest/getCoverArt.view?id=a",
+                "largeImageUrl": "https://example.com/rest/getCoverArt.view?id=a",
+            }
+            })),),
+        );
+    }
+
+    #[tokio::test]
+    async fn get_album_info2() {
+        let mpd = fake_server(|command| match command.split_whitespace().next() {
+            Some("find") => "file: alpha/beta/01.flac\n".to_string(),
+            Some("list") => {
+                "MUSICBRAINZ_ALBUMID: 1fd1ca3a-2b21-4f0f-9c35-9cc7ac4e8bc4\n".to_string()
+            }
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let query = |name: &str| {
+            Query(GetAlbumInfo2Query {
+                album: AlbumID::new(name, "alpha"),
+            })
+        };
+
+        let Ok(info) =
+            super::get_album_info2(Extension(state.clone()), test_server_url(), query("beta"))
+                .await
+        else {
+            panic!("getAlbumInfo2 failed");
+        };
+        let cover: String = CoverArtID::new("alpha/beta/01.flac")
+            .try_into()
+            .unwrap_or_default();
+        assert_eq!(
+            info.music_brainz_id.as_deref(),
+            Some("1fd1ca3a-2b21-4f0f-9c35-9cc7ac4e8bc4")
+        );
+        assert_eq!(
+            info.large_image_url,
+            format!(
+                "http://localhost/rest/getCoverArt.view?{}",
+                serde_urlencoded::to_string([("id", cover)]).unwrap()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn get_artist_info2_provider_unreachable() {
+        let mpd = fake_server(|command| match command.split_whitespace().next() {
+            Some("list") => {
+                "MUSICBRAINZ_ARTISTID: 788ad31c-bf0c-4a31-83f8-b8b130d79c76\n".to_string()
+            }
+            _ => String::new(),
+        })
+        .await;
+        let mut state = Arc::into_inner(test_state_with_mpd(mpd).await).unwrap();
+        state.artist_info = Some(
+            artistinfo::Client::with_endpoints(
+                "http://127.0.0.1:1",
+                "http://127.0.0.1:1",
+                "http://127.0.0.1:1",
+            )
+            .unwrap(),
+        );
+
+        let Ok(info) = super::get_artist_info2(
+            Extension(Arc::new(state)),
+            test_server_url(),
+            Query(GetArtistInfo2Query {
+                artist: ArtistID::new("alpha"),
+            }),
+        )
+        .await
+        else {
+            panic!("getArtistInfo2 failed");
+        };
+        assert_eq!(
+            info.music_brainz_id.as_deref(),
+            Some("788ad31c-bf0c-4a31-83f8-b8b130d79c76")
+        );
+        assert_eq!(info.biography, None);
+        assert!(info
+            .large_image_url
+            .starts_with("http://localhost/rest/getCoverArt.view?id="));
+    }
+
+    #[tokio::test]
+    async fn get_artist_info2_local_image() {
+        let mpd = fake_server(|command| match command.split_whitespace().next() {
+            Some("list") => "MUSICBRAINZ_ARTISTID: alpha\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let (provider, _) = artistinfo::testing::fake_provider().await;
+        let dir = std::env::temp_dir().join(format!("mpdsonic-artist-info-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("alpha.jpg"), b"image").unwrap();
+        let mut state = Arc::into_inner(test_state_with_mpd(mpd).await).unwrap();
+        state.artist_info =
+            Some(artistinfo::Client::with_endpoints(&provider, &provider, &provider).unwrap());
+        let state = Arc::new(state);
+        let query = || {
+            Query(GetArtistInfo2Query {
+                artist: ArtistID::new("alpha"),
+            })
+        };
+
+        // Wikipedia images are used when there is no local one
+        let Ok(info) =
+            super::get_artist_info2(Extension(state.clone()), test_server_url(), query()).await
+        else {
+            panic!("getArtistInfo2 failed");
+        };
+        assert_eq!(info.large_image_url, "https://example.com/large.jpg");
+        assert_eq!(info.small_image_url, "https://example.com/small.jpg");
+
+        // Local images win, the biography is still there
+        let mut state = Arc::into_inner(state).unwrap();
+        state.artist_image_dir = Some(dir.clone());
+        let Ok(info) =
+            super::get_artist_info2(Extension(Arc::new(state)), test_server_url(), query()).await
+        else {
+            panic!("getArtistInfo2 failed");
+        };
+        assert!(info.biography.is_some());
+        assert!(info
+            .large_image_url
+            .starts_with("http://localhost/rest/getCoverArt.view?id="));
+        assert_eq!(info.small_image_url, info.large_image_url);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn similar_songs() {
+        let mpd = fake_server(|command| {
+            if !command.starts_with("find") {
+                String::new()
+            } else if command.contains("file") {
+                "file: alpha/1.flac\nAlbumArtist: alpha\nGenre: Rock\n".to_string()
+            } else if command.contains("AlbumArtist") {
+                "file: alpha/1.flac\nfile: alpha/2.flac\n".to_string()
+            } else if command.contains("Rock") {
+                "file: alpha/2.flac\nfile: beta/1.flac\n".to_string()
+            } else {
+                String::new()
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let query = |count| {
+            let id = serde_json::to_value(SongID::new("alpha/1.flac")).unwrap();
+            let query = serde_urlencoded::to_string([("id", id.as_str().unwrap())]).unwrap();
+            Query(
+                serde_urlencoded::from_str::<GetSimilarSongsQuery>(&match count {
+                    Some(count) => format!("{query}&count={count}"),
+                    None => query,
+                })
+                .unwrap(),
+            )
+        };
+
+        let Ok(similar) = super::get_similar_songs2(Extension(state.clone()), query(None)).await
+        else {
+            panic!("getSimilarSongs2 failed");
+        };
+        let mut paths = similar
+            .songs
+            .iter()
+            .map(|s| s.path.clone().unwrap_or_default())
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(paths, vec!["alpha/2.flac", "beta/1.flac"]);
+
+        let Ok(similar) = super::get_similar_songs(Extension(state), query(Some(1))).await else {
+            panic!("getSimilarSongs failed");
+        };
+        assert_eq!(similar.songs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn recommended_songs() {
+        let mpd = fake_server(|command| {
+            if !command.starts_with("find") {
+                String::new()
+            } else if command.contains("file") && command.contains("alpha/1.flac") {
+                "file: alpha/1.flac\nAlbumArtist: alpha\nGenre: Rock\nMUSICBRAINZ_TRACKID: rec-alpha\n"
+                    .to_string()
+            } else if command.contains("file") {
+                "file: alpha/2.flac\nAlbumArtist: alpha\nGenre: Rock\nMUSICBRAINZ_TRACKID: rec-beta\n"
+                    .to_string()
+            } else if command.contains("rec-gamma") {
+                "file: gamma/1.flac\n".to_string()
+            } else if command.contains("AlbumArtist") {
+                "file: alpha/1.flac\nfile: alpha/2.flac\n".to_string()
+            } else if command.contains("Rock") {
+                "file: alpha/2.flac\nfile: beta/1.flac\n".to_string()
+            } else {
+                String::new()
+            }
+        })
+        .await;
+        let mut state = Arc::into_inner(test_state_with_mpd(mpd).await).unwrap();
+        state.listenbrainz = Some(
+            listenbrainz::Client::with_labs_endpoint(
+                "token",
+                &listenbrainz::testing::fake_labs().await,
+            )
+            .unwrap(),
+        );
+        let state = Arc::new(state);
+        let similar = |path| {
+            let state = state.clone();
+            async move {
+                let id = serde_json::to_value(SongID::new(path)).unwrap();
+                let query = serde_urlencoded::to_string([("id", id.as_str().unwrap())]).unwrap();
+                let Ok(similar) = super::get_similar_songs2(
+                    Extension(state),
+                    Query(serde_urlencoded::from_str::<GetSimilarSongsQuery>(&query).unwrap()),
+                )
+                .await
+                else {
+                    panic!("getSimilarSongs2 failed");
+                };
+                let mut paths = similar
+                    .songs
+                    .into_iter()
+                    .map(|s| s.path.unwrap_or_default())
+                    .collect::<Vec<_>>();
+                paths.sort();
+                paths
+            }
+        };
+
+        // Only the recommended song is in the library
+        assert_eq!(similar("alpha/1.flac").await, vec!["gamma/1.flac"]);
+        // Nothing recommended is in the library, fall back to genres
+        assert_eq!(
+            similar("alpha/2.flac").await,
+            vec!["alpha/1.flac", "beta/1.flac"]
+        );
+    }
+
+    #[tokio::test]
+    async fn top_songs() {
+        let top_songs = |play_counts: &'static str| async move {
+            let mpd = fake_server(move |command| {
+                if command.starts_with("find") {
+                    concat!(
+                        "file: alpha/gamma/1.flac\nAlbum: gamma\nTrack: 1\n",
+                        "file: alpha/beta/2.flac\nAlbum: beta\nTrack: 2\n",
+                        "file: alpha/beta/1.flac\nAlbum: beta\nTrack: 1\n",
+                    )
+                    .to_string()
+                } else if command.starts_with("sticker find") && command.ends_with("playcount") {
+                    play_counts.to_string()
+                } else {
+                    String::new()
+                }
+            })
+            .await;
+            let Ok(top) = super::get_top_songs(
+                Extension(test_state_with_mpd(mpd).await),
+                Query(GetTopSongsQuery {
+                    artist: "alpha".to_string(),
+                    count: Some(2),
+                }),
+            )
+            .await
+            else {
+                panic!("getTopSongs failed");
+            };
+
+            top.songs
+                .into_iter()
+                .map(|s| s.path.unwrap_or_default())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            top_songs(concat!(
+                "file: alpha/gamma/1.flac\nsticker: playcount=3\n",
+                "file: alpha/beta/2.flac\nsticker: playcount=7\n",
+            ))
+            .await,
+            vec!["alpha/beta/2.flac", "alpha/gamma/1.flac"]
+        );
+        assert_eq!(
+            top_songs("").await,
+            vec!["alpha/beta/1.flac", "alpha/beta/2.flac"]
+        );
+    }
 }
@@ -1,88 +1,483 @@
 use super::{
-    types::{AlbumID, ArtistID, CoverArtID, Song, SongID},
+    retrieval::{song_mime, TranscodeFormat},
+    types::{AlbumID, AlbumModel, ArtistID, Contributor, CoverArtID, Song, SongArtist, SongID},
     Result,
 };
-use mpd_client::{commands::StickerFind, responses, tag::Tag, Client};
+use crate::{library::Library, mpd::Connection};
+use futures::{stream, StreamExt};
+use mpd_client::{
+    commands::{Count, Find, List, StickerFind},
+    filter::{Filter, Operator},
+    responses,
+    tag::Tag,
+};
 use std::{
     collections::{HashMap, HashSet},
     str::FromStr,
 };
+use tracing::warn;
+
+// Maximum number of song sizes looked up in the library at once
+const SONG_SIZE_LOOKUPS: usize = 8;
 
 pub(crate) const STICKER_RATING: &str = "rating";
 pub(crate) const STICKER_STARRED: &str = "starred";
+pub(crate) const STICKER_PLAY_COUNT: &str = "playcount";
+
+// SongOptions configures how songs are presented to clients
+#[derive(Clone, Copy, Default)]
+pub(crate) struct SongOptions {
+    // Path of the song is not exposed to the client
+    pub(crate) hide_path: bool,
+    // Format stream.view transcodes songs to unless the client asks for another one
+    pub(crate) transcode_format: TranscodeFormat,
+}
 
+// mpd_song_to_subsonic converts MPD song into Subsonic one
 pub(crate) fn mpd_song_to_subsonic(
     song: responses::Song,
-    ratings: &HashMap<String, u8>,
-    starred: &HashMap<String, String>,
+    annotations: &Annotations,
+    options: SongOptions,
 ) -> Song {
-    let artists = song.artists().join(", ");
+    let artist = song.artists().join(", ");
     let path = song.file_path().display().to_string();
+    let (sampling_rate, bit_depth) = song.format.as_deref().map_or((None, None), audio_format);
+    let suffix = song
+        .file_path()
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
 
     Song {
         id: SongID::new(&path),
         title: song.title().map(str::to_string),
         album: song.album().map(str::to_string),
-        artist: artists.clone(),
+        artist: artist.clone(),
+        display_artist: (!artist.is_empty()).then(|| artist.clone()),
         track: get_single_tag(&song.tags, &Tag::Track),
         disc_number: get_single_tag(&song.tags, &Tag::Disc),
         year: get_song_year(&song),
         genre: song.tags.get(&Tag::Genre).map(|v| v.join(", ")),
         cover_art: CoverArtID::new(&path),
+        // Sizes are only known to the library, see fill_songs_sizes
+        size: None,
+        content_type: suffix.as_ref().map(|_| song_mime(&path).to_string()),
+        suffix,
+        transcoded_content_type: Some(options.transcode_format.mime().to_string()),
+        transcoded_suffix: Some(options.transcode_format.suffix().to_string()),
         duration: song.duration.map(|v| v.as_secs()),
-        path: path.clone(),
-        album_id: song.album().map(|album| AlbumID::new(album, &artists)),
-        artist_id: ArtistID::new(&artists),
-        user_rating: ratings.get(&song.url).cloned(),
-        starred: starred.get(&song.url).cloned(),
+        bit_rate: None,
+        bit_depth,
+        sampling_rate,
+        path: (!options.hide_path).then(|| path.clone()),
+        album_id: song
+            .album()
+            .map(|album| AlbumID::new(album, song_album_artist(&song))),
+        // Links must lead to a single artist, so the primary one is linked
+        artist_id: ArtistID::new(song.artists().first().map_or("", String::as_str)),
+        user_rating: annotations.ratings.get(&song.url).cloned(),
+        starred: annotations.starred.get(&song.url).cloned(),
+        play_count: annotations.play_counts.get(&song.url).cloned(),
+        music_brainz_id: get_single_tag(&song.tags, &Tag::MusicBrainzRecordingId),
+        artists: song
+            .artists()
+            .iter()
+            .map(|name| SongArtist {
+                id: ArtistID::new(name),
+                name: name.clone(),
+            })
+            .collect(),
+        contributors: song_contributors(&song),
     }
 }
 
-pub(crate) async fn get_songs_ratings_starred(
-    client: &Client,
+// song_album_artist returns the primary album artist of the song, which albums are browsed by.
+// Like MPD, it falls back to the artist for songs without album artist.
+fn song_album_artist(song: &responses::Song) -> &str {
+    song.album_artists()
+        .first()
+        .or_else(|| song.artists().first())
+        .map_or("", String::as_str)
+}
+
+// audio_format returns sampling rate and bit depth from MPD audio format (samplerate:bits:channels).
+// Either is missing if MPD doesn't report it as a plain number, e.g. for DSD (samplerate:channels)
+// or floating point samples.
+fn audio_format(format: &str) -> (Option<u32>, Option<u32>) {
+    match format.split(':').collect::<Vec<_>>()[..] {
+        [rate, bits, _] => (rate.parse().ok(), bits.parse().ok()),
+        _ => (None, None),
+    }
+}
+
+// fill_songs_sizes sets sizes of the songs along with their average bit rates (in kbps). Sizes
+// are only known to the library, so this requires a lookup per song and is best effort. Remote
+// libraries would need a request per song, so sizes are left out for them.
+pub(crate) async fn fill_songs_sizes(lib: &(dyn Library + Send + Sync), songs: &mut [Song]) {
+    if lib.is_remote() {
+        return;
+    }
+
+    let paths = songs.iter().map(|s| s.id.path.clone()).collect::<Vec<_>>();
+    let sizes = stream::iter(paths)
+        .map(|path| async move { lib.song_size(&path).await })
+        .buffered(SONG_SIZE_LOOKUPS)
+        .collect::<Vec<_>>()
+        .await;
+
+    for (song, size) in songs.iter_mut().zip(sizes) {
+        let size = match size {
+            Ok(size) => size,
+            Err(err) => {
+                warn!(path = ?song.id.path, action = "get song size", err = ?err);
+                None
+            }
+        };
+        song.size = size;
+        song.bit_rate = size
+            .zip(song.duration)
+            .filter(|(_, duration)| *duration > 0)
+            .map(|(size, duration)| size * 8 / duration / 1000);
+    }
+}
+
+// Tags crediting contributors of a song along with their roles
+const CONTRIBUTOR_ROLES: [(Tag, &str); 3] = [
+    (Tag::Composer, "composer"),
+    (Tag::Conductor, "conductor"),
+    (Tag::Performer, "performer"),
+];
+
+// song_contributors returns contributors credited in the song tags
+fn song_contributors(song: &responses::Song) -> Vec<Contributor> {
+    CONTRIBUTOR_ROLES
+        .iter()
+        .flat_map(|(tag, role)| {
+            song.tags
+                .get(tag)
+                .into_iter()
+                .flatten()
+                .map(|name| Contributor {
+                    role: role.to_string(),
+                    artist: SongArtist {
+                        id: ArtistID::new(name),
+                        name: name.clone(),
+                    },
+                })
+        })
+        .collect()
+}
+
+// Annotations are what users attached to songs (ratings, stars and play counts) by song path.
+// Songs without a sticker are missing from the corresponding map.
+#[derive(Default)]
+pub(crate) struct Annotations {
+    pub(crate) ratings: HashMap<String, u8>,
+    pub(crate) starred: HashMap<String, String>,
+    pub(crate) play_counts: HashMap<String, u64>,
+}
+
+// Stickers annotations are made of
+const ANNOTATION_STICKERS: [&str; 3] = [STICKER_RATING, STICKER_STARRED, STICKER_PLAY_COUNT];
+
+// get_songs_annotations returns annotations of the songs. All of them are looked up with a single
+// command list.
+pub(crate) async fn get_songs_annotations(
+    client: &Connection,
     songs: &[responses::Song],
-) -> Result<(HashMap<String, u8>, HashMap<String, String>)> {
+) -> Result<Annotations> {
     if songs.is_empty() {
-        return Ok((HashMap::new(), HashMap::new()));
+        return Ok(Annotations::default());
+    }
+
+    let dirs = song_dirs(songs);
+    let found = client
+        .command_list(
+            ANNOTATION_STICKERS
+                .iter()
+                .flat_map(|sticker| dirs.iter().map(|dir| StickerFind::new(dir, sticker)))
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+    let mut annotations = Annotations::default();
+    for (i, stickers) in found.into_iter().enumerate() {
+        let stickers = stickers.value.into_iter();
+        match ANNOTATION_STICKERS[i / dirs.len()] {
+            STICKER_RATING => annotations
+                .ratings
+                .extend(stickers.filter_map(|(path, v)| Some((path, v.parse::<u8>().ok()?)))),
+            STICKER_STARRED => annotations.starred.extend(stickers),
+            _ => annotations
+                .play_counts
+                .extend(stickers.filter_map(|(path, v)| Some((path, v.parse::<u64>().ok()?)))),
+        }
+    }
+
+    Ok(annotations)
+}
+
+// album_filter matches songs of the album, with the artist of the album taken from the given tag
+pub(crate) fn album_filter(artist_tag: Tag, album: &AlbumID) -> Filter {
+    Filter::tag(artist_tag, &album.artist).and(Filter::tag(Tag::Album, &album.name))
+}
+
+// find_albums_songs finds songs of each of the albums. Albums whose songs are not found by album
+// artist (e.g. because the songs are tagged inconsistently) are looked up by artist instead.
+pub(crate) async fn find_albums_songs(
+    conn: &Connection,
+    albums: &[&AlbumID],
+) -> Result<Vec<Vec<responses::Song>>> {
+    if albums.is_empty() {
+        return Ok(Vec::new());
     }
 
-    let dirs = songs
+    let mut songs = conn
+        .command_list(
+            albums
+                .iter()
+                .map(|album| Find::new(album_filter(Tag::AlbumArtist, album)))
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+
+    let missing = songs
+        .iter()
+        .enumerate()
+        .filter(|(_, songs)| songs.is_empty())
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(songs);
+    }
+
+    let found = conn
+        .command_list(
+            missing
+                .iter()
+                .map(|&i| Find::new(album_filter(Tag::Artist, albums[i])))
+                .collect::<Vec<_>>(),
+        )
+        .await?;
+    for (i, found) in missing.into_iter().zip(found) {
+        songs[i] = found;
+    }
+
+    Ok(songs)
+}
+
+// is_stream checks if the playlist entry is a stream (e.g. an internet radio) rather than a song
+// from the library
+pub(crate) fn is_stream(song: &responses::Song) -> bool {
+    song.url.contains("://")
+}
+
+// song_dirs returns unique directories containing the songs
+fn song_dirs(songs: &[responses::Song]) -> Vec<String> {
+    songs
         .iter()
         .filter_map(|s| s.file_path().parent())
-        .collect::<HashSet<_>>();
-    let dirs = dirs
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|d| d.to_string_lossy().into_owned())
+        .collect()
+}
+
+// merge_artists merges album artists whose names differ only by case and counts their albums.
+// The artists are expected to be given once per album. The most common spelling of a name is
+// used for the merged artist, and artists are returned in the order they were first seen in.
+pub(crate) fn merge_artists<'a>(artists: impl Iterator<Item = &'a str>) -> Vec<(usize, &'a str)> {
+    let mut merged: Vec<Vec<(usize, &str)>> = Vec::new();
+    let mut index = HashMap::new();
+    for artist in artists {
+        let idx = *index.entry(artist.to_lowercase()).or_insert_with(|| {
+            merged.push(Vec::new());
+            merged.len() - 1
+        });
+        match merged[idx].iter_mut().find(|(_, s)| *s == artist) {
+            Some((count, _)) => *count += 1,
+            None => merged[idx].push((1, artist)),
+        }
+    }
+
+    merged
         .into_iter()
-        .map(|d| d.to_string_lossy())
+        .filter_map(|spellings| {
+            let total = spellings.iter().map(|(count, _)| count).sum();
+            // max_by_key returns the last maximum, prefer the first seen spelling on ties
+            let (_, name) = spellings
+                .into_iter()
+                .rev()
+                .max_by_key(|(count, _)| *count)?;
+            Some((total, name))
+        })
+        .collect()
+}
+
+// artist_spellings returns all spellings of the album artist name found in the library. MPD
+// matches tags case-sensitively, so artists merged by merge_artists have to be looked up by each
+// of the spellings.
+pub(crate) async fn artist_spellings(conn: &Connection, name: &str) -> Result<Vec<String>> {
+    let name_lower = name.to_lowercase();
+    let spellings = conn
+        .command(List::new(Tag::AlbumArtist))
+        .await?
+        .values()
+        .filter(|artist| artist.to_lowercase() == name_lower)
+        .map(str::to_string)
         .collect::<Vec<_>>();
 
-    let ratings = client
+    Ok(match spellings.is_empty() {
+        true => vec![name.to_string()],
+        false => spellings,
+    })
+}
+
+// get_songs_by_path fetches songs with the given paths
+pub(crate) async fn get_songs_by_path<P>(
+    conn: &Connection,
+    paths: &[P],
+) -> Result<Vec<responses::Song>>
+where
+    P: AsRef<str>,
+{
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    Ok(conn
+        .command_list(
+            paths
+                .iter()
+                .map(|p| Find::new(Filter::tag(Tag::Other("file".into()), p.as_ref())))
+                .collect::<Vec<_>>(),
+        )
+        .await?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+// get_albums fetches details of the given albums
+pub(crate) async fn get_albums(
+    client: &Connection,
+    albums: Vec<AlbumID>,
+) -> Result<Vec<AlbumModel>> {
+    if albums.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Albums are counted by album artist, like find_albums_songs, falling back to artist for
+    // albums without songs
+    let mut counts = client
         .command_list(
-            dirs.iter()
-                .map(|s| StickerFind::new(s, STICKER_RATING))
+            albums
+                .iter()
+                .map(|a| Count::new(album_filter(Tag::AlbumArtist, a)))
                 .collect::<Vec<_>>(),
         )
         .await?;
-    let starred = client
+    let mut tags = vec![Tag::AlbumArtist; albums.len()];
+    let missing = counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| count.songs == 0)
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        let found = client
+            .command_list(
+                missing
+                    .iter()
+                    .map(|&i| Count::new(album_filter(Tag::Artist, &albums[i])))
+                    .collect::<Vec<_>>(),
+            )
+            .await?;
+        for (i, found) in missing.into_iter().zip(found) {
+            counts[i] = found;
+            tags[i] = Tag::Artist;
+        }
+    }
+
+    let songs = client
         .command_list(
-            dirs.iter()
-                .map(|s| StickerFind::new(s, STICKER_STARRED))
+            albums
+                .iter()
+                .zip(tags)
+                .map(|(a, tag)| Find::new(album_filter(tag, a)).window(0..1))
                 .collect::<Vec<_>>(),
         )
         .await?;
 
-    let ratings = ratings.into_iter().fold(HashMap::new(), |mut acc, r| {
-        acc.extend(r.value.into_iter().filter_map(|(k, v)| {
-            let v = v.parse::<u8>().ok()?;
-            Some((k, v))
-        }));
-        acc
-    });
-    let starred = starred.into_iter().fold(HashMap::new(), |mut acc, r| {
-        acc.extend(r.value);
-        acc
-    });
+    Ok(albums
+        .into_iter()
+        .zip(counts)
+        .zip(songs)
+        .map(|((id, count), songs)| album_model(id, &count, songs.first()))
+        .collect())
+}
+
+// album_model builds an album from its song count and the first of its songs. Year, genre and
+// cover art of the album are those of the song.
+pub(crate) fn album_model(
+    id: AlbumID,
+    count: &responses::Count,
+    song: Option<&responses::Song>,
+) -> AlbumModel {
+    AlbumModel {
+        id,
+        song_count: count.songs,
+        duration: count.playtime.as_secs(),
+        year: song.and_then(get_song_year),
+        genre: song.and_then(|s| s.tags.get(&Tag::Genre).map(|v| v.join(", "))),
+        cover_art: song
+            .map(|s| CoverArtID::new(&s.file_path().display().to_string()))
+            .unwrap_or_default(),
+        is_compilation: song.map_or(false, is_compilation),
+        ..Default::default()
+    }
+}
 
-    Ok((ratings, starred))
+// AlbumRating is the album-level rating and starred state aggregated from its songs
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct AlbumRating {
+    pub(crate) user_rating: Option<u8>,
+    pub(crate) average_rating: Option<f64>,
+    pub(crate) starred: Option<String>,
+}
+
+// get_album_rating aggregates ratings and starred stickers of the album songs. The average rating
+// is computed over rated songs only. The album is starred if all of its songs are starred, or if
+// any of them is when any_starred is set. The most recent starred timestamp is used.
+pub(crate) fn get_album_rating(
+    songs: &[responses::Song],
+    annotations: &Annotations,
+    any_starred: bool,
+) -> AlbumRating {
+    let song_ratings = songs
+        .iter()
+        .filter_map(|s| annotations.ratings.get(&s.url))
+        .map(|&r| f64::from(r))
+        .collect::<Vec<_>>();
+    let average_rating = (!song_ratings.is_empty())
+        .then(|| song_ratings.iter().sum::<f64>() / song_ratings.len() as f64);
+
+    let song_starred = songs
+        .iter()
+        .filter_map(|s| annotations.starred.get(&s.url))
+        .collect::<Vec<_>>();
+    let is_starred = match any_starred {
+        true => !song_starred.is_empty(),
+        false => !songs.is_empty() && song_starred.len() == songs.len(),
+    };
+
+    AlbumRating {
+        user_rating: average_rating.map(|r| r.round() as u8),
+        average_rating,
+        starred: is_starred
+            .then(|| song_starred.into_iter().max().cloned())
+            .flatten(),
+    }
 }
 
 pub(crate) fn get_single_tag<T>(tags: &HashMap<Tag, Vec<String>>, tag: &Tag) -> Option<T>
@@ -93,9 +488,547 @@ where
         .and_then(|v| v.first().and_then(|v| v.parse().ok()))
 }
 
+// all_songs returns a filter that matches every song in the library
+pub(crate) fn all_songs() -> Filter {
+    Filter::new(Tag::Other("file".into()), Operator::NotEqual, "")
+}
+
+// get_song_year returns the year the song was originally released in, or the year of its release
+// if the original date is unknown. MPD reports bare year tags (e.g. ID3 TYER) as the date.
 pub(crate) fn get_song_year(song: &responses::Song) -> Option<i32> {
-    get_single_tag::<String>(&song.tags, &Tag::OriginalDate)?
-        .split('-')
-        .next()
-        .and_then(|y| y.parse().ok())
+    [Tag::OriginalDate, Tag::Date]
+        .iter()
+        .find_map(|tag| parse_year(&get_single_tag::<String>(&song.tags, tag)?))
+}
+
+// parse_year returns the year of a date in YYYY, YYYY-MM or YYYY-MM-DD form
+pub(crate) fn parse_year(date: &str) -> Option<i32> {
+    let year = date.trim().split('-').next()?;
+    match year.len() == 4 && year.bytes().all(|b| b.is_ascii_digit()) {
+        true => year.parse().ok(),
+        false => None,
+    }
+}
+
+// is_compilation checks if the song is a part of a compilation album
+pub(crate) fn is_compilation(song: &responses::Song) -> bool {
+    get_single_tag::<String>(&song.tags, &Tag::Other("compilation".into()))
+        .map_or(false, |v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        album_model, audio_format, fill_songs_sizes, get_album_rating, get_albums, get_song_year,
+        get_songs_annotations, is_compilation, merge_artists, mpd_song_to_subsonic, parse_year,
+        AlbumRating, Annotations, SongOptions,
+    };
+    use crate::{
+        api::{
+            types::{Album, AlbumID, ArtistID, DirectoryAlbum, Song, SongID},
+            TranscodeFormat,
+        },
+        library::get_library,
+        mpd::testing::fake_client,
+    };
+    use mpd_client::{
+        commands::{Count, Find},
+        filter::Filter,
+        tag::Tag,
+    };
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn hide_path() {
+        let client = fake_client(|_| "file: alpha/song1.flac\nTitle: song1\n".to_string()).await;
+        let find = || Find::new(Filter::tag(Tag::Title, "song1"));
+
+        let song = client.command(find()).await.unwrap().remove(0);
+        let song = mpd_song_to_subsonic(song, &Annotations::default(), SongOptions::default());
+        assert_eq!(song.path.as_deref(), Some("alpha/song1.flac"));
+
+        let song = client.command(find()).await.unwrap().remove(0);
+        let song = mpd_song_to_subsonic(
+            song,
+            &Annotations::default(),
+            SongOptions {
+                hide_path: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(song.path, None);
+        assert_eq!(song.id.path, "alpha/song1.flac");
+        assert!(!serde_json::to_string(&song).unwrap().contains("\"path\""));
+    }
+
+    #[tokio::test]
+    async fn content_type() {
+        let client = fake_client(|req| match req {
+            "find \"(Title == \\\"song1\\\")\"" => "file: alpha/song1.MP3\n".to_string(),
+            _ => "file: alpha/song2\n".to_string(),
+        })
+        .await;
+        let find = |title| Find::new(Filter::tag(Tag::Title, title));
+        let options = SongOptions {
+            transcode_format: TranscodeFormat::Mp3,
+            ..Default::default()
+        };
+
+        let song = client.command(find("song1")).await.unwrap().remove(0);
+        let song = mpd_song_to_subsonic(song, &Annotations::default(), options);
+        assert_eq!(song.suffix.as_deref(), Some("mp3"));
+        assert_eq!(song.content_type.as_deref(), Some("audio/mpeg"));
+        assert_eq!(song.transcoded_suffix.as_deref(), Some("mp3"));
+        assert_eq!(song.transcoded_content_type.as_deref(), Some("audio/mpeg"));
+
+        // Songs without an extension have unknown format
+        let song = client.command(find("song2")).await.unwrap().remove(0);
+        let song = mpd_song_to_subsonic(song, &Annotations::default(), SongOptions::default());
+        assert_eq!(song.suffix, None);
+        assert_eq!(song.content_type, None);
+        assert_eq!(song.transcoded_suffix.as_deref(), Some("opus"));
+        assert_eq!(song.transcoded_content_type.as_deref(), Some("audio/ogg"));
+    }
+
+    #[test]
+    fn years() {
+        assert_eq!(parse_year("1998"), Some(1998));
+        assert_eq!(parse_year("1998-05"), Some(1998));
+        assert_eq!(parse_year("1998-05-01"), Some(1998));
+        assert_eq!(parse_year(" 1998 "), Some(1998));
+        assert_eq!(parse_year(""), None);
+        assert_eq!(parse_year("unknown"), None);
+        assert_eq!(parse_year("98"), None);
+        assert_eq!(parse_year("19980501"), None);
+        assert_eq!(parse_year("-1998"), None);
+        assert_eq!(parse_year("199x-05-01"), None);
+    }
+
+    #[tokio::test]
+    async fn song_year() {
+        let client = fake_client(|req| {
+            match req {
+                r#"find "(Title == \"original\")""# => {
+                    "file: 1.flac\nOriginalDate: 1998-05-01\nDate: 2008\n"
+                }
+                r#"find "(Title == \"date\")""# => "file: 2.flac\nDate: 2008-10\n",
+                r#"find "(Title == \"garbage\")""# => {
+                    "file: 3.flac\nOriginalDate: unknown\nDate: 2008\n"
+                }
+                _ => "file: 4.flac\nDate: someday\n",
+            }
+            .to_string()
+        })
+        .await;
+
+        for (title, year) in [
+            ("original", Some(1998)),
+            ("date", Some(2008)),
+            ("garbage", Some(2008)),
+            ("none", None),
+        ] {
+            let song = client
+                .command(Find::new(Filter::tag(Tag::Title, title)))
+                .await
+                .unwrap()
+                .remove(0);
+            assert_eq!(get_song_year(&song), year, "{title}");
+        }
+    }
+
+    #[test]
+    fn audio_formats() {
+        assert_eq!(audio_format("44100:16:2"), (Some(44100), Some(16)));
+        assert_eq!(audio_format("48000:f:2"), (Some(48000), None));
+        assert_eq!(audio_format("dsd64:2"), (None, None));
+        assert_eq!(audio_format(""), (None, None));
+    }
+
+    #[tokio::test]
+    async fn songs_sizes() {
+        let dir = std::env::temp_dir().join(format!("mpdsonic-sizes-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("alpha")).unwrap();
+        std::fs::write(dir.join("alpha/1.flac"), vec![0; 100000]).unwrap();
+        std::fs::write(dir.join("alpha/2.flac"), vec![0; 1000]).unwrap();
+        let lib = get_library(dir.to_str().unwrap()).await.unwrap();
+
+        let song = |path, duration| Song {
+            id: SongID::new(path),
+            duration,
+            ..Default::default()
+        };
+        let mut songs = vec![
+            song("alpha/1.flac", Some(4)),
+            song("alpha/2.flac", None),
+            song("alpha/3.flac", Some(4)),
+        ];
+        fill_songs_sizes(&*lib, &mut songs).await;
+        assert_eq!(
+            songs
+                .iter()
+                .map(|s| (s.size, s.bit_rate))
+                .collect::<Vec<_>>(),
+            [(Some(100000), Some(200)), (Some(1000), None), (None, None)]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Remote libraries are not asked at all, the server would never reply
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let lib = get_library(&format!("http://{}/", listener.local_addr().unwrap()))
+            .await
+            .unwrap();
+        let mut songs = vec![song("alpha/1.flac", Some(4))];
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            fill_songs_sizes(&*lib, &mut songs),
+        )
+        .await
+        .unwrap();
+        assert_eq!((songs[0].size, songs[0].bit_rate), (None, None));
+    }
+
+    #[tokio::test]
+    async fn songs_annotations() {
+        let client = fake_client(|command| {
+            match command {
+                c if c.starts_with("find") => concat!(
+                    "file: alpha/song1.flac\n",
+                    "file: alpha/song2.flac\n",
+                    "file: beta/song1.flac\n",
+                ),
+                "sticker find song alpha rating" => concat!(
+                    "file: alpha/song1.flac\nsticker: rating=4\n",
+                    "file: alpha/song2.flac\nsticker: rating=bad\n",
+                ),
+                "sticker find song beta starred" => {
+                    "file: beta/song1.flac\nsticker: starred=2023-08-05T21:56:13Z\n"
+                }
+                "sticker find song alpha playcount" => {
+                    "file: alpha/song2.flac\nsticker: playcount=7\n"
+                }
+                _ => "",
+            }
+            .to_string()
+        })
+        .await;
+        let songs = client
+            .command(Find::new(Filter::tag(Tag::Album, "alpha")))
+            .await
+            .unwrap();
+
+        let Ok(annotations) = get_songs_annotations(&client, &songs).await else {
+            panic!("failed to get annotations");
+        };
+        assert_eq!(
+            annotations.ratings,
+            HashMap::from([("alpha/song1.flac".to_string(), 4)])
+        );
+        assert_eq!(
+            annotations.starred,
+            HashMap::from([(
+                "beta/song1.flac".to_string(),
+                "2023-08-05T21:56:13Z".to_string()
+            )])
+        );
+        assert_eq!(
+            annotations.play_counts,
+            HashMap::from([("alpha/song2.flac".to_string(), 7)])
+        );
+
+        let Ok(annotations) = get_songs_annotations(&client, &[]).await else {
+            panic!("failed to get annotations");
+        };
+        assert!(annotations.ratings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn play_count() {
+        let client = fake_client(|_| "file: alpha/song1.flac\nTitle: song1\n".to_string()).await;
+        let find = || Find::new(Filter::tag(Tag::Title, "song1"));
+
+        let song = client.command(find()).await.unwrap().remove(0);
+        let annotations = Annotations {
+            play_counts: HashMap::from([("alpha/song1.flac".to_string(), 3)]),
+            ..Default::default()
+        };
+        let song = mpd_song_to_subsonic(song, &annotations, SongOptions::default());
+        assert_eq!(song.play_count, Some(3));
+        assert!(serde_json::to_string(&song)
+            .unwrap()
+            .contains("\"playCount\":3"));
+
+        let song = client.command(find()).await.unwrap().remove(0);
+        let song = mpd_song_to_subsonic(song, &Annotations::default(), SongOptions::default());
+        assert_eq!(song.play_count, None);
+        assert!(!serde_json::to_string(&song).unwrap().contains("playCount"));
+    }
+
+    #[tokio::test]
+    async fn music_brainz_id() {
+        let client = fake_client(|_| {
+            "file: alpha/song1.flac\nMUSICBRAINZ_TRACKID: 8f3471b5-7e6a-48da-86a9-c1c07a0f47ae\n"
+                .to_string()
+        })
+        .await;
+
+        let song = client
+            .command(Find::new(Filter::tag(Tag::Title, "song1")))
+            .await
+            .unwrap()
+            .remove(0);
+        let song = mpd_song_to_subsonic(song, &Annotations::default(), SongOptions::default());
+        assert_eq!(
+            song.music_brainz_id.as_deref(),
+            Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae")
+        );
+    }
+
+    #[tokio::test]
+    async fn multiple_artists() {
+        let client =
+            fake_client(|_| "file: alpha/song1.flac\nArtist: alpha\nArtist: beta\n".to_string())
+                .await;
+
+        let song = client
+            .command(Find::new(Filter::tag(Tag::Title, "song1")))
+            .await
+            .unwrap()
+            .remove(0);
+        let song = mpd_song_to_subsonic(song, &Annotations::default(), SongOptions::default());
+        assert_eq!(song.artist, "alpha, beta");
+        assert_eq!(song.display_artist.as_deref(), Some("alpha, beta"));
+        // The primary artist is linked, with the same ID getArtists uses
+        assert_eq!(
+            format!("{:?}", song.artist_id),
+            format!("{:?}", ArtistID::new("alpha"))
+        );
+        assert_eq!(
+            song.artists
+                .iter()
+                .map(|a| (format!("{:?}", a.id), a.name.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (format!("{:?}", ArtistID::new("alpha")), "alpha"),
+                (format!("{:?}", ArtistID::new("beta")), "beta"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn contributors() {
+        let client = fake_client(|_| {
+            "file: alpha/song1.flac\nComposer: Bach\nPerformer: Gould\nPerformer: Richter\n"
+                .to_string()
+        })
+        .await;
+        let song = client
+            .command(Find::new(Filter::tag(Tag::Title, "song1")))
+            .await
+            .unwrap()
+            .remove(0);
+        let song = mpd_song_to_subsonic(song, &Annotations::default(), SongOptions::default());
+
+        let contributors = song
+            .contributors
+            .iter()
+            .map(|c| (c.role.as_str(), c.artist.name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            contributors,
+            [
+                ("composer", "Bach"),
+                ("performer", "Gould"),
+                ("performer", "Richter")
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&song).unwrap()["contributors"][0],
+            serde_json::json!({
+                "role": "composer",
+                "artist": {"id": "eyJuYW1lIjoiQmFjaCJ9", "name": "Bach"},
+            })
+        );
+        assert!(yaserde::ser::to_string(&song).unwrap().contains(
+            r#"<contributors role="composer"><artist id="eyJuYW1lIjoiQmFjaCJ9" name="Bach" /></contributors>"#
+        ));
+
+        let client = fake_client(|_| "file: alpha/song1.flac\n".to_string()).await;
+        let song = client
+            .command(Find::new(Filter::tag(Tag::Title, "song1")))
+            .await
+            .unwrap()
+            .remove(0);
+        let song = mpd_song_to_subsonic(song, &Annotations::default(), SongOptions::default());
+        assert!(song.contributors.is_empty());
+        assert!(!serde_json::to_string(&song)
+            .unwrap()
+            .contains("contributors"));
+    }
+
+    #[test]
+    fn merged_artists() {
+        assert_eq!(
+            merge_artists(
+                [
+                    "Beatles", "Blur", "beatles", "Beatles", "beatles", "beatles", "BLUR",
+                    "Coldplay"
+                ]
+                .into_iter()
+            ),
+            [(5, "beatles"), (2, "Blur"), (1, "Coldplay")]
+        );
+        assert!(merge_artists(std::iter::empty()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn album_rating() {
+        let client = fake_client(|_| {
+            "file: alpha/song1.flac\nfile: alpha/song2.flac\nfile: alpha/song3.flac\n".to_string()
+        })
+        .await;
+        let songs = client
+            .command(Find::new(Filter::tag(Tag::Album, "alpha")))
+            .await
+            .unwrap();
+        let some_starred = Annotations {
+            ratings: HashMap::from([
+                ("alpha/song1.flac".to_string(), 4),
+                ("alpha/song2.flac".to_string(), 5),
+            ]),
+            starred: HashMap::from([
+                (
+                    "alpha/song1.flac".to_string(),
+                    "2023-08-05T21:56:13Z".to_string(),
+                ),
+                (
+                    "alpha/song3.flac".to_string(),
+                    "2023-09-05T21:56:13Z".to_string(),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let mut all_starred = Annotations {
+            ratings: some_starred.ratings.clone(),
+            starred: some_starred.starred.clone(),
+            ..Default::default()
+        };
+        all_starred.starred.insert(
+            "alpha/song2.flac".to_string(),
+            "2023-07-05T21:56:13Z".to_string(),
+        );
+
+        assert_eq!(
+            get_album_rating(&songs, &some_starred, false),
+            AlbumRating {
+                user_rating: Some(5),
+                average_rating: Some(4.5),
+                starred: None,
+            }
+        );
+        assert_eq!(
+            get_album_rating(&songs, &some_starred, true).starred,
+            Some("2023-09-05T21:56:13Z".to_string())
+        );
+        assert_eq!(
+            get_album_rating(&songs, &all_starred, false).starred,
+            Some("2023-09-05T21:56:13Z".to_string())
+        );
+        assert_eq!(
+            get_album_rating(&songs, &Annotations::default(), true),
+            AlbumRating::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn compilation() {
+        let client = fake_client(|_| {
+            "file: a/1.flac\ncompilation: 1\nfile: b/1.flac\ncompilation: 0\nfile: c/1.flac\n"
+                .to_string()
+        })
+        .await;
+        let songs = client
+            .command(Find::new(Filter::tag(Tag::Album, "alpha")))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            songs.iter().map(is_compilation).collect::<Vec<_>>(),
+            vec![true, false, false]
+        );
+    }
+
+    #[tokio::test]
+    async fn album_representations() {
+        let client = fake_client(|command| match command.split_whitespace().next() {
+            Some("count") => "songs: 2\nplaytime: 300\n".to_string(),
+            _ => "file: alpha/beta/1.flac\nOriginalDate: 2020\nGenre: Rock\nGenre: Metal\n"
+                .to_string(),
+        })
+        .await;
+        let filter = Filter::tag(Tag::Album, "beta");
+        let (count, songs) = client
+            .command_list((Count::new(filter.clone()), Find::new(filter)))
+            .await
+            .unwrap();
+
+        let model = album_model(AlbumID::new("beta", "alpha"), &count, songs.first());
+        let album = Album::from(model.clone());
+        let directory = DirectoryAlbum::from(model);
+
+        assert_eq!(album.song_count, 2);
+        assert_eq!(album.year, Some(2020));
+        assert_eq!(album.genre.as_deref(), Some("Rock, Metal"));
+
+        assert_eq!(format!("{:?}", album.id), format!("{:?}", directory.id));
+        assert_eq!(album.name, directory.title);
+        assert_eq!(album.name, directory.album);
+        assert_eq!(album.artist, directory.artist);
+        assert_eq!(
+            format!("{:?}", album.artist_id),
+            format!("{:?}", directory.artist_id)
+        );
+        assert_eq!(album.year, directory.year);
+        assert_eq!(album.genre, directory.genre);
+        assert_eq!(
+            format!("{:?}", album.cover_art),
+            format!("{:?}", directory.cover_art)
+        );
+        assert_eq!(album.duration, directory.duration);
+    }
+
+    #[tokio::test]
+    async fn albums_artist_fallback() {
+        // beta is tagged with the album artist, gamma only with the artist
+        let client = fake_client(|command| {
+            match (
+                command.split_whitespace().next(),
+                command.contains("AlbumArtist"),
+                command.contains("beta"),
+            ) {
+                (Some("count"), true, false) => "songs: 0\nplaytime: 0\n",
+                (Some("count"), _, _) => "songs: 2\nplaytime: 300\n",
+                (_, true, false) => "",
+                (_, _, true) => "file: alpha/beta/1.flac\nOriginalDate: 2020\n",
+                _ => "file: delta/gamma/1.flac\nOriginalDate: 2021\n",
+            }
+            .to_string()
+        })
+        .await;
+
+        let albums = vec![
+            AlbumID::new("beta", "alpha"),
+            AlbumID::new("gamma", "delta"),
+        ];
+        let Ok(albums) = get_albums(&client, albums).await else {
+            panic!("get_albums failed");
+        };
+        assert_eq!(
+            albums
+                .iter()
+                .map(|a| (a.song_count, a.year))
+                .collect::<Vec<_>>(),
+            [(2, Some(2020)), (2, Some(2021))]
+        );
+    }
 }
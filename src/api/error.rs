@@ -1,5 +1,6 @@
 use crate::{library, listenbrainz, mpd};
 use axum::extract::rejection;
+use mpd_client::client::CommandError;
 use serde::Serialize;
 use std::convert::Infallible;
 use yaserde_derive::YaSerialize;
@@ -37,6 +38,21 @@ impl Error {
         Error::new(40, "Wrong username or password")
     }
 
+    pub(crate) fn authentication_not_supported(msg: &str) -> Self {
+        Error::new(42, msg)
+    }
+
+    pub(crate) fn conflicting_authentication() -> Self {
+        Error::new(
+            43,
+            "Multiple conflicting authentication mechanisms provided",
+        )
+    }
+
+    pub(crate) fn invalid_api_key() -> Self {
+        Error::new(44, "Invalid API key")
+    }
+
     pub(crate) fn not_authorized(msg: &str) -> Self {
         Error::new(50, msg)
     }
@@ -73,10 +89,28 @@ impl From<rejection::ExtensionRejection> for Error {
     }
 }
 
-impl From<mpd_client::client::CommandError> for Error {
-    fn from(err: mpd_client::client::CommandError) -> Self {
-        // TODO: handle specific cases
-        Error::generic_error(Some(&err.to_string()))
+impl From<CommandError> for Error {
+    fn from(err: CommandError) -> Self {
+        match &err {
+            CommandError::ErrorResponse { error, .. } if error.code == mpd::ACK_ERROR_NO_EXIST => {
+                Error::not_found()
+            }
+            CommandError::ErrorResponse { error, .. }
+                if error.code == mpd::ACK_ERROR_PERMISSION =>
+            {
+                Error::not_authorized(&error.message)
+            }
+            _ => Error::generic_error(Some(&err.to_string())),
+        }
+    }
+}
+
+impl From<mpd::Error> for Error {
+    fn from(err: mpd::Error) -> Self {
+        match err {
+            mpd::Error::Command(err) => err.into(),
+            err => Error::generic_error(Some(&err.to_string())),
+        }
     }
 }
 
@@ -111,8 +145,13 @@ impl From<listenbrainz::Error> for Error {
 #[cfg(test)]
 mod tests {
     use super::Error;
-    use crate::api::{expect_json, expect_xml, json, xml};
+    use crate::{
+        api::{expect_json, expect_xml, json, xml},
+        mpd,
+    };
+    use mpd_client::{client::CommandError, protocol::response};
     use serde_json::json;
+    use std::time::Duration;
 
     #[test]
     fn empty() {
@@ -139,4 +178,43 @@ mod tests {
             ),
         );
     }
+
+    #[test]
+    fn mpd_errors() {
+        let ack = |code, message: &str| CommandError::ErrorResponse {
+            error: response::Error {
+                code,
+                command_index: 0,
+                current_command: Some("load".into()),
+                message: message.into(),
+            },
+            succesful_frames: Vec::new(),
+        };
+        let code = |err: Error| (err.code, err.message);
+
+        assert_eq!(
+            code(ack(50, "No such playlist").into()),
+            (70, "The requested data was not found".to_string())
+        );
+        assert_eq!(
+            code(mpd::Error::Command(ack(50, "No such song")).into()),
+            (70, "The requested data was not found".to_string())
+        );
+        assert_eq!(
+            code(ack(4, "you don't have permission for \"load\"").into()),
+            (50, "you don't have permission for \"load\"".to_string())
+        );
+        assert_eq!(
+            code(ack(2, "Bad song index").into()),
+            (
+                0,
+                "A generic error: command returned an error [code 2]: Bad song index".to_string()
+            )
+        );
+        assert_eq!(code(CommandError::ConnectionClosed.into()).0, 0);
+        assert_eq!(
+            code(mpd::Error::Timeout(Duration::from_secs(1)).into()).0,
+            0
+        );
+    }
 }
@@ -2,14 +2,16 @@ use axum::{
     async_trait,
     body::Body,
     extract::FromRequestParts,
-    http::{request::Parts, Request},
+    http::{request::Parts, HeaderMap, HeaderName, HeaderValue, Request},
     response::{IntoResponse, Response},
 };
-use futures::future::Map;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{channel::mpsc, future::Map, SinkExt};
 use serde::Serialize;
 use std::{
     convert::Infallible,
     future::Future,
+    io,
     marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
@@ -60,7 +62,7 @@ where
 
             let format = super::serialization_format(&parts);
             match self().await {
-                Ok(reply) => super::serialize_reply(reply.into_reply(), &format),
+                Ok(reply) => serialize_into_reply(reply, &format),
                 Err(error) => super::serialize_reply(error, &format),
             }
         })
@@ -98,7 +100,7 @@ macro_rules! impl_handler {
                     )*
 
                     match self($($ty,)*).await {
-                        Ok(reply) => super::serialize_reply(reply.into_reply(), &format),
+                        Ok(reply) => serialize_into_reply(reply, &format),
                         Err(error) => super::serialize_reply(error, &format),
                     }
                 })
@@ -147,6 +149,7 @@ macro_rules! impl_handler {
 impl_handler!(T1);
 impl_handler!(T1, T2);
 impl_handler!(T1, T2, T3);
+impl_handler!(T1, T2, T3, T4);
 
 // An adapter that makes Handler into tower_service::Service
 #[derive(Clone)]
@@ -233,11 +236,11 @@ impl super::Reply for Empty {
     }
 }
 
-// Trait for data that can be converted into api::Reply
+// Trait for data that can be converted into api::Reply and optional response headers
 trait IntoReply {
     type Reply;
 
-    fn into_reply(self) -> Self::Reply;
+    fn into_reply(self) -> (Self::Reply, HeaderMap);
 }
 
 // IntoReply is implemented trivially for all replies
@@ -246,8 +249,8 @@ where
     T: super::Reply,
 {
     type Reply = T;
-    fn into_reply(self) -> Self::Reply {
-        self
+    fn into_reply(self) -> (Self::Reply, HeaderMap) {
+        (self, HeaderMap::new())
     }
 }
 
@@ -255,8 +258,76 @@ where
 // also implements YaSerialize, which isn't implemented on an empty tuple :(
 impl IntoReply for () {
     type Reply = Empty;
-    fn into_reply(self) -> Self::Reply {
-        Empty
+    fn into_reply(self) -> (Self::Reply, HeaderMap) {
+        (Empty, HeaderMap::new())
+    }
+}
+
+// serialize_into_reply serializes the reply and attaches its headers to the response
+fn serialize_into_reply<IR, R>(reply: IR, format: &super::SerializationQuery) -> Response
+where
+    IR: IntoReply<Reply = R>,
+    R: super::Reply,
+{
+    let (reply, headers) = reply.into_reply();
+
+    let mut response = super::serialize_reply(reply, format);
+    response.headers_mut().extend(headers);
+    response
+}
+
+// Header with the total number of items of a paged list
+pub(crate) const X_TOTAL_COUNT: &str = "x-total-count";
+
+// A page of a longer list. Total sizes of the lists are sent as X-Total-Count headers, so
+// clients can paginate without counting the items themselves.
+pub(crate) struct Paged<T> {
+    pub(crate) reply: T,
+    pub(crate) totals: Vec<(&'static str, usize)>,
+}
+
+impl<T> Paged<T> {
+    // new creates a page of a single list of total items
+    pub(crate) fn new(reply: T, total: usize) -> Self {
+        Paged {
+            reply,
+            totals: vec![(X_TOTAL_COUNT, total)],
+        }
+    }
+
+    // with_totals creates a page of several lists, each with its own total header
+    pub(crate) fn with_totals(reply: T, totals: Vec<(&'static str, usize)>) -> Self {
+        Paged { reply, totals }
+    }
+}
+
+impl<T> Paged<T>
+where
+    T: super::Reply + Send + 'static,
+{
+    // stream sends the page to the client while it is being serialized, see stream_reply
+    pub(crate) fn stream(self, format: super::SerializationQuery) -> Response {
+        let (reply, headers) = self.into_reply();
+
+        let mut response = super::stream_reply(reply, format);
+        response.headers_mut().extend(headers);
+        response
+    }
+}
+
+impl<T> IntoReply for Paged<T>
+where
+    T: super::Reply,
+{
+    type Reply = T;
+    fn into_reply(self) -> (Self::Reply, HeaderMap) {
+        let headers = self
+            .totals
+            .into_iter()
+            .map(|(name, total)| (HeaderName::from_static(name), HeaderValue::from(total)))
+            .collect();
+
+        (self.reply, headers)
     }
 }
 
@@ -275,10 +346,72 @@ where
     }
 }
 
+#[async_trait]
+impl<S> FromRequestParts<S> for super::SerializationQuery
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+    async fn from_request_parts(req: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(super::serialization_format(req))
+    }
+}
+
+// A blocking writer that splits the written data into chunks of bounded size and sends
+// them over a channel. Used to stream replies to the client while they are being serialized.
+pub(crate) struct ChunkWriter {
+    tx: mpsc::Sender<Bytes>,
+    buf: BytesMut,
+    chunk_size: usize,
+}
+
+impl ChunkWriter {
+    pub(crate) fn new(tx: mpsc::Sender<Bytes>, chunk_size: usize) -> Self {
+        Self {
+            tx,
+            buf: BytesMut::with_capacity(chunk_size),
+            chunk_size,
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        let chunk = self.buf.split().freeze();
+        futures::executor::block_on(self.tx.send(chunk))
+            .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))
+    }
+}
+
+impl io::Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = data.len().min(self.chunk_size - self.buf.len());
+        self.buf.put_slice(&data[..n]);
+        if self.buf.len() == self.chunk_size {
+            self.send()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.buf.is_empty() {
+            true => Ok(()),
+            false => self.send(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Empty;
-    use crate::api::{expect_ok_json, expect_ok_xml, json, xml};
+    use super::{serialize_into_reply, ChunkWriter, Empty, Paged, X_TOTAL_COUNT};
+    use crate::api::{expect_ok_json, expect_ok_xml, json, xml, SerializationQuery};
+    use futures::{channel::mpsc, StreamExt};
+    use std::{
+        io::Write,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
     #[test]
     fn error() {
@@ -286,4 +419,54 @@ mod tests {
 
         assert_eq!(json(&Empty), expect_ok_json(None));
     }
+
+    #[test]
+    fn total_count() {
+        let format = SerializationQuery::default();
+
+        let response = serialize_into_reply(Empty, &format);
+        assert!(response.headers().get(X_TOTAL_COUNT).is_none());
+
+        let response = serialize_into_reply(Paged::new(Empty, 42), &format);
+        assert_eq!(response.headers()[X_TOTAL_COUNT], "42");
+
+        let response = serialize_into_reply(
+            Paged::with_totals(Empty, vec![("x-total-count-songs", 3)]),
+            &format,
+        );
+        assert_eq!(response.headers()["x-total-count-songs"], "3");
+        assert!(response.headers().get(X_TOTAL_COUNT).is_none());
+    }
+
+    #[tokio::test]
+    async fn chunk_writer_bounded() {
+        const CHUNK: usize = 1024;
+        const TOTAL: usize = 1024 * CHUNK;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let written = Arc::new(AtomicUsize::new(0));
+        let writer = tokio::task::spawn_blocking({
+            let written = written.clone();
+            move || {
+                let mut writer = ChunkWriter::new(tx, CHUNK);
+                for _ in 0..TOTAL / 100 {
+                    writer.write_all(&[b'a'; 100]).unwrap();
+                    written.fetch_add(100, Ordering::SeqCst);
+                }
+                writer.flush().unwrap();
+            }
+        });
+
+        // The writer blocks as soon as the client stops reading, only a few chunks are buffered
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(written.load(Ordering::SeqCst) <= 4 * CHUNK);
+
+        let mut received = 0;
+        while let Some(chunk) = rx.next().await {
+            assert!(chunk.len() <= CHUNK);
+            received += chunk.len();
+        }
+        writer.await.unwrap();
+        assert_eq!(received, TOTAL / 100 * 100);
+    }
 }
@@ -0,0 +1,311 @@
+use super::{
+    common::{get_songs_annotations, mpd_song_to_subsonic},
+    glue::RawQuery,
+    types::{Song, SongID},
+    Error,
+};
+use axum::{
+    extract::{Extension, Query},
+    response::Response,
+    routing::Router,
+};
+use mpd_client::{
+    commands::{
+        Add, ClearQueue, Delete, Play, Queue, SeekTo, SetPause, SetVolume, Shuffle,
+        Song as SongSelector, SongPosition, Status,
+    },
+    responses::{self, PlayState},
+};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use yaserde_derive::YaSerialize;
+
+pub(crate) fn get_router() -> Router {
+    Router::new().route("/jukeboxControl.view", super::raw_handler(jukebox_control))
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum JukeboxAction {
+    Get,
+    Status,
+    Set,
+    Start,
+    Stop,
+    Skip,
+    Add,
+    Clear,
+    Remove,
+    Shuffle,
+    SetGain,
+}
+
+#[derive(Clone, Deserialize)]
+struct JukeboxControlQuery {
+    action: JukeboxAction,
+    index: Option<usize>,
+    offset: Option<u64>,
+    gain: Option<f32>,
+}
+
+// jukebox_control controls MPD playback. The jukebox playlist is the MPD play queue.
+async fn jukebox_control(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<JukeboxControlQuery>,
+    RawQuery(query): RawQuery,
+    format: super::SerializationQuery,
+) -> super::Result<Response> {
+    if !state.jukebox {
+        return Err(Error::not_authorized("Jukebox is disabled"));
+    }
+
+    let songs = url::form_urlencoded::parse(
+        &query
+            .ok_or_else(|| Error::missing_parameter("failed to parse URL query"))?
+            .into_bytes(),
+    )
+    .filter(|(k, _)| k == "id")
+    // Invalid IDs are rejected just like they are by the query parser of other endpoints
+    .map(|(_, v)| {
+        SongID::try_from(v.as_ref())
+            .map(|s| s.path)
+            .map_err(|err| Error::missing_parameter(&format!("id: {err}")))
+    })
+    .collect::<super::Result<Vec<_>>>()?;
+    let index = || param.index.ok_or_else(|| Error::missing_parameter("index"));
+
+    let conn = state.pool.get().await?;
+    match param.action {
+        JukeboxAction::Get | JukeboxAction::Status => {}
+        JukeboxAction::Start => conn.command(Play::current()).await?,
+        JukeboxAction::Stop => conn.command(SetPause(true)).await?,
+        JukeboxAction::Skip => {
+            let position = SongPosition(index()?);
+            conn.command(Play::song(position)).await?;
+            if let Some(offset) = param.offset.filter(|&o| o > 0) {
+                conn.command(SeekTo(
+                    SongSelector::Position(position),
+                    Duration::from_secs(offset),
+                ))
+                .await?;
+            }
+        }
+        JukeboxAction::Set | JukeboxAction::Add => {
+            if param.action == JukeboxAction::Set {
+                conn.command(ClearQueue).await?;
+            }
+            if !songs.is_empty() {
+                conn.command_list(songs.iter().map(|s| Add::uri(s)).collect::<Vec<_>>())
+                    .await?;
+            }
+        }
+        JukeboxAction::Clear => conn.command(ClearQueue).await?,
+        JukeboxAction::Remove => {
+            conn.command(Delete::position(SongPosition(index()?)))
+                .await?
+        }
+        JukeboxAction::Shuffle => conn.command(Shuffle::all()).await?,
+        JukeboxAction::SetGain => {
+            let gain = param.gain.ok_or_else(|| Error::missing_parameter("gain"))?;
+            conn.command(SetVolume(gain_to_volume(gain))).await?
+        }
+    }
+
+    let status = jukebox_status(&conn.command(Status).await?);
+    if param.action != JukeboxAction::Get {
+        return Ok(super::serialize_reply(status, &format));
+    }
+
+    let queue = conn
+        .command(Queue)
+        .await?
+        .into_iter()
+        .map(|s| s.song)
+        .collect::<Vec<_>>();
+    let annotations = get_songs_annotations(&conn, &queue).await?;
+
+    Ok(super::serialize_reply(
+        JukeboxPlaylist {
+            current_index: status.current_index,
+            playing: status.playing,
+            gain: status.gain,
+            position: status.position,
+            entries: queue
+                .into_iter()
+                .map(|s| mpd_song_to_subsonic(s, &annotations, state.song_options()))
+                .collect(),
+        },
+        &format,
+    ))
+}
+
+// gain_to_volume converts Subsonic gain (0.0 - 1.0) to MPD volume (0 - 100)
+fn gain_to_volume(gain: f32) -> u8 {
+    (gain.clamp(0.0, 1.0) * 100.0).round() as u8
+}
+
+// jukebox_status converts MPD status into jukebox status. Current index is -1 if MPD has no
+// current song.
+fn jukebox_status(status: &responses::Status) -> JukeboxStatus {
+    JukeboxStatus {
+        current_index: status
+            .current_song
+            .map_or(-1, |(position, _)| position.0 as i64),
+        playing: status.state == PlayState::Playing,
+        gain: f32::from(status.volume) / 100.0,
+        position: status.elapsed.map(|e| e.as_secs()),
+    }
+}
+
+#[derive(Serialize, YaSerialize, Debug, PartialEq)]
+#[yaserde(rename = "jukeboxStatus")]
+#[serde(rename_all = "camelCase")]
+struct JukeboxStatus {
+    #[yaserde(attribute, rename = "currentIndex")]
+    current_index: i64,
+    #[yaserde(attribute)]
+    playing: bool,
+    #[yaserde(attribute)]
+    gain: f32,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<u64>,
+}
+
+impl super::Reply for JukeboxStatus {
+    fn field_name() -> Option<&'static str> {
+        Some("jukeboxStatus")
+    }
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "jukeboxPlaylist")]
+#[serde(rename_all = "camelCase")]
+struct JukeboxPlaylist {
+    #[yaserde(attribute, rename = "currentIndex")]
+    current_index: i64,
+    #[yaserde(attribute)]
+    playing: bool,
+    #[yaserde(attribute)]
+    gain: f32,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<u64>,
+    #[yaserde(child, rename = "entry")]
+    #[serde(rename = "entry")]
+    entries: Vec<Song>,
+}
+
+impl super::Reply for JukeboxPlaylist {
+    fn field_name() -> Option<&'static str> {
+        Some("jukeboxPlaylist")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gain_to_volume, jukebox_control, JukeboxControlQuery, JukeboxStatus};
+    use crate::{
+        api::{
+            expect_ok_json, expect_ok_xml, glue::RawQuery, json, test_state, test_state_with_mpd,
+            xml, SerializationQuery,
+        },
+        mpd::testing::fake_server,
+    };
+    use axum::extract::{Extension, Query};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn jukebox_status() {
+        let status = JukeboxStatus {
+            current_index: 1,
+            playing: true,
+            gain: 0.5,
+            position: Some(42),
+        };
+        assert_eq!(
+            xml(&status),
+            expect_ok_xml(Some(
+                r#"<jukeboxStatus currentIndex="1" playing="true" gain="0.5" position="42" />"#
+            ))
+        );
+        assert_eq!(
+            json(&status),
+            expect_ok_json(Some(json!({"jukeboxStatus": {
+                "currentIndex": 1,
+                "playing": true,
+                "gain": 0.5,
+                "position": 42,
+            }})))
+        );
+    }
+
+    #[test]
+    fn gain() {
+        assert_eq!(gain_to_volume(0.0), 0);
+        assert_eq!(gain_to_volume(0.42), 42);
+        assert_eq!(gain_to_volume(1.0), 100);
+        assert_eq!(gain_to_volume(-1.0), 0);
+        assert_eq!(gain_to_volume(2.0), 100);
+    }
+
+    #[tokio::test]
+    async fn control() {
+        let res = jukebox_control(
+            Extension(test_state().await),
+            Query(serde_urlencoded::from_str("action=status").unwrap()),
+            RawQuery(Some("action=status".to_string())),
+            SerializationQuery::default(),
+        )
+        .await;
+        assert!(res.is_err());
+
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let commands = commands.clone();
+            move |command| {
+                commands.lock().unwrap().push(command.to_string());
+                match command {
+                    "status" => {
+                        "volume: 50\nrepeat: 0\nrandom: 0\nconsume: 0\nstate: play\nsong: 1\n\
+                         songid: 2\nelapsed: 42.5\n"
+                    }
+                    _ => "",
+                }
+                .to_string()
+            }
+        })
+        .await;
+        let mut state = Arc::into_inner(test_state_with_mpd(mpd).await).unwrap();
+        state.jukebox = true;
+        let state = Arc::new(state);
+        let control = |query: &str| {
+            let param: JukeboxControlQuery = serde_urlencoded::from_str(query).unwrap();
+            jukebox_control(
+                Extension(state.clone()),
+                Query(param),
+                RawQuery(Some(query.to_string())),
+                SerializationQuery::default(),
+            )
+        };
+
+        assert!(control("action=skip").await.is_err());
+        assert!(control("action=skip&index=3&offset=10").await.is_ok());
+        assert!(control("action=setGain&gain=0.8").await.is_ok());
+        assert!(control("action=stop").await.is_ok());
+        // Nothing is added if any of the songs is invalid
+        assert!(control("action=add&id=garbage").await.is_err());
+
+        let commands = commands.lock().unwrap();
+        let commands = commands
+            .iter()
+            .filter(|&c| c != "status" && c != "ping")
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            commands,
+            ["play 3", "seek 3 10.000", "setvol 80", "pause 1"]
+        );
+    }
+}
@@ -0,0 +1,1087 @@
+use super::{
+    browsing::{validate_music_folder, ROOT_FOLDER},
+    common::{
+        all_songs, get_albums, get_song_year, get_songs_annotations, get_songs_by_path,
+        mpd_song_to_subsonic, parse_year, SongOptions, STICKER_STARRED,
+    },
+    glue::Paged,
+    types::{Album, AlbumID, AlbumModel, Artist, DirectoryAlbum, DirectoryArtist, Song},
+    Error,
+};
+use crate::mpd::Connection;
+use axum::{
+    extract::{Extension, Query},
+    routing::Router,
+};
+use mpd_client::{
+    commands::{Count, CurrentSong, Find, List, Queue, Status, StickerFind},
+    filter::Filter,
+    responses::PlayState,
+    tag::Tag,
+};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use time::{format_description::well_known, OffsetDateTime};
+use yaserde_derive::YaSerialize;
+
+const RANDOM_SONGS_DEFAULT_SIZE: usize = 10;
+const RANDOM_SONGS_MAX_SIZE: usize = 500;
+const SONGS_BY_GENRE_DEFAULT_COUNT: usize = 10;
+const SONGS_BY_GENRE_MAX_COUNT: usize = 500;
+const ALBUM_LIST_DEFAULT_SIZE: usize = 10;
+const ALBUM_LIST_MAX_SIZE: usize = 500;
+// MPD is the only player, so it always has the same ID
+const MPD_PLAYER_ID: u32 = 0;
+
+pub(crate) fn get_router() -> Router {
+    Router::new()
+        .route("/getAlbumList.view", super::handler(get_album_list))
+        .route("/getAlbumList2.view", super::handler(get_album_list2))
+        .route("/getNowPlaying.view", super::handler(get_now_playing))
+        .route("/getRandomSongs.view", super::handler(get_random_songs))
+        .route("/getSongsByGenre.view", super::handler(get_songs_by_genre))
+        .route("/getStarred.view", super::handler(get_starred))
+        .route("/getStarred2.view", super::handler(get_starred2))
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetAlbumListQuery {
+    #[serde(rename = "type")]
+    list_type: String,
+    size: Option<usize>,
+    offset: Option<usize>,
+    from_year: Option<i32>,
+    to_year: Option<i32>,
+    // Shorthand for the same fromYear and toYear some clients send
+    year: Option<i32>,
+    genre: Option<String>,
+    music_folder_id: Option<String>,
+}
+
+async fn get_album_list(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetAlbumListQuery>,
+) -> super::Result<Paged<AlbumList>> {
+    let (albums, total) = album_list(&state, param).await?;
+
+    Ok(Paged::new(
+        AlbumList {
+            albums: albums.into_iter().map(Into::into).collect(),
+        },
+        total,
+    ))
+}
+
+async fn get_album_list2(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetAlbumListQuery>,
+) -> super::Result<Paged<AlbumList2>> {
+    let (albums, total) = album_list(&state, param).await?;
+
+    Ok(Paged::new(
+        AlbumList2 {
+            albums: albums.into_iter().map(Into::into).collect(),
+        },
+        total,
+    ))
+}
+
+// album_list returns a page of albums of the requested album list type along with the total
+// number of albums in the list
+async fn album_list(
+    state: &super::State,
+    param: GetAlbumListQuery,
+) -> super::Result<(Vec<AlbumModel>, usize)> {
+    validate_music_folder(param.music_folder_id.as_deref())?;
+
+    let size = param
+        .size
+        .unwrap_or(ALBUM_LIST_DEFAULT_SIZE)
+        .min(ALBUM_LIST_MAX_SIZE);
+    let offset = param.offset.unwrap_or(0);
+
+    let conn = state.pool.get().await?;
+    let albums = match param.list_type.as_str() {
+        "random" => {
+            let mut albums = list_albums(&conn, None).await?;
+            albums.shuffle(&mut rand::thread_rng());
+            albums
+        }
+        "alphabeticalByName" => {
+            let mut albums = list_albums(&conn, None).await?;
+            albums.sort_by_cached_key(|a| (a.name.to_lowercase(), a.artist.to_lowercase()));
+            albums
+        }
+        "alphabeticalByArtist" => {
+            let mut albums = list_albums(&conn, None).await?;
+            albums.sort_by_cached_key(|a| (a.artist.to_lowercase(), a.name.to_lowercase()));
+            albums
+        }
+        "byGenre" => {
+            let genre = param
+                .genre
+                .ok_or_else(|| Error::missing_parameter("genre"))?;
+            list_albums(&conn, Some(Filter::tag(Tag::Genre, genre))).await?
+        }
+        "byYear" => {
+            let (Some(from), Some(to)) =
+                (param.from_year.or(param.year), param.to_year.or(param.year))
+            else {
+                return Err(Error::missing_parameter("fromYear and toYear"));
+            };
+            list_albums_by_year(&conn, from, to).await?
+        }
+        _ => {
+            return Err(Error::generic_error(Some(&format!(
+                "unsupported album list type: {}",
+                param.list_type
+            ))))
+        }
+    };
+
+    let total = albums.len();
+    let albums = get_albums(&conn, albums.into_iter().skip(offset).take(size).collect()).await?;
+
+    Ok((albums, total))
+}
+
+// list_albums returns all albums having at least one song matching the filter. An album with
+// songs of several genres thus matches each of the genres.
+async fn list_albums(conn: &Connection, filter: Option<Filter>) -> super::Result<Vec<AlbumID>> {
+    let list = List::new(Tag::Album);
+    let list = match filter {
+        Some(filter) => list.filter(filter),
+        None => list,
+    };
+
+    Ok(conn
+        .command(list.group_by([Tag::AlbumArtist]))
+        .await?
+        .grouped_values()
+        .map(|(album, [artist])| AlbumID::new(album, artist))
+        .collect())
+}
+
+// list_albums_by_year returns albums released within the (inclusive) range of years ordered by
+// year. If from is after to, albums are ordered from the newest to the oldest.
+async fn list_albums_by_year(conn: &Connection, from: i32, to: i32) -> super::Result<Vec<AlbumID>> {
+    let list = conn
+        .command(List::new(Tag::Album).group_by([Tag::OriginalDate, Tag::Date, Tag::AlbumArtist]))
+        .await?;
+
+    // Same as get_song_year, the original date wins over the date
+    let mut seen = HashSet::new();
+    let mut albums = list
+        .grouped_values()
+        .filter_map(|(album, [original, date, artist])| {
+            let year = parse_year(original).or_else(|| parse_year(date))?;
+            (year >= from.min(to) && year <= from.max(to) && seen.insert((album, artist)))
+                .then(|| (year, AlbumID::new(album, artist)))
+        })
+        .collect::<Vec<_>>();
+    albums.sort_by_cached_key(|(year, album)| (*year, album.name.to_lowercase()));
+    if from > to {
+        albums.reverse();
+    }
+
+    Ok(albums.into_iter().map(|(_, album)| album).collect())
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "albumList")]
+struct AlbumList {
+    #[yaserde(child, rename = "album")]
+    #[serde(rename = "album")]
+    albums: Vec<DirectoryAlbum>,
+}
+
+impl super::Reply for AlbumList {
+    fn field_name() -> Option<&'static str> {
+        Some("albumList")
+    }
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "albumList2")]
+struct AlbumList2 {
+    #[yaserde(child, rename = "album")]
+    #[serde(rename = "album")]
+    albums: Vec<Album>,
+}
+
+impl super::Reply for AlbumList2 {
+    fn field_name() -> Option<&'static str> {
+        Some("albumList2")
+    }
+}
+
+#[derive(Clone, Deserialize)]
+struct GetNowPlayingQuery {
+    u: String,
+}
+
+async fn get_now_playing(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetNowPlayingQuery>,
+) -> super::Result<NowPlaying> {
+    let conn = state.pool.get().await?;
+
+    let (status, current) = conn.command_list((Status, CurrentSong)).await?;
+    let song = match (status.state, current) {
+        (PlayState::Stopped, _) | (_, None) => {
+            return Ok(NowPlaying {
+                entries: Vec::new(),
+            })
+        }
+        (_, Some(current)) => current.song,
+    };
+
+    let songs = [song];
+    let annotations = get_songs_annotations(&conn, &songs).await?;
+    let [song] = songs;
+
+    Ok(NowPlaying {
+        entries: vec![NowPlayingEntry::new(
+            mpd_song_to_subsonic(song, &annotations, state.song_options()),
+            param.u,
+        )],
+    })
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "nowPlaying")]
+struct NowPlaying {
+    #[yaserde(child, rename = "entry")]
+    #[serde(rename = "entry")]
+    entries: Vec<NowPlayingEntry>,
+}
+
+impl super::Reply for NowPlaying {
+    fn field_name() -> Option<&'static str> {
+        Some("nowPlaying")
+    }
+}
+
+// NowPlayingEntry is a song being played along with who and where plays it
+#[derive(Serialize, YaSerialize)]
+#[serde(rename_all = "camelCase")]
+struct NowPlayingEntry {
+    #[yaserde(attribute)]
+    username: String,
+    #[yaserde(attribute, rename = "minutesAgo")]
+    minutes_ago: u32,
+    #[yaserde(attribute, rename = "playerId")]
+    player_id: u32,
+    #[serde(flatten)]
+    #[yaserde(flatten)]
+    song: Song,
+}
+
+impl NowPlayingEntry {
+    // new creates an entry for the song MPD is playing right now on behalf of the user
+    fn new(song: Song, username: String) -> Self {
+        NowPlayingEntry {
+            username,
+            minutes_ago: 0,
+            player_id: MPD_PLAYER_ID,
+            song,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetRandomSongsQuery {
+    size: Option<usize>,
+    genre: Option<String>,
+    from_year: Option<i32>,
+    to_year: Option<i32>,
+    music_folder_id: Option<String>,
+    // Non-standard extension: do not return songs that are already in the MPD queue
+    exclude_queued: Option<bool>,
+}
+
+async fn get_random_songs(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetRandomSongsQuery>,
+) -> super::Result<RandomSongs> {
+    validate_music_folder(param.music_folder_id.as_deref())?;
+
+    let size = param
+        .size
+        .unwrap_or(RANDOM_SONGS_DEFAULT_SIZE)
+        .min(RANDOM_SONGS_MAX_SIZE);
+    let filter = match param.genre {
+        Some(genre) => Filter::tag(Tag::Genre, genre),
+        None => all_songs(),
+    };
+
+    let conn = state.pool.get().await?;
+    let queued = match param.exclude_queued {
+        Some(true) => conn
+            .command(Queue)
+            .await?
+            .into_iter()
+            .map(|s| s.song.url)
+            .collect::<HashSet<_>>(),
+        _ => HashSet::new(),
+    };
+    let mut songs = conn
+        .command(Find::new(filter))
+        .await?
+        .into_iter()
+        .filter(|s| year_in_range(get_song_year(s), param.from_year, param.to_year))
+        .filter(|s| !queued.contains(&s.url))
+        .collect::<Vec<_>>();
+    songs.shuffle(&mut rand::thread_rng());
+    songs.truncate(size);
+
+    let annotations = get_songs_annotations(&conn, &songs).await?;
+
+    Ok(RandomSongs {
+        songs: songs
+            .into_iter()
+            .map(|s| mpd_song_to_subsonic(s, &annotations, state.song_options()))
+            .collect(),
+    })
+}
+
+// year_in_range checks if the year is within the (inclusive) range. Songs without a year never
+// match a bounded range.
+fn year_in_range(year: Option<i32>, from: Option<i32>, to: Option<i32>) -> bool {
+    match (year, from, to) {
+        (_, None, None) => true,
+        (None, _, _) => false,
+        (Some(year), from, to) => {
+            from.map_or(true, |from| year >= from) && to.map_or(true, |to| year <= to)
+        }
+    }
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "randomSongs")]
+struct RandomSongs {
+    #[yaserde(child, rename = "song")]
+    #[serde(rename = "song")]
+    songs: Vec<Song>,
+}
+
+impl super::Reply for RandomSongs {
+    fn field_name() -> Option<&'static str> {
+        Some("randomSongs")
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetSongsByGenreQuery {
+    genre: String,
+    count: Option<usize>,
+    offset: Option<usize>,
+    music_folder_id: Option<String>,
+}
+
+async fn get_songs_by_genre(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetSongsByGenreQuery>,
+) -> super::Result<Paged<SongsByGenre>> {
+    validate_music_folder(param.music_folder_id.as_deref())?;
+
+    let count = param
+        .count
+        .unwrap_or(SONGS_BY_GENRE_DEFAULT_COUNT)
+        .min(SONGS_BY_GENRE_MAX_COUNT);
+    let offset = param.offset.unwrap_or(0);
+    let filter = Filter::tag(Tag::Genre, param.genre);
+
+    let conn = state.pool.get().await?;
+    let total = conn.command(Count::new(filter.clone())).await?.songs as usize;
+    let songs = match count {
+        0 => Vec::new(),
+        count => {
+            conn.command(Find::new(filter).window(offset..offset + count))
+                .await?
+        }
+    };
+    let annotations = get_songs_annotations(&conn, &songs).await?;
+
+    Ok(Paged::new(
+        SongsByGenre {
+            songs: songs
+                .into_iter()
+                .map(|s| mpd_song_to_subsonic(s, &annotations, state.song_options()))
+                .collect(),
+        },
+        total,
+    ))
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "songsByGenre")]
+struct SongsByGenre {
+    #[yaserde(child, rename = "song")]
+    #[serde(rename = "song")]
+    songs: Vec<Song>,
+}
+
+impl super::Reply for SongsByGenre {
+    fn field_name() -> Option<&'static str> {
+        Some("songsByGenre")
+    }
+}
+
+async fn get_starred(Extension(state): Extension<Arc<super::State>>) -> super::Result<Starred> {
+    Ok(Starred {
+        artists: Vec::new(),
+        albums: Vec::new(),
+        songs: get_starred_songs(&*state.pool.get().await?, state.song_options()).await?,
+    })
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "starred")]
+struct Starred {
+    #[yaserde(child, rename = "artist")]
+    #[serde(rename = "artist")]
+    artists: Vec<DirectoryArtist>,
+    #[yaserde(child, rename = "album")]
+    #[serde(rename = "album")]
+    albums: Vec<DirectoryAlbum>,
+    #[yaserde(child, rename = "song")]
+    #[serde(rename = "song")]
+    songs: Vec<Song>,
+}
+
+impl super::Reply for Starred {
+    fn field_name() -> Option<&'static str> {
+        Some("starred")
+    }
+}
+
+async fn get_starred2(Extension(state): Extension<Arc<super::State>>) -> super::Result<Starred2> {
+    Ok(Starred2 {
+        artists: Vec::new(),
+        albums: Vec::new(),
+        songs: get_starred_songs(&*state.pool.get().await?, state.song_options()).await?,
+    })
+}
+
+// get_starred_songs returns all starred songs, most recently starred first
+async fn get_starred_songs(conn: &Connection, options: SongOptions) -> super::Result<Vec<Song>> {
+    let starred = conn
+        .command(StickerFind::new(ROOT_FOLDER, STICKER_STARRED))
+        .await?
+        .value;
+    let songs = get_songs_by_path(conn, &starred_order(&starred)).await?;
+    let annotations = get_songs_annotations(conn, &songs).await?;
+
+    Ok(songs
+        .into_iter()
+        .map(|s| mpd_song_to_subsonic(s, &annotations, options))
+        .collect())
+}
+
+// starred_order returns paths of the starred songs sorted by the time they were starred at,
+// most recent first. Songs with invalid timestamps go last.
+fn starred_order(starred: &HashMap<String, String>) -> Vec<&str> {
+    let mut songs = starred
+        .iter()
+        .map(|(path, at)| {
+            (
+                path.as_str(),
+                OffsetDateTime::parse(at, &well_known::Rfc3339).ok(),
+            )
+        })
+        .collect::<Vec<_>>();
+    songs.sort_by(|(p1, at1), (p2, at2)| at2.cmp(at1).then_with(|| p1.cmp(p2)));
+
+    songs.into_iter().map(|(path, _)| path).collect()
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "starred2")]
+struct Starred2 {
+    #[yaserde(child, rename = "artist")]
+    #[serde(rename = "artist")]
+    artists: Vec<Artist>,
+    #[yaserde(child, rename = "album")]
+    #[serde(rename = "album")]
+    albums: Vec<Album>,
+    #[yaserde(child, rename = "song")]
+    #[serde(rename = "song")]
+    songs: Vec<Song>,
+}
+
+impl super::Reply for Starred2 {
+    fn field_name() -> Option<&'static str> {
+        Some("starred2")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_album_list2, get_random_songs, get_songs_by_genre, starred_order, year_in_range,
+        GetAlbumListQuery, GetRandomSongsQuery, GetSongsByGenreQuery, NowPlaying, NowPlayingEntry,
+        RandomSongs, SongsByGenre, Starred, Starred2,
+    };
+    use crate::{
+        api::{
+            error::Error,
+            expect_ok_json, expect_ok_xml,
+            glue::X_TOTAL_COUNT,
+            json, test_state, test_state_with_mpd,
+            types::{AlbumID, ArtistID, CoverArtID, Song, SongID},
+            xml,
+        },
+        mpd::testing::fake_server,
+    };
+    use axum::extract::{Extension, Query};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn now_playing() {
+        let now_playing = NowPlaying {
+            entries: vec![NowPlayingEntry::new(
+                Song {
+                    id: SongID::new("song1"),
+                    title: Some("song1".to_string()),
+                    album: Some("beta".to_string()),
+                    artist: "alpha".to_string(),
+                    cover_art: CoverArtID::new("artwork"),
+                    content_type: Some("audio/flac".to_string()),
+                    suffix: Some("flac".to_string()),
+                    duration: Some(180),
+                    path: Some("path1".to_string()),
+                    album_id: Some(AlbumID::new("beta", "alpha")),
+                    artist_id: ArtistID::new("alpha"),
+                    play_count: Some(3),
+                    ..Default::default()
+                },
+                "user".to_string(),
+            )],
+        };
+        assert_eq!(
+            xml(&now_playing),
+            expect_ok_xml(Some(
+                r#"<nowPlaying>
+    <entry username="user" minutesAgo="0" playerId="0" id="eyJwYXRoIjoic29uZzEifQ==" title="song1" album="beta" artist="alpha" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" contentType="audio/flac" suffix="flac" duration="180" path="path1" albumId="eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" playCount="3" />
+  </nowPlaying>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&now_playing),
+            expect_ok_json(Some(json!({"nowPlaying": {
+                "entry": [
+                    {
+                        "username": "user",
+                        "minutesAgo": 0,
+                        "playerId": 0,
+                        "id": "eyJwYXRoIjoic29uZzEifQ==",
+                        "title": "song1",
+                        "album": "beta",
+                        "artist": "alpha",
+                        "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+                        "contentType": "audio/flac",
+                        "suffix": "flac",
+                        "duration": 180,
+                        "path": "path1",
+                        "albumId": "eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=",
+                        "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                        "playCount": 3,
+                    },
+                ]
+            }
+            })),),
+        );
+
+        let nothing = NowPlaying {
+            entries: Vec::new(),
+        };
+        assert_eq!(xml(&nothing), expect_ok_xml(Some("<nowPlaying />")));
+        assert_eq!(
+            json(&nothing),
+            expect_ok_json(Some(json!({"nowPlaying": {"entry": []}})))
+        );
+    }
+
+    #[test]
+    fn random_songs() {
+        let random_songs = RandomSongs {
+            songs: vec![Song {
+                id: SongID::new("song1"),
+                title: Some("song1".to_string()),
+                album: Some("beta".to_string()),
+                artist: "alpha".to_string(),
+                year: Some(2020),
+                cover_art: CoverArtID::new("artwork"),
+                path: Some("path1".to_string()),
+                album_id: Some(AlbumID::new("alpha", "beta")),
+                artist_id: ArtistID::new("alpha"),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(
+            xml(&random_songs),
+            expect_ok_xml(Some(
+                r#"<randomSongs>
+    <song id="eyJwYXRoIjoic29uZzEifQ==" title="song1" album="beta" artist="alpha" year="2020" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" path="path1" albumId="eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" />
+  </randomSongs>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&random_songs),
+            expect_ok_json(Some(json!({"randomSongs": {
+                "song": [
+                    {
+                        "id": "eyJwYXRoIjoic29uZzEifQ==",
+                        "title": "song1",
+                        "album": "beta",
+                        "artist": "alpha",
+                        "year": 2020,
+                        "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+                        "path": "path1",
+                        "albumId": "eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=",
+                        "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                    },
+                ]
+            }
+            })),),
+        );
+    }
+
+    #[test]
+    fn year_range() {
+        assert!(year_in_range(None, None, None));
+        assert!(year_in_range(Some(2000), None, None));
+        assert!(!year_in_range(None, Some(1990), None));
+        assert!(year_in_range(Some(2000), Some(1990), Some(2000)));
+        assert!(!year_in_range(Some(2001), Some(1990), Some(2000)));
+        assert!(!year_in_range(Some(1989), Some(1990), None));
+        assert!(year_in_range(Some(1989), None, Some(1990)));
+    }
+
+    #[test]
+    fn starred() {
+        let starred = Starred {
+            artists: Vec::new(),
+            albums: Vec::new(),
+            songs: vec![Song {
+                id: SongID::new("song1"),
+                title: Some("song1".to_string()),
+                album: Some("beta".to_string()),
+                artist: "alpha".to_string(),
+                cover_art: CoverArtID::new("artwork"),
+                path: Some("path1".to_string()),
+                album_id: Some(AlbumID::new("beta", "alpha")),
+                artist_id: ArtistID::new("alpha"),
+                starred: Some("2023-01-02T03:04:05Z".to_string()),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(
+            xml(&starred),
+            expect_ok_xml(Some(
+                r#"<starred>
+    <song id="eyJwYXRoIjoic29uZzEifQ==" title="song1" album="beta" artist="alpha" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" path="path1" albumId="eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" starred="2023-01-02T03:04:05Z" />
+  </starred>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&starred),
+            expect_ok_json(Some(json!({"starred": {
+                "artist": [],
+                "album": [],
+                "song": [
+                    {
+                        "id": "eyJwYXRoIjoic29uZzEifQ==",
+                        "title": "song1",
+                        "album": "beta",
+                        "artist": "alpha",
+                        "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+                        "path": "path1",
+                        "albumId": "eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=",
+                        "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                        "starred": "2023-01-02T03:04:05Z",
+                    },
+                ]
+            }
+            })),),
+        );
+    }
+
+    #[test]
+    fn starred2() {
+        let starred2 = Starred2 {
+            artists: Vec::new(),
+            albums: Vec::new(),
+            songs: vec![Song {
+                id: SongID::new("song1"),
+                title: Some("song1".to_string()),
+                album: Some("beta".to_string()),
+                artist: "alpha".to_string(),
+                cover_art: CoverArtID::new("artwork"),
+                path: Some("path1".to_string()),
+                album_id: Some(AlbumID::new("beta", "alpha")),
+                artist_id: ArtistID::new("alpha"),
+                starred: Some("2023-01-02T03:04:05Z".to_string()),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(
+            xml(&starred2),
+            expect_ok_xml(Some(
+                r#"<starred2>
+    <song id="eyJwYXRoIjoic29uZzEifQ==" title="song1" album="beta" artist="alpha" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" path="path1" albumId="eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" starred="2023-01-02T03:04:05Z" />
+  </starred2>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&starred2),
+            expect_ok_json(Some(json!({"starred2": {
+                "artist": [],
+                "album": [],
+                "song": [
+                    {
+                        "id": "eyJwYXRoIjoic29uZzEifQ==",
+                        "title": "song1",
+                        "album": "beta",
+                        "artist": "alpha",
+                        "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+                        "path": "path1",
+                        "albumId": "eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=",
+                        "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                        "starred": "2023-01-02T03:04:05Z",
+                    },
+                ]
+            }
+            })),),
+        );
+    }
+
+    #[test]
+    fn starred_songs_order() {
+        let starred = HashMap::from([
+            ("a".to_string(), "2023-01-02T03:04:05Z".to_string()),
+            ("b".to_string(), "2023-01-02T03:04:05.5Z".to_string()),
+            ("c".to_string(), "invalid".to_string()),
+            ("d".to_string(), "2022-12-31T23:59:59+02:00".to_string()),
+            ("e".to_string(), "2024-01-01T00:00:00Z".to_string()),
+        ]);
+        assert_eq!(starred_order(&starred), vec!["e", "b", "a", "d", "c"]);
+    }
+
+    #[tokio::test]
+    async fn random_songs_music_folder() {
+        let query = |id: &str| GetRandomSongsQuery {
+            size: Some(0),
+            genre: None,
+            from_year: None,
+            to_year: None,
+            music_folder_id: Some(id.to_string()),
+            exclude_queued: None,
+        };
+
+        let err = get_random_songs(Extension(test_state().await), Query(query("/unknown")))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            xml(&err),
+            xml(&Error::generic_error(Some("unknown music folder")))
+        );
+    }
+
+    #[tokio::test]
+    async fn songs_by_genre_music_folder() {
+        let query = |id: Option<&str>| GetSongsByGenreQuery {
+            genre: "rock".to_string(),
+            count: Some(0),
+            offset: None,
+            music_folder_id: id.map(str::to_string),
+        };
+
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("count") => "songs: 0\nplaytime: 0\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let err = get_songs_by_genre(Extension(state.clone()), Query(query(Some("/unknown"))))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            xml(&err),
+            xml(&Error::generic_error(Some("unknown music folder")))
+        );
+
+        for id in [None, Some("/")] {
+            let songs = get_songs_by_genre(Extension(state.clone()), Query(query(id))).await;
+            assert!(matches!(songs, Ok(songs) if songs.reply.songs.is_empty()));
+        }
+    }
+
+    #[test]
+    fn songs_by_genre() {
+        let songs_by_genre = SongsByGenre {
+            songs: vec![Song {
+                id: SongID::new("song1"),
+                title: Some("song1".to_string()),
+                album: Some("beta".to_string()),
+                artist: "alpha".to_string(),
+                genre: Some("rock".to_string()),
+                cover_art: CoverArtID::new("artwork"),
+                path: Some("path1".to_string()),
+                album_id: Some(AlbumID::new("beta", "alpha")),
+                artist_id: ArtistID::new("alpha"),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(
+            xml(&songs_by_genre),
+            expect_ok_xml(Some(
+                r#"<songsByGenre>
+    <song id="eyJwYXRoIjoic29uZzEifQ==" title="song1" album="beta" artist="alpha" genre="rock" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" path="path1" albumId="eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" />
+  </songsByGenre>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&songs_by_genre),
+            expect_ok_json(Some(json!({"songsByGenre": {
+                "song": [
+                    {
+                        "id": "eyJwYXRoIjoic29uZzEifQ==",
+                        "title": "song1",
+                        "album": "beta",
+                        "artist": "alpha",
+                        "genre": "rock",
+                        "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+                        "path": "path1",
+                        "albumId": "eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=",
+                        "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                    },
+                ]
+            }
+            })),),
+        );
+    }
+
+    #[tokio::test]
+    async fn random_songs_exclude_queued() {
+        let mpd = fake_server(|command| {
+            if command.starts_with("find") {
+                "file: alpha/1.flac\nfile: alpha/2.flac\nfile: alpha/3.flac\n".to_string()
+            } else if command.starts_with("playlistinfo") {
+                "file: alpha/2.flac\nPos: 0\nId: 1\n".to_string()
+            } else {
+                String::new()
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let query = |exclude_queued| GetRandomSongsQuery {
+            size: None,
+            genre: None,
+            from_year: None,
+            to_year: None,
+            music_folder_id: None,
+            exclude_queued,
+        };
+        let paths = |songs: RandomSongs| {
+            let mut paths = songs
+                .songs
+                .into_iter()
+                .map(|s| s.id.path)
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+
+        let Ok(songs) = get_random_songs(Extension(state.clone()), Query(query(None))).await else {
+            panic!("getRandomSongs failed");
+        };
+        assert_eq!(
+            paths(songs),
+            ["alpha/1.flac", "alpha/2.flac", "alpha/3.flac"]
+        );
+
+        let Ok(songs) = get_random_songs(Extension(state), Query(query(Some(true)))).await else {
+            panic!("getRandomSongs failed");
+        };
+        assert_eq!(paths(songs), ["alpha/1.flac", "alpha/3.flac"]);
+    }
+
+    #[tokio::test]
+    async fn album_list_by_genre() {
+        // The album has songs of two genres
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("list") if command.contains("Rock") || command.contains("Metal") => {
+                "AlbumArtist: alpha\nAlbum: beta\n".to_string()
+            }
+            Some("count") => "songs: 2\nplaytime: 600\n".to_string(),
+            Some("find") => "file: alpha/beta/1.flac\nGenre: Rock\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let query = |genre: &str| GetAlbumListQuery {
+            list_type: "byGenre".to_string(),
+            size: None,
+            offset: None,
+            from_year: None,
+            to_year: None,
+            year: None,
+            genre: Some(genre.to_string()),
+            music_folder_id: None,
+        };
+
+        for genre in ["Rock", "Metal"] {
+            let Ok(list) = get_album_list2(Extension(state.clone()), Query(query(genre))).await
+            else {
+                panic!("getAlbumList2 failed");
+            };
+            assert_eq!(list.totals, [(X_TOTAL_COUNT, 1)]);
+            let list = list.reply;
+            assert_eq!(list.albums.len(), 1);
+            assert_eq!(list.albums[0].name, "beta");
+            assert_eq!(list.albums[0].artist, "alpha");
+            assert_eq!(list.albums[0].song_count, 2);
+        }
+
+        let Ok(list) = get_album_list2(Extension(state), Query(query("Jazz"))).await else {
+            panic!("getAlbumList2 failed");
+        };
+        assert_eq!(list.totals, [(X_TOTAL_COUNT, 0)]);
+        assert!(list.reply.albums.is_empty());
+    }
+
+    #[tokio::test]
+    async fn album_list_by_year() {
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("list") => concat!(
+                "OriginalDate: 1998\nAlbumArtist: alpha\nAlbum: beta\n",
+                "OriginalDate: 1999-05-01\nAlbumArtist: alpha\nAlbum: gamma\n",
+                "OriginalDate: 2000\nAlbumArtist: delta\nAlbum: epsilon\n",
+                "OriginalDate: \nDate: 1999-02\nAlbumArtist: zeta\nAlbum: eta\n",
+            )
+            .to_string(),
+            Some("count") => "songs: 1\nplaytime: 300\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let query = "type=byYear&year=1999";
+        let Ok(list) = get_album_list2(
+            Extension(state.clone()),
+            Query(serde_urlencoded::from_str(query).unwrap()),
+        )
+        .await
+        else {
+            panic!("getAlbumList2 failed");
+        };
+        assert_eq!(
+            list.reply
+                .albums
+                .iter()
+                .map(|a| a.name.as_str())
+                .collect::<Vec<_>>(),
+            ["eta", "gamma"]
+        );
+
+        // Explicit range takes precedence
+        let query = "type=byYear&year=1999&fromYear=2000&toYear=1998";
+        let Ok(list) = get_album_list2(
+            Extension(state.clone()),
+            Query(serde_urlencoded::from_str(query).unwrap()),
+        )
+        .await
+        else {
+            panic!("getAlbumList2 failed");
+        };
+        assert_eq!(
+            list.reply
+                .albums
+                .iter()
+                .map(|a| a.name.as_str())
+                .collect::<Vec<_>>(),
+            ["epsilon", "gamma", "eta", "beta"]
+        );
+
+        let query = "type=byYear&fromYear=1999";
+        assert!(get_album_list2(
+            Extension(state),
+            Query(serde_urlencoded::from_str(query).unwrap())
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn album_list_total() {
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("list") => {
+                "AlbumArtist: alpha\nAlbum: a\nAlbum: b\nAlbum: c\nAlbum: d\nAlbum: e\n".to_string()
+            }
+            Some("count") => "songs: 1\nplaytime: 300\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let query = |offset| GetAlbumListQuery {
+            list_type: "alphabeticalByName".to_string(),
+            size: Some(2),
+            offset: Some(offset),
+            from_year: None,
+            to_year: None,
+            year: None,
+            genre: None,
+            music_folder_id: None,
+        };
+
+        for (offset, len) in [(0, 2), (4, 1), (10, 0)] {
+            let Ok(list) = get_album_list2(Extension(state.clone()), Query(query(offset))).await
+            else {
+                panic!("getAlbumList2 failed");
+            };
+            assert_eq!(list.reply.albums.len(), len);
+            assert_eq!(list.totals, [(X_TOTAL_COUNT, 5)]);
+        }
+    }
+
+    #[tokio::test]
+    async fn songs_by_genre_total() {
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("count") => "songs: 42\nplaytime: 12600\n".to_string(),
+            Some("find") => "file: alpha/1.flac\nfile: alpha/2.flac\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let query = GetSongsByGenreQuery {
+            genre: "rock".to_string(),
+            count: Some(2),
+            offset: Some(10),
+            music_folder_id: None,
+        };
+
+        let Ok(songs) = get_songs_by_genre(Extension(state), Query(query)).await else {
+            panic!("getSongsByGenre failed");
+        };
+        assert_eq!(songs.reply.songs.len(), 2);
+        assert_eq!(songs.totals, [(X_TOTAL_COUNT, 42)]);
+    }
+}
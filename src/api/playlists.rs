@@ -1,20 +1,44 @@
 use super::{
-    common::{get_songs_ratings_starred, mpd_song_to_subsonic},
+    common::{all_songs, get_song_year, get_songs_annotations, is_stream, mpd_song_to_subsonic},
     glue::RawQuery,
     types::{PlaylistID, Song, SongID},
 };
-use crate::api::error::Error;
+use crate::{
+    api::error::Error,
+    mpd::{Connection, PlaylistStickerDelete, PlaylistStickerFind, PlaylistStickerSet},
+};
 use axum::{
     extract::{Extension, Query},
     routing::Router,
 };
-use mpd_client::commands::{
-    self, AddToPlaylist, DeletePlaylist, RemoveFromPlaylist, RenamePlaylist, SaveQueueAsPlaylist,
+use mpd_client::{
+    commands::{
+        self, AddToPlaylist, ClearPlaylist, DeletePlaylist, Find, Queue, RemoveFromPlaylist,
+        RenamePlaylist,
+    },
+    filter::Filter,
+    responses,
+    tag::Tag,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use time::{format_description::well_known, OffsetDateTime};
+use tracing::warn;
 use yaserde_derive::YaSerialize;
 
+// MPD play queue is exposed as a read-only playlist with a special ID. Names of MPD playlists
+// can't contain '/', so the ID never clashes with a stored playlist.
+const QUEUE_PLAYLIST_ID: &str = "/queue";
+const QUEUE_PLAYLIST_NAME: &str = "Play Queue";
+
+// Playlists mpdsonic stores for its own use (e.g. saved play queues) are prefixed with this and
+// are not listed as user playlists
+pub(crate) const HIDDEN_PLAYLIST_PREFIX: &str = "__mpdsonic_";
+
+// MPD playlists have no comment or visibility, these are kept in stickers of the playlist
+const STICKER_COMMENT: &str = "comment";
+const STICKER_PUBLIC: &str = "public";
+
 pub(crate) fn get_router() -> Router {
     Router::new()
         .route("/getPlaylists.view", super::handler(get_playlists))
@@ -41,16 +65,20 @@ async fn get_playlists(
         )));
     }
 
-    let playlists = state
+    let (playlists, queue) = state
         .pool
         .get()
         .await?
-        .command(commands::GetPlaylists)
+        .command_list((commands::GetPlaylists, Queue))
         .await?;
-    let playlists_songs = state
-        .pool
-        .get()
-        .await?
+    let playlists = playlists
+        .into_iter()
+        .filter(|p| !p.name.starts_with(HIDDEN_PLAYLIST_PREFIX))
+        .collect::<Vec<_>>();
+    let queue = queue.into_iter().map(|s| s.song).collect::<Vec<_>>();
+    let conn = state.pool.get().await?;
+    let metadata = playlists_metadata(&conn).await;
+    let playlists_songs = conn
         .command_list(
             playlists
                 .iter()
@@ -58,27 +86,165 @@ async fn get_playlists(
                 .collect::<Vec<_>>(),
         )
         .await?;
+    let mut totals = playlists_totals(
+        &conn,
+        &std::iter::once(&queue)
+            .chain(&playlists_songs)
+            .map(Vec::as_slice)
+            .collect::<Vec<_>>(),
+    )
+    .await?
+    .into_iter();
+    let (queue_count, queue_duration) = totals.next().unwrap_or_default();
+
+    // The queue has no modification time, so it always looks freshly changed
+    let now = OffsetDateTime::now_utc()
+        .format(&well_known::Rfc3339)
+        .map_err(|_| super::Error::generic_error(None))?;
 
     Ok(GetPlaylists {
-        playlists: playlists
-            .iter()
-            .zip(playlists_songs)
-            .map(|(p, songs)| Playlist {
-                id: PlaylistID::new(&p.name),
-                name: p.name.clone(),
-                owner: params.u.clone(),
-                public: true,
-                song_count: songs.len(),
-                duration: songs
-                    .iter()
-                    .map(|s| s.duration.map(|v| v.as_secs()).unwrap_or(0))
-                    .sum(),
-                changed: p.last_modified.raw().to_owned(),
-            })
-            .collect(),
+        playlists: std::iter::once(Playlist {
+            id: PlaylistID::new(QUEUE_PLAYLIST_ID),
+            name: QUEUE_PLAYLIST_NAME.to_string(),
+            comment: None,
+            owner: params.u.clone(),
+            public: false,
+            song_count: queue_count,
+            duration: queue_duration,
+            changed: now,
+        })
+        .chain(
+            playlists
+                .iter()
+                .zip(totals)
+                .map(|(p, (song_count, duration))| Playlist {
+                    id: PlaylistID::new(&p.name),
+                    name: p.name.clone(),
+                    comment: metadata.comment(&p.name),
+                    owner: params.u.clone(),
+                    public: metadata.public(&p.name),
+                    song_count,
+                    duration,
+                    changed: p.last_modified.raw().to_owned(),
+                }),
+        )
+        .collect(),
     })
 }
 
+// PlaylistsMetadata is what Subsonic knows about playlists, but MPD doesn't
+#[derive(Default)]
+struct PlaylistsMetadata {
+    comments: HashMap<String, String>,
+    public: HashMap<String, String>,
+}
+
+impl PlaylistsMetadata {
+    fn comment(&self, playlist: &str) -> Option<String> {
+        self.comments.get(playlist).cloned()
+    }
+
+    // Playlists are public unless made private
+    fn public(&self, playlist: &str) -> bool {
+        self.public.get(playlist).map_or(true, |p| p != "false")
+    }
+}
+
+// playlists_metadata returns metadata of all the stored playlists. Playlist stickers need MPD
+// 0.24 or later, with older versions all the playlists look like they have no metadata.
+async fn playlists_metadata(conn: &Connection) -> PlaylistsMetadata {
+    match conn
+        .command_list((
+            PlaylistStickerFind::new(STICKER_COMMENT),
+            PlaylistStickerFind::new(STICKER_PUBLIC),
+        ))
+        .await
+    {
+        Ok((comments, public)) => PlaylistsMetadata { comments, public },
+        Err(err) => {
+            warn!(action = "playlists metadata", err = ?err);
+            PlaylistsMetadata::default()
+        }
+    }
+}
+
+// set_playlist_sticker sets the sticker of the playlist, an empty value deletes it. Deleting
+// a sticker which is not set is not an error.
+async fn set_playlist_sticker(
+    conn: &Connection,
+    playlist: &str,
+    name: &str,
+    value: &str,
+) -> super::Result<()> {
+    let result = if value.is_empty() {
+        conn.command(PlaylistStickerDelete::new(playlist, name))
+            .await
+    } else {
+        conn.command(PlaylistStickerSet::new(playlist, name, value))
+            .await
+    };
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) if value.is_empty() && err.is_no_exist() => Ok(()),
+        Err(_) if !supports_playlist_stickers(conn.protocol_version()) => Err(
+            Error::generic_error(Some("playlist metadata requires MPD 0.24")),
+        ),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// supports_playlist_stickers checks if MPD of the given protocol version supports stickers on
+// playlists, which appeared in 0.24
+fn supports_playlist_stickers(version: &str) -> bool {
+    let mut parts = version.split('.').map(|p| p.parse::<u32>().unwrap_or(0));
+    (parts.next().unwrap_or(0), parts.next().unwrap_or(0)) >= (0, 24)
+}
+
+// playlists_totals returns the number of entries in each of the playlists and their total
+// duration in seconds. Streams are counted like songs (getPlaylist returns them too), but don't
+// add to the duration. Songs listed without duration are looked up in the database, all at once.
+async fn playlists_totals(
+    conn: &Connection,
+    playlists: &[&[responses::Song]],
+) -> super::Result<Vec<(usize, u64)>> {
+    let mut missing = playlists
+        .iter()
+        .flat_map(|songs| songs.iter())
+        .filter(|s| s.duration.is_none() && !is_stream(s))
+        .map(|s| s.url.as_str())
+        .collect::<Vec<_>>();
+    missing.sort_unstable();
+    missing.dedup();
+
+    let durations = if missing.is_empty() {
+        HashMap::new()
+    } else {
+        conn.command_list(
+            missing
+                .iter()
+                .map(|&path| Find::new(Filter::tag(Tag::Other("file".into()), path)).window(0..1))
+                .collect::<Vec<_>>(),
+        )
+        .await?
+        .into_iter()
+        .flatten()
+        .filter_map(|s| Some((s.url, s.duration?)))
+        .collect::<HashMap<_, _>>()
+    };
+
+    Ok(playlists
+        .iter()
+        .map(|songs| {
+            let duration = songs
+                .iter()
+                .filter(|s| !is_stream(s))
+                .filter_map(|s| s.duration.or_else(|| durations.get(&s.url).copied()))
+                .sum::<Duration>();
+            (songs.len(), duration.as_secs_f64().round() as u64)
+        })
+        .collect())
+}
+
 #[derive(Serialize, YaSerialize)]
 #[yaserde(rename = "playlists")]
 struct GetPlaylists {
@@ -101,6 +267,9 @@ struct Playlist {
     #[yaserde(attribute)]
     name: String,
     #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[yaserde(attribute)]
     owner: String,
     #[yaserde(attribute)]
     public: bool,
@@ -125,31 +294,53 @@ async fn get_playlist(
 ) -> super::Result<GetPlaylist> {
     let conn = state.pool.get().await?;
 
-    let (playlists, songs) = conn
-        .command_list((
-            commands::GetPlaylists,
-            commands::GetPlaylist(&params.playlist.name),
-        ))
-        .await?;
-    let (ratings, starred) = get_songs_ratings_starred(&conn, &songs).await?;
+    let (name, comment, public, changed, songs) = match params.playlist.name.as_str() {
+        QUEUE_PLAYLIST_ID => {
+            let queue = conn.command(Queue).await?;
+            (
+                QUEUE_PLAYLIST_NAME.to_string(),
+                None,
+                false,
+                None,
+                queue.into_iter().map(|s| s.song).collect::<Vec<_>>(),
+            )
+        }
+        name => {
+            let (playlists, songs) = conn
+                .command_list((commands::GetPlaylists, commands::GetPlaylist(name)))
+                .await?;
+            let changed = playlists
+                .iter()
+                .find(|&p| p.name == name)
+                .map(|p| p.last_modified.raw().to_owned());
+            let metadata = playlists_metadata(&conn).await;
+            (
+                name.to_string(),
+                metadata.comment(name),
+                metadata.public(name),
+                changed,
+                songs,
+            )
+        }
+    };
+    let annotations = get_songs_annotations(&conn, &songs).await?;
+    let (song_count, duration) = playlists_totals(&conn, &[&songs])
+        .await?
+        .pop()
+        .unwrap_or_default();
 
     Ok(GetPlaylist {
         id: params.playlist.clone(),
-        name: params.playlist.name.clone(),
+        name,
+        comment,
         owner: params.u.clone(),
-        public: true,
-        song_count: songs.len(),
-        duration: songs
-            .iter()
-            .map(|s| s.duration.map(|v| v.as_secs()).unwrap_or(0))
-            .sum(),
-        changed: playlists
-            .iter()
-            .find(|&p| p.name == params.playlist.name)
-            .map(|p| p.last_modified.raw().to_owned()),
+        public,
+        song_count,
+        duration,
+        changed,
         songs: songs
             .into_iter()
-            .map(|s| mpd_song_to_subsonic(s, &ratings, &starred))
+            .map(|s| mpd_song_to_subsonic(s, &annotations, state.song_options()))
             .collect(),
     })
 }
@@ -163,6 +354,9 @@ struct GetPlaylist {
     #[yaserde(attribute)]
     name: String,
     #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[yaserde(attribute)]
     owner: String,
     #[yaserde(attribute)]
     public: bool,
@@ -187,8 +381,13 @@ impl super::Reply for GetPlaylist {
 #[derive(Clone, Deserialize, Debug)]
 struct CreatePlaylistQuery {
     u: String,
-    #[serde(rename = "name")]
-    playlist: String,
+    name: Option<String>,
+    // Existing playlist to replace songs of
+    #[serde(rename = "playlistId")]
+    playlist: Option<PlaylistID>,
+    genre: Option<String>,
+    artist: Option<String>,
+    year: Option<i32>,
 }
 
 async fn create_playlist(
@@ -196,49 +395,142 @@ async fn create_playlist(
     Query(params): Query<CreatePlaylistQuery>,
     RawQuery(query): RawQuery,
 ) -> super::Result<GetPlaylist> {
-    let songs = url::form_urlencoded::parse(
+    state.ensure_writable()?;
+    let (playlist, replace) = match (&params.playlist, &params.name) {
+        (Some(playlist), _) => {
+            check_stored_playlist(playlist)?;
+            (playlist.name.clone(), true)
+        }
+        (None, Some(name)) => {
+            check_playlist_name(name)?;
+            (name.clone(), false)
+        }
+        (None, None) => return Err(Error::missing_parameter("either name or playlistId")),
+    };
+
+    let mut songs = url::form_urlencoded::parse(
         &query
             .ok_or_else(|| Error::missing_parameter("failed to parse URL query"))?
             .into_bytes(),
     )
     .filter_map(|(k, v)| match k.as_ref() {
-        "songId" => SongID::try_from(v.as_ref()).ok(),
+        "songId" => SongID::try_from(v.as_ref()).ok().map(|s| s.path),
         _ => None,
     })
     .collect::<Vec<_>>();
-    if songs.is_empty() {
-        return Err(Error::missing_parameter("songId is missing"));
-    }
 
     let conn = state.pool.get().await?;
 
-    conn.command(SaveQueueAsPlaylist(&params.playlist)).await?;
-    conn.command(RemoveFromPlaylist::range(&params.playlist, ..))
+    // A replaced playlist may be emptied, a new one needs some songs to start with
+    let filtered = params.genre.is_some() || params.artist.is_some() || params.year.is_some();
+    if songs.is_empty() && (!replace || filtered) {
+        songs = find_playlist_songs(&conn, &params).await?;
+    }
+
+    let exists = conn
+        .command(commands::GetPlaylists)
+        .await?
+        .iter()
+        .any(|p| p.name == playlist);
+    match (replace, exists) {
+        (true, false) => return Err(Error::not_found()),
+        (false, true) => {
+            return Err(Error::generic_error(Some(&format!(
+                "playlist {playlist:?} already exists"
+            ))))
+        }
+        _ => (),
+    }
+
+    // Clearing a playlist that doesn't exist creates an empty one. Unlike saving the play queue
+    // and clearing the result, this doesn't depend on what is being played.
+    conn.command(ClearPlaylist(&playlist)).await?;
+    if !songs.is_empty() {
+        conn.command_list(
+            songs
+                .iter()
+                .map(|s| AddToPlaylist::new(&playlist, s))
+                .collect::<Vec<_>>(),
+        )
         .await?;
-    conn.command_list(
-        songs
-            .iter()
-            .map(|s| AddToPlaylist::new(&params.playlist, &s.path))
-            .collect::<Vec<_>>(),
-    )
-    .await?;
+    }
     drop(conn);
 
     get_playlist(
         Extension(state),
         Query(GetPlaylistQuery {
             u: params.u,
-            playlist: PlaylistID::new(&params.playlist),
+            playlist: PlaylistID::new(&playlist),
         }),
     )
     .await
 }
 
+// check_playlist_name fails if MPD can't store a playlist with the given name. Playlists are
+// stored as files in MPD's playlist directory, so names can't be empty, contain path separators
+// or line breaks.
+fn check_playlist_name(name: &str) -> super::Result<()> {
+    if name.is_empty() || name.contains(['/', '\n', '\r']) {
+        return Err(Error::generic_error(Some(&format!(
+            "invalid playlist name {name:?}: must not be empty or contain '/' or line breaks"
+        ))));
+    }
+    Ok(())
+}
+
+// check_stored_playlist fails if the playlist is the read-only play queue
+fn check_stored_playlist(playlist: &PlaylistID) -> super::Result<()> {
+    match playlist.name.as_str() {
+        QUEUE_PLAYLIST_ID => Err(Error::not_authorized(
+            "The play queue can't be modified through playlists.",
+        )),
+        _ => Ok(()),
+    }
+}
+
+// find_playlist_songs returns paths of songs matching genre, artist and year filters of the
+// createPlaylist request
+async fn find_playlist_songs(
+    conn: &Connection,
+    params: &CreatePlaylistQuery,
+) -> super::Result<Vec<String>> {
+    let filter = [
+        params.genre.as_ref().map(|g| Filter::tag(Tag::Genre, g)),
+        params
+            .artist
+            .as_ref()
+            .map(|a| Filter::tag(Tag::AlbumArtist, a)),
+    ]
+    .into_iter()
+    .flatten()
+    .reduce(Filter::and);
+    if filter.is_none() && params.year.is_none() {
+        return Err(Error::missing_parameter(
+            "either songId, genre, artist or year",
+        ));
+    }
+
+    let songs = conn
+        .command(Find::new(filter.unwrap_or_else(all_songs)))
+        .await?
+        .into_iter()
+        .filter(|s| params.year.is_none() || get_song_year(s) == params.year)
+        .map(|s| s.url)
+        .collect::<Vec<_>>();
+    if songs.is_empty() {
+        return Err(Error::not_found());
+    }
+
+    Ok(songs)
+}
+
 #[derive(Clone, Deserialize, Debug)]
 struct UpdatePlaylistQuery {
     #[serde(rename = "playlistId")]
     playlist: PlaylistID,
     name: Option<String>,
+    comment: Option<String>,
+    public: Option<bool>,
 }
 
 async fn update_playlist(
@@ -246,6 +538,12 @@ async fn update_playlist(
     Query(params): Query<UpdatePlaylistQuery>,
     RawQuery(query): RawQuery,
 ) -> super::Result<()> {
+    state.ensure_writable()?;
+    check_stored_playlist(&params.playlist)?;
+    if let Some(name) = &params.name {
+        check_playlist_name(name)?;
+    }
+
     let query = query
         .ok_or_else(|| Error::missing_parameter("failed to parse URL query"))?
         .into_bytes();
@@ -256,16 +554,20 @@ async fn update_playlist(
             _ => None,
         })
         .collect::<Vec<_>>();
-    let mut to_remove = query
-        .filter_map(|(k, v)| match k.as_ref() {
-            "songIndexToRemove" => v.parse::<usize>().ok(),
-            _ => None,
-        })
-        .collect::<Vec<_>>();
-    // reverse sort to make sure that song indicies are always valid during removal
-    to_remove.sort_by(|a, b| b.cmp(a));
+    let to_remove = removal_order(query.filter_map(|(k, v)| match k.as_ref() {
+        "songIndexToRemove" => v.parse::<usize>().ok(),
+        _ => None,
+    }));
 
     let conn = state.pool.get().await?;
+    // Fail before touching the playlist if its metadata can't be stored
+    if (params.comment.is_some() || params.public.is_some())
+        && !supports_playlist_stickers(conn.protocol_version())
+    {
+        return Err(Error::generic_error(Some(
+            "playlist metadata requires MPD 0.24",
+        )));
+    }
     if !to_remove.is_empty() {
         conn.command_list(
             to_remove
@@ -284,14 +586,49 @@ async fn update_playlist(
         )
         .await?;
     }
-    if let Some(name) = params.name {
-        conn.command(RenamePlaylist::new(&params.playlist.name, &name))
-            .await?;
+
+    let mut comment = params.comment;
+    let mut public = params.public.map(|p| p.to_string());
+    let playlist = match params.name {
+        Some(name) => {
+            conn.command(RenamePlaylist::new(&params.playlist.name, &name))
+                .await?;
+
+            // Metadata stays with the old name, move it to the new one
+            let metadata = playlists_metadata(&conn).await;
+            for (sticker, value, old) in [
+                (STICKER_COMMENT, &mut comment, &metadata.comments),
+                (STICKER_PUBLIC, &mut public, &metadata.public),
+            ] {
+                if let Some(old) = old.get(&params.playlist.name) {
+                    set_playlist_sticker(&conn, &params.playlist.name, sticker, "").await?;
+                    value.get_or_insert_with(|| old.clone());
+                }
+            }
+            name
+        }
+        None => params.playlist.name,
     };
+    for (sticker, value) in [(STICKER_COMMENT, comment), (STICKER_PUBLIC, public)] {
+        if let Some(value) = value {
+            set_playlist_sticker(&conn, &playlist, sticker, &value).await?;
+        }
+    }
 
     Ok(())
 }
 
+// removal_order returns positions of playlist entries to remove in the order they should be
+// removed in. Playlists may contain the same song multiple times, so entries are always removed
+// by position, starting from the last one to make sure that the remaining positions stay valid.
+// Repeated positions are removed only once.
+fn removal_order(positions: impl Iterator<Item = usize>) -> Vec<usize> {
+    let mut positions = positions.collect::<Vec<_>>();
+    positions.sort_by(|a, b| b.cmp(a));
+    positions.dedup();
+    positions
+}
+
 #[derive(Clone, Deserialize, Debug)]
 struct DeletePlaylistQuery {
     #[serde(rename = "id")]
@@ -302,25 +639,48 @@ async fn delete_playlist(
     Extension(state): Extension<Arc<super::State>>,
     Query(params): Query<DeletePlaylistQuery>,
 ) -> super::Result<()> {
-    state
-        .pool
-        .get()
-        .await?
-        .command(DeletePlaylist(&params.playlist.name))
-        .await?;
+    state.ensure_writable()?;
+    check_stored_playlist(&params.playlist)?;
+
+    let conn = state.pool.get().await?;
+    conn.command(DeletePlaylist(&params.playlist.name)).await?;
+
+    // Otherwise a new playlist with the same name would get the metadata
+    let metadata = playlists_metadata(&conn).await;
+    for (sticker, values) in [
+        (STICKER_COMMENT, &metadata.comments),
+        (STICKER_PUBLIC, &metadata.public),
+    ] {
+        if values.contains_key(&params.playlist.name) {
+            set_playlist_sticker(&conn, &params.playlist.name, sticker, "").await?;
+        }
+    }
 
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{GetPlaylist, GetPlaylists, Playlist};
-    use crate::api::{
-        expect_ok_json, expect_ok_xml, json,
-        types::{AlbumID, ArtistID, CoverArtID, PlaylistID, Song, SongID},
-        xml,
+    use super::{
+        check_playlist_name, create_playlist, delete_playlist, removal_order,
+        supports_playlist_stickers, update_playlist, DeletePlaylistQuery, GetPlaylist,
+        GetPlaylistQuery, GetPlaylists, GetPlaylistsQuery, Playlist, QUEUE_PLAYLIST_ID,
+        QUEUE_PLAYLIST_NAME,
     };
+    use crate::{
+        api::{
+            error::Error,
+            expect_ok_json, expect_ok_xml,
+            glue::RawQuery,
+            json, test_state_with_mpd,
+            types::{AlbumID, ArtistID, CoverArtID, PlaylistID, Song, SongID},
+            xml,
+        },
+        mpd::testing::{fake_server, fake_server_version},
+    };
+    use axum::extract::{Extension, Query};
     use serde_json::json;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn get_playlists() {
@@ -329,6 +689,7 @@ mod tests {
                 Playlist {
                     id: PlaylistID::new("metal"),
                     name: "metal".to_string(),
+                    comment: Some("heavy".to_string()),
                     owner: "me".to_string(),
                     public: true,
                     song_count: 10,
@@ -338,8 +699,9 @@ mod tests {
                 Playlist {
                     id: PlaylistID::new("rock"),
                     name: "rock".to_string(),
+                    comment: None,
                     owner: "me".to_string(),
-                    public: true,
+                    public: false,
                     song_count: 16,
                     duration: 5678,
                     changed: "2021-06-10T10:19:57.652Z".to_string(),
@@ -350,8 +712,8 @@ mod tests {
             xml(&get_playlists),
             expect_ok_xml(Some(
                 r#"<playlists>
-    <playlist id="eyJuYW1lIjoibWV0YWwifQ==" name="metal" owner="me" public="true" songCount="10" duration="1234" changed="2022-07-11T10:19:57.652Z" />
-    <playlist id="eyJuYW1lIjoicm9jayJ9" name="rock" owner="me" public="true" songCount="16" duration="5678" changed="2021-06-10T10:19:57.652Z" />
+    <playlist id="eyJuYW1lIjoibWV0YWwifQ==" name="metal" comment="heavy" owner="me" public="true" songCount="10" duration="1234" changed="2022-07-11T10:19:57.652Z" />
+    <playlist id="eyJuYW1lIjoicm9jayJ9" name="rock" owner="me" public="false" songCount="16" duration="5678" changed="2021-06-10T10:19:57.652Z" />
   </playlists>"#
             ),)
         );
@@ -363,6 +725,7 @@ mod tests {
                     {
                         "id": "eyJuYW1lIjoibWV0YWwifQ==",
                         "name": "metal",
+                        "comment": "heavy",
                         "owner": "me",
                         "public": true,
                         "songCount": 10,
@@ -373,7 +736,7 @@ mod tests {
                         "id": "eyJuYW1lIjoicm9jayJ9",
                         "name": "rock",
                         "owner": "me",
-                        "public": true,
+                        "public": false,
                         "songCount": 16,
                         "duration": 5678,
                         "changed": "2021-06-10T10:19:57.652Z",
@@ -389,6 +752,7 @@ mod tests {
         let get_playlist = GetPlaylist {
             id: PlaylistID::new("metal"),
             name: "metal".to_string(),
+            comment: None,
             owner: "me".to_string(),
             public: true,
             song_count: 10,
@@ -405,19 +769,32 @@ mod tests {
                     year: Some(2020),
                     genre: Some("rock".to_string()),
                     cover_art: CoverArtID::new("artwork"),
+                    size: Some(30000000),
+                    content_type: Some("audio/flac".to_string()),
+                    suffix: Some("flac".to_string()),
+                    transcoded_content_type: Some("audio/ogg".to_string()),
+                    transcoded_suffix: Some("opus".to_string()),
                     duration: Some(300),
-                    path: "path1".to_string(),
+                    bit_rate: Some(800),
+                    bit_depth: Some(16),
+                    sampling_rate: Some(44100),
+                    path: Some("path1".to_string()),
                     album_id: Some(AlbumID::new("alpha", "beta")),
                     artist_id: ArtistID::new("alpha"),
                     user_rating: Some(3),
                     starred: Some("2023-08-05T21:56:13Z".into()),
+                    display_artist: None,
+                    play_count: None,
+                    music_brainz_id: Some("8f3471b5-7e6a-48da-86a9-c1c07a0f47ae".to_string()),
+                    artists: Vec::new(),
+                    contributors: Vec::new(),
                 },
                 Song {
                     id: SongID::new("song2"),
                     album: Some("beta".to_string()),
                     artist: "alpha".to_string(),
                     cover_art: CoverArtID::new("artwork"),
-                    path: "path2".to_string(),
+                    path: Some("path2".to_string()),
                     album_id: Some(AlbumID::new("alpha", "beta")),
                     artist_id: ArtistID::new("alpha"),
                     ..Default::default()
@@ -428,7 +805,7 @@ mod tests {
             xml(&get_playlist),
             expect_ok_xml(Some(
                 r#"<playlist id="eyJuYW1lIjoibWV0YWwifQ==" name="metal" owner="me" public="true" songCount="10" duration="1234" changed="2022-07-11T10:19:57.652Z">
-    <entry id="eyJwYXRoIjoic29uZzEifQ==" title="song1" album="beta" artist="alpha" track="1" discNumber="1" year="2020" genre="rock" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" duration="300" path="path1" albumId="eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" userRating="3" starred="2023-08-05T21:56:13Z" />
+    <entry id="eyJwYXRoIjoic29uZzEifQ==" title="song1" album="beta" artist="alpha" track="1" discNumber="1" year="2020" genre="rock" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" size="30000000" contentType="audio/flac" suffix="flac" transcodedContentType="audio/ogg" transcodedSuffix="opus" duration="300" bitRate="800" bitDepth="16" samplingRate="44100" path="path1" albumId="eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" userRating="3" starred="2023-08-05T21:56:13Z" musicBrainzId="8f3471b5-7e6a-48da-86a9-c1c07a0f47ae" />
     <entry id="eyJwYXRoIjoic29uZzIifQ==" album="beta" artist="alpha" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" path="path2" albumId="eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" />
   </playlist>"#
             ),)
@@ -455,12 +832,21 @@ mod tests {
                         "year": 2020,
                         "genre": "rock",
                         "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+                        "size": 30000000,
+                        "contentType": "audio/flac",
+                        "suffix": "flac",
+                        "transcodedContentType": "audio/ogg",
+                        "transcodedSuffix": "opus",
                         "duration": 300,
+                        "bitRate": 800,
+                        "bitDepth": 16,
+                        "samplingRate": 44100,
                         "path": "path1",
                         "albumId": "eyJuYW1lIjoiYWxwaGEiLCJhcnRpc3QiOiJiZXRhIn0=",
                         "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
                         "userRating": 3,
                         "starred": "2023-08-05T21:56:13Z",
+                        "musicBrainzId": "8f3471b5-7e6a-48da-86a9-c1c07a0f47ae",
                     },
                     {
                         "id": "eyJwYXRoIjoic29uZzIifQ==",
@@ -476,4 +862,568 @@ mod tests {
             })),),
         );
     }
+
+    #[test]
+    fn remove_duplicated_song() {
+        let mut playlist = vec!["song1", "song2", "song1", "song3", "song1"];
+
+        for pos in removal_order([2, 4, 2].into_iter()) {
+            playlist.remove(pos);
+        }
+        assert_eq!(playlist, vec!["song1", "song2", "song3"]);
+    }
+
+    #[tokio::test]
+    async fn create_playlist_from_genre() {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let commands = commands.clone();
+            move |command| {
+                commands.lock().unwrap().push(command.to_string());
+                if command.starts_with("find") {
+                    "file: alpha/1.flac\nfile: alpha/2.flac\n".to_string()
+                } else {
+                    String::new()
+                }
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let query = "u=me&name=rock&genre=Rock";
+        let res = create_playlist(
+            Extension(state),
+            Query(serde_urlencoded::from_str(query).unwrap()),
+            RawQuery(Some(query.to_string())),
+        )
+        .await;
+        assert!(res.is_ok());
+
+        let commands = commands.lock().unwrap();
+        let find = commands.iter().find(|c| c.starts_with("find")).unwrap();
+        assert!(find.contains("Genre") && find.contains("Rock"));
+        assert_eq!(
+            commands
+                .iter()
+                .filter(|c| c.starts_with("playlistadd"))
+                .collect::<Vec<_>>(),
+            vec![
+                "playlistadd rock alpha/1.flac",
+                "playlistadd rock alpha/2.flac"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn create_playlist_keeps_queue() {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let commands = commands.clone();
+            move |command| {
+                commands.lock().unwrap().push(command.to_string());
+                match command {
+                    "listplaylists" => "playlist: metal\nLast-Modified: 2023-01-02T03:04:05Z\n",
+                    _ => "",
+                }
+                .to_string()
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let song = serde_json::to_value(SongID::new("alpha/1.flac")).unwrap();
+        let song = serde_urlencoded::to_string([("songId", song.as_str().unwrap())]).unwrap();
+        let create = |name: &str| {
+            let query = format!("u=me&name={name}&{song}");
+            create_playlist(
+                Extension(state.clone()),
+                Query(serde_urlencoded::from_str(&query).unwrap()),
+                RawQuery(Some(query)),
+            )
+        };
+
+        assert!(create("rock").await.is_ok());
+        assert!(create("metal").await.is_err());
+
+        let commands = std::mem::take(&mut *commands.lock().unwrap());
+        let commands = commands
+            .iter()
+            .map(String::as_str)
+            .filter(|&c| {
+                c != "ping" && !c.starts_with("listplaylistinfo") && !c.starts_with("sticker")
+            })
+            .collect::<Vec<_>>();
+        // Nothing but the playlist is touched, the play queue in particular
+        assert_eq!(
+            commands,
+            [
+                "listplaylists",
+                "playlistclear rock",
+                "playlistadd rock alpha/1.flac",
+                "listplaylists",
+                "listplaylists",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn create_playlist_replaces() {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let commands = commands.clone();
+            move |command| {
+                commands.lock().unwrap().push(command.to_string());
+                match command {
+                    "listplaylists" => "playlist: metal\nLast-Modified: 2023-01-02T03:04:05Z\n",
+                    _ => "",
+                }
+                .to_string()
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let song = serde_json::to_value(SongID::new("alpha/1.flac")).unwrap();
+        let song = serde_urlencoded::to_string([("songId", song.as_str().unwrap())]).unwrap();
+        let replace = |name: &str, songs: &str| {
+            let id = serde_json::to_value(PlaylistID::new(name)).unwrap();
+            let id = serde_urlencoded::to_string([("playlistId", id.as_str().unwrap())]).unwrap();
+            let query = format!("u=me&{id}{songs}");
+            commands.lock().unwrap().clear();
+            create_playlist(
+                Extension(state.clone()),
+                Query(serde_urlencoded::from_str(&query).unwrap()),
+                RawQuery(Some(query)),
+            )
+        };
+        let changes = || {
+            commands
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|c| c.starts_with("playlist"))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        assert!(replace("metal", &format!("&{song}")).await.is_ok());
+        assert_eq!(
+            changes(),
+            ["playlistclear metal", "playlistadd metal alpha/1.flac"]
+        );
+
+        // Without songs the playlist is emptied
+        assert!(replace("metal", "").await.is_ok());
+        assert_eq!(changes(), ["playlistclear metal"]);
+
+        assert!(replace("rock", &format!("&{song}")).await.is_err());
+        assert!(replace(QUEUE_PLAYLIST_ID, &format!("&{song}"))
+            .await
+            .is_err());
+        assert!(changes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn playlist_totals() {
+        let mpd = fake_server(|command| {
+            match command.split(' ').next() {
+                Some("listplaylists") => "playlist: mix\nLast-Modified: 2023-08-05T21:56:13Z\n",
+                Some("listplaylistinfo") => {
+                    "file: alpha/1.flac\nduration: 100.400\nfile: alpha/2.flac\nduration: 50.400\n\
+                 file: alpha/3.flac\nfile: http://radio.example.com/stream\n"
+                }
+                Some("find") if command.contains("alpha/3.flac") => {
+                    "file: alpha/3.flac\nduration: 30.000\n"
+                }
+                _ => "",
+            }
+            .to_string()
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let Ok(playlists) = super::get_playlists(
+            Extension(state.clone()),
+            Query(GetPlaylistsQuery {
+                u: "me".to_string(),
+                username: None,
+            }),
+        )
+        .await
+        else {
+            panic!("getPlaylists failed");
+        };
+        let mix = &playlists.playlists[1];
+        assert_eq!((mix.song_count, mix.duration), (4, 181));
+
+        let Ok(mix) = super::get_playlist(
+            Extension(state),
+            Query(GetPlaylistQuery {
+                u: "me".to_string(),
+                playlist: PlaylistID::new("mix"),
+            }),
+        )
+        .await
+        else {
+            panic!("getPlaylist failed");
+        };
+        // Entries keep their positions, so that they can be removed by index
+        assert_eq!((mix.song_count, mix.duration), (4, 181));
+        assert_eq!(mix.songs.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn queue_playlist() {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let commands = commands.clone();
+            move |command| {
+                commands.lock().unwrap().push(command.to_string());
+                match command.split(' ').next() {
+                    Some("listplaylists") => {
+                        "playlist: rock\nLast-Modified: 2023-08-05T21:56:13Z\n".to_string()
+                    }
+                    Some("listplaylistinfo") => "file: alpha/3.flac\nduration: 50.0\n".to_string(),
+                    Some("playlistinfo") => "file: alpha/1.flac\nduration: 100.0\nPos: 0\nId: 1\n\
+                                             file: alpha/2.flac\nduration: 200.0\nPos: 1\nId: 2\n"
+                        .to_string(),
+                    _ => String::new(),
+                }
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let Ok(playlists) = super::get_playlists(
+            Extension(state.clone()),
+            Query(GetPlaylistsQuery {
+                u: "me".to_string(),
+                username: None,
+            }),
+        )
+        .await
+        else {
+            panic!("getPlaylists failed");
+        };
+        assert_eq!(playlists.playlists.len(), 2);
+        let queue = &playlists.playlists[0];
+        assert_eq!(queue.id.name, QUEUE_PLAYLIST_ID);
+        assert_eq!(queue.name, QUEUE_PLAYLIST_NAME);
+        assert!(!queue.public);
+        assert_eq!((queue.song_count, queue.duration), (2, 300));
+        let rock = &playlists.playlists[1];
+        assert_eq!(rock.name, "rock");
+        assert_eq!((rock.song_count, rock.duration), (1, 50));
+
+        let Ok(queue) = super::get_playlist(
+            Extension(state.clone()),
+            Query(GetPlaylistQuery {
+                u: "me".to_string(),
+                playlist: PlaylistID::new(QUEUE_PLAYLIST_ID),
+            }),
+        )
+        .await
+        else {
+            panic!("getPlaylist failed");
+        };
+        assert_eq!(queue.name, QUEUE_PLAYLIST_NAME);
+        assert_eq!(queue.changed, None);
+        assert_eq!(
+            queue
+                .songs
+                .iter()
+                .map(|s| s.id.path.as_str())
+                .collect::<Vec<_>>(),
+            ["alpha/1.flac", "alpha/2.flac"]
+        );
+
+        commands.lock().unwrap().clear();
+        let res = delete_playlist(
+            Extension(state.clone()),
+            Query(DeletePlaylistQuery {
+                playlist: PlaylistID::new(QUEUE_PLAYLIST_ID),
+            }),
+        )
+        .await;
+        assert!(res.is_err());
+
+        let id = serde_json::to_value(PlaylistID::new(QUEUE_PLAYLIST_ID)).unwrap();
+        let query = format!("playlistId={}&songIndexToRemove=0", id.as_str().unwrap());
+        let res = update_playlist(
+            Extension(state),
+            Query(serde_urlencoded::from_str(&query).unwrap()),
+            RawQuery(Some(query)),
+        )
+        .await;
+        assert!(res.is_err());
+        assert!(commands.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn playlist_metadata() {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server_version("0.24.0", {
+            let commands = commands.clone();
+            move |command| {
+                commands.lock().unwrap().push(command.to_string());
+                match command {
+                    "listplaylists" => {
+                        "playlist: rock\nLast-Modified: 2023-08-05T21:56:13Z\n\
+                                        playlist: metal\nLast-Modified: 2023-08-05T21:56:13Z\n"
+                    }
+                    r#"sticker find playlist "" comment"# => {
+                        "playlist: rock\nsticker: comment=loud and proud\n"
+                    }
+                    r#"sticker find playlist "" public"# => {
+                        "playlist: rock\nsticker: public=false\n"
+                    }
+                    _ => "",
+                }
+                .to_string()
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let Ok(playlists) = super::get_playlists(
+            Extension(state.clone()),
+            Query(GetPlaylistsQuery {
+                u: "me".to_string(),
+                username: None,
+            }),
+        )
+        .await
+        else {
+            panic!("getPlaylists failed");
+        };
+        let metadata = playlists
+            .playlists
+            .iter()
+            .map(|p| (p.name.as_str(), p.comment.as_deref(), p.public))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            metadata,
+            [
+                (QUEUE_PLAYLIST_NAME, None, false),
+                ("rock", Some("loud and proud"), false),
+                ("metal", None, true),
+            ]
+        );
+
+        let Ok(rock) = super::get_playlist(
+            Extension(state.clone()),
+            Query(GetPlaylistQuery {
+                u: "me".to_string(),
+                playlist: PlaylistID::new("rock"),
+            }),
+        )
+        .await
+        else {
+            panic!("getPlaylist failed");
+        };
+        assert_eq!(
+            (rock.comment.as_deref(), rock.public),
+            (Some("loud and proud"), false)
+        );
+
+        let update = |query: String| {
+            let state = state.clone();
+            let commands = commands.clone();
+            async move {
+                commands.lock().unwrap().clear();
+                let res = update_playlist(
+                    Extension(state),
+                    Query(serde_urlencoded::from_str(&query).unwrap()),
+                    RawQuery(Some(query)),
+                )
+                .await;
+                assert!(res.is_ok());
+                std::mem::take(&mut *commands.lock().unwrap())
+                    .into_iter()
+                    .filter(|c| c != "ping" && !c.starts_with("sticker find"))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let id = |name| {
+            let id = serde_json::to_value(PlaylistID::new(name)).unwrap();
+            serde_urlencoded::to_string([("playlistId", id.as_str().unwrap())]).unwrap()
+        };
+
+        assert_eq!(
+            update(format!("{}&comment=quiet&public=true", id("metal"))).await,
+            [
+                "sticker set playlist metal comment quiet",
+                "sticker set playlist metal public true",
+            ]
+        );
+        assert_eq!(
+            update(format!("{}&comment=", id("metal"))).await,
+            ["sticker delete playlist metal comment"]
+        );
+        // Metadata follows renamed playlists
+        assert_eq!(
+            update(format!("{}&name=stone", id("rock"))).await,
+            [
+                "rename rock stone",
+                "sticker delete playlist rock comment",
+                "sticker delete playlist rock public",
+                r#"sticker set playlist stone comment "loud and proud""#,
+                "sticker set playlist stone public false",
+            ]
+        );
+
+        commands.lock().unwrap().clear();
+        let res = delete_playlist(
+            Extension(state),
+            Query(DeletePlaylistQuery {
+                playlist: PlaylistID::new("rock"),
+            }),
+        )
+        .await;
+        assert!(res.is_ok());
+        assert!(commands
+            .lock()
+            .unwrap()
+            .contains(&"sticker delete playlist rock public".to_string()));
+    }
+
+    #[tokio::test]
+    async fn playlist_metadata_unsupported() {
+        // The fake server speaks MPD 0.23, which knows nothing about playlist stickers
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let commands = commands.clone();
+            move |command| {
+                commands.lock().unwrap().push(command.to_string());
+                if command.starts_with("sticker") {
+                    "ACK [2@0] {sticker} unknown sticker domain\n".to_string()
+                } else {
+                    String::new()
+                }
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        // The playlist is left untouched if its metadata can't be stored
+        let id = serde_json::to_value(PlaylistID::new("rock")).unwrap();
+        let query = format!(
+            "{}&songIndexToRemove=0&songIdToAdd={}",
+            serde_urlencoded::to_string([
+                ("playlistId", id.as_str().unwrap()),
+                ("name", "metal"),
+                ("comment", "loud"),
+            ])
+            .unwrap(),
+            serde_json::to_value(SongID::new("song.flac"))
+                .unwrap()
+                .as_str()
+                .unwrap(),
+        );
+        let res = update_playlist(
+            Extension(state.clone()),
+            Query(serde_urlencoded::from_str(&query).unwrap()),
+            RawQuery(Some(query)),
+        )
+        .await;
+        assert!(
+            matches!(res, Err(err) if xml(&err) == xml(&Error::generic_error(
+                Some("playlist metadata requires MPD 0.24")
+            )))
+        );
+        assert!(commands.lock().unwrap().iter().all(|c| {
+            !c.starts_with("playlist") && !c.starts_with("rename") && !c.starts_with("sticker")
+        }));
+
+        // Playlists without metadata still work
+        let query = format!(
+            "{}&songIndexToRemove=0",
+            serde_urlencoded::to_string([("playlistId", id.as_str().unwrap())]).unwrap()
+        );
+        let res = update_playlist(
+            Extension(state),
+            Query(serde_urlencoded::from_str(&query).unwrap()),
+            RawQuery(Some(query)),
+        )
+        .await;
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn playlist_stickers_support() {
+        assert!(!supports_playlist_stickers("0.23.5"));
+        assert!(supports_playlist_stickers("0.24.0"));
+        assert!(supports_playlist_stickers("0.25"));
+        assert!(supports_playlist_stickers("1.0.0"));
+        assert!(!supports_playlist_stickers("garbage"));
+    }
+
+    #[tokio::test]
+    async fn playlist_names() {
+        assert!(check_playlist_name("rock").is_ok());
+        assert!(check_playlist_name("my rock").is_ok());
+        assert!(check_playlist_name("Sigur Rós ✓").is_ok());
+        assert!(check_playlist_name("").is_err());
+        assert!(check_playlist_name("rock/metal").is_err());
+        assert!(check_playlist_name("rock\nmetal").is_err());
+
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let commands = commands.clone();
+            move |command| {
+                commands.lock().unwrap().push(command.to_string());
+                String::new()
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let song = serde_json::to_value(SongID::new("alpha/1.flac")).unwrap();
+        let song = serde_urlencoded::to_string([("songId", song.as_str().unwrap())]).unwrap();
+
+        let query = format!("u=me&name=rock%2Fmetal&{song}");
+        let res = create_playlist(
+            Extension(state.clone()),
+            Query(serde_urlencoded::from_str(&query).unwrap()),
+            RawQuery(Some(query)),
+        )
+        .await;
+        assert!(res.is_err());
+        assert!(commands.lock().unwrap().is_empty());
+
+        let query = format!("u=me&name=my+rock&{song}");
+        let res = create_playlist(
+            Extension(state.clone()),
+            Query(serde_urlencoded::from_str(&query).unwrap()),
+            RawQuery(Some(query)),
+        )
+        .await;
+        assert!(res.is_ok());
+        assert!(commands
+            .lock()
+            .unwrap()
+            .contains(&r#"playlistadd "my rock" alpha/1.flac"#.to_string()));
+
+        // Playlist IDs of names with spaces and unicode survive URL encoding
+        let id = serde_json::to_value(PlaylistID::new("my rock ✓")).unwrap();
+        let query = serde_urlencoded::to_string([("playlistId", id.as_str().unwrap())]).unwrap();
+        let rename = format!("{query}&name=rock%2Fmetal");
+        let res = update_playlist(
+            Extension(state.clone()),
+            Query(serde_urlencoded::from_str(&rename).unwrap()),
+            RawQuery(Some(rename)),
+        )
+        .await;
+        assert!(res.is_err());
+
+        let query = format!("{query}&{}", song.replace("songId", "songIdToAdd"));
+        let res = update_playlist(
+            Extension(state),
+            Query(serde_urlencoded::from_str(&query).unwrap()),
+            RawQuery(Some(query)),
+        )
+        .await;
+        assert!(res.is_ok());
+        assert!(commands
+            .lock()
+            .unwrap()
+            .contains(&r#"playlistadd "my rock ✓" alpha/1.flac"#.to_string()));
+    }
 }
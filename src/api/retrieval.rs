@@ -1,29 +1,65 @@
 use super::{
+    common::{find_albums_songs, is_stream},
     error::Error,
-    types::{CoverArtID, SongID},
+    types::{AlbumID, CoverArtID, PlaylistID, SongID},
 };
+use crate::{
+    coverartarchive,
+    library::{self, ByteRange},
+    mpd::ReadComments,
+};
+use async_zip::{base::write::ZipFileWriter, Compression, ZipEntryBuilder};
 use axum::{
     body::Body,
     extract::{Extension, Query},
-    http::{header, HeaderValue},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     routing::Router,
 };
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 
-use futures::StreamExt;
-use mpd_client::commands::{AlbumArt, GetPlaylist};
-use serde::Deserialize;
-use std::{process::Stdio, sync::Arc};
-use tokio::process::Command;
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use lru::LruCache;
+use mpd_client::{
+    commands::{AlbumArt, CurrentSong, Find, GetPlaylist},
+    filter::Filter,
+    tag::Tag,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::Stdio,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    process::{Child, Command},
+    sync::OnceCell,
+};
 use tokio_util::io::{ReaderStream, StreamReader};
-use tracing::warn;
+use tracing::{error, warn};
+use yaserde_derive::YaSerialize;
 
 pub(crate) fn get_router() -> Router {
     Router::new()
         .route("/getCoverArt.view", super::raw_handler(get_cover_art))
         .route("/stream.view", super::raw_handler(stream))
+        .route("/download.view", super::raw_handler(download))
         .route("/getAvatar.view", super::raw_handler(get_avatar))
+        .route("/getLyrics.view", super::handler(get_lyrics))
+        .route(
+            "/getLyricsBySongId.view",
+            super::handler(get_lyrics_by_song_id),
+        )
 }
 
 #[derive(Clone, Deserialize)]
@@ -46,15 +82,138 @@ async fn get_cover_art(
                 .map(|s| s.file_path().display().to_string())
                 .ok_or_else(Error::not_found)?
         }
+        CoverArtID::Artist { artist } => {
+            if let Some(image) = artist_image_path(state.artist_image_dir.as_deref(), &artist) {
+                let mut res = tokio::fs::read(image).await?.into_response();
+                res.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(ARTIST_IMAGE_MIME),
+                );
+
+                return Ok(res);
+            }
+
+            // Fall back to the album art of one of the artist's songs
+            let songs = state
+                .pool
+                .get()
+                .await?
+                .command(Find::new(Filter::tag(Tag::AlbumArtist, &artist)).window(0..1))
+                .await?;
+
+            songs
+                .first()
+                .map(|s| s.file_path().display().to_string())
+                .ok_or_else(Error::not_found)?
+        }
+    };
+
+    let cover = state
+        .cover_cache
+        .get_or_fetch(&path, || fetch_cover(&state, &path))
+        .await?;
+
+    let mut res = cover.data.into_response();
+    if let Some(m) = cover.mime.and_then(|m| HeaderValue::from_str(&m).ok()) {
+        res.headers_mut().insert(header::CONTENT_TYPE, m);
+    }
+
+    Ok(res)
+}
+
+// Names of cover images looked up next to songs, in the order of preference
+const COVER_FILES: &[&str] = &[
+    "cover.jpg",
+    "cover.png",
+    "folder.jpg",
+    "folder.png",
+    "front.jpg",
+    "front.png",
+];
+
+// fetch_cover fetches album art of the song from MPD, falling back to the Cover Art Archive
+// (if enabled) when MPD has none. Covers next to songs in HTTP libraries are fetched from the
+// library directly, which is cheaper than going through MPD.
+async fn fetch_cover(state: &super::State, path: &str) -> super::Result<Cover> {
+    if state.lib.is_remote() {
+        if let Some(cover) = fetch_library_cover(state.lib.as_ref(), path).await {
+            return Ok(cover);
+        }
+    }
+
+    let err = match fetch_mpd_cover(state, path).await {
+        Ok(cover) => return Ok(cover),
+        Err(err) => err,
+    };
+    let Some(archive) = &state.cover_art_archive else {
+        return Err(err);
+    };
+
+    let songs = state
+        .pool
+        .get()
+        .await?
+        .command(Find::new(Filter::tag(Tag::Other("file".into()), path)).window(0..1))
+        .await?;
+    let Some(release) = songs
+        .first()
+        .and_then(|s| s.tags.get(&Tag::MusicBrainzReleaseId))
+        .and_then(|v| v.first())
+    else {
+        return Err(err);
+    };
+
+    match archive.front_cover(release).await {
+        Ok(image) => Ok(Cover {
+            data: image.data,
+            mime: image.mime,
+        }),
+        Err(coverartarchive::Error::NotFound) => Err(err),
+        Err(archive_err) => {
+            warn!(path = ?path, release = ?release, action = "remote cover", err = ?archive_err);
+            Err(err)
+        }
+    }
+}
+
+// fetch_library_cover fetches a cover image from the directory of the song, if there is one
+async fn fetch_library_cover(
+    lib: &(dyn library::Library + Send + Sync),
+    path: &str,
+) -> Option<Cover> {
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let cover = match lib.find_sibling(&dir.to_string_lossy(), COVER_FILES).await {
+        Ok(cover) => cover?,
+        Err(err) => {
+            warn!(path = ?path, action = "library cover", err = ?err);
+            return None;
+        }
     };
 
+    let data = lib
+        .get_song(&cover, None)
+        .await
+        .ok()?
+        .stream
+        .try_collect::<Vec<_>>()
+        .await
+        .ok()?
+        .concat();
+    Some(Cover {
+        data: data.into(),
+        mime: Some(image_mime(Path::new(&cover)).to_string()),
+    })
+}
+
+// fetch_mpd_cover fetches album art of the song from MPD
+async fn fetch_mpd_cover(state: &super::State, path: &str) -> super::Result<Cover> {
     let mut cover = BytesMut::new();
     loop {
         let resp = state
             .pool
             .get()
             .await?
-            .command(AlbumArt::new(&path).offset(cover.len()))
+            .command(AlbumArt::new(path).offset(cover.len()))
             .await?
             .ok_or_else(Error::not_found)?;
 
@@ -63,128 +222,646 @@ async fn get_cover_art(
             continue;
         }
 
-        let mut res = cover.into_response();
-        if let Some(m) = resp.mime.and_then(|m| HeaderValue::from_str(&m).ok()) {
-            res.headers_mut().insert(header::CONTENT_TYPE, m);
+        return Ok(Cover {
+            data: cover.freeze(),
+            mime: resp.mime,
+        });
+    }
+}
+
+// Cover is album art of a song
+#[derive(Clone)]
+struct Cover {
+    data: Bytes,
+    mime: Option<String>,
+}
+
+// Maximum total size of covers kept in the cache
+const COVER_CACHE_MAX_BYTES: usize = 64 * 1024 * 1024;
+// Cached covers are fetched again after this time, so that changed album art shows up
+const COVER_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+// CoverCache keeps recently requested album art in memory, so that clients showing grids of
+// albums don't fetch the same covers from MPD over and over again
+pub(crate) struct CoverCache {
+    covers: Option<Mutex<LruCache<String, CachedCover>>>,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+// CachedCover is a cover along with the time it was requested at. The cover is empty while it is
+// being fetched.
+type CachedCover = (Instant, Arc<OnceCell<Cover>>);
+
+impl CoverCache {
+    // new returns a cache holding covers of at most size songs. Zero size disables caching.
+    pub(crate) fn new(size: usize) -> Self {
+        CoverCache::with_limits(size, COVER_CACHE_MAX_BYTES, COVER_CACHE_TTL)
+    }
+
+    fn with_limits(size: usize, max_bytes: usize, ttl: Duration) -> Self {
+        CoverCache {
+            covers: NonZeroUsize::new(size).map(|size| Mutex::new(LruCache::new(size))),
+            max_bytes,
+            ttl,
         }
+    }
+
+    // get_or_fetch returns the cached cover of the song or fetches it. Concurrent requests for the
+    // same song wait for a single fetch. Failed fetches are not cached.
+    async fn get_or_fetch<F, Fut>(&self, path: &str, fetch: F) -> super::Result<Cover>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = super::Result<Cover>>,
+    {
+        let Some(covers) = &self.covers else {
+            return fetch().await;
+        };
+
+        // A cover pushed out to make room for the pending one, restored if the fetch fails
+        let mut evicted = None;
+        let cover = {
+            let mut covers = covers.lock().unwrap();
+            if covers
+                .peek(path)
+                .is_some_and(|(requested, _)| requested.elapsed() >= self.ttl)
+            {
+                covers.pop(path);
+            }
+            match covers.get(path) {
+                Some((_, cover)) => cover.clone(),
+                None => {
+                    let cover: Arc<OnceCell<Cover>> = Default::default();
+                    evicted = covers.push(path.to_string(), (Instant::now(), cover.clone()));
+                    cover
+                }
+            }
+        };
 
-        return Ok(res);
+        let res = cover.get_or_try_init(fetch).await.cloned();
+
+        let mut covers = covers.lock().unwrap();
+        match res {
+            Ok(_) => {
+                // The least recently used covers go first, including this one if it is too large
+                while covers
+                    .iter()
+                    .filter_map(|(_, (_, c))| c.get())
+                    .map(|c| c.data.len())
+                    .sum::<usize>()
+                    > self.max_bytes
+                {
+                    covers.pop_lru();
+                }
+            }
+            Err(_) => {
+                if covers
+                    .peek(path)
+                    .is_some_and(|(_, c)| Arc::ptr_eq(c, &cover))
+                {
+                    covers.pop(path);
+                }
+                if let Some((path, cover)) = evicted {
+                    if !covers.contains(&path) && covers.len() < covers.cap().get() {
+                        covers.push(path, cover);
+                    }
+                }
+            }
+        }
+        res
+    }
+}
+
+// Artist images are expected to be <artist name>.jpg
+const ARTIST_IMAGE_EXTENSION: &str = "jpg";
+const ARTIST_IMAGE_MIME: &str = "image/jpeg";
+
+// artist_image_path returns path to the image of the artist if the artist image directory
+// is configured and has the image
+pub(crate) fn artist_image_path(dir: Option<&Path>, artist: &str) -> Option<PathBuf> {
+    // Artist name must not be able to point outside of the directory
+    if artist.is_empty() || artist.contains(['/', '\\']) || artist.starts_with('.') {
+        return None;
     }
+
+    let path = dir?.join(format!("{artist}.{ARTIST_IMAGE_EXTENSION}"));
+    path.is_file().then_some(path)
 }
 
 #[derive(Clone, Deserialize)]
 struct StreamQuery {
     #[serde(rename = "id")]
-    song: SongID,
+    song: StreamID,
     #[serde(rename = "maxBitRate")]
     max_bitrate: Option<u32>,
     format: Option<String>,
 }
 
-static FFMPEG_ARGS: &[&str] = &[
-                "-v",
-                "0",
-                "-i",
-                "-",
-                "-map",
-                "0:a:0",
-                "-vn",
-                "-b:a",
-                "<bitrate>",
-                "-c:a",
-                "libopus",
-                "-vbr",
-                "on",
-                "-af",
-                "volume=replaygain=track:replaygain_preamp=6dB:replaygain_noclip=0, alimiter=level=disabled, asidedata=mode=delete:type=REPLAYGAIN",
-                "-metadata",
-                "replaygain_album_gain=",
-                "-metadata",
-                "replaygain_album_peak=",
-                "-metadata",
-                "replaygain_track_gain=",
-                "-metadata",
-                "replaygain_track_peak=",
-                "-metadata",
-                "r128_album_gain=",
-                "-metadata",
-                "r128_track_gain=",
-                "-f",
-                "opus",
-                "-"
-    ];
+// StreamID identifies what is being streamed. Besides songs, clients can stream whatever MPD is
+// currently playing by passing "current" as the ID.
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+enum StreamID {
+    Current(CurrentSongID),
+    Song(SongID),
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CurrentSongID {
+    Current,
+}
+
+// stream_path returns path of the song to stream
+async fn stream_path(state: &super::State, id: StreamID) -> super::Result<String> {
+    match id {
+        StreamID::Song(song) => Ok(song.path),
+        StreamID::Current(_) => Ok(state
+            .pool
+            .get()
+            .await?
+            .command(CurrentSong)
+            .await?
+            .ok_or_else(Error::not_found)?
+            .song
+            .url),
+    }
+}
+
+// TranscodeFormat is a format songs are transcoded to before streaming
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum TranscodeFormat {
+    #[default]
+    Opus,
+    Mp3,
+    Aac,
+}
+
+impl TranscodeFormat {
+    // codec returns ffmpeg audio codec and its codec-specific options
+    fn codec(self) -> &'static [&'static str] {
+        match self {
+            TranscodeFormat::Opus => &["-c:a", "libopus", "-vbr", "on"],
+            TranscodeFormat::Mp3 => &["-c:a", "libmp3lame"],
+            TranscodeFormat::Aac => &["-c:a", "aac"],
+        }
+    }
+
+    // container returns ffmpeg output format
+    fn container(self) -> &'static str {
+        match self {
+            TranscodeFormat::Opus => "opus",
+            TranscodeFormat::Mp3 => "mp3",
+            TranscodeFormat::Aac => "adts",
+        }
+    }
+
+    // suffix returns file extension of the transcoded song
+    pub(crate) fn suffix(self) -> &'static str {
+        match self {
+            TranscodeFormat::Opus => "opus",
+            TranscodeFormat::Mp3 => "mp3",
+            TranscodeFormat::Aac => "aac",
+        }
+    }
+
+    pub(crate) fn mime(self) -> &'static str {
+        match self {
+            TranscodeFormat::Opus => "audio/ogg",
+            TranscodeFormat::Mp3 => "audio/mpeg",
+            TranscodeFormat::Aac => "audio/aac",
+        }
+    }
+}
+
+impl FromStr for TranscodeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "opus" | "ogg" => Ok(TranscodeFormat::Opus),
+            "mp3" => Ok(TranscodeFormat::Mp3),
+            "aac" => Ok(TranscodeFormat::Aac),
+            _ => Err(format!("unsupported transcode format: {s}")),
+        }
+    }
+}
+
 static FFMPEG_BITRATES: &[u32] = &[96, 112, 128, 160, 192];
 
+// ffmpeg_args returns arguments for ffmpeg transcoding stdin to stdout in the given format and
+// bitrate (in bits per second). ReplayGain is applied to the audio and its tags are dropped.
+fn ffmpeg_args(format: TranscodeFormat, bitrate: u32) -> Vec<String> {
+    ["-v", "error", "-i", "-", "-map", "0:a:0", "-vn", "-b:a", &bitrate.to_string()]
+        .into_iter()
+        .chain(format.codec().iter().copied())
+        .chain([
+            "-af",
+            "volume=replaygain=track:replaygain_preamp=6dB:replaygain_noclip=0, alimiter=level=disabled, asidedata=mode=delete:type=REPLAYGAIN",
+            "-metadata",
+            "replaygain_album_gain=",
+            "-metadata",
+            "replaygain_album_peak=",
+            "-metadata",
+            "replaygain_track_gain=",
+            "-metadata",
+            "replaygain_track_peak=",
+            "-metadata",
+            "r128_album_gain=",
+            "-metadata",
+            "r128_track_gain=",
+            "-f",
+            format.container(),
+            "-",
+        ])
+        .map(str::to_string)
+        .collect()
+}
+
 async fn stream(
     Extension(state): Extension<Arc<super::State>>,
     Query(params): Query<StreamQuery>,
-) -> super::Result<Body> {
-    let input_stream = state.lib.get_song(&params.song.path).await?;
-
-    let output_stream = match params.format.as_deref() {
-        Some("raw") => input_stream,
-        Some("ogg") | None => {
-            let max_available_bitrate = FFMPEG_BITRATES[FFMPEG_BITRATES.len() - 1];
-            let max_desired_bitrate = match params.max_bitrate {
-                None | Some(0) => max_available_bitrate,
-                Some(b) => b,
+    headers: HeaderMap,
+) -> super::Result<Response> {
+    let path = stream_path(&state, params.song).await?;
+    let format = match params.format.as_deref() {
+        Some("raw") => {
+            let range = headers
+                .get(header::RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(ByteRange::parse);
+            let song = match state.lib.get_song(&path, range).await {
+                Err(library::Error::RangeNotSatisfiable(size)) => {
+                    return Ok(range_not_satisfiable(size))
+                }
+                res => res?,
             };
-            let bitrate = FFMPEG_BITRATES
-                .get(
-                    FFMPEG_BITRATES
-                        .partition_point(|&x| x <= max_desired_bitrate)
-                        .saturating_sub(1),
-                )
-                .copied()
-                .unwrap_or(max_available_bitrate)
-                * 1024;
-            let ffmpeg_args = FFMPEG_ARGS
-                .iter()
-                .map(|&a| {
-                    if a == "<bitrate>" {
-                        bitrate.to_string()
-                    } else {
-                        a.to_string()
-                    }
-                })
-                .collect::<Vec<_>>();
 
-            let mut child = Command::new("ffmpeg")
-                .args(ffmpeg_args)
-                .kill_on_drop(true)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()?;
-
-            let mut stdin = child
-                .stdin
-                .take()
-                .ok_or_else(|| Error::generic_error(Some("cannot capture child's stdin")))?;
-            let stdout = child
-                .stdout
-                .take()
-                .ok_or_else(|| Error::generic_error(Some("cannot capture child's stdout")))?;
-
-            tokio::spawn(async move {
-                if let Err(err) =
-                    tokio::io::copy(&mut StreamReader::new(input_stream), &mut stdin).await
-                {
-                    warn!(path = ?params.song.path, action = "copy", err = ?err);
+            let mut res = Body::from_stream(song.stream).into_response();
+            let headers = res.headers_mut();
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(song_mime(&path)),
+            );
+            headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+            if let Some(range) = song.range {
+                if let Ok(v) = HeaderValue::from_str(&range.header()) {
+                    headers.insert(header::CONTENT_RANGE, v);
                 }
-                drop(stdin);
-                if let Err(err) = child.wait().await {
-                    warn!(path = ?params.song.path, action = "wait", err = ?err);
+                *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+            }
+
+            return Ok(res);
+        }
+        Some(format) => format
+            .parse()
+            .map_err(|err: String| Error::generic_error(Some(&err)))?,
+        None => state.default_transcode_format,
+    };
+
+    let max_available_bitrate = FFMPEG_BITRATES[FFMPEG_BITRATES.len() - 1];
+    let max_desired_bitrate = match params.max_bitrate {
+        None | Some(0) => max_available_bitrate,
+        Some(b) => b,
+    };
+    let bitrate = FFMPEG_BITRATES
+        .get(
+            FFMPEG_BITRATES
+                .partition_point(|&x| x <= max_desired_bitrate)
+                .saturating_sub(1),
+        )
+        .copied()
+        .unwrap_or(max_available_bitrate)
+        * 1024;
+
+    let input_stream = state.lib.get_song(&path, None).await?.stream;
+
+    let mut child = Command::new("ffmpeg")
+        .args(ffmpeg_args(format, bitrate))
+        .kill_on_drop(true)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| Error::generic_error(Some("cannot capture child's stdin")))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| Error::generic_error(Some("cannot capture child's stdout")))?;
+
+    let abandoned = Arc::new(AtomicBool::new(false));
+    tokio::spawn({
+        let abandoned = abandoned.clone();
+        async move {
+            // ffmpeg's stderr is drained while the song is being fed to it, so that it never
+            // blocks on writing errors
+            let copy = async {
+                let mut input = StreamReader::new(input_stream);
+                if let Err(err) = tokio::io::copy(&mut input, &mut stdin).await {
+                    warn!(path = ?path, action = "copy", err = ?err);
                 }
-            });
+                drop(stdin);
+            };
+            let ((), res) = tokio::join!(copy, wait_transcoder(child, &abandoned));
+            if let Err(err) = res {
+                error!(path = ?path, action = "transcode", err = %err);
+            }
+        }
+    });
+
+    let mut res = Body::from_stream(transcoded_stream(stdout, abandoned)).into_response();
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.mime()),
+    );
+
+    Ok(res)
+}
+
+#[derive(Clone, Deserialize)]
+struct DownloadQuery {
+    id: DownloadID,
+}
 
-            ReaderStream::new(stdout)
-                .map(|x| x.map_err(Into::into))
-                .boxed()
+// DownloadID identifies what is being downloaded. Albums and playlists are downloaded as
+// zip archives.
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+enum DownloadID {
+    Song(SongID),
+    Album(AlbumID),
+    Playlist(PlaylistID),
+}
+
+// Size of the buffer between the zip writer and the client
+const DOWNLOAD_BUFFER_SIZE: usize = 64 * 1024;
+
+// download is denied in read-only mode, getUser reports no download role then
+async fn download(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(params): Query<DownloadQuery>,
+) -> super::Result<Response> {
+    state.ensure_writable()?;
+
+    let conn = state.pool.get().await?;
+    let (name, entries) = match params.id {
+        DownloadID::Song(song) => {
+            let stream = state.lib.get_song(&song.path, None).await?.stream;
+
+            let mut res = Body::from_stream(stream).into_response();
+            res.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(song_mime(&song.path)),
+            );
+            return Ok(res);
+        }
+        DownloadID::Album(album) => {
+            let songs = find_albums_songs(&conn, &[&album]).await?.concat();
+            let paths = songs.into_iter().map(|s| s.url).collect::<Vec<_>>();
+
+            (album.name, album_entries(paths))
+        }
+        DownloadID::Playlist(playlist) => {
+            let songs = conn.command(GetPlaylist(&playlist.name)).await?;
+            // Streams can't be downloaded, there is nothing to put into the archive
+            let paths = songs
+                .into_iter()
+                .filter(|s| !is_stream(s))
+                .map(|s| s.url)
+                .collect::<Vec<_>>();
+
+            (playlist.name, playlist_entries(paths))
+        }
+    };
+    drop(conn);
+    if entries.is_empty() {
+        return Err(Error::not_found());
+    }
+
+    let disposition = format!("attachment; filename=\"{}.zip\"", attachment_name(&name));
+
+    let (writer, reader) = tokio::io::duplex(DOWNLOAD_BUFFER_SIZE);
+    tokio::spawn(async move {
+        if let Err(err) = write_zip(&*state.lib, &entries, writer).await {
+            warn!(archive = ?name, action = "zip", err = ?err);
         }
-        Some(_) => return Err(Error::generic_error(Some("unsupported format"))),
+    });
+
+    let mut res = Body::from_stream(ReaderStream::new(reader)).into_response();
+    let headers = res.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/zip"),
+    );
+    if let Ok(v) = HeaderValue::from_str(&disposition) {
+        headers.insert(header::CONTENT_DISPOSITION, v);
+    }
+
+    Ok(res)
+}
+
+// ZipEntry is a song stored in a zip archive under the given name
+struct ZipEntry {
+    path: String,
+    name: String,
+}
+
+// album_entries names songs of an album by their paths relative to the album directory
+fn album_entries(paths: Vec<String>) -> Vec<ZipEntry> {
+    let components = paths
+        .iter()
+        .map(|p| p.split('/').collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    // Number of leading directories shared by all the songs
+    let common = components.iter().map(|c| c.len() - 1).min().unwrap_or(0);
+    let common = (0..common)
+        .take_while(|&i| components.iter().all(|c| c[i] == components[0][i]))
+        .count();
+
+    paths
+        .iter()
+        .zip(components.iter())
+        .map(|(path, components)| ZipEntry {
+            path: path.clone(),
+            name: components[common..].join("/"),
+        })
+        .collect()
+}
+
+// playlist_entries names songs of a playlist by their file names prefixed with their positions,
+// so that the order is kept and the same file names in different directories don't clash
+fn playlist_entries(paths: Vec<String>) -> Vec<ZipEntry> {
+    let width = paths.len().to_string().len().max(2);
+
+    paths
+        .into_iter()
+        .enumerate()
+        .map(|(idx, path)| ZipEntry {
+            name: format!(
+                "{:0width$} - {}",
+                idx + 1,
+                path.rsplit('/').next().unwrap_or(&path)
+            ),
+            path,
+        })
+        .collect()
+}
+
+// attachment_name makes the name safe to be used as a file name in Content-Disposition header
+fn attachment_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            ' '..='~' if !matches!(c, '"' | '\\' | '/') => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+// write_zip writes an uncompressed zip archive with the songs to the writer. Songs are streamed
+// from the library one by one, so only a small part of the archive is kept in memory. The reply
+// is already on its way to the client, so songs the library can't open are left out rather than
+// failing the whole archive.
+async fn write_zip<W>(
+    lib: &(dyn library::Library + Send + Sync),
+    entries: &[ZipEntry],
+    writer: W,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    for entry in entries {
+        let song = match lib.get_song(&entry.path, None).await {
+            Ok(song) => song,
+            Err(err) => {
+                warn!(path = ?entry.path, action = "zip", err = ?err);
+                continue;
+            }
+        };
+
+        let mut writer = zip
+            .write_entry_stream(ZipEntryBuilder::new(
+                entry.name.clone().into(),
+                Compression::Stored,
+            ))
+            .await?;
+        futures::io::copy(
+            song.stream
+                .map(|x| x.map_err(std::io::Error::from))
+                .into_async_read(),
+            &mut writer,
+        )
+        .await?;
+        writer.close().await?;
+    }
+    zip.close().await?;
+
+    Ok(())
+}
+
+// song_mime guesses MIME type of a song file from its extension
+pub(crate) fn song_mime(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("flac") => "audio/flac",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg" | "oga" | "opus") => "audio/ogg",
+        Some("m4a" | "mp4") => "audio/mp4",
+        Some("aac") => "audio/aac",
+        Some("wav") => "audio/wav",
+        Some("wv") => "audio/x-wavpack",
+        _ => "application/octet-stream",
+    }
+}
+
+// wait_transcoder waits for the transcoder to exit. If it exits with a non-zero code, the error
+// contains whatever it printed to stderr. The output stream simply ends in this case. Being
+// killed by a signal (e.g. when the server shuts down) is not an error, and neither is failing
+// after the client abandoned the output (ffmpeg ignores SIGPIPE and fails to write instead).
+async fn wait_transcoder(child: Child, abandoned: &AtomicBool) -> Result<(), String> {
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    match output.status.code() {
+        Some(0) | None => Ok(()),
+        Some(_) if abandoned.load(Ordering::SeqCst) => Ok(()),
+        Some(code) => Err(format!(
+            "ffmpeg exited with code {code}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+// range_not_satisfiable is the reply to a range request outside of the song. The size of the song
+// is reported if known, so that the client can retry with a valid range.
+fn range_not_satisfiable(size: Option<u64>) -> Response {
+    let mut res = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+    if let Some(v) = size.and_then(|s| HeaderValue::from_str(&format!("bytes */{s}")).ok()) {
+        res.headers_mut().insert(header::CONTENT_RANGE, v);
+    }
+    res
+}
+
+// transcoded_stream converts transcoder output into a stream of chunks. A chunk is produced as
+// soon as any output is available, without waiting for the buffer to fill up, so that clients
+// can start playback quickly. abandoned is set if the stream is dropped before the end of the
+// output.
+fn transcoded_stream<R>(
+    output: R,
+    abandoned: Arc<AtomicBool>,
+) -> BoxStream<'static, library::Result<Bytes>>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let output = TranscoderOutput {
+        output,
+        eof: false,
+        abandoned,
     };
+    ReaderStream::new(output)
+        .map(|x| x.map_err(Into::into))
+        .boxed()
+}
+
+// TranscoderOutput is transcoder output which records if it was dropped before the end
+struct TranscoderOutput<R> {
+    output: R,
+    eof: bool,
+    abandoned: Arc<AtomicBool>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TranscoderOutput<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut this.output).poll_read(cx, buf);
+        if matches!(res, Poll::Ready(Ok(()))) && buf.filled().len() == filled {
+            this.eof = true;
+        }
+        res
+    }
+}
 
-    Ok(Body::from_stream(output_stream))
+impl<R> Drop for TranscoderOutput<R> {
+    fn drop(&mut self) {
+        if !self.eof {
+            self.abandoned.store(true, Ordering::SeqCst);
+        }
+    }
 }
 
 #[derive(Clone, Deserialize)]
@@ -193,12 +870,1245 @@ struct GetAvatarQuery {
     username: String,
 }
 
-async fn get_avatar(Query(params): Query<GetAvatarQuery>) -> super::Result<Response> {
-    match params.u == params.username {
-        true => Err(Error::not_found()),
-        false => Err(Error::not_authorized(&format!(
+async fn get_avatar(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(params): Query<GetAvatarQuery>,
+) -> super::Result<Response> {
+    if params.u != params.username {
+        return Err(Error::not_authorized(&format!(
             "{} is not authorized to get details for other users.",
             params.u
-        ))),
+        )));
+    }
+
+    // Other users don't have avatars
+    let avatar = match &state.avatar {
+        Some((username, avatar)) if *username == params.username => avatar,
+        _ => return Err(Error::not_found()),
+    };
+    let mut res = tokio::fs::read(avatar).await?.into_response();
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(image_mime(avatar)),
+    );
+
+    Ok(res)
+}
+
+// Keys of the raw song tags which may contain lyrics
+const LYRICS_TAGS: &[&str] = &["lyrics", "unsyncedlyrics", "unsynced lyrics", "uslt"];
+
+// Extensions of sidecar lyrics files, in the order of preference
+const LYRICS_EXTENSIONS: &[&str] = &["lrc", "txt"];
+
+// ISO 639 code of lyrics in an unknown language
+const LYRICS_LANG_UNDETERMINED: &str = "und";
+
+#[derive(Clone, Deserialize)]
+struct GetLyricsQuery {
+    artist: Option<String>,
+    title: Option<String>,
+}
+
+async fn get_lyrics(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(params): Query<GetLyricsQuery>,
+) -> super::Result<Lyrics> {
+    let Some(title) = params.title.as_deref() else {
+        return Ok(Lyrics::default());
+    };
+    let filter = params
+        .artist
+        .as_ref()
+        .map(|artist| Filter::tag(Tag::Artist, artist))
+        .into_iter()
+        .fold(Filter::tag(Tag::Title, title), Filter::and);
+
+    let conn = state.pool.get().await?;
+    let songs = conn.command(Find::new(filter).window(0..1)).await?;
+    let Some(song) = songs.first() else {
+        return Ok(Lyrics::default());
+    };
+
+    drop(conn);
+
+    let lyrics = song_lyrics(&state, &song.url)
+        .await?
+        .into_iter()
+        .next()
+        .map(|lyrics| match is_lrc(&lyrics) {
+            true => lrc_to_text(&lyrics),
+            false => lyrics.trim().to_string(),
+        })
+        .unwrap_or_default();
+
+    Ok(Lyrics {
+        artist: song.artists().first().cloned(),
+        title: song.title().map(str::to_string),
+        value: lyrics,
+    })
+}
+
+// song_lyrics returns all lyrics of the song in the order of preference: embedded in the song
+// tags first, then sidecar files
+async fn song_lyrics(state: &super::State, path: &str) -> super::Result<Vec<String>> {
+    // Not all files support reading raw tags, treat failures as if there are no lyrics
+    let comments = state
+        .pool
+        .get()
+        .await?
+        .command(ReadComments::new(path))
+        .await
+        .unwrap_or_default();
+
+    let mut lyrics = embedded_lyrics(&comments).into_iter().collect::<Vec<_>>();
+    lyrics.extend(sidecar_lyrics(state.lib.as_ref(), path).await);
+    Ok(lyrics)
+}
+
+// embedded_lyrics returns lyrics from raw song tags if the song has them
+fn embedded_lyrics(comments: &[(String, String)]) -> Option<String> {
+    comments
+        .iter()
+        .find(|(key, value)| {
+            !value.trim().is_empty() && LYRICS_TAGS.contains(&key.to_lowercase().as_str())
+        })
+        .map(|(_, value)| value.clone())
+}
+
+// sidecar_lyrics returns lyrics from files next to the song with the same name and .lrc or .txt
+// extension
+async fn sidecar_lyrics(lib: &(dyn library::Library + Send + Sync), path: &str) -> Vec<String> {
+    let path = Path::new(path);
+    let dir = path.parent().unwrap_or(Path::new("")).to_string_lossy();
+    let mut lyrics = Vec::new();
+    for extension in LYRICS_EXTENSIONS {
+        let Some(name) = path
+            .with_extension(extension)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+        else {
+            continue;
+        };
+        let Ok(Some(sidecar)) = lib.find_sibling(&dir, &[&name]).await else {
+            continue;
+        };
+        let Ok(file) = lib.get_song(&sidecar, None).await else {
+            continue;
+        };
+        let Ok(data) = file.stream.try_collect::<Vec<_>>().await else {
+            continue;
+        };
+        lyrics.push(decode_lyrics(&data.concat()));
+    }
+
+    lyrics
+}
+
+// decode_lyrics decodes contents of a lyrics file. Files are expected to be UTF-8, possibly with
+// a BOM, but older ones are often Latin-1. Latin-1 is assumed for anything that is not valid
+// UTF-8, as every byte sequence is valid Latin-1.
+fn decode_lyrics(data: &[u8]) -> String {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    match std::str::from_utf8(data) {
+        Ok(lyrics) => lyrics.to_string(),
+        Err(_) => data.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+// is_lrc checks if the lyrics have LRC timestamps. Some taggers store synced lyrics in the
+// unsynced tags, so this is checked regardless of where the lyrics come from.
+fn is_lrc(lyrics: &str) -> bool {
+    lyrics.lines().any(|line| {
+        line.trim()
+            .strip_prefix('[')
+            .and_then(|l| l.split_once(']'))
+            .is_some_and(|(tag, _)| lrc_timestamp(tag).is_some())
+    })
+}
+
+// lrc_to_text converts LRC lyrics into plain text by dropping timestamps and metadata lines
+fn lrc_to_text(lrc: &str) -> String {
+    lrc.lines()
+        .filter_map(|line| {
+            let mut line = line.trim();
+            let mut timed = false;
+            while let Some((tag, rest)) = line.strip_prefix('[').and_then(|l| l.split_once(']')) {
+                // Lines with metadata (e.g. [ar:Artist]) are dropped
+                lrc_timestamp(tag)?;
+                timed = true;
+                line = rest.trim_start();
+            }
+            (timed || !line.is_empty()).then(|| line.to_string())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+// parse_lrc parses LRC lyrics into lines ordered by their start time (in milliseconds). Lines
+// with several timestamps are repeated at each of them, metadata and untimed lines are skipped.
+fn parse_lrc(lrc: &str) -> Vec<LyricsLine> {
+    let mut lines = lrc
+        .lines()
+        .flat_map(|line| {
+            let mut line = line.trim();
+            let mut starts = Vec::new();
+            while let Some((tag, rest)) = line.strip_prefix('[').and_then(|l| l.split_once(']')) {
+                match lrc_timestamp(tag) {
+                    Some(start) => starts.push(start),
+                    None => return Vec::new(),
+                }
+                line = rest.trim_start();
+            }
+            starts
+                .into_iter()
+                .map(|start| LyricsLine {
+                    start: Some(start),
+                    value: line.to_string(),
+                })
+                .collect()
+        })
+        .collect::<Vec<_>>();
+    lines.sort_by_key(|l| l.start);
+    lines
+}
+
+// lrc_timestamp parses LRC timestamp in [mm:ss], [mm:ss.xx] or [mm:ss.xxx] format into
+// milliseconds
+fn lrc_timestamp(tag: &str) -> Option<u64> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+    if !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let millis = format!("{fraction:0<3}")[..3].parse::<u64>().ok()?;
+
+    Some(minutes.parse::<u64>().ok()? * 60_000 + seconds.parse::<u64>().ok()? * 1000 + millis)
+}
+
+#[derive(Serialize, YaSerialize, Default)]
+#[yaserde(rename = "lyrics")]
+struct Lyrics {
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artist: Option<String>,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[yaserde(text)]
+    value: String,
+}
+
+impl super::Reply for Lyrics {
+    fn field_name() -> Option<&'static str> {
+        Some("lyrics")
+    }
+}
+
+#[derive(Clone, Deserialize)]
+struct GetLyricsBySongIdQuery {
+    #[serde(rename = "id")]
+    song: SongID,
+}
+
+async fn get_lyrics_by_song_id(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(params): Query<GetLyricsBySongIdQuery>,
+) -> super::Result<LyricsList> {
+    let conn = state.pool.get().await?;
+    let songs = conn
+        .command(Find::new(Filter::tag(
+            Tag::Other("file".into()),
+            &params.song.path,
+        )))
+        .await?;
+    let song = songs.into_iter().next().ok_or_else(Error::not_found)?;
+    drop(conn);
+
+    let lyrics = song_lyrics(&state, &song.url).await?;
+    // Synced lyrics are preferred even if they come from a less preferred source
+    let lyrics = match lyrics.iter().position(|l| is_lrc(l)) {
+        Some(synced) => Some(StructuredLyrics::synced(&lyrics[synced])),
+        None => lyrics.first().map(|l| StructuredLyrics::unsynced(l)),
+    };
+
+    Ok(LyricsList {
+        lyrics: lyrics
+            .map(|lyrics| StructuredLyrics {
+                display_artist: song.artists().first().cloned(),
+                display_title: song.title().map(str::to_string),
+                ..lyrics
+            })
+            .into_iter()
+            .collect(),
+    })
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "lyricsList")]
+struct LyricsList {
+    #[yaserde(child, rename = "structuredLyrics")]
+    #[serde(rename = "structuredLyrics")]
+    lyrics: Vec<StructuredLyrics>,
+}
+
+impl super::Reply for LyricsList {
+    fn field_name() -> Option<&'static str> {
+        Some("lyricsList")
+    }
+}
+
+#[derive(Serialize, YaSerialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct StructuredLyrics {
+    #[yaserde(attribute, rename = "displayArtist")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_artist: Option<String>,
+    #[yaserde(attribute, rename = "displayTitle")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_title: Option<String>,
+    // Language of the lyrics is unknown, so it's always "und" (undetermined)
+    #[yaserde(attribute)]
+    lang: String,
+    #[yaserde(attribute)]
+    synced: bool,
+    #[yaserde(child)]
+    line: Vec<LyricsLine>,
+}
+
+impl StructuredLyrics {
+    // synced creates lyrics from LRC, keeping start time of every line
+    fn synced(lrc: &str) -> Self {
+        StructuredLyrics {
+            lang: LYRICS_LANG_UNDETERMINED.to_string(),
+            synced: true,
+            line: parse_lrc(lrc),
+            ..Default::default()
+        }
+    }
+
+    // unsynced creates lyrics from plain text, line by line
+    fn unsynced(text: &str) -> Self {
+        StructuredLyrics {
+            lang: LYRICS_LANG_UNDETERMINED.to_string(),
+            synced: false,
+            line: text
+                .trim()
+                .lines()
+                .map(|line| LyricsLine {
+                    start: None,
+                    value: line.trim().to_string(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, YaSerialize, Debug, Default, PartialEq)]
+struct LyricsLine {
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start: Option<u64>,
+    #[yaserde(text)]
+    value: String,
+}
+
+// image_mime guesses MIME type of an image file from its extension
+fn image_mime(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("bmp") => "image/bmp",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        album_entries, artist_image_path, attachment_name, download, ffmpeg_args, get_avatar,
+        get_cover_art, get_lyrics, get_lyrics_by_song_id, image_mime, is_lrc, lrc_to_text,
+        parse_lrc, playlist_entries, sidecar_lyrics, song_mime, stream, stream_path,
+        transcoded_stream, wait_transcoder, Cover, CoverCache, DownloadQuery, GetAvatarQuery,
+        GetCoverArtQuery, GetLyricsBySongIdQuery, GetLyricsQuery, Lyrics, LyricsLine, LyricsList,
+        StreamQuery, StructuredLyrics, TranscodeFormat,
+    };
+    use crate::{
+        api::{
+            error::Error,
+            expect_ok_json, expect_ok_xml, json, test_state_with_mpd,
+            types::{AlbumID, CoverArtID, PlaylistID, SongID},
+            xml,
+        },
+        coverartarchive::{self, testing::fake_archive},
+        library::get_library,
+        mpd::testing::fake_server,
+    };
+    use async_zip::base::read::mem::ZipFileReader;
+    use axum::{
+        extract::{Extension, Query},
+        http::{header, HeaderMap, HeaderValue, StatusCode},
+    };
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+    use std::{
+        fs,
+        path::Path,
+        time::{Duration, Instant},
+    };
+    use tokio::{io::AsyncWriteExt, net::TcpListener, time::timeout};
+
+    #[tokio::test]
+    async fn stream_current_song() {
+        let mpd = fake_server(|command| match command {
+            "currentsong" => "file: alpha/1.flac\nTitle: one\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let path = |id: &str| {
+            let query: StreamQuery = serde_urlencoded::from_str(&format!("id={id}")).unwrap();
+            stream_path(&state, query.song)
+        };
+
+        assert!(matches!(path("current").await, Ok(p) if p == "alpha/1.flac"));
+        let id = serde_json::to_value(SongID::new("alpha/2.flac")).unwrap();
+        assert!(matches!(path(id.as_str().unwrap()).await, Ok(p) if p == "alpha/2.flac"));
+    }
+
+    fn transcoder(script: &str) -> tokio::process::Child {
+        tokio::process::Command::new("sh")
+            .args(["-c", script])
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn transcoder_failure() {
+        let running = AtomicBool::new(false);
+        let res = wait_transcoder(
+            transcoder("echo 'pipe:: Invalid data found when processing input' >&2; exit 183"),
+            &running,
+        )
+        .await;
+        assert_eq!(
+            res,
+            Err(
+                "ffmpeg exited with code 183: pipe:: Invalid data found when processing input"
+                    .to_string()
+            )
+        );
+
+        assert_eq!(
+            wait_transcoder(transcoder("exit 0"), &running).await,
+            Ok(())
+        );
+        assert_eq!(
+            wait_transcoder(transcoder("kill -KILL $$"), &running).await,
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    async fn transcoder_abandoned() {
+        // Like ffmpeg, the transcoder ignores SIGPIPE and fails to write once the client is gone
+        let mut child = transcoder("trap '' PIPE; while echo output; do :; done; exit 1");
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let mut stream = transcoded_stream(child.stdout.take().unwrap(), abandoned.clone());
+        assert!(stream.next().await.unwrap().is_ok());
+        drop(stream);
+        assert_eq!(wait_transcoder(child, &abandoned).await, Ok(()));
+
+        // Output read to the end doesn't hide failures
+        let mut child = transcoder("echo output; echo failed >&2; exit 1");
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let mut stream = transcoded_stream(child.stdout.take().unwrap(), abandoned.clone());
+        while stream.next().await.is_some() {}
+        drop(stream);
+        assert_eq!(
+            wait_transcoder(child, &abandoned).await,
+            Err("ffmpeg exited with code 1: failed".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn transcoded_stream_latency() {
+        let (mut ffmpeg, output) = tokio::io::duplex(64 * 1024);
+        let mut stream = transcoded_stream(output, Default::default());
+
+        // A small piece of output must be sent right away even though the transcoder is still
+        // running and the buffer is far from full
+        let started = Instant::now();
+        ffmpeg.write_all(&[1; 100]).await.unwrap();
+        let chunk = timeout(Duration::from_secs(1), stream.next())
+            .await
+            .expect("first chunk is delayed")
+            .unwrap()
+            .unwrap();
+        assert_eq!(&chunk[..], &[1; 100]);
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        ffmpeg.write_all(&[2; 100]).await.unwrap();
+        drop(ffmpeg);
+
+        let mut len = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.unwrap();
+            assert!(chunk.iter().all(|&b| b == 2));
+            len += chunk.len();
+        }
+        assert_eq!(len, 100);
+    }
+
+    #[test]
+    fn artist_image() {
+        let dir =
+            std::env::temp_dir().join(format!("mpdsonic-artist-images-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("alpha.jpg"), b"image").unwrap();
+        fs::write(dir.join(".jpg"), b"image").unwrap();
+
+        // Image is found
+        assert_eq!(
+            artist_image_path(Some(&dir), "alpha"),
+            Some(dir.join("alpha.jpg"))
+        );
+
+        // Fallback to album art
+        assert_eq!(artist_image_path(None, "alpha"), None);
+        assert_eq!(artist_image_path(Some(&dir), "beta"), None);
+        assert_eq!(artist_image_path(Some(&dir), ""), None);
+        assert_eq!(artist_image_path(Some(&dir), "../alpha"), None);
+        assert_eq!(artist_image_path(Some(&dir.join("sub")), "../alpha"), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn transcode_format() {
+        assert_eq!("ogg".parse(), Ok(TranscodeFormat::Opus));
+        assert_eq!("opus".parse(), Ok(TranscodeFormat::Opus));
+        assert_eq!("mp3".parse(), Ok(TranscodeFormat::Mp3));
+        assert_eq!("aac".parse(), Ok(TranscodeFormat::Aac));
+        assert!("flac".parse::<TranscodeFormat>().is_err());
+
+        let args = ffmpeg_args(TranscodeFormat::Mp3, 128 * 1024);
+        let arg = |name: &str| {
+            let pos = args.iter().position(|a| a == name).unwrap();
+            args[pos + 1].as_str()
+        };
+        assert_eq!(arg("-b:a"), "131072");
+        assert_eq!(arg("-c:a"), "libmp3lame");
+        assert_eq!(arg("-f"), "mp3");
+        assert!(!args.iter().any(|a| a == "-vbr"));
+
+        let args = ffmpeg_args(TranscodeFormat::Opus, 96 * 1024);
+        assert!(args.windows(2).any(|a| a == ["-c:a", "libopus"]));
+        assert!(args.windows(2).any(|a| a == ["-vbr", "on"]));
+        assert!(args.ends_with(&["-f".to_string(), "opus".to_string(), "-".to_string()]));
+    }
+
+    #[test]
+    fn content_type() {
+        assert_eq!(TranscodeFormat::Opus.mime(), "audio/ogg");
+        assert_eq!(TranscodeFormat::Mp3.mime(), "audio/mpeg");
+        assert_eq!(TranscodeFormat::Aac.mime(), "audio/aac");
+
+        assert_eq!(song_mime("alpha/beta/01 - song.flac"), "audio/flac");
+        assert_eq!(song_mime("alpha/beta/01 - song.MP3"), "audio/mpeg");
+        assert_eq!(song_mime("alpha/beta/01 - song.opus"), "audio/ogg");
+        assert_eq!(song_mime("alpha/beta/song"), "application/octet-stream");
+    }
+
+    #[test]
+    fn zip_entries() {
+        let names = |entries: Vec<super::ZipEntry>| {
+            entries
+                .into_iter()
+                .map(|e| (e.path, e.name))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(album_entries(vec![
+                "alpha/beta/CD1/01.flac".to_string(),
+                "alpha/beta/CD2/01.flac".to_string(),
+            ])),
+            vec![
+                (
+                    "alpha/beta/CD1/01.flac".to_string(),
+                    "CD1/01.flac".to_string()
+                ),
+                (
+                    "alpha/beta/CD2/01.flac".to_string(),
+                    "CD2/01.flac".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            names(album_entries(vec!["01.flac".to_string()])),
+            vec![("01.flac".to_string(), "01.flac".to_string())]
+        );
+
+        assert_eq!(
+            names(playlist_entries(vec![
+                "alpha/01.flac".to_string(),
+                "beta/01.flac".to_string(),
+            ])),
+            vec![
+                ("alpha/01.flac".to_string(), "01 - 01.flac".to_string()),
+                ("beta/01.flac".to_string(), "02 - 01.flac".to_string()),
+            ]
+        );
+
+        assert_eq!(
+            attachment_name("AC/DC \"Back in Black\""),
+            "AC_DC _Back in Black_"
+        );
+        assert_eq!(attachment_name("Sigur Rós"), "Sigur R_s");
+    }
+
+    #[tokio::test]
+    async fn stream_range() {
+        let dir = std::env::temp_dir().join(format!("mpdsonic-range-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("1.flac"), b"0123456789").unwrap();
+
+        let mpd = fake_server(|_| String::new()).await;
+        let mut state = Arc::into_inner(test_state_with_mpd(mpd).await).unwrap();
+        state.lib = get_library(dir.to_str().unwrap()).await.unwrap();
+        let state = Arc::new(state);
+        let request = |range: &'static str| {
+            let id = serde_json::to_value(SongID::new("1.flac")).unwrap();
+            let query: StreamQuery = serde_urlencoded::from_str(
+                &serde_urlencoded::to_string([("id", id.as_str().unwrap()), ("format", "raw")])
+                    .unwrap(),
+            )
+            .unwrap();
+            let mut headers = HeaderMap::new();
+            headers.insert(header::RANGE, HeaderValue::from_static(range));
+            stream(Extension(state.clone()), Query(query), headers)
+        };
+
+        let Ok(res) = request("bytes=2-4").await else {
+            panic!("stream failed");
+        };
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes 2-4/10");
+
+        let Ok(res) = request("bytes=10-").await else {
+            panic!("stream failed");
+        };
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes */10");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_album() {
+        let dir = std::env::temp_dir().join(format!("mpdsonic-download-{}", std::process::id()));
+        fs::create_dir_all(dir.join("alpha/beta")).unwrap();
+        fs::write(dir.join("alpha/beta/1.flac"), b"first song").unwrap();
+        fs::write(dir.join("alpha/beta/2.flac"), b"second song").unwrap();
+
+        let mpd = fake_server(|command| match command.starts_with("find") {
+            true => "file: alpha/beta/1.flac\nfile: alpha/beta/2.flac\n".to_string(),
+            false => String::new(),
+        })
+        .await;
+        let mut state = Arc::into_inner(test_state_with_mpd(mpd).await).unwrap();
+        state.lib = get_library(dir.to_str().unwrap()).await.unwrap();
+
+        let id = serde_json::to_value(AlbumID::new("beta", "alpha")).unwrap();
+        let query: DownloadQuery = serde_urlencoded::from_str(
+            &serde_urlencoded::to_string([("id", id.as_str())]).unwrap(),
+        )
+        .unwrap();
+        let Ok(res) = download(Extension(Arc::new(state)), Query(query)).await else {
+            panic!("download failed");
+        };
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/zip");
+        assert_eq!(
+            res.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"beta.zip\""
+        );
+
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let zip = ZipFileReader::new(body.to_vec()).await.unwrap();
+        let mut entries = Vec::new();
+        for (idx, entry) in zip.file().entries().iter().enumerate() {
+            let mut data = String::new();
+            zip.reader_with_entry(idx)
+                .await
+                .unwrap()
+                .read_to_string_checked(&mut data)
+                .await
+                .unwrap();
+            entries.push((entry.filename().as_str().unwrap().to_string(), data));
+        }
+        assert_eq!(
+            entries,
+            vec![
+                ("1.flac".to_string(), "first song".to_string()),
+                ("2.flac".to_string(), "second song".to_string()),
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_read_only() {
+        let mpd = fake_server(|_| String::new()).await;
+        let mut state = Arc::into_inner(test_state_with_mpd(mpd).await).unwrap();
+        state.read_only = true;
+
+        let query: DownloadQuery = serde_urlencoded::from_str(
+            &serde_urlencoded::to_string([("id", SongID::new("alpha/1.flac"))]).unwrap(),
+        )
+        .unwrap();
+        let res = download(Extension(Arc::new(state)), Query(query)).await;
+        assert!(matches!(res, Err(err) if xml(&err)
+            == xml(&Error::not_authorized("Server is running in read-only mode"))));
+    }
+
+    #[tokio::test]
+    async fn download_playlist() {
+        let dir =
+            std::env::temp_dir().join(format!("mpdsonic-download-playlist-{}", std::process::id()));
+        fs::create_dir_all(dir.join("alpha")).unwrap();
+        fs::write(dir.join("alpha/1.flac"), b"first song").unwrap();
+        fs::write(dir.join("alpha/2.flac"), b"second song").unwrap();
+
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("listplaylistinfo") => "file: alpha/1.flac\n\
+                                         file: http://radio.example.com/stream\n\
+                                         file: alpha/missing.flac\n\
+                                         file: alpha/2.flac\n"
+                .to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let mut state = Arc::into_inner(test_state_with_mpd(mpd).await).unwrap();
+        state.lib = get_library(dir.to_str().unwrap()).await.unwrap();
+
+        let id = serde_json::to_value(PlaylistID::new("gamma")).unwrap();
+        let query: DownloadQuery = serde_urlencoded::from_str(
+            &serde_urlencoded::to_string([("id", id.as_str())]).unwrap(),
+        )
+        .unwrap();
+        let Ok(res) = download(Extension(Arc::new(state)), Query(query)).await else {
+            panic!("download failed");
+        };
+
+        // Streams and unreadable songs are left out, the archive is still complete
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let zip = ZipFileReader::new(body.to_vec()).await.unwrap();
+        let names = zip
+            .file()
+            .entries()
+            .iter()
+            .map(|e| e.filename().as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["01 - 1.flac", "03 - 2.flac"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn remote_cover() {
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("albumart") => "ACK [50@0] {albumart} No file exists\n".to_string(),
+            Some("find") if command.contains("alpha") => {
+                "file: alpha/1.flac\nMUSICBRAINZ_ALBUMID: alpha\n".to_string()
+            }
+            Some("find") => "file: beta/1.flac\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let (endpoint, requests) = fake_archive().await;
+        let query = |path: &str| {
+            Query(GetCoverArtQuery {
+                cover: CoverArtID::new(path),
+            })
+        };
+
+        // Remote covers are not fetched unless enabled
+        let state = test_state_with_mpd(mpd).await;
+        assert!(get_cover_art(Extension(state), query("alpha/1.flac"))
+            .await
+            .is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        let mut state = Arc::into_inner(test_state_with_mpd(mpd).await).unwrap();
+        state.cover_art_archive = Some(coverartarchive::Client::with_endpoint(&endpoint).unwrap());
+        let state = Arc::new(state);
+
+        let Ok(res) = get_cover_art(Extension(state.clone()), query("alpha/1.flac")).await else {
+            panic!("remote cover is not fetched");
+        };
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "image/jpeg");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "alpha cover");
+
+        // Songs without a release ID have no remote cover
+        assert!(get_cover_art(Extension(state), query("beta/1.flac"))
+            .await
+            .is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn library_cover() {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let commands = commands.clone();
+            move |command| {
+                commands.lock().unwrap().push(command.to_string());
+                match command.split(' ').next() {
+                    Some("albumart") => "size: 9\ntype: image/png\nbinary: 9\nmpd cover\n",
+                    _ => "",
+                }
+                .to_string()
+            }
+        })
+        .await;
+        let router = axum::Router::new().route(
+            "/music/alpha/folder.png",
+            axum::routing::get(|| async { "lib cover" }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let mut state = Arc::into_inner(test_state_with_mpd(mpd).await).unwrap();
+        state.lib = get_library(&format!("http://{address}/music/"))
+            .await
+            .unwrap();
+        let state = Arc::new(state);
+        let cover = |path: &str| {
+            let state = state.clone();
+            let cover = CoverArtID::new(path);
+            async move {
+                let Ok(res) =
+                    get_cover_art(Extension(state), Query(GetCoverArtQuery { cover })).await
+                else {
+                    panic!("getCoverArt failed");
+                };
+                let mime = res.headers()[header::CONTENT_TYPE]
+                    .to_str()
+                    .unwrap()
+                    .to_string();
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (mime, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        assert_eq!(
+            cover("alpha/1.flac").await,
+            ("image/png".to_string(), "lib cover".to_string())
+        );
+        assert!(!commands
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|c| c.starts_with("albumart")));
+
+        // Without a cover next to the song, MPD is asked for it
+        assert_eq!(
+            cover("beta/1.flac").await,
+            ("image/png".to_string(), "mpd cover".to_string())
+        );
+        assert!(commands
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|c| c.starts_with("albumart")));
+    }
+
+    #[tokio::test]
+    async fn cover_cache() {
+        let fetches = AtomicUsize::new(0);
+        let fetch = |data: &'static str| {
+            let fetches = &fetches;
+            move || async move {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                // Give concurrent requests a chance to pile up
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(Cover {
+                    data: Bytes::from_static(data.as_bytes()),
+                    mime: Some("image/png".to_string()),
+                })
+            }
+        };
+        let cache = CoverCache::new(1);
+
+        // Concurrent requests for the same cover fetch it only once
+        let (first, second) = tokio::join!(
+            cache.get_or_fetch("alpha", fetch("alpha")),
+            cache.get_or_fetch("alpha", fetch("alpha"))
+        );
+        assert!(
+            matches!((first, second), (Ok(a), Ok(b)) if a.data == "alpha" && b.data == "alpha")
+        );
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        let Ok(cover) = cache.get_or_fetch("alpha", fetch("alpha")).await else {
+            panic!("cover is not fetched");
+        };
+        assert_eq!(cover.mime.as_deref(), Some("image/png"));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // The least recently used cover is evicted
+        assert!(cache.get_or_fetch("beta", fetch("beta")).await.is_ok());
+        assert!(cache.get_or_fetch("alpha", fetch("alpha")).await.is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+
+        // Failures are not cached and don't push covers out of the cache
+        let fail = || async { Err(crate::api::Error::not_found()) };
+        assert!(cache.get_or_fetch("gamma", fail).await.is_err());
+        assert!(cache.get_or_fetch("alpha", fetch("alpha")).await.is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+        assert!(cache.get_or_fetch("gamma", fetch("gamma")).await.is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 4);
+
+        // Covers are limited by their total size too
+        let cache = CoverCache::with_limits(10, 8, Duration::from_secs(60));
+        assert!(cache.get_or_fetch("alpha", fetch("alpha")).await.is_ok());
+        assert!(cache.get_or_fetch("beta", fetch("beta")).await.is_ok());
+        assert!(cache.get_or_fetch("beta", fetch("beta")).await.is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 6);
+        assert!(cache.get_or_fetch("alpha", fetch("alpha")).await.is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 7);
+        // A cover larger than the limit is not kept at all
+        assert!(cache
+            .get_or_fetch("too large", fetch("too large"))
+            .await
+            .is_ok());
+        assert!(cache
+            .get_or_fetch("too large", fetch("too large"))
+            .await
+            .is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 9);
+
+        // Expired covers are fetched again
+        let cache = CoverCache::with_limits(10, 1024, Duration::ZERO);
+        assert!(cache.get_or_fetch("alpha", fetch("alpha")).await.is_ok());
+        assert!(cache.get_or_fetch("alpha", fetch("alpha")).await.is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 11);
+
+        // Zero size disables caching
+        let cache = CoverCache::new(0);
+        assert!(cache.get_or_fetch("alpha", fetch("alpha")).await.is_ok());
+        assert!(cache.get_or_fetch("alpha", fetch("alpha")).await.is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 13);
+    }
+
+    #[tokio::test]
+    async fn avatar() {
+        let dir = std::env::temp_dir().join(format!("mpdsonic-avatar-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("alice.png");
+        fs::write(&path, b"avatar").unwrap();
+
+        let query = |u: &str, username: &str| {
+            Query(GetAvatarQuery {
+                u: u.to_string(),
+                username: username.to_string(),
+            })
+        };
+
+        let state = test_state_with_mpd(([127, 0, 0, 1], 0).into()).await;
+        let res = get_avatar(Extension(state.clone()), query("alice", "alice")).await;
+        assert!(matches!(res, Err(err) if xml(&err) == xml(&Error::not_found())));
+
+        let mut state = Arc::into_inner(state).unwrap();
+        state.avatar = Some(("alice".to_string(), path));
+        let state = Arc::new(state);
+        let Ok(res) = get_avatar(Extension(state.clone()), query("alice", "alice")).await else {
+            panic!("getAvatar failed");
+        };
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "image/png");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"avatar");
+
+        let res = get_avatar(Extension(state.clone()), query("alice", "bob")).await;
+        assert!(
+            matches!(res, Err(err) if xml(&err) == xml(&Error::not_authorized(
+                "alice is not authorized to get details for other users."
+            )))
+        );
+
+        // The avatar belongs to the main user only
+        let res = get_avatar(Extension(state), query("bob", "bob")).await;
+        assert!(matches!(res, Err(err) if xml(&err) == xml(&Error::not_found())));
+
+        assert_eq!(image_mime(Path::new("a/b.JPG")), "image/jpeg");
+        assert_eq!(image_mime(Path::new("a/b")), "application/octet-stream");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lyrics() {
+        let lyrics = Lyrics {
+            artist: Some("alpha".to_string()),
+            title: Some("beta".to_string()),
+            value: "la la la".to_string(),
+        };
+        assert_eq!(
+            xml(&lyrics),
+            expect_ok_xml(Some(
+                r#"<lyrics artist="alpha" title="beta">la la la</lyrics>"#
+            ))
+        );
+        assert_eq!(
+            json(&lyrics),
+            expect_ok_json(Some(serde_json::json!({"lyrics": {
+                "artist": "alpha",
+                "title": "beta",
+                "value": "la la la",
+            }})))
+        );
+
+        assert_eq!(
+            lrc_to_text(
+                "[ar:alpha]\n[ti:beta]\n[00:01.00]first\n[00:02.00]\n[00:03.00][00:05.00]second\n"
+            ),
+            "first\n\nsecond"
+        );
+
+        // Section headers of plain lyrics are not timestamps
+        let plain = "[1st verse]\nla la la\n[2nd verse]\nlo lo lo";
+        assert!(!is_lrc(plain));
+        assert!(is_lrc("[01:02.50]la la la"));
+    }
+
+    #[test]
+    fn structured_lyrics() {
+        let lyrics = LyricsList {
+            lyrics: vec![StructuredLyrics {
+                display_artist: Some("alpha".to_string()),
+                display_title: Some("beta".to_string()),
+                lang: "und".to_string(),
+                synced: true,
+                line: vec![
+                    LyricsLine {
+                        start: Some(1000),
+                        value: "first".to_string(),
+                    },
+                    LyricsLine {
+                        start: Some(2500),
+                        value: "second".to_string(),
+                    },
+                ],
+            }],
+        };
+        assert_eq!(
+            xml(&lyrics),
+            expect_ok_xml(Some(
+                r#"<lyricsList>
+    <structuredLyrics displayArtist="alpha" displayTitle="beta" lang="und" synced="true">
+      <line start="1000">first</line>
+      <line start="2500">second</line>
+    </structuredLyrics>
+  </lyricsList>"#
+            ))
+        );
+        assert_eq!(
+            json(&lyrics),
+            expect_ok_json(Some(serde_json::json!({"lyricsList": {
+                "structuredLyrics": [{
+                    "displayArtist": "alpha",
+                    "displayTitle": "beta",
+                    "lang": "und",
+                    "synced": true,
+                    "line": [
+                        {"start": 1000, "value": "first"},
+                        {"start": 2500, "value": "second"},
+                    ],
+                }],
+            }})))
+        );
+
+        let line = |start, value: &str| LyricsLine {
+            start: Some(start),
+            value: value.to_string(),
+        };
+        assert_eq!(
+            parse_lrc(
+                "[ar:alpha]\n[00:01.5]first\n[00:02.25]\nuntimed\n[01:03.123][00:03]second\n"
+            ),
+            [
+                line(1500, "first"),
+                line(2250, ""),
+                line(3000, "second"),
+                line(63123, "second"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lyrics_by_song_id() {
+        let dir = std::env::temp_dir().join(format!("mpdsonic-lyrics-id-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let song = dir.join("song.flac").display().to_string();
+        let song = song.trim_start_matches('/').to_string();
+
+        let lyrics = |embedded: &'static str| {
+            let song = song.clone();
+            async move {
+                let mpd = fake_server({
+                    let song = song.clone();
+                    move |command| match command.split_whitespace().next() {
+                        Some("find") => format!("file: {song}\nTitle: beta\nArtist: alpha\n"),
+                        Some("readcomments") => embedded.to_string(),
+                        _ => String::new(),
+                    }
+                })
+                .await;
+                let query = GetLyricsBySongIdQuery {
+                    song: SongID::new(&song),
+                };
+                let Ok(lyrics) =
+                    get_lyrics_by_song_id(Extension(test_state_with_mpd(mpd).await), Query(query))
+                        .await
+                else {
+                    panic!("getLyricsBySongId failed");
+                };
+                lyrics.lyrics
+            }
+        };
+
+        assert!(lyrics("TITLE: beta\n").await.is_empty());
+
+        let unsynced = lyrics("LYRICS: from tags\n").await;
+        assert_eq!(unsynced.len(), 1);
+        assert!(!unsynced[0].synced);
+        assert_eq!(unsynced[0].display_artist.as_deref(), Some("alpha"));
+        assert_eq!(unsynced[0].display_title.as_deref(), Some("beta"));
+        assert_eq!(
+            unsynced[0].line,
+            [LyricsLine {
+                start: None,
+                value: "from tags".to_string()
+            }]
+        );
+
+        // Synced sidecar lyrics win over unsynced embedded ones
+        fs::write(dir.join("song.lrc"), "[00:01.00]from sidecar\n").unwrap();
+        let synced = lyrics("LYRICS: from tags\n").await;
+        assert_eq!(synced.len(), 1);
+        assert!(synced[0].synced);
+        assert_eq!(
+            synced[0].line,
+            [LyricsLine {
+                start: Some(1000),
+                value: "from sidecar".to_string()
+            }]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sidecar_lyrics_encoding() {
+        let dir = std::env::temp_dir().join(format!("mpdsonic-encoding-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("song.lrc"), "\u{FEFF}[00:01.00]Sigur Rós\n").unwrap();
+        fs::write(dir.join("song.txt"), b"Sigur R\xF3s\n").unwrap();
+        let lib = get_library(dir.to_str().unwrap()).await.unwrap();
+
+        assert_eq!(
+            sidecar_lyrics(lib.as_ref(), "song.flac").await,
+            ["[00:01.00]Sigur Rós\n", "Sigur Rós\n"]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn embedded_lyrics() {
+        let dir = std::env::temp_dir().join(format!("mpdsonic-lyrics-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("song.lrc"), "[00:01.00]from sidecar\n").unwrap();
+        let song = dir.join("song.flac").display().to_string();
+        let song = song.trim_start_matches('/').to_string();
+
+        let lyrics = |embedded: &'static str| {
+            let song = song.clone();
+            async move {
+                let mpd = fake_server(move |command| match command.split_whitespace().next() {
+                    Some("find") => format!("file: {song}\nTitle: beta\nArtist: alpha\n"),
+                    Some("readcomments") => embedded.to_string(),
+                    _ => String::new(),
+                })
+                .await;
+                let query = GetLyricsQuery {
+                    artist: Some("alpha".to_string()),
+                    title: Some("beta".to_string()),
+                };
+                let Ok(lyrics) =
+                    get_lyrics(Extension(test_state_with_mpd(mpd).await), Query(query)).await
+                else {
+                    panic!("getLyrics failed");
+                };
+                lyrics
+            }
+        };
+
+        let embedded = lyrics("TITLE: beta\nLYRICS: from tags\n").await;
+        assert_eq!(embedded.artist.as_deref(), Some("alpha"));
+        assert_eq!(embedded.title.as_deref(), Some("beta"));
+        assert_eq!(embedded.value, "from tags");
+
+        let embedded = lyrics("UNSYNCEDLYRICS: unsynced\n").await;
+        assert_eq!(embedded.value, "unsynced");
+
+        let embedded = lyrics("LYRICS: [00:01.00]timed\n").await;
+        assert_eq!(embedded.value, "timed");
+
+        let embedded = lyrics("LYRICS: [chorus] la la\n").await;
+        assert_eq!(embedded.value, "[chorus] la la");
+
+        let sidecar = lyrics("TITLE: beta\n").await;
+        assert_eq!(sidecar.value, "from sidecar");
+
+        let sidecar = lyrics("ACK [50@0] {readcomments} No such file\n").await;
+        assert_eq!(sidecar.value, "from sidecar");
+
+        fs::remove_file(dir.join("song.lrc")).unwrap();
+        let missing = lyrics("TITLE: beta\n").await;
+        assert_eq!(missing.value, "");
+
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,9 +1,13 @@
-use axum::{routing::Router, Extension};
-use mpd_client::commands::{Stats, Status, Update};
-use serde::Serialize;
-use std::sync::Arc;
+use crate::mpd::{Connection, IdleUpdate, UpdatingDb};
+use axum::{extract::Query, routing::Router, Extension};
+use mpd_client::commands::{Stats, Update};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
 use yaserde_derive::YaSerialize;
 
+// Maximum time getScanStatus waits for the scan to finish in long-poll mode
+const SCAN_STATUS_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub(crate) fn get_router() -> Router {
     Router::new()
         .route("/startScan.view", super::handler(start_scan))
@@ -11,15 +15,18 @@ pub(crate) fn get_router() -> Router {
 }
 
 async fn start_scan(Extension(state): Extension<Arc<super::State>>) -> super::Result<ScanStatus> {
-    let (_, stats, status) = state
+    state.ensure_writable()?;
+    state.artists_cache.clear();
+
+    let (_, stats, job) = state
         .pool
         .get()
         .await?
-        .command_list((Update::new(), Stats, Status))
+        .command_list((Update::new(), Stats, UpdatingDb))
         .await?;
 
     Ok(ScanStatus {
-        scanning: status.update_job.is_some(),
+        scanning: job.is_some(),
         count: stats.songs,
     })
 }
@@ -39,27 +46,61 @@ impl super::Reply for ScanStatus {
     }
 }
 
+#[derive(Clone, Deserialize)]
+struct GetScanStatusQuery {
+    #[serde(default)]
+    wait: bool,
+}
+
 async fn get_scan_status(
     Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<GetScanStatusQuery>,
 ) -> super::Result<ScanStatus> {
-    let (stats, status) = state
+    if param.wait {
+        // Idle blocks the connection, so don't hold up the pooled ones
+        let conn = state.pool.dedicated_connection().await?;
+        let _ = tokio::time::timeout(SCAN_STATUS_WAIT_TIMEOUT, wait_for_scan(&conn)).await;
+    }
+
+    let (stats, job) = state
         .pool
         .get()
         .await?
-        .command_list((Stats, Status))
+        .command_list((Stats, UpdatingDb))
         .await?;
 
     Ok(ScanStatus {
-        scanning: status.update_job.is_some(),
+        scanning: job.is_some(),
         count: stats.songs,
     })
 }
 
+// wait_for_scan waits until MPD is done updating the database. It returns right away if
+// the database is not being updated.
+async fn wait_for_scan(conn: &Connection) -> super::Result<()> {
+    while conn.command(UpdatingDb).await?.is_some() {
+        conn.command_without_timeout(IdleUpdate).await?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::ScanStatus;
-    use crate::api::{expect_ok_json, expect_ok_xml, json, xml};
+    use super::{get_scan_status, GetScanStatusQuery, ScanStatus};
+    use crate::{
+        api::{expect_ok_json, expect_ok_xml, json, test_state_with_mpd, xml},
+        mpd::testing::fake_server,
+    };
+    use axum::extract::{Extension, Query};
     use serde_json::json;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
     #[test]
     fn start_scan() {
@@ -81,4 +122,56 @@ mod tests {
             })),),
         );
     }
+
+    #[tokio::test]
+    async fn get_scan_status_wait() {
+        let scanning = Arc::new(AtomicBool::new(true));
+        let idles = Arc::new(AtomicBool::new(false));
+        let mpd = fake_server({
+            let scanning = scanning.clone();
+            let idles = idles.clone();
+            move |command| match command {
+                "stats" => "artists: 1\nalbums: 1\nsongs: 1234\nuptime: 1\nplaytime: 0\n\
+                            db_playtime: 1\ndb_update: 1700000000\n"
+                    .to_string(),
+                "status" if scanning.load(Ordering::SeqCst) => "updating_db: 1\n".to_string(),
+                // Scan completes while the client is waiting for it
+                "idle update" => {
+                    idles.store(true, Ordering::SeqCst);
+                    scanning.store(false, Ordering::SeqCst);
+                    "changed: update\n".to_string()
+                }
+                _ => String::new(),
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let status = |wait| {
+            tokio::time::timeout(
+                Duration::from_secs(5),
+                get_scan_status(Extension(state.clone()), Query(GetScanStatusQuery { wait })),
+            )
+        };
+
+        let Ok(Ok(reply)) = status(false).await else {
+            panic!("getScanStatus failed");
+        };
+        assert!(reply.scanning);
+        assert!(!idles.load(Ordering::SeqCst));
+
+        let Ok(Ok(reply)) = status(true).await else {
+            panic!("getScanStatus failed");
+        };
+        assert!(!reply.scanning);
+        assert_eq!(reply.count, 1234);
+        assert!(idles.load(Ordering::SeqCst));
+
+        // Not scanning, return right away
+        idles.store(false, Ordering::SeqCst);
+        let Ok(Ok(reply)) = status(true).await else {
+            panic!("getScanStatus failed");
+        };
+        assert!(!reply.scanning);
+        assert!(!idles.load(Ordering::SeqCst));
+    }
 }
@@ -0,0 +1,720 @@
+use super::{
+    browsing::validate_music_folder,
+    common::{
+        all_songs, get_albums, get_songs_annotations, merge_artists, mpd_song_to_subsonic,
+        SongOptions,
+    },
+    glue::Paged,
+    types::{Album, AlbumID, AlbumModel, Artist, ArtistID, DirectoryAlbum, DirectoryArtist, Song},
+    Error,
+};
+use crate::mpd::{Connection, Search, SearchCount};
+use axum::{
+    extract::{Extension, Query},
+    routing::Router,
+};
+use mpd_client::{
+    commands::List,
+    filter::{Filter, Operator},
+    tag::Tag,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use yaserde_derive::YaSerialize;
+
+const SEARCH_DEFAULT_COUNT: usize = 20;
+const SEARCH_MAX_COUNT: usize = 500;
+// Shorter search terms match nearly the whole library, so nothing is returned for them. A single
+// character of other scripts (e.g. CJK) can be a whole word, so only ASCII terms are limited.
+const SEARCH_MIN_TERM_LENGTH: usize = 2;
+// Headers with the total number of matching artists, albums and songs
+pub(crate) const X_TOTAL_COUNT_ARTISTS: &str = "x-total-count-artists";
+pub(crate) const X_TOTAL_COUNT_ALBUMS: &str = "x-total-count-albums";
+pub(crate) const X_TOTAL_COUNT_SONGS: &str = "x-total-count-songs";
+
+pub(crate) fn get_router() -> Router {
+    Router::new()
+        .route("/search2.view", super::handler(search2))
+        .route("/search3.view", super::handler(search3))
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchQuery {
+    #[serde(default)]
+    query: String,
+    artist_count: Option<usize>,
+    artist_offset: Option<usize>,
+    album_count: Option<usize>,
+    album_offset: Option<usize>,
+    song_count: Option<usize>,
+    song_offset: Option<usize>,
+    music_folder_id: Option<String>,
+}
+
+// Page of search results of a single category
+#[derive(Clone, Copy, Debug)]
+struct Page {
+    offset: usize,
+    count: usize,
+}
+
+impl Page {
+    fn new(offset: Option<usize>, count: Option<usize>) -> Self {
+        Page {
+            offset: offset.unwrap_or(0),
+            count: count.unwrap_or(SEARCH_DEFAULT_COUNT).min(SEARCH_MAX_COUNT),
+        }
+    }
+}
+
+// Requested pages of artists, albums and songs
+#[derive(Clone, Copy, Debug)]
+struct SearchPages {
+    artists: Page,
+    albums: Page,
+    songs: Page,
+}
+
+impl TryFrom<&SearchQuery> for SearchPages {
+    type Error = Error;
+
+    fn try_from(param: &SearchQuery) -> super::Result<Self> {
+        validate_music_folder(param.music_folder_id.as_deref())?;
+
+        Ok(SearchPages {
+            artists: Page::new(param.artist_offset, param.artist_count),
+            albums: Page::new(param.album_offset, param.album_count),
+            songs: Page::new(param.song_offset, param.song_count),
+        })
+    }
+}
+
+struct SearchResults {
+    artists: Vec<Artist>,
+    albums: Vec<AlbumModel>,
+    songs: Vec<Song>,
+    totals: Vec<(&'static str, usize)>,
+}
+
+// do_search searches for artists, albums and songs matching the query. Matching is substring
+// based and case-insensitive. Artists and albums are matched by their names, songs are matched
+// by any of their tags. Terms that are too short match nothing.
+async fn do_search(
+    conn: &Connection,
+    query: &str,
+    pages: SearchPages,
+    options: SongOptions,
+) -> super::Result<SearchResults> {
+    let term = search_term(query).map(str::to_lowercase);
+    if term.as_deref().is_some_and(too_short) {
+        return Ok(SearchResults {
+            artists: Vec::new(),
+            albums: Vec::new(),
+            songs: Vec::new(),
+            totals: vec![
+                (X_TOTAL_COUNT_ARTISTS, 0),
+                (X_TOTAL_COUNT_ALBUMS, 0),
+                (X_TOTAL_COUNT_SONGS, 0),
+            ],
+        });
+    }
+
+    // Album counts of artists need all the albums, so artists are matched after they are merged.
+    // Albums are matched by MPD, with a case-insensitive regex as `list` filters are
+    // case-sensitive otherwise.
+    let all_albums = List::new(Tag::Album).group_by([Tag::AlbumArtist]);
+    let (all_albums, albums) = match term.as_deref() {
+        Some(term) => {
+            let (all_albums, albums) = conn
+                .command_list((
+                    all_albums,
+                    List::new(Tag::Album)
+                        .filter(Filter::new(Tag::Album, Operator::Match, regex_term(term)))
+                        .group_by([Tag::AlbumArtist]),
+                ))
+                .await?;
+            (all_albums, Some(albums))
+        }
+        None => (conn.command(all_albums).await?, None),
+    };
+
+    let artists = all_albums.grouped_values().map(|(_, [artist])| artist);
+    let artists = merge_artists(artists)
+        .into_iter()
+        .filter(|(_, artist)| matches(artist, term.as_deref()))
+        .collect::<Vec<_>>();
+    let total_artists = artists.len();
+    let artists = artists
+        .into_iter()
+        .skip(pages.artists.offset)
+        .take(pages.artists.count)
+        .map(|(count, artist)| Artist {
+            id: ArtistID::new(artist),
+            name: artist.to_string(),
+            album_count: count,
+        })
+        .collect();
+
+    let albums = albums
+        .as_ref()
+        .unwrap_or(&all_albums)
+        .grouped_values()
+        .collect::<Vec<_>>();
+    let total_albums = albums.len();
+    let albums = get_albums(
+        conn,
+        albums
+            .into_iter()
+            .skip(pages.albums.offset)
+            .take(pages.albums.count)
+            .map(|(album, [artist])| AlbumID::new(album, artist))
+            .collect(),
+    )
+    .await?;
+
+    let filter = match term.as_deref() {
+        Some(term) => Filter::new(Tag::any(), Operator::Contain, term),
+        None => all_songs(),
+    };
+    let total_songs = conn.command(SearchCount::new(filter.clone())).await?.songs as usize;
+    let songs = match pages.songs.count {
+        0 => Vec::new(),
+        count => {
+            conn.command(Search::new(filter).window(pages.songs.offset..pages.songs.offset + count))
+                .await?
+        }
+    };
+    let annotations = get_songs_annotations(conn, &songs).await?;
+
+    Ok(SearchResults {
+        artists,
+        albums,
+        songs: songs
+            .into_iter()
+            .map(|s| mpd_song_to_subsonic(s, &annotations, options))
+            .collect(),
+        totals: vec![
+            (X_TOTAL_COUNT_ARTISTS, total_artists),
+            (X_TOTAL_COUNT_ALBUMS, total_albums),
+            (X_TOTAL_COUNT_SONGS, total_songs),
+        ],
+    })
+}
+
+// search_term extracts the search term from the query. Some clients wrap the query in quotes,
+// and an empty query means that everything should be returned.
+fn search_term(query: &str) -> Option<&str> {
+    match query.trim().trim_matches('"').trim() {
+        "" => None,
+        term => Some(term),
+    }
+}
+
+// too_short checks if the search term is too short to be searched for
+fn too_short(term: &str) -> bool {
+    term.is_ascii() && term.len() < SEARCH_MIN_TERM_LENGTH
+}
+
+// regex_term returns a case-insensitive regex matching the search term anywhere in a value. The
+// regex is escaped once more for each level of MPD's unescaping of filter values.
+fn regex_term(term: &str) -> String {
+    let mut regex = String::from("(?i)");
+    for c in term.chars() {
+        if r"\^$.|?*+()[]{}".contains(c) {
+            regex.push_str(r"\\\\");
+        }
+        regex.push(c);
+    }
+    regex
+}
+
+// matches checks if the value contains the lowercased search term
+fn matches(value: &str, term: Option<&str>) -> bool {
+    term.map_or(true, |term| value.to_lowercase().contains(term))
+}
+
+async fn search2(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<SearchQuery>,
+) -> super::Result<Paged<SearchResult2>> {
+    let pages = SearchPages::try_from(&param)?;
+    let results = do_search(
+        &*state.pool.get().await?,
+        &param.query,
+        pages,
+        state.song_options(),
+    )
+    .await?;
+
+    Ok(Paged::with_totals(
+        SearchResult2 {
+            artists: results.artists.into_iter().map(Into::into).collect(),
+            albums: results.albums.into_iter().map(Into::into).collect(),
+            songs: results.songs,
+        },
+        results.totals,
+    ))
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "searchResult2")]
+struct SearchResult2 {
+    #[yaserde(child, rename = "artist")]
+    #[serde(rename = "artist")]
+    artists: Vec<DirectoryArtist>,
+    #[yaserde(child, rename = "album")]
+    #[serde(rename = "album")]
+    albums: Vec<DirectoryAlbum>,
+    #[yaserde(child, rename = "song")]
+    #[serde(rename = "song")]
+    songs: Vec<Song>,
+}
+
+impl super::Reply for SearchResult2 {
+    fn field_name() -> Option<&'static str> {
+        Some("searchResult2")
+    }
+}
+
+async fn search3(
+    Extension(state): Extension<Arc<super::State>>,
+    Query(param): Query<SearchQuery>,
+) -> super::Result<Paged<SearchResult3>> {
+    let pages = SearchPages::try_from(&param)?;
+    let results = do_search(
+        &*state.pool.get().await?,
+        &param.query,
+        pages,
+        state.song_options(),
+    )
+    .await?;
+
+    Ok(Paged::with_totals(
+        SearchResult3 {
+            artists: results.artists,
+            albums: results.albums.into_iter().map(Into::into).collect(),
+            songs: results.songs,
+        },
+        results.totals,
+    ))
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "searchResult3")]
+struct SearchResult3 {
+    #[yaserde(child, rename = "artist")]
+    #[serde(rename = "artist")]
+    artists: Vec<Artist>,
+    #[yaserde(child, rename = "album")]
+    #[serde(rename = "album")]
+    albums: Vec<Album>,
+    #[yaserde(child, rename = "song")]
+    #[serde(rename = "song")]
+    songs: Vec<Song>,
+}
+
+impl super::Reply for SearchResult3 {
+    fn field_name() -> Option<&'static str> {
+        Some("searchResult3")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        matches, regex_term, search3, search_term, too_short, SearchQuery, SearchResult2,
+        SearchResult3, SEARCH_MAX_COUNT, X_TOTAL_COUNT_ALBUMS, X_TOTAL_COUNT_ARTISTS,
+        X_TOTAL_COUNT_SONGS,
+    };
+    use crate::{
+        api::{
+            expect_ok_json, expect_ok_xml, json, test_state_with_mpd,
+            types::{
+                Album, AlbumID, Artist, ArtistID, CoverArtID, DirectoryAlbum, DirectoryArtist,
+                Song, SongID,
+            },
+            xml,
+        },
+        mpd::testing::fake_server,
+    };
+    use axum::extract::{Extension, Query};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn search_terms() {
+        assert_eq!(search_term(""), None);
+        assert_eq!(search_term(r#""""#), None);
+        assert_eq!(search_term("  "), None);
+        assert_eq!(search_term("beta"), Some("beta"));
+        assert_eq!(search_term(r#""beta gamma""#), Some("beta gamma"));
+    }
+
+    #[tokio::test]
+    async fn search_totals() {
+        let mpd = fake_server(|command| match command.split(' ').next() {
+            Some("list") if command.contains("=~") => {
+                "AlbumArtist: alpha\nAlbum: beta\nAlbum: betamax\n".to_string()
+            }
+            Some("list") => "AlbumArtist: alpha\nAlbum: beta\nAlbum: betamax\n\
+                             AlbumArtist: delta\nAlbum: epsilon\n"
+                .to_string(),
+            Some("count") => "songs: 1\nplaytime: 300\n".to_string(),
+            Some("searchcount") => "songs: 7\nplaytime: 2100\n".to_string(),
+            Some("search") => "file: alpha/1.flac\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let query = |query: &str| SearchQuery {
+            query: query.to_string(),
+            artist_count: Some(1),
+            artist_offset: None,
+            album_count: Some(1),
+            album_offset: None,
+            song_count: Some(1),
+            song_offset: None,
+            music_folder_id: None,
+        };
+
+        for (q, artists, albums) in [("", 2, 3), ("beta", 0, 2)] {
+            let Ok(result) = search3(Extension(state.clone()), Query(query(q))).await else {
+                panic!("search3 failed");
+            };
+            assert_eq!(
+                result.totals,
+                [
+                    (X_TOTAL_COUNT_ARTISTS, artists),
+                    (X_TOTAL_COUNT_ALBUMS, albums),
+                    (X_TOTAL_COUNT_SONGS, 7)
+                ]
+            );
+            assert_eq!(result.reply.artists.len(), artists.min(1));
+            assert_eq!(result.reply.albums.len(), 1);
+            assert_eq!(result.reply.songs.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn search_albums_in_mpd() {
+        let lists = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let lists = lists.clone();
+            move |command| {
+                match command.split(' ').next() {
+                    Some("list") => lists.lock().unwrap().push(command.to_string()),
+                    Some("searchcount") => return "songs: 0\nplaytime: 0\n".to_string(),
+                    _ => (),
+                }
+                String::new()
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+        let query = |query: &str| SearchQuery {
+            query: query.to_string(),
+            artist_count: None,
+            artist_offset: None,
+            album_count: None,
+            album_offset: None,
+            song_count: None,
+            song_offset: None,
+            music_folder_id: None,
+        };
+
+        for (q, expected) in [
+            ("", vec![r#"list Album group AlbumArtist"#]),
+            (
+                "Beta (Live)",
+                vec![
+                    r#"list Album group AlbumArtist"#,
+                    r#"list Album "(Album =~ \"(?i)beta \\\\(live\\\\)\")" group AlbumArtist"#,
+                ],
+            ),
+        ] {
+            lists.lock().unwrap().clear();
+            let Ok(_) = search3(Extension(state.clone()), Query(query(q))).await else {
+                panic!("search3 failed");
+            };
+            assert_eq!(*lists.lock().unwrap(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn search_too_short() {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let commands = commands.clone();
+            move |command| {
+                if command != "ping" {
+                    commands.lock().unwrap().push(command.to_string());
+                }
+                String::new()
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let Ok(result) = search3(
+            Extension(state),
+            Query(SearchQuery {
+                query: r#""a""#.to_string(),
+                artist_count: None,
+                artist_offset: None,
+                album_count: None,
+                album_offset: None,
+                song_count: None,
+                song_offset: None,
+                music_folder_id: None,
+            }),
+        )
+        .await
+        else {
+            panic!("search3 failed");
+        };
+        assert_eq!(
+            result.totals,
+            [
+                (X_TOTAL_COUNT_ARTISTS, 0),
+                (X_TOTAL_COUNT_ALBUMS, 0),
+                (X_TOTAL_COUNT_SONGS, 0)
+            ]
+        );
+        assert!(result.reply.artists.is_empty());
+        assert!(result.reply.albums.is_empty());
+        assert!(result.reply.songs.is_empty());
+        assert!(commands.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_capped() {
+        let searches = Arc::new(Mutex::new(Vec::new()));
+        let mpd = fake_server({
+            let searches = searches.clone();
+            move |command| match command.split(' ').next() {
+                Some("list") => "AlbumArtist: alpha\nAlbum: beta\n".to_string(),
+                Some("count") => "songs: 1\nplaytime: 300\n".to_string(),
+                Some("searchcount") => "songs: 100000\nplaytime: 30000000\n".to_string(),
+                Some("search") => {
+                    searches.lock().unwrap().push(command.to_string());
+                    "file: alpha/1.flac\n".to_string()
+                }
+                _ => String::new(),
+            }
+        })
+        .await;
+        let state = test_state_with_mpd(mpd).await;
+
+        let Ok(result) = search3(
+            Extension(state),
+            Query(SearchQuery {
+                query: String::new(),
+                artist_count: Some(100000),
+                artist_offset: None,
+                album_count: Some(100000),
+                album_offset: None,
+                song_count: Some(100000),
+                song_offset: Some(10),
+                music_folder_id: None,
+            }),
+        )
+        .await
+        else {
+            panic!("search3 failed");
+        };
+        assert_eq!(result.totals[2], (X_TOTAL_COUNT_SONGS, 100000));
+        assert_eq!(
+            searches.lock().unwrap().as_slice(),
+            [format!(
+                "search \"(file != \\\"\\\")\" window 10:{}",
+                10 + SEARCH_MAX_COUNT
+            )]
+        );
+    }
+
+    #[test]
+    fn short_terms() {
+        assert!(too_short("a"));
+        assert!(!too_short("ab"));
+        assert!(!too_short("愛"));
+        assert!(!too_short("é"));
+    }
+
+    #[test]
+    fn regex_terms() {
+        assert_eq!(regex_term("beta"), "(?i)beta");
+        assert_eq!(regex_term("a.b*"), r"(?i)a\\\\.b\\\\*");
+        assert_eq!(regex_term(r#"a"b'c"#), r#"(?i)a"b'c"#);
+    }
+
+    #[test]
+    fn case_insensitive_matching() {
+        assert!(matches("Beta Gamma", None));
+        assert!(matches("Beta Gamma", Some("beta")));
+        assert!(matches("Beta Gamma", Some("a g")));
+        assert!(matches("ÄLPHA", Some("älpha")));
+        assert!(!matches("Beta Gamma", Some("delta")));
+    }
+
+    #[test]
+    fn search_result2() {
+        let search_result2 = SearchResult2 {
+            artists: vec![DirectoryArtist {
+                id: ArtistID::new("alpha"),
+                name: "alpha".to_string(),
+            }],
+            albums: vec![DirectoryAlbum {
+                id: AlbumID::new("beta", "alpha"),
+                is_dir: true,
+                title: "beta".to_string(),
+                album: "beta".to_string(),
+                artist: "alpha".to_string(),
+                year: Some(2020),
+                cover_art: CoverArtID::new("artwork"),
+                duration: 300,
+                artist_id: ArtistID::new("alpha"),
+                ..Default::default()
+            }],
+            songs: vec![Song {
+                id: SongID::new("song1"),
+                title: Some("song1".to_string()),
+                album: Some("beta".to_string()),
+                artist: "alpha".to_string(),
+                cover_art: CoverArtID::new("artwork"),
+                path: Some("path1".to_string()),
+                album_id: Some(AlbumID::new("beta", "alpha")),
+                artist_id: ArtistID::new("alpha"),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(
+            xml(&search_result2),
+            expect_ok_xml(Some(
+                r#"<searchResult2>
+    <artist id="eyJuYW1lIjoiYWxwaGEifQ==" name="alpha" />
+    <album id="eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=" isDir="true" title="beta" album="beta" artist="alpha" year="2020" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" duration="300" artistId="eyJuYW1lIjoiYWxwaGEifQ==" />
+    <song id="eyJwYXRoIjoic29uZzEifQ==" title="song1" album="beta" artist="alpha" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" path="path1" albumId="eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" />
+  </searchResult2>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&search_result2),
+            expect_ok_json(Some(json!({"searchResult2": {
+                "artist": [
+                    {
+                        "id": "eyJuYW1lIjoiYWxwaGEifQ==",
+                        "name": "alpha",
+                    }
+                ],
+                "album": [
+                    {
+                        "id": "eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=",
+                        "isDir": true,
+                        "title": "beta",
+                        "album": "beta",
+                        "artist": "alpha",
+                        "year": 2020,
+                        "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+                        "duration": 300,
+                        "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                    }
+                ],
+                "song": [
+                    {
+                        "id": "eyJwYXRoIjoic29uZzEifQ==",
+                        "title": "song1",
+                        "album": "beta",
+                        "artist": "alpha",
+                        "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+                        "path": "path1",
+                        "albumId": "eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=",
+                        "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                    }
+                ]
+            }
+            })),),
+        );
+    }
+
+    #[test]
+    fn search_result3() {
+        let search_result3 = SearchResult3 {
+            artists: vec![Artist {
+                id: ArtistID::new("alpha"),
+                name: "alpha".to_string(),
+                album_count: 1,
+            }],
+            albums: vec![Album {
+                id: AlbumID::new("beta", "alpha"),
+                name: "beta".to_string(),
+                artist: "alpha".to_string(),
+                artist_id: ArtistID::new("alpha"),
+                song_count: 1,
+                duration: 300,
+                cover_art: CoverArtID::new("artwork"),
+                ..Default::default()
+            }],
+            songs: vec![Song {
+                id: SongID::new("song1"),
+                title: Some("song1".to_string()),
+                album: Some("beta".to_string()),
+                artist: "alpha".to_string(),
+                cover_art: CoverArtID::new("artwork"),
+                path: Some("path1".to_string()),
+                album_id: Some(AlbumID::new("beta", "alpha")),
+                artist_id: ArtistID::new("alpha"),
+                ..Default::default()
+            }],
+        };
+        assert_eq!(
+            xml(&search_result3),
+            expect_ok_xml(Some(
+                r#"<searchResult3>
+    <artist id="eyJuYW1lIjoiYWxwaGEifQ==" name="alpha" albumCount="1" />
+    <album id="eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=" name="beta" artist="alpha" artistId="eyJuYW1lIjoiYWxwaGEifQ==" songCount="1" duration="300" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" isCompilation="false" />
+    <song id="eyJwYXRoIjoic29uZzEifQ==" title="song1" album="beta" artist="alpha" coverArt="eyJwYXRoIjoiYXJ0d29yayJ9" path="path1" albumId="eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=" artistId="eyJuYW1lIjoiYWxwaGEifQ==" />
+  </searchResult3>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&search_result3),
+            expect_ok_json(Some(json!({"searchResult3": {
+                "artist": [
+                    {
+                        "id": "eyJuYW1lIjoiYWxwaGEifQ==",
+                        "name": "alpha",
+                        "albumCount": 1,
+                    }
+                ],
+                "album": [
+                    {
+                        "id": "eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=",
+                        "name": "beta",
+                        "artist": "alpha",
+                        "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                        "songCount": 1,
+                        "duration": 300,
+                        "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+                        "isCompilation": false,
+                    }
+                ],
+                "song": [
+                    {
+                        "id": "eyJwYXRoIjoic29uZzEifQ==",
+                        "title": "song1",
+                        "album": "beta",
+                        "artist": "alpha",
+                        "coverArt": "eyJwYXRoIjoiYXJ0d29yayJ9",
+                        "path": "path1",
+                        "albumId": "eyJuYW1lIjoiYmV0YSIsImFydGlzdCI6ImFscGhhIn0=",
+                        "artistId": "eyJuYW1lIjoiYWxwaGEifQ==",
+                    }
+                ]
+            }
+            })),),
+        );
+    }
+}
@@ -2,10 +2,21 @@ use axum::routing::Router;
 use serde::Serialize;
 use yaserde_derive::YaSerialize;
 
+// OpenSubsonic extensions supported by the server along with their versions
+const OPEN_SUBSONIC_EXTENSIONS: &[(&str, &[u32])] = &[
+    ("apiKeyAuthentication", &[1]),
+    ("formPost", &[1]),
+    ("songLyrics", &[1]),
+];
+
 pub(crate) fn get_router() -> Router {
     Router::new()
         .route("/ping.view", super::handler(ping))
         .route("/getLicense.view", super::handler(get_license))
+        .route(
+            "/getOpenSubsonicExtensions.view",
+            super::handler(get_open_subsonic_extensions),
+        )
 }
 
 async fn ping() -> super::Result<()> {
@@ -29,11 +40,95 @@ async fn get_license() -> super::Result<License> {
     Ok(License { valid: true })
 }
 
+async fn get_open_subsonic_extensions() -> super::Result<OpenSubsonicExtensions> {
+    Ok(OpenSubsonicExtensions(
+        OPEN_SUBSONIC_EXTENSIONS
+            .iter()
+            .map(|(name, versions)| OpenSubsonicExtension {
+                name: name.to_string(),
+                versions: versions.to_vec(),
+            })
+            .collect(),
+    ))
+}
+
+// OpenSubsonicExtensions is a list of extensions put directly into the response, without a
+// wrapping element
+#[derive(Serialize)]
+#[serde(transparent)]
+struct OpenSubsonicExtensions(Vec<OpenSubsonicExtension>);
+
+impl yaserde::YaSerialize for OpenSubsonicExtensions {
+    fn serialize<W: std::io::Write>(
+        &self,
+        writer: &mut yaserde::ser::Serializer<W>,
+    ) -> Result<(), String> {
+        self.0
+            .iter()
+            .try_for_each(|extension| yaserde::YaSerialize::serialize(extension, writer))
+    }
+
+    fn serialize_attributes(
+        &self,
+        attributes: Vec<xml::attribute::OwnedAttribute>,
+        namespace: xml::namespace::Namespace,
+    ) -> Result<
+        (
+            Vec<xml::attribute::OwnedAttribute>,
+            xml::namespace::Namespace,
+        ),
+        String,
+    > {
+        Ok((attributes, namespace))
+    }
+}
+
+impl super::Reply for OpenSubsonicExtensions {
+    fn field_name() -> Option<&'static str> {
+        Some("openSubsonicExtensions")
+    }
+}
+
+#[derive(Serialize, YaSerialize)]
+#[yaserde(rename = "openSubsonicExtensions")]
+struct OpenSubsonicExtension {
+    #[yaserde(attribute)]
+    name: String,
+    #[yaserde(child)]
+    versions: Vec<u32>,
+}
+
 #[cfg(test)]
 mod tests {
-    use super::License;
-    use crate::api::{expect_ok_json, expect_ok_xml, json, xml};
+    use super::{get_open_subsonic_extensions, get_router, License};
+    use crate::api::{expect_ok_json, expect_ok_xml, json, test_state, xml};
+    use axum::{
+        body::{to_bytes, Body},
+        http::Request,
+        Extension,
+    };
     use serde_json::json;
+    use std::sync::Arc;
+    use tower_service::Service;
+
+    #[tokio::test]
+    async fn announce_type() {
+        let mut state = Arc::into_inner(test_state().await).unwrap();
+        state.announce_type = "subsonic".to_string();
+        let mut router = get_router().layer(Extension(Arc::new(state)));
+
+        for (uri, expected) in [
+            ("/ping.view", r#"type="subsonic""#),
+            ("/ping.view?f=json", r#""type": "subsonic""#),
+            ("/getLicense.view?f=json", r#""type": "subsonic""#),
+        ] {
+            let req = Request::get(uri).body(Body::empty()).unwrap();
+            let resp = router.call(req).await.unwrap();
+            let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(body.contains(expected), "{uri}: {body}");
+        }
+    }
 
     #[test]
     fn license() {
@@ -50,4 +145,43 @@ mod tests {
             }})),),
         );
     }
+
+    #[tokio::test]
+    async fn open_subsonic_extensions() {
+        let Ok(extensions) = get_open_subsonic_extensions().await else {
+            panic!("getOpenSubsonicExtensions failed");
+        };
+        assert_eq!(
+            xml(&extensions),
+            expect_ok_xml(Some(
+                r#"<openSubsonicExtensions name="apiKeyAuthentication">
+    <versions>1</versions>
+  </openSubsonicExtensions>
+  <openSubsonicExtensions name="formPost">
+    <versions>1</versions>
+  </openSubsonicExtensions>
+  <openSubsonicExtensions name="songLyrics">
+    <versions>1</versions>
+  </openSubsonicExtensions>"#
+            ),)
+        );
+
+        assert_eq!(
+            json(&extensions),
+            expect_ok_json(Some(json!({"openSubsonicExtensions": [
+                {
+                    "name": "apiKeyAuthentication",
+                    "versions": [1],
+                },
+                {
+                    "name": "formPost",
+                    "versions": [1],
+                },
+                {
+                    "name": "songLyrics",
+                    "versions": [1],
+                },
+            ]})),),
+        );
+    }
 }
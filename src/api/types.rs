@@ -189,6 +189,7 @@ impl SongID {
 pub(crate) enum CoverArtID {
     Song { path: String },
     Playlist { name: String },
+    Artist { artist: String },
 }
 
 impl CoverArtID {
@@ -234,9 +235,25 @@ impl PlaylistID {
     }
 }
 
+// DirectoryID identifies an MPD directory
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(remote = "Self")]
+pub(crate) struct DirectoryID {
+    pub(crate) directory: String,
+}
+
+impl DirectoryID {
+    pub(crate) fn new(directory: &str) -> Self {
+        DirectoryID {
+            directory: directory.to_string(),
+        }
+    }
+}
+
 api_id!(ArtistID);
 api_id!(AlbumID);
 api_id!(SongID);
+api_id!(DirectoryID);
 api_id_into_string!(CoverArtID);
 api_id_serialize!(CoverArtID);
 api_id_deserialize!(CoverArtID);
@@ -255,6 +272,9 @@ pub(crate) struct Song {
     pub(crate) album: Option<String>,
     #[yaserde(attribute)]
     pub(crate) artist: String,
+    #[yaserde(attribute, rename = "displayArtist")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) display_artist: Option<String>,
     #[yaserde(attribute)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) track: Option<u32>,
@@ -271,9 +291,34 @@ pub(crate) struct Song {
     pub(crate) cover_art: CoverArtID,
     #[yaserde(attribute)]
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) size: Option<u64>,
+    #[yaserde(attribute, rename = "contentType")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) content_type: Option<String>,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) suffix: Option<String>,
+    #[yaserde(attribute, rename = "transcodedContentType")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) transcoded_content_type: Option<String>,
+    #[yaserde(attribute, rename = "transcodedSuffix")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) transcoded_suffix: Option<String>,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) duration: Option<u64>,
+    #[yaserde(attribute, rename = "bitRate")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) bit_rate: Option<u64>,
+    #[yaserde(attribute, rename = "bitDepth")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) bit_depth: Option<u32>,
+    #[yaserde(attribute, rename = "samplingRate")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) sampling_rate: Option<u32>,
     #[yaserde(attribute)]
-    pub(crate) path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) path: Option<String>,
     #[yaserde(attribute, rename = "albumId")]
     pub(crate) album_id: Option<AlbumID>,
     #[yaserde(attribute, rename = "artistId")]
@@ -284,4 +329,270 @@ pub(crate) struct Song {
     #[yaserde(attribute)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) starred: Option<String>,
+    #[yaserde(attribute, rename = "playCount")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) play_count: Option<u64>,
+    #[yaserde(attribute, rename = "musicBrainzId")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) music_brainz_id: Option<String>,
+    // All the artists of the song, artist and displayArtist have them joined
+    #[yaserde(child)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) artists: Vec<SongArtist>,
+    #[yaserde(child)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) contributors: Vec<Contributor>,
+}
+
+// Contributor credits an artist with a role (e.g. composer) in a song
+#[derive(Serialize, YaSerialize, Debug, Default)]
+pub(crate) struct Contributor {
+    #[yaserde(attribute)]
+    pub(crate) role: String,
+    #[yaserde(child)]
+    pub(crate) artist: SongArtist,
+}
+
+#[derive(Serialize, YaSerialize, Debug, Default)]
+pub(crate) struct SongArtist {
+    #[yaserde(attribute)]
+    pub(crate) id: ArtistID,
+    #[yaserde(attribute)]
+    pub(crate) name: String,
+}
+
+#[derive(Serialize, YaSerialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Artist {
+    #[yaserde(attribute)]
+    pub(crate) id: ArtistID,
+    #[yaserde(attribute)]
+    pub(crate) name: String,
+    #[yaserde(attribute, rename = "albumCount")]
+    pub(crate) album_count: usize,
+}
+
+#[derive(Serialize, YaSerialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Album {
+    #[yaserde(attribute)]
+    pub(crate) id: AlbumID,
+    #[yaserde(attribute)]
+    pub(crate) name: String,
+    #[yaserde(attribute)]
+    pub(crate) artist: String,
+    #[yaserde(attribute, rename = "artistId")]
+    pub(crate) artist_id: ArtistID,
+    #[yaserde(attribute, rename = "songCount")]
+    pub(crate) song_count: u64,
+    #[yaserde(attribute)]
+    pub(crate) duration: u64,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) year: Option<i32>,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) genre: Option<String>,
+    #[yaserde(attribute, rename = "coverArt")]
+    pub(crate) cover_art: CoverArtID,
+    #[yaserde(attribute, rename = "userRating")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) user_rating: Option<u8>,
+    #[yaserde(attribute, rename = "averageRating")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) average_rating: Option<f64>,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) starred: Option<String>,
+    #[yaserde(attribute, rename = "isCompilation")]
+    pub(crate) is_compilation: bool,
+}
+
+// AlbumModel is everything known about an album. Both ID3 and directory-based representations
+// of albums are built from it, so that they always agree with each other.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AlbumModel {
+    pub(crate) id: AlbumID,
+    pub(crate) song_count: u64,
+    pub(crate) duration: u64,
+    pub(crate) year: Option<i32>,
+    pub(crate) genre: Option<String>,
+    pub(crate) cover_art: CoverArtID,
+    pub(crate) user_rating: Option<u8>,
+    pub(crate) average_rating: Option<f64>,
+    pub(crate) starred: Option<String>,
+    pub(crate) is_compilation: bool,
+}
+
+impl From<AlbumModel> for Album {
+    fn from(album: AlbumModel) -> Self {
+        Album {
+            name: album.id.name.clone(),
+            artist: album.id.artist.clone(),
+            artist_id: ArtistID::new(&album.id.artist),
+            song_count: album.song_count,
+            duration: album.duration,
+            year: album.year,
+            genre: album.genre,
+            cover_art: album.cover_art,
+            user_rating: album.user_rating,
+            average_rating: album.average_rating,
+            starred: album.starred,
+            is_compilation: album.is_compilation,
+            id: album.id,
+        }
+    }
+}
+
+// DirectoryArtist is an artist as seen by directory-based (non-ID3) endpoints
+#[derive(Serialize, YaSerialize, Debug)]
+pub(crate) struct DirectoryArtist {
+    #[yaserde(attribute)]
+    pub(crate) id: ArtistID,
+    #[yaserde(attribute)]
+    pub(crate) name: String,
+}
+
+impl From<Artist> for DirectoryArtist {
+    fn from(artist: Artist) -> Self {
+        DirectoryArtist {
+            id: artist.id,
+            name: artist.name,
+        }
+    }
+}
+
+// DirectoryAlbum is an album as seen by directory-based (non-ID3) endpoints
+#[derive(Serialize, YaSerialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DirectoryAlbum {
+    #[yaserde(attribute)]
+    pub(crate) id: AlbumID,
+    #[yaserde(attribute, rename = "isDir")]
+    pub(crate) is_dir: bool,
+    #[yaserde(attribute)]
+    pub(crate) title: String,
+    #[yaserde(attribute)]
+    pub(crate) album: String,
+    #[yaserde(attribute)]
+    pub(crate) artist: String,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) year: Option<i32>,
+    #[yaserde(attribute)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) genre: Option<String>,
+    #[yaserde(attribute, rename = "coverArt")]
+    pub(crate) cover_art: CoverArtID,
+    #[yaserde(attribute)]
+    pub(crate) duration: u64,
+    #[yaserde(attribute, rename = "artistId")]
+    pub(crate) artist_id: ArtistID,
+}
+
+impl From<AlbumModel> for DirectoryAlbum {
+    fn from(album: AlbumModel) -> Self {
+        DirectoryAlbum {
+            is_dir: true,
+            title: album.id.name.clone(),
+            album: album.id.name.clone(),
+            artist: album.id.artist.clone(),
+            artist_id: ArtistID::new(&album.id.artist),
+            year: album.year,
+            genre: album.genre,
+            cover_art: album.cover_art,
+            duration: album.duration,
+            id: album.id,
+        }
+    }
+}
+
+// Child is an entry of a directory, either a subdirectory or a song
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub(crate) enum Child {
+    Directory(DirectoryChild),
+    Song(Box<SongChild>),
+}
+
+// DirectoryChild is a subdirectory of a directory
+#[derive(Serialize, YaSerialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DirectoryChild {
+    #[yaserde(attribute)]
+    pub(crate) id: DirectoryID,
+    #[yaserde(attribute)]
+    pub(crate) parent: DirectoryID,
+    #[yaserde(attribute, rename = "isDir")]
+    pub(crate) is_dir: bool,
+    #[yaserde(attribute)]
+    pub(crate) title: String,
+}
+
+// SongChild is a song of a directory, it has everything the song has elsewhere
+#[derive(Serialize, YaSerialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SongChild {
+    #[yaserde(attribute)]
+    pub(crate) parent: DirectoryID,
+    #[yaserde(attribute, rename = "isDir")]
+    pub(crate) is_dir: bool,
+    #[serde(flatten)]
+    #[yaserde(flatten)]
+    pub(crate) song: Song,
+}
+
+impl Child {
+    // directory creates a child for a subdirectory of the parent directory
+    pub(crate) fn directory(directory: &str, name: &str, parent: DirectoryID) -> Self {
+        Child::Directory(DirectoryChild {
+            id: DirectoryID::new(directory),
+            parent,
+            is_dir: true,
+            title: name.to_string(),
+        })
+    }
+
+    // song creates a child for a song in the parent directory. Songs without a title are
+    // named after their file.
+    pub(crate) fn song(mut song: Song, parent: DirectoryID) -> Self {
+        if song.title.is_none() {
+            song.title = song.id.path.rsplit('/').next().map(str::to_string);
+        }
+
+        Child::Song(Box::new(SongChild {
+            parent,
+            is_dir: false,
+            song,
+        }))
+    }
+}
+
+impl yaserde::YaSerialize for Child {
+    fn serialize<W: std::io::Write>(
+        &self,
+        writer: &mut yaserde::ser::Serializer<W>,
+    ) -> std::result::Result<(), String> {
+        match self {
+            Child::Directory(dir) => yaserde::YaSerialize::serialize(dir, writer),
+            Child::Song(song) => yaserde::YaSerialize::serialize(&**song, writer),
+        }
+    }
+
+    fn serialize_attributes(
+        &self,
+        attributes: Vec<xml::attribute::OwnedAttribute>,
+        namespace: xml::namespace::Namespace,
+    ) -> std::result::Result<
+        (
+            Vec<xml::attribute::OwnedAttribute>,
+            xml::namespace::Namespace,
+        ),
+        String,
+    > {
+        match self {
+            Child::Directory(dir) => dir.serialize_attributes(attributes, namespace),
+            Child::Song(song) => song.serialize_attributes(attributes, namespace),
+        }
+    }
 }
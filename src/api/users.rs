@@ -18,23 +18,7 @@ async fn get_user(
     Query(params): Query<GetUserQuery>,
 ) -> super::Result<GetUser> {
     match params.u == params.username {
-        true => Ok(GetUser {
-            username: params.username,
-            scrobbling_enabled: state.listenbrainz.is_some(),
-            admin_role: true,
-            settings_role: false,
-            download_role: true,
-            upload_role: false,
-            playlist_role: true,
-            cover_art_role: true,
-            comment_role: false,
-            podcast_role: false,
-            stream_role: true,
-            jukebox_role: false,
-            share_role: false,
-            video_conversion_role: false,
-            folder: vec!["/".to_string()],
-        }),
+        true => Ok(GetUser::new(params.username, &state)),
         false => Err(super::Error::not_authorized(&format!(
             "{} is not authorized to get details for other users.",
             params.u
@@ -78,6 +62,31 @@ struct GetUser {
     folder: Vec<String>,
 }
 
+impl GetUser {
+    // new returns the user with roles matching capabilities of the server
+    fn new(username: String, state: &super::State) -> Self {
+        let writable = !state.read_only;
+
+        GetUser {
+            username,
+            scrobbling_enabled: state.listenbrainz.is_some(),
+            admin_role: writable,
+            settings_role: false,
+            download_role: writable,
+            upload_role: false,
+            playlist_role: writable,
+            cover_art_role: true,
+            comment_role: false,
+            podcast_role: false,
+            stream_role: true,
+            jukebox_role: state.jukebox,
+            share_role: false,
+            video_conversion_role: false,
+            folder: vec!["/".to_string()],
+        }
+    }
+}
+
 impl super::Reply for GetUser {
     fn field_name() -> Option<&'static str> {
         Some("user")
@@ -87,8 +96,9 @@ impl super::Reply for GetUser {
 #[cfg(test)]
 mod tests {
     use super::GetUser;
-    use crate::api::{expect_ok_json, expect_ok_xml, json, xml};
+    use crate::api::{expect_ok_json, expect_ok_xml, json, test_state, xml};
     use serde_json::json;
+    use std::sync::Arc;
 
     #[test]
     fn get_user() {
@@ -139,4 +149,24 @@ mod tests {
             }})),),
         );
     }
+
+    #[tokio::test]
+    async fn roles() {
+        let mut state = Arc::into_inner(test_state().await).unwrap();
+
+        let user = GetUser::new("test".to_string(), &state);
+        assert!(!user.scrobbling_enabled);
+        assert!(user.admin_role && user.download_role && user.playlist_role && user.stream_role);
+        assert!(!user.jukebox_role && !user.share_role);
+
+        state.read_only = true;
+        let user = GetUser::new("test".to_string(), &state);
+        assert!(!user.admin_role && !user.download_role && !user.playlist_role);
+        assert!(user.stream_role && user.cover_art_role);
+        assert!(!user.jukebox_role && !user.share_role);
+
+        state.jukebox = true;
+        let user = GetUser::new("test".to_string(), &state);
+        assert!(user.jukebox_role);
+    }
 }
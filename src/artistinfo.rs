@@ -0,0 +1,417 @@
+use lru::LruCache;
+use reqwest::{header, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Maximum time a whole lookup may take, clients are waiting for the reply. Every step depends on
+// the previous one, so this covers all the requests of a lookup together.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+// How long to stop asking if a server is overloaded and doesn't say when to come back
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(60);
+// Number of artists remembered, along with the artists nothing was found for
+const CACHE_SIZE: usize = 1024;
+// Artist information rarely changes, but it does eventually
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Provider of artist information
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Provider {
+    // Wikipedia article linked to the artist's MusicBrainz entry (through Wikidata)
+    MusicBrainz,
+}
+
+impl FromStr for Provider {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "musicbrainz" => Ok(Provider::MusicBrainz),
+            _ => Err(format!("unsupported artist info provider: {s}")),
+        }
+    }
+}
+
+pub(crate) struct Client {
+    client: reqwest::Client,
+    musicbrainz: Url,
+    wikidata: Url,
+    wikipedia: Url,
+    // Results of recent lookups, None if the artist has no article
+    cache: Mutex<LruCache<String, (Instant, Option<ArtistInfo>)>>,
+    // Requests are not sent until then after a server asked to slow down
+    backoff_until: Mutex<Option<Instant>>,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    Http(reqwest::Error),
+    Url(url::ParseError),
+    NoArticle,
+    RateLimited,
+    Timeout,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(err: url::ParseError) -> Self {
+        Error::Url(err)
+    }
+}
+
+// Information about an artist
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ArtistInfo {
+    pub(crate) biography: Option<String>,
+    pub(crate) small_image_url: Option<String>,
+    pub(crate) large_image_url: Option<String>,
+}
+
+impl Client {
+    pub(crate) fn new(provider: Provider) -> Result<Client> {
+        match provider {
+            Provider::MusicBrainz => Client::with_endpoints(
+                "https://musicbrainz.org",
+                "https://www.wikidata.org",
+                "https://en.wikipedia.org",
+            ),
+        }
+    }
+
+    pub(crate) fn with_endpoints(
+        musicbrainz: &str,
+        wikidata: &str,
+        wikipedia: &str,
+    ) -> Result<Client> {
+        Ok(Client {
+            client: reqwest::ClientBuilder::new()
+                // MusicBrainz rejects requests without a meaningful user agent
+                .user_agent(concat!(
+                    env!("CARGO_PKG_NAME"),
+                    "/",
+                    env!("CARGO_PKG_VERSION"),
+                    " ( https://github.com/pborzenkov/mpdsonic )"
+                ))
+                .timeout(LOOKUP_TIMEOUT)
+                .build()?,
+            musicbrainz: Url::parse(musicbrainz)?,
+            wikidata: Url::parse(wikidata)?,
+            wikipedia: Url::parse(wikipedia)?,
+            cache: Mutex::new(LruCache::new(NonZeroUsize::new(CACHE_SIZE).unwrap())),
+            backoff_until: Mutex::new(None),
+        })
+    }
+
+    // artist_info returns information about the artist with the given MusicBrainz ID
+    pub(crate) async fn artist_info(&self, mbid: &str) -> Result<ArtistInfo> {
+        if let Some((fetched, info)) = self.cache.lock().unwrap().get(mbid) {
+            if fetched.elapsed() < CACHE_TTL {
+                return info.clone().ok_or(Error::NoArticle);
+            }
+        }
+        if self
+            .backoff_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| Instant::now() < until)
+        {
+            return Err(Error::RateLimited);
+        }
+
+        let res = tokio::time::timeout(LOOKUP_TIMEOUT, self.lookup(mbid))
+            .await
+            .unwrap_or(Err(Error::Timeout));
+        // Only definite answers are remembered, failed lookups are retried next time
+        match res {
+            Ok(ref info) => {
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .put(mbid.to_string(), (Instant::now(), Some(info.clone())));
+            }
+            Err(Error::NoArticle) => {
+                self.cache
+                    .lock()
+                    .unwrap()
+                    .put(mbid.to_string(), (Instant::now(), None));
+            }
+            Err(_) => (),
+        }
+        res
+    }
+
+    async fn lookup(&self, mbid: &str) -> Result<ArtistInfo> {
+        let article = self.wikipedia_article(mbid).await?;
+
+        let mut url = self.wikipedia.clone();
+        url.path_segments_mut()
+            .map_err(|_| Error::NoArticle)?
+            .extend(["api", "rest_v1", "page", "summary", &article]);
+        let summary: Summary = self.get_json(url).await?;
+
+        Ok(ArtistInfo {
+            biography: summary.extract.filter(|e| !e.is_empty()),
+            small_image_url: summary.thumbnail.map(|t| t.source),
+            large_image_url: summary.originalimage.map(|i| i.source),
+        })
+    }
+
+    // wikipedia_article returns title of the English Wikipedia article about the artist
+    async fn wikipedia_article(&self, mbid: &str) -> Result<String> {
+        let mut url = self.musicbrainz.clone();
+        url.path_segments_mut()
+            .map_err(|_| Error::NoArticle)?
+            .extend(["ws", "2", "artist", mbid]);
+        url.query_pairs_mut()
+            .append_pair("inc", "url-rels")
+            .append_pair("fmt", "json");
+        let artist: MusicBrainzArtist = self.get_json(url).await?;
+        let entity = artist.wikidata_entity().ok_or(Error::NoArticle)?;
+
+        let mut url = self.wikidata.join("w/api.php")?;
+        url.query_pairs_mut()
+            .append_pair("action", "wbgetentities")
+            .append_pair("ids", &entity)
+            .append_pair("props", "sitelinks")
+            .append_pair("sitefilter", "enwiki")
+            .append_pair("format", "json");
+        let mut entities: WikidataEntities = self.get_json(url).await?;
+
+        entities
+            .entities
+            .remove(&entity)
+            .and_then(|mut e| e.sitelinks.remove("enwiki"))
+            .map(|s| s.title)
+            .ok_or(Error::NoArticle)
+    }
+
+    // get_json fetches the document, backing off all the lookups if the server is overloaded
+    async fn get_json<T: DeserializeOwned>(&self, url: Url) -> Result<T> {
+        let resp = self.client.get(url).send().await?;
+
+        match resp.status() {
+            StatusCode::NOT_FOUND => Err(Error::NoArticle),
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                let backoff = resp
+                    .headers()
+                    .get(header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .map_or(DEFAULT_BACKOFF, Duration::from_secs);
+                *self.backoff_until.lock().unwrap() = Some(Instant::now() + backoff);
+                Err(Error::RateLimited)
+            }
+            _ => Ok(resp.error_for_status()?.json().await?),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MusicBrainzArtist {
+    #[serde(default)]
+    relations: Vec<MusicBrainzRelation>,
+}
+
+impl MusicBrainzArtist {
+    // wikidata_entity returns ID of the Wikidata entity linked to the artist
+    fn wikidata_entity(&self) -> Option<String> {
+        self.relations
+            .iter()
+            .filter(|r| r.kind == "wikidata")
+            .find_map(|r| r.url.resource.rsplit('/').next())
+            .filter(|id| !id.is_empty())
+            .map(str::to_string)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MusicBrainzRelation {
+    #[serde(rename = "type")]
+    kind: String,
+    url: MusicBrainzUrl,
+}
+
+#[derive(Debug, Deserialize)]
+struct MusicBrainzUrl {
+    resource: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WikidataEntities {
+    entities: HashMap<String, WikidataEntity>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WikidataEntity {
+    #[serde(default)]
+    sitelinks: HashMap<String, WikidataSitelink>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WikidataSitelink {
+    title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Summary {
+    extract: Option<String>,
+    thumbnail: Option<Image>,
+    originalimage: Option<Image>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Image {
+    source: String,
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use axum::{
+        extract::{Path, Query},
+        http::{header, StatusCode},
+        response::IntoResponse,
+        routing::{get, Router},
+        Json,
+    };
+    use serde_json::{json, Value};
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::net::TcpListener;
+
+    // fake_provider starts fake MusicBrainz, Wikidata and Wikipedia servers. Artist "alpha" has
+    // an article, "busy" is rate limited and others have no article. It returns the address of
+    // the servers and the number of MusicBrainz requests they received.
+    pub(crate) async fn fake_provider() -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let router = Router::new()
+            .route(
+                "/ws/2/artist/:mbid",
+                get({
+                    let requests = requests.clone();
+                    |Path(mbid): Path<String>| async move {
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        match mbid.as_str() {
+                            "alpha" => Json(json!({"relations": [
+                                {"type": "discogs", "url": {"resource": "https://www.discogs.com/artist/1"}},
+                                {"type": "wikidata", "url": {"resource": "https://www.wikidata.org/wiki/Q1"}},
+                            ]}))
+                            .into_response(),
+                            "busy" => (
+                                StatusCode::SERVICE_UNAVAILABLE,
+                                [(header::RETRY_AFTER, "3600")],
+                            )
+                                .into_response(),
+                            _ => Json(json!({"relations": []})).into_response(),
+                        }
+                    }
+                }),
+            )
+            .route(
+                "/w/api.php",
+                get(|Query(q): Query<HashMap<String, String>>| async move {
+                    assert_eq!(q["ids"], "Q1");
+                    Json(json!({"entities": {"Q1": {"sitelinks": {
+                        "enwiki": {"site": "enwiki", "title": "Sigur Rós"},
+                    }}}}))
+                }),
+            )
+            .route(
+                "/api/rest_v1/page/summary/:title",
+                get(|Path(title): Path<String>| async move {
+                    assert_eq!(title, "Sigur Rós");
+                    Json::<Value>(json!({
+                        "extract": "Sigur Rós is an Icelandic post-rock band.",
+                        "thumbnail": {"source": "https://example.com/small.jpg"},
+                        "originalimage": {"source": "https://example.com/large.jpg"},
+                    }))
+                }),
+            );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        (format!("http://{address}"), requests)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{testing::fake_provider, ArtistInfo, Client, Error, Provider};
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn provider() {
+        assert_eq!("musicbrainz".parse(), Ok(Provider::MusicBrainz));
+        assert!("lastfm".parse::<Provider>().is_err());
+    }
+
+    #[tokio::test]
+    async fn artist_info() {
+        let (base, requests) = fake_provider().await;
+        let client = Client::with_endpoints(&base, &base, &base).unwrap();
+
+        let alpha = ArtistInfo {
+            biography: Some("Sigur Rós is an Icelandic post-rock band.".to_string()),
+            small_image_url: Some("https://example.com/small.jpg".to_string()),
+            large_image_url: Some("https://example.com/large.jpg".to_string()),
+        };
+        assert_eq!(client.artist_info("alpha").await.unwrap(), alpha);
+        assert!(matches!(
+            client.artist_info("beta").await,
+            Err(Error::NoArticle)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Both found and missing articles are cached
+        assert_eq!(client.artist_info("alpha").await.unwrap(), alpha);
+        assert!(matches!(
+            client.artist_info("beta").await,
+            Err(Error::NoArticle)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn artist_info_rate_limited() {
+        let (base, requests) = fake_provider().await;
+        let client = Client::with_endpoints(&base, &base, &base).unwrap();
+
+        assert!(matches!(
+            client.artist_info("busy").await,
+            Err(Error::RateLimited)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Nothing is sent until the backoff is over
+        assert!(matches!(
+            client.artist_info("alpha").await,
+            Err(Error::RateLimited)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,246 @@
+use clap::Command;
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path};
+
+// config_file! defines ConfigFile with an optional field per command line argument. Values are
+// handed to clap as strings, so clap still parses and validates them.
+macro_rules! config_file {
+    ($($name:ident: $type:ty),* $(,)?) => {
+        #[derive(Deserialize, Default, Debug, PartialEq)]
+        #[serde(deny_unknown_fields)]
+        pub(crate) struct ConfigFile {
+            $(#[serde(default)] $name: Option<$type>,)*
+        }
+
+        impl ConfigFile {
+            // values returns (argument id, values) pairs for all the values set in the file
+            fn values(&self) -> Vec<(&'static str, Vec<String>)> {
+                let mut values = Vec::new();
+                $(if let Some(value) = &self.$name {
+                    values.push((stringify!($name), value.to_args()));
+                })*
+                values
+            }
+        }
+    };
+}
+
+// ConfigValue converts a value from the file to argument values
+trait ConfigValue {
+    fn to_args(&self) -> Vec<String>;
+}
+
+macro_rules! scalar_config_value {
+    ($($type:ty),*) => {
+        $(impl ConfigValue for $type {
+            fn to_args(&self) -> Vec<String> {
+                vec![self.to_string()]
+            }
+        })*
+    };
+}
+
+scalar_config_value!(String, bool, u32, u64, usize);
+
+// Users are given as a table of username = password pairs
+impl ConfigValue for BTreeMap<String, String> {
+    fn to_args(&self) -> Vec<String> {
+        self.iter()
+            .map(|(username, password)| format!("{username}:{password}"))
+            .collect()
+    }
+}
+
+config_file! {
+    address: String,
+    username: String,
+    password: String,
+    api_key: String,
+    users: BTreeMap<String, String>,
+    disable_authentication: bool,
+    mpd_address: String,
+    mpd_password: String,
+    mpd_command_timeout: u64,
+    mpd_pool_size: u32,
+    mpd_connect_timeout: u64,
+    mpd_binary_limit: usize,
+    mpd_library: String,
+    listenbrainz_token: String,
+    artist_info_provider: String,
+    fetch_remote_art: bool,
+    artist_image_dir: String,
+    hide_paths: bool,
+    album_starred_any: bool,
+    default_transcode_format: String,
+    read_only: bool,
+    cover_cache_size: usize,
+    artists_cache_ttl: u64,
+    announce_https: bool,
+    announce_type: String,
+    avatar: String,
+    jukebox: bool,
+    web_ui: bool,
+    tls_cert: String,
+    tls_key: String,
+    shutdown_timeout: u64,
+}
+
+impl ConfigFile {
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let config = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read config file {}: {err}", path.display()))?;
+        toml::from_str(&config)
+            .map_err(|err| format!("failed to parse config file {}: {err}", path.display()))
+    }
+
+    // apply makes values from the file the defaults of the corresponding arguments, so that
+    // command line flags and environment variables take precedence over the file
+    pub(crate) fn apply(&self, mut cmd: Command) -> Command {
+        for (id, value) in self.values() {
+            cmd = cmd.mut_arg(id, |arg| arg.default_values(value).required(false));
+        }
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConfigFile;
+    use crate::parse_args;
+
+    #[test]
+    fn values() {
+        let config: ConfigFile = toml::from_str(
+            r#"
+            username = "alice"
+            mpd_library = "/music"
+            mpd_pool_size = 4
+            hide_paths = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.values(),
+            [
+                ("username", vec!["alice".to_string()]),
+                ("mpd_pool_size", vec!["4".to_string()]),
+                ("mpd_library", vec!["/music".to_string()]),
+                ("hide_paths", vec!["true".to_string()]),
+            ]
+        );
+
+        assert!(toml::from_str::<ConfigFile>("unknown = 1").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        let path = std::env::temp_dir().join(format!("mpdsonic-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "username = \"alice\"\npassword = \"secret\"\nmpd_library = \"/music\"\n\
+             mpd_pool_size = 4\nhide_paths = true\n[users]\nbob = \"hunter2\"\n",
+        )
+        .unwrap();
+        let parse = |args: &[&str]| {
+            let config = ["mpdsonic", "--config", path.to_str().unwrap()];
+            parse_args(config.iter().chain(args).map(Into::into).collect())
+        };
+
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.username, "alice");
+        assert_eq!(args.password.as_deref(), Some("secret"));
+        assert_eq!(args.mpd_library.as_deref(), Some("/music"));
+        assert_eq!(args.mpd_pool_size, 4);
+        assert!(args.hide_paths);
+        assert_eq!(args.mpd_connect_timeout, 1);
+        assert_eq!(args.users, [("bob".to_string(), "hunter2".to_string())]);
+
+        let args = parse(&["-u", "bob", "--mpd-pool-size", "2", "--user", "carol:pw"]).unwrap();
+        assert_eq!(args.username, "bob");
+        assert_eq!(args.mpd_pool_size, 2);
+        assert_eq!(args.users, [("carol".to_string(), "pw".to_string())]);
+
+        assert!(parse(&["--mpd-pool-size", "0"]).is_err());
+        assert!(parse(&["--password", ""]).is_err());
+
+        std::fs::write(&path, "username = \"alice\"\npassword = \"\"\n").unwrap();
+        assert!(parse(&[]).is_err());
+        assert_eq!(
+            parse(&["--password", "secret"])
+                .unwrap()
+                .password
+                .as_deref(),
+            Some("secret")
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn users() {
+        let parse = |args: &[&str]| {
+            let base = ["mpdsonic", "--username", "alice", "--password", "secret"];
+            parse_args(base.iter().chain(args).map(Into::into).collect())
+        };
+
+        let args = parse(&["--user", "bob:pw", "--user", "carol:pw:with:colons"]).unwrap();
+        assert_eq!(
+            args.users,
+            [
+                ("bob".to_string(), "pw".to_string()),
+                ("carol".to_string(), "pw:with:colons".to_string())
+            ]
+        );
+
+        assert!(parse(&["--user", "bob"]).is_err());
+        assert!(parse(&["--user", ":pw"]).is_err());
+        assert!(parse(&["--user", "bob:"]).is_err());
+        assert!(parse(&["--user", "alice:pw"]).is_err());
+        assert!(parse(&["--user", "bob:pw", "--user", "bob:other"]).is_err());
+    }
+
+    #[test]
+    fn api_key() {
+        let parse = |args: &[&str]| {
+            let base = ["mpdsonic", "--username", "alice", "--password", "secret"];
+            parse_args(base.iter().chain(args).map(Into::into).collect())
+        };
+
+        let args = parse(&["--api-key", "key"]).unwrap();
+        assert_eq!(args.api_key.as_deref(), Some("key"));
+        assert!(parse(&["--api-key", ""]).is_err());
+    }
+
+    #[test]
+    fn tls_files() {
+        let dir = std::env::temp_dir().join(format!("mpdsonic-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert, "cert").unwrap();
+        std::fs::write(&key, "key").unwrap();
+        let config = dir.join("config.toml");
+        std::fs::write(
+            &config,
+            format!(
+                "username = \"alice\"\npassword = \"secret\"\ntls_cert = {:?}\n",
+                cert.to_str().unwrap()
+            ),
+        )
+        .unwrap();
+        let parse = |args: &[&str]| {
+            let base = ["mpdsonic", "--config", config.to_str().unwrap()];
+            parse_args(base.iter().chain(args).map(Into::into).collect())
+        };
+
+        assert!(parse(&[]).is_err());
+        let args = parse(&["--tls-key", key.to_str().unwrap()]).unwrap();
+        assert_eq!(args.tls_cert.as_deref(), Some(cert.as_path()));
+        assert_eq!(args.tls_key.as_deref(), Some(key.as_path()));
+
+        let missing = dir.join("missing.pem");
+        assert!(parse(&["--tls-key", missing.to_str().unwrap()]).is_err());
+        std::fs::remove_file(&cert).unwrap();
+        assert!(parse(&["--tls-key", key.to_str().unwrap()]).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+use bytes::Bytes;
+use lru::LruCache;
+use reqwest::{header, StatusCode, Url};
+use std::{
+    fmt,
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Maximum time a single lookup may take, clients are waiting for the reply
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+// How long to stop asking if the server is overloaded and doesn't say when to come back
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(60);
+// Number of releases remembered to have no cover art
+const MISSES_CACHE_SIZE: usize = 4096;
+
+// Client fetches front covers of releases from the Cover Art Archive
+pub(crate) struct Client {
+    client: reqwest::Client,
+    endpoint: Url,
+    // Releases the archive has no cover art for
+    misses: Mutex<LruCache<String, ()>>,
+    // Requests are not sent until then after the archive asked to slow down
+    backoff_until: Mutex<Option<Instant>>,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    Http(reqwest::Error),
+    Url(url::ParseError),
+    NotFound,
+    RateLimited,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(err: url::ParseError) -> Self {
+        Error::Url(err)
+    }
+}
+
+// Cover image as returned by the archive
+#[derive(Debug, PartialEq)]
+pub(crate) struct Image {
+    pub(crate) data: Bytes,
+    pub(crate) mime: Option<String>,
+}
+
+impl Client {
+    pub(crate) fn new() -> Result<Client> {
+        Client::with_endpoint("https://coverartarchive.org")
+    }
+
+    pub(crate) fn with_endpoint(endpoint: &str) -> Result<Client> {
+        Ok(Client {
+            client: reqwest::ClientBuilder::new()
+                .user_agent(concat!(
+                    env!("CARGO_PKG_NAME"),
+                    "/",
+                    env!("CARGO_PKG_VERSION"),
+                    " ( https://github.com/pborzenkov/mpdsonic )"
+                ))
+                .timeout(LOOKUP_TIMEOUT)
+                .build()?,
+            endpoint: Url::parse(endpoint)?,
+            misses: Mutex::new(LruCache::new(NonZeroUsize::new(MISSES_CACHE_SIZE).unwrap())),
+            backoff_until: Mutex::new(None),
+        })
+    }
+
+    // front_cover returns front cover of the release with the given MusicBrainz ID
+    pub(crate) async fn front_cover(&self, release: &str) -> Result<Image> {
+        if self.misses.lock().unwrap().get(release).is_some() {
+            return Err(Error::NotFound);
+        }
+        if self
+            .backoff_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| Instant::now() < until)
+        {
+            return Err(Error::RateLimited);
+        }
+
+        let mut url = self.endpoint.clone();
+        url.path_segments_mut()
+            .map_err(|_| Error::NotFound)?
+            .extend(["release", release, "front-500"]);
+        let resp = self.client.get(url).send().await?;
+
+        match resp.status() {
+            StatusCode::NOT_FOUND => {
+                self.misses.lock().unwrap().put(release.to_string(), ());
+                Err(Error::NotFound)
+            }
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                let backoff = resp
+                    .headers()
+                    .get(header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .map_or(DEFAULT_BACKOFF, Duration::from_secs);
+                *self.backoff_until.lock().unwrap() = Some(Instant::now() + backoff);
+                Err(Error::RateLimited)
+            }
+            _ => {
+                let resp = resp.error_for_status()?;
+                let mime = resp
+                    .headers()
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_string);
+
+                Ok(Image {
+                    data: resp.bytes().await?,
+                    mime,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use axum::{
+        extract::Path,
+        http::{header, StatusCode},
+        response::IntoResponse,
+        routing::{get, Router},
+    };
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::net::TcpListener;
+
+    // fake_archive starts a fake Cover Art Archive. Release "alpha" has a cover, "busy" is
+    // rate limited and others have no cover. It returns the address of the archive and the
+    // number of requests it received.
+    pub(crate) async fn fake_archive() -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let router = Router::new().route(
+            "/release/:mbid/front-500",
+            get({
+                let requests = requests.clone();
+                |Path(mbid): Path<String>| async move {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    match mbid.as_str() {
+                        "alpha" => {
+                            ([(header::CONTENT_TYPE, "image/jpeg")], "alpha cover").into_response()
+                        }
+                        "busy" => (
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            [(header::RETRY_AFTER, "3600")],
+                        )
+                            .into_response(),
+                        _ => StatusCode::NOT_FOUND.into_response(),
+                    }
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        (format!("http://{address}"), requests)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{testing::fake_archive, Client, Error, Image};
+    use bytes::Bytes;
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test]
+    async fn front_cover() {
+        let (endpoint, requests) = fake_archive().await;
+        let client = Client::with_endpoint(&endpoint).unwrap();
+
+        assert_eq!(
+            client.front_cover("alpha").await.unwrap(),
+            Image {
+                data: Bytes::from_static(b"alpha cover"),
+                mime: Some("image/jpeg".to_string()),
+            }
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Misses are cached
+        assert!(matches!(
+            client.front_cover("beta").await,
+            Err(Error::NotFound)
+        ));
+        assert!(matches!(
+            client.front_cover("beta").await,
+            Err(Error::NotFound)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // Nothing is requested while rate limited
+        assert!(matches!(
+            client.front_cover("busy").await,
+            Err(Error::RateLimited)
+        ));
+        assert!(matches!(
+            client.front_cover("alpha").await,
+            Err(Error::RateLimited)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,15 +1,18 @@
 use axum::async_trait;
 use bytes::Bytes;
 use futures::{Stream, StreamExt};
-use reqwest::StatusCode;
+use reqwest::{header, StatusCode};
 use std::{
     error::Error as StdError,
     fmt,
-    io::ErrorKind,
+    io::{ErrorKind, SeekFrom},
     path::{Path, PathBuf},
     pin::Pin,
 };
-use tokio::fs::File;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
 use tokio_util::io::ReaderStream;
 use url::Url;
 
@@ -18,6 +21,8 @@ pub(crate) enum Error {
     IO(std::io::Error),
     Url(url::ParseError),
     Http(reqwest::Error),
+    // The requested range is outside of the song, whose size is given if known
+    RangeNotSatisfiable(Option<u64>),
 }
 
 impl Error {
@@ -50,6 +55,7 @@ impl From<Error> for std::io::Error {
             Error::IO(x) => x,
             Error::Url(x) => std::io::Error::new(ErrorKind::Other, x),
             Error::Http(x) => std::io::Error::new(ErrorKind::Other, x),
+            err @ Error::RangeNotSatisfiable(_) => std::io::Error::new(ErrorKind::Other, err),
         }
     }
 }
@@ -68,12 +74,121 @@ impl From<reqwest::Error> for Error {
 
 pub(crate) type Result<T> = std::result::Result<T, Error>;
 
+// ByteRange is a range of bytes of a song requested by a client
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ByteRange {
+    // Bytes starting at the offset till the end of the song
+    From(u64),
+    // Bytes between the offsets, inclusive
+    Between(u64, u64),
+    // The last bytes of the song
+    Last(u64),
+}
+
+impl ByteRange {
+    // parse parses a single range from HTTP Range header value (e.g. bytes=100-200). Multiple
+    // ranges are not supported.
+    pub(crate) fn parse(header: &str) -> Option<Self> {
+        let (start, end) = header.trim().strip_prefix("bytes=")?.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+
+        match (start.is_empty(), end.is_empty()) {
+            (false, true) => Some(ByteRange::From(start.parse().ok()?)),
+            (false, false) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                (start <= end).then_some(ByteRange::Between(start, end))
+            }
+            (true, false) => Some(ByteRange::Last(end.parse().ok()?)),
+            (true, true) => None,
+        }
+    }
+
+    // resolve returns inclusive offsets of the range within a song of the given size, if the
+    // range is satisfiable
+    pub(crate) fn resolve(self, size: u64) -> Option<(u64, u64)> {
+        let (start, end) = match self {
+            ByteRange::From(start) => (start, size.checked_sub(1)?),
+            ByteRange::Between(start, end) => (start, end.min(size.checked_sub(1)?)),
+            ByteRange::Last(0) => return None,
+            ByteRange::Last(len) => (size.saturating_sub(len), size.checked_sub(1)?),
+        };
+
+        (start <= end).then_some((start, end))
+    }
+
+    fn header(self) -> String {
+        match self {
+            ByteRange::From(start) => format!("bytes={start}-"),
+            ByteRange::Between(start, end) => format!("bytes={start}-{end}"),
+            ByteRange::Last(len) => format!("bytes=-{len}"),
+        }
+    }
+}
+
+// ContentRange is a range of bytes of a song actually returned by a library
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ContentRange {
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    // Size of the whole song, if known
+    pub(crate) size: Option<u64>,
+}
+
+impl ContentRange {
+    // parse parses HTTP Content-Range header value (e.g. bytes 100-200/1000)
+    fn parse(header: &str) -> Option<Self> {
+        let (range, size) = header.trim().strip_prefix("bytes ")?.split_once('/')?;
+        let (start, end) = range.split_once('-')?;
+
+        Some(ContentRange {
+            start: start.parse().ok()?,
+            end: end.parse().ok()?,
+            size: match size {
+                "*" => None,
+                size => Some(size.parse().ok()?),
+            },
+        })
+    }
+
+    pub(crate) fn header(&self) -> String {
+        match self.size {
+            Some(size) => format!("bytes {}-{}/{size}", self.start, self.end),
+            None => format!("bytes {}-{}/*", self.start, self.end),
+        }
+    }
+}
+
+// SongStream is contents of a song. If only a part of the song is returned, range describes it.
+pub(crate) struct SongStream {
+    pub(crate) stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send + 'static>>,
+    pub(crate) range: Option<ContentRange>,
+}
+
 #[async_trait]
 pub(crate) trait Library {
-    async fn get_song(
-        &self,
-        uri: &str,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send + 'static>>>;
+    // get_song returns contents of the song. If range is set and the library supports ranges,
+    // only the requested part of the song is returned, or RangeNotSatisfiable if the range is
+    // outside of the song. Otherwise, the whole song is returned.
+    async fn get_song(&self, uri: &str, range: Option<ByteRange>) -> Result<SongStream>;
+
+    // find_sibling returns URI of the first of the candidate files found in the directory (e.g.
+    // cover images or lyrics next to songs). Names are matched case-insensitively where the
+    // library can list directories.
+    async fn find_sibling(&self, dir: &str, candidates: &[&str]) -> Result<Option<String>>;
+
+    // song_size returns size of the song in bytes, if the library knows it
+    async fn song_size(&self, uri: &str) -> Result<Option<u64>>;
+
+    // is_remote returns true if files of the library are fetched over the network
+    fn is_remote(&self) -> bool;
+}
+
+// sibling_uri returns URI of the file in the directory
+fn sibling_uri(dir: &str, name: &str) -> String {
+    match dir.trim_end_matches('/') {
+        "" => name.to_string(),
+        dir => format!("{dir}/{name}"),
+    }
 }
 
 pub(crate) async fn get_library(path: &str) -> Result<Box<dyn Library + Send + Sync>> {
@@ -99,38 +214,488 @@ impl FSLibrary {
 
 #[async_trait]
 impl Library for FSLibrary {
-    async fn get_song(
-        &self,
-        uri: &str,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send + 'static>>> {
+    async fn get_song(&self, uri: &str, range: Option<ByteRange>) -> Result<SongStream> {
         let uri = uri.to_string();
-        let file = File::open(self.root.join(Path::new(&uri))).await?;
+        let mut file = File::open(self.root.join(Path::new(&uri))).await?;
+
+        let size = file.metadata().await?.len();
+        let Some(range) = range else {
+            return Ok(SongStream {
+                stream: ReaderStream::new(file)
+                    .map(|x| x.map_err(Into::into))
+                    .boxed(),
+                range: None,
+            });
+        };
+        let (start, end) = range
+            .resolve(size)
+            .ok_or(Error::RangeNotSatisfiable(Some(size)))?;
 
-        Ok(ReaderStream::new(file)
-            .map(|x| x.map_err(Into::into))
-            .boxed())
+        file.seek(SeekFrom::Start(start)).await?;
+        Ok(SongStream {
+            stream: ReaderStream::new(file.take(end - start + 1))
+                .map(|x| x.map_err(Into::into))
+                .boxed(),
+            range: Some(ContentRange {
+                start,
+                end,
+                size: Some(size),
+            }),
+        })
+    }
+
+    async fn find_sibling(&self, dir: &str, candidates: &[&str]) -> Result<Option<String>> {
+        let mut entries = tokio::fs::read_dir(self.root.join(Path::new(dir))).await?;
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(name.to_string());
+            }
+        }
+
+        Ok(candidates
+            .iter()
+            .find_map(|c| names.iter().find(|n| n.eq_ignore_ascii_case(c)))
+            .map(|name| sibling_uri(dir, name)))
+    }
+
+    async fn song_size(&self, uri: &str) -> Result<Option<u64>> {
+        let metadata = tokio::fs::metadata(self.root.join(Path::new(uri))).await?;
+        Ok(Some(metadata.len()))
+    }
+
+    fn is_remote(&self) -> bool {
+        false
     }
 }
 
 struct HTTPLibrary {
     base: Url,
+    client: reqwest::Client,
 }
 
 // HTTPLibrary implements Library on top of HTTP/HTTPS server.
 impl HTTPLibrary {
     fn new(base: Url) -> Self {
-        HTTPLibrary { base }
+        HTTPLibrary {
+            base,
+            client: reqwest::Client::new(),
+        }
     }
 }
 
 #[async_trait]
 impl Library for HTTPLibrary {
-    async fn get_song(
-        &self,
-        uri: &str,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send + 'static>>> {
-        let stream = reqwest::get(self.base.join(uri)?).await?.bytes_stream();
+    async fn get_song(&self, uri: &str, range: Option<ByteRange>) -> Result<SongStream> {
+        let mut request = self.client.get(self.base.join(uri)?);
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range.header());
+        }
+
+        let mut response = request.send().await?;
+        // The server is free to ignore the range and return the whole song
+        let range = match response.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let range = response
+                    .headers()
+                    .get(header::CONTENT_RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(ContentRange::parse);
+                // There is no telling which part of the song was returned, get all of it then
+                if range.is_none() {
+                    response = self
+                        .client
+                        .get(self.base.join(uri)?)
+                        .send()
+                        .await?
+                        .error_for_status()?;
+                }
+                range
+            }
+            StatusCode::RANGE_NOT_SATISFIABLE => {
+                return Err(Error::RangeNotSatisfiable(
+                    response
+                        .headers()
+                        .get(header::CONTENT_RANGE)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.trim().strip_prefix("bytes */"))
+                        .and_then(|v| v.parse().ok()),
+                ))
+            }
+            _ => None,
+        };
+
+        Ok(SongStream {
+            stream: response
+                .bytes_stream()
+                .map(|x| x.map_err(Into::into))
+                .boxed(),
+            range,
+        })
+    }
+
+    // HTTP servers don't necessarily list directories, so the candidates are probed one by one.
+    // Servers that don't allow HEAD are asked for the first byte of the file instead.
+    async fn find_sibling(&self, dir: &str, candidates: &[&str]) -> Result<Option<String>> {
+        for candidate in candidates {
+            let uri = sibling_uri(dir, candidate);
+            let url = self.base.join(&uri)?;
+            let mut response = self.client.head(url.clone()).send().await?;
+            if matches!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+            ) {
+                response = self
+                    .client
+                    .get(url)
+                    .header(header::RANGE, ByteRange::Between(0, 0).header())
+                    .send()
+                    .await?;
+            }
+            match response.status() {
+                StatusCode::NOT_FOUND => continue,
+                // The file exists, but is empty
+                StatusCode::RANGE_NOT_SATISFIABLE => (),
+                _ => {
+                    response.error_for_status()?;
+                }
+            }
+            return Ok(Some(uri));
+        }
+
+        Ok(None)
+    }
+
+    // Replies to HEAD have no body, so the length is taken from the header as is
+    async fn song_size(&self, uri: &str) -> Result<Option<u64>> {
+        let response = self
+            .client
+            .head(self.base.join(uri)?)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok()))
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_library, ByteRange, ContentRange, Error};
+    use axum::{
+        http::{header, HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::{get, Router},
+    };
+    use futures::StreamExt;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn byte_range() {
+        assert_eq!(ByteRange::parse("bytes=100-"), Some(ByteRange::From(100)));
+        assert_eq!(
+            ByteRange::parse("bytes=100-199"),
+            Some(ByteRange::Between(100, 199))
+        );
+        assert_eq!(ByteRange::parse("bytes=-100"), Some(ByteRange::Last(100)));
+        assert_eq!(ByteRange::parse("bytes=200-100"), None);
+        assert_eq!(ByteRange::parse("bytes=0-1,5-6"), None);
+        assert_eq!(ByteRange::parse("items=0-1"), None);
+        assert_eq!(ByteRange::parse("bytes=-"), None);
+
+        assert_eq!(ByteRange::From(100).resolve(1000), Some((100, 999)));
+        assert_eq!(ByteRange::From(1000).resolve(1000), None);
+        assert_eq!(
+            ByteRange::Between(100, 5000).resolve(1000),
+            Some((100, 999))
+        );
+        assert_eq!(ByteRange::Last(100).resolve(1000), Some((900, 999)));
+        assert_eq!(ByteRange::Last(5000).resolve(1000), Some((0, 999)));
+        assert_eq!(ByteRange::Last(0).resolve(1000), None);
+        assert_eq!(ByteRange::From(0).resolve(0), None);
+    }
+
+    #[test]
+    fn content_range() {
+        let range = ContentRange {
+            start: 100,
+            end: 199,
+            size: Some(1000),
+        };
+        assert_eq!(range.header(), "bytes 100-199/1000");
+        assert_eq!(ContentRange::parse("bytes 100-199/1000"), Some(range));
+        assert_eq!(
+            ContentRange::parse("bytes 100-199/*"),
+            Some(ContentRange {
+                size: None,
+                ..range
+            })
+        );
+        assert_eq!(ContentRange::parse("bytes */1000"), None);
+    }
+
+    #[tokio::test]
+    async fn fs_library_range() {
+        let dir = std::env::temp_dir().join(format!("mpdsonic-library-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("song.flac"), (0..=255).collect::<Vec<u8>>()).unwrap();
+        let lib = get_library(dir.to_str().unwrap()).await.unwrap();
+
+        let read = |range| {
+            let lib = &lib;
+            async move {
+                let song = lib.get_song("song.flac", range).await.unwrap();
+                let data = song.stream.map(|c| c.unwrap().to_vec()).concat().await;
+                (data, song.range)
+            }
+        };
+
+        let (data, range) = read(None).await;
+        assert_eq!(data, (0..=255).collect::<Vec<u8>>());
+        assert_eq!(range, None);
+
+        let (data, range) = read(Some(ByteRange::Between(10, 19))).await;
+        assert_eq!(data, (10..20).collect::<Vec<u8>>());
+        assert_eq!(
+            range,
+            Some(ContentRange {
+                start: 10,
+                end: 19,
+                size: Some(256)
+            })
+        );
+
+        let (data, range) = read(Some(ByteRange::Last(6))).await;
+        assert_eq!(data, (250..=255).collect::<Vec<u8>>());
+        assert_eq!(range.map(|r| r.start), Some(250));
+
+        assert!(matches!(
+            lib.get_song("song.flac", Some(ByteRange::From(1000))).await,
+            Err(Error::RangeNotSatisfiable(Some(256)))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn http_library_range() {
+        let router = Router::new()
+            .route(
+                "/music/short.flac",
+                get(|| async {
+                    (
+                        StatusCode::RANGE_NOT_SATISFIABLE,
+                        [(header::CONTENT_RANGE, "bytes */200")],
+                    )
+                }),
+            )
+            .route(
+                "/music/odd.flac",
+                get(|headers: HeaderMap| async move {
+                    // A partial reply which doesn't tell which part it is
+                    match headers.contains_key(header::RANGE) {
+                        true => (StatusCode::PARTIAL_CONTENT, vec![1; 10]),
+                        false => (StatusCode::OK, vec![0; 200]),
+                    }
+                }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let lib = get_library(&format!("http://{address}/music/"))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            lib.get_song("short.flac", Some(ByteRange::From(1000)))
+                .await,
+            Err(Error::RangeNotSatisfiable(Some(200)))
+        ));
+
+        let song = lib
+            .get_song("odd.flac", Some(ByteRange::Between(10, 19)))
+            .await
+            .unwrap();
+        assert_eq!(song.range, None);
+        let data = song.stream.map(|c| c.unwrap().to_vec()).concat().await;
+        assert_eq!(data, vec![0; 200]);
+    }
+
+    #[tokio::test]
+    async fn fs_library_find_sibling() {
+        let dir = std::env::temp_dir().join(format!("mpdsonic-sibling-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("alpha")).unwrap();
+        for file in [
+            "alpha/1.flac",
+            "alpha/Cover.JPG",
+            "alpha/folder.png",
+            "root.jpg",
+        ] {
+            std::fs::write(dir.join(file), b"").unwrap();
+        }
+        let lib = get_library(dir.to_str().unwrap()).await.unwrap();
+
+        assert_eq!(
+            lib.find_sibling("alpha", &["cover.jpg", "folder.png"])
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("alpha/Cover.JPG")
+        );
+        assert_eq!(
+            lib.find_sibling("alpha", &["front.jpg", "folder.png"])
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("alpha/folder.png")
+        );
+        assert_eq!(
+            lib.find_sibling("alpha", &["front.jpg"]).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            lib.find_sibling("", &["root.jpg"])
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("root.jpg")
+        );
+        assert!(lib.find_sibling("beta", &["cover.jpg"]).await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn song_size() {
+        let dir = std::env::temp_dir().join(format!("mpdsonic-size-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("song.flac"), [0; 100]).unwrap();
+        let lib = get_library(dir.to_str().unwrap()).await.unwrap();
+
+        assert_eq!(lib.song_size("song.flac").await.unwrap(), Some(100));
+        assert!(lib.song_size("missing.flac").await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let router = Router::new().route("/music/song.flac", get(|| async { vec![0; 200] }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let lib = get_library(&format!("http://{address}/music/"))
+            .await
+            .unwrap();
+
+        assert_eq!(lib.song_size("song.flac").await.unwrap(), Some(200));
+        assert!(lib.song_size("missing.flac").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn http_library_find_sibling() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let router = Router::new()
+            .route("/music/alpha/folder.png", get(|| async { "folder" }))
+            .route("/music/alpha/cover.jpg", get(|| async { "cover" }))
+            .layer(axum::middleware::from_fn({
+                let requests = requests.clone();
+                move |req: axum::extract::Request, next: axum::middleware::Next| {
+                    requests
+                        .lock()
+                        .unwrap()
+                        .push(format!("{} {}", req.method(), req.uri()));
+                    next.run(req)
+                }
+            }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let lib = get_library(&format!("http://{address}/music/"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            lib.find_sibling("alpha", &["front.jpg", "folder.png", "cover.jpg"])
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("alpha/folder.png")
+        );
+        // Candidates are probed in order, until one is found
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                "HEAD /music/alpha/front.jpg",
+                "HEAD /music/alpha/folder.png"
+            ]
+        );
+        assert_eq!(
+            lib.find_sibling("beta", &["cover.jpg"]).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn http_library_find_sibling_without_head() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let router = Router::new()
+            .route(
+                "/music/alpha/cover.jpg",
+                get(|headers: HeaderMap| async move {
+                    assert_eq!(headers[header::RANGE], "bytes=0-0");
+                    (StatusCode::PARTIAL_CONTENT, "c")
+                }),
+            )
+            .route(
+                "/music/alpha/front.jpg",
+                get(|| async { StatusCode::NOT_FOUND }),
+            )
+            .layer(axum::middleware::from_fn({
+                let requests = requests.clone();
+                move |req: axum::extract::Request, next: axum::middleware::Next| {
+                    requests
+                        .lock()
+                        .unwrap()
+                        .push(format!("{} {}", req.method(), req.uri()));
+                    let head = req.method() == axum::http::Method::HEAD;
+                    async move {
+                        if head {
+                            StatusCode::METHOD_NOT_ALLOWED.into_response()
+                        } else {
+                            next.run(req).await
+                        }
+                    }
+                }
+            }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        let lib = get_library(&format!("http://{address}/music/"))
+            .await
+            .unwrap();
 
-        Ok(stream.map(|x| x.map_err(Into::into)).boxed())
+        assert_eq!(
+            lib.find_sibling("alpha", &["front.jpg", "cover.jpg"])
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("alpha/cover.jpg")
+        );
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                "HEAD /music/alpha/front.jpg",
+                "GET /music/alpha/front.jpg",
+                "HEAD /music/alpha/cover.jpg",
+                "GET /music/alpha/cover.jpg"
+            ]
+        );
     }
 }
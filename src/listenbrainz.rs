@@ -1,11 +1,23 @@
 use mpd_client::{responses::Song, tag::Tag};
-use reqwest::header::{self, HeaderMap, HeaderValue};
-use serde::Serialize;
-use std::{collections::HashMap, fmt};
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    Url,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, time::Duration};
+
+// Maximum time a single lookup may take, clients are waiting for the reply
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+// Datasets ListenBrainz Labs computes similar recordings and artists with
+const SIMILAR_RECORDINGS_ALGORITHM: &str =
+    "session_based_days_9000_session_300_contribution_5_threshold_15_limit_50_skip_30";
+const SIMILAR_ARTISTS_ALGORITHM: &str =
+    "session_based_days_7500_session_300_contribution_5_threshold_10_limit_100_filter_True_skip_30";
 
 #[derive(Clone)]
 pub(crate) struct Client {
     client: reqwest::Client,
+    labs: Url,
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -14,6 +26,7 @@ type Result<T> = std::result::Result<T, Error>;
 pub(crate) enum Error {
     Http(reqwest::Error),
     Header(header::InvalidHeaderValue),
+    Url(url::ParseError),
     Song,
 }
 
@@ -29,6 +42,12 @@ impl From<reqwest::Error> for Error {
     }
 }
 
+impl From<url::ParseError> for Error {
+    fn from(err: url::ParseError) -> Self {
+        Error::Url(err)
+    }
+}
+
 impl From<header::InvalidHeaderValue> for Error {
     fn from(err: header::InvalidHeaderValue) -> Self {
         Error::Header(err)
@@ -37,6 +56,10 @@ impl From<header::InvalidHeaderValue> for Error {
 
 impl Client {
     pub(crate) fn new(token: &str) -> Result<Client> {
+        Client::with_labs_endpoint(token, "https://labs.api.listenbrainz.org")
+    }
+
+    pub(crate) fn with_labs_endpoint(token: &str, labs: &str) -> Result<Client> {
         let mut headers = HeaderMap::new();
         headers.insert(
             header::AUTHORIZATION,
@@ -47,9 +70,62 @@ impl Client {
             client: reqwest::ClientBuilder::new()
                 .default_headers(headers)
                 .build()?,
+            labs: Url::parse(labs)?,
         })
     }
 
+    // similar_recordings returns MusicBrainz IDs of recordings similar to the given one, the most
+    // similar first
+    pub(crate) async fn similar_recordings(&self, mbid: &str) -> Result<Vec<String>> {
+        self.similar(
+            "similar-recordings",
+            "recording_mbids",
+            SIMILAR_RECORDINGS_ALGORITHM,
+            mbid,
+        )
+        .await
+    }
+
+    // similar_artists returns MusicBrainz IDs of artists similar to the given one, the most
+    // similar first
+    pub(crate) async fn similar_artists(&self, mbid: &str) -> Result<Vec<String>> {
+        self.similar(
+            "similar-artists",
+            "artist_mbids",
+            SIMILAR_ARTISTS_ALGORITHM,
+            mbid,
+        )
+        .await
+    }
+
+    async fn similar(
+        &self,
+        dataset: &str,
+        param: &str,
+        algorithm: &str,
+        mbid: &str,
+    ) -> Result<Vec<String>> {
+        let mut url = self.labs.join(&format!("{dataset}/json"))?;
+        url.query_pairs_mut()
+            .append_pair(param, mbid)
+            .append_pair("algorithm", algorithm);
+        let similar: Vec<Similar> = self
+            .client
+            .get(url)
+            .timeout(LOOKUP_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(similar
+            .into_iter()
+            .map(|s| s.mbid)
+            .filter(|m| m != mbid)
+            .collect())
+    }
+
     pub(crate) async fn listen(&self, song: &Song, timestamp: i64) -> Result<()> {
         self.submit(Submission::Listen([Listen {
             listened_at: timestamp,
@@ -91,6 +167,13 @@ impl Client {
     }
 }
 
+// Similar recording or artist as returned by ListenBrainz Labs
+#[derive(Debug, Deserialize)]
+struct Similar {
+    #[serde(alias = "recording_mbid", alias = "artist_mbid")]
+    mbid: String,
+}
+
 #[derive(Debug, Serialize)]
 #[serde(tag = "listen_type", content = "payload")]
 enum Submission {
@@ -201,3 +284,78 @@ where
         Score::Remove => 0,
     })
 }
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use axum::{
+        extract::Query,
+        routing::{get, Router},
+        Json,
+    };
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    // fake_labs starts a fake ListenBrainz Labs API. Recording "rec-alpha" is similar to
+    // "rec-beta" and "rec-gamma", artist "art-alpha" is similar to "art-beta". It returns the
+    // address of the API.
+    pub(crate) async fn fake_labs() -> String {
+        let router = Router::new()
+            .route(
+                "/similar-recordings/json",
+                get(|Query(query): Query<HashMap<String, String>>| async move {
+                    Json(match query.get("recording_mbids").map(String::as_str) {
+                        Some("rec-alpha") => json!([
+                            {"recording_mbid": "rec-gamma", "score": 20},
+                            {"recording_mbid": "rec-beta", "score": 10},
+                        ]),
+                        _ => Value::Array(vec![]),
+                    })
+                }),
+            )
+            .route(
+                "/similar-artists/json",
+                get(|Query(query): Query<HashMap<String, String>>| async move {
+                    Json(match query.get("artist_mbids").map(String::as_str) {
+                        Some("art-alpha") => json!([
+                            {"artist_mbid": "art-beta", "name": "beta", "score": 10},
+                        ]),
+                        _ => Value::Array(vec![]),
+                    })
+                }),
+            );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        format!("http://{address}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{testing::fake_labs, Client};
+
+    #[tokio::test]
+    async fn similar() {
+        let client = Client::with_labs_endpoint("token", &fake_labs().await).unwrap();
+
+        assert_eq!(
+            client.similar_recordings("rec-alpha").await.unwrap(),
+            ["rec-gamma", "rec-beta"]
+        );
+        assert!(client
+            .similar_recordings("rec-beta")
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            client.similar_artists("art-alpha").await.unwrap(),
+            ["art-beta"]
+        );
+
+        let client = Client::with_labs_endpoint("token", "http://127.0.0.1:1").unwrap();
+        assert!(client.similar_recordings("rec-alpha").await.is_err());
+    }
+}
@@ -5,19 +5,31 @@ use axum::{
     middleware::{self, Next},
     response::Response,
 };
-use clap::Parser;
-use std::{net::SocketAddr, time::Duration};
+use axum_server::tls_rustls::RustlsConfig;
+use clap::{error::ErrorKind, CommandFactory, FromArgMatches, Parser};
+use futures::FutureExt;
+use std::{ffi::OsString, future::IntoFuture, net::SocketAddr, path::PathBuf, time::Duration};
 use tokio::net::TcpListener;
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 
 mod api;
+mod artistinfo;
+mod config;
+mod coverartarchive;
 mod library;
 mod listenbrainz;
 mod mpd;
+mod webui;
 
 #[derive(Parser)]
 #[clap(author, version, about)]
 struct Args {
+    #[clap(
+        long,
+        help = "TOML file with default values of the flags below (e.g. mpd_library = \"/music\")",
+        env = "MPDSONIC_CONFIG"
+    )]
+    config: Option<PathBuf>,
     #[clap(
         short,
         long,
@@ -27,16 +39,226 @@ struct Args {
     address: SocketAddr,
     #[clap(short, long, help = "Subsonic API username", env = "MPDSONIC_USERNAME")]
     username: String,
-    #[clap(short, long, help = "Subsonic API password", env = "MPDSONIC_PASSWORD")]
-    password: String,
-    #[clap(long, help = "MPD address", default_value = "127.0.0.1:6600")]
-    mpd_address: SocketAddr,
+    #[clap(
+        short,
+        long,
+        help = "Subsonic API password (required unless authentication is disabled)",
+        env = "MPDSONIC_PASSWORD"
+    )]
+    password: Option<String>,
+    #[clap(
+        long,
+        help = "API key clients can use instead of the password of the main user",
+        env = "MPDSONIC_API_KEY"
+    )]
+    api_key: Option<String>,
+    #[clap(
+        long = "user",
+        help = "Additional Subsonic API user as name:password (can be repeated)",
+        value_parser = parse_user
+    )]
+    users: Vec<(String, String)>,
+    #[clap(
+        long,
+        help = "Disable Subsonic API authentication (INSECURE, use only on trusted networks)"
+    )]
+    disable_authentication: bool,
+    #[clap(
+        long,
+        help = "MPD address (host:port, or path of a local socket)",
+        default_value = "127.0.0.1:6600"
+    )]
+    mpd_address: mpd::Address,
     #[clap(long, help = "MPD password", env = "MPDSONIC_MPD_PASSWORD")]
     mpd_password: Option<String>,
-    #[clap(long, help = "MPD library location")]
-    mpd_library: String,
+    #[clap(
+        long,
+        help = "Fail MPD commands that take longer than this many seconds",
+        default_value = "10"
+    )]
+    mpd_command_timeout: u64,
+    #[clap(
+        long,
+        help = "Maximum number of connections to MPD",
+        default_value = "8",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    mpd_pool_size: u32,
+    #[clap(
+        long,
+        help = "Fail requests that can't get a connection to MPD within this many seconds",
+        default_value = "1",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    mpd_connect_timeout: u64,
+    #[clap(
+        long,
+        help = "Maximum size in bytes of binary chunks (e.g. album art) MPD sends at once",
+        default_value = "131072"
+    )]
+    mpd_binary_limit: usize,
+    #[clap(
+        long,
+        help = "MPD library location (asked from MPD if not given, which works only if \
+                --mpd-address is a local socket)"
+    )]
+    mpd_library: Option<String>,
     #[clap(long, help = "ListenBrainz token", env = "MPDSONIC_LISTENBRAINZ_TOKEN")]
     listenbrainz_token: Option<String>,
+    #[clap(
+        long,
+        help = "Fetch artist biography and images from an external provider (musicbrainz)"
+    )]
+    artist_info_provider: Option<artistinfo::Provider>,
+    #[clap(
+        long,
+        help = "Fetch album art missing in the library from the Cover Art Archive \
+                (needs MusicBrainz release IDs in tags)"
+    )]
+    fetch_remote_art: bool,
+    #[clap(long, help = "Directory with artist images named <artist name>.jpg")]
+    artist_image_dir: Option<PathBuf>,
+    #[clap(long, help = "Do not expose song paths to clients")]
+    hide_paths: bool,
+    #[clap(
+        long,
+        help = "Treat an album as starred if any of its songs is starred, not all of them"
+    )]
+    album_starred_any: bool,
+    #[clap(
+        long,
+        help = "Format to transcode to if the client doesn't ask for one (opus, mp3 or aac)",
+        default_value = "opus"
+    )]
+    default_transcode_format: api::TranscodeFormat,
+    #[clap(
+        long,
+        help = "Do not allow clients to modify playlists, ratings, stars or start library scans"
+    )]
+    read_only: bool,
+    #[clap(
+        long,
+        help = "Number of album art images to cache in memory (0 disables caching)",
+        default_value = "256"
+    )]
+    cover_cache_size: usize,
+    #[clap(
+        long,
+        help = "Number of seconds to cache the artists index for (0 disables caching). Library \
+                changes other than scans started by clients show up only once it expires",
+        default_value = "0"
+    )]
+    artists_cache_ttl: u64,
+    #[clap(
+        long,
+        help = "Use https in URLs returned to clients (e.g. behind a TLS-terminating proxy \
+                that doesn't set X-Forwarded-Proto)"
+    )]
+    announce_https: bool,
+    #[clap(
+        long,
+        help = "Server type announced to OpenSubsonic clients (for clients accepting only \
+                known servers)",
+        default_value = env!("CARGO_PKG_NAME")
+    )]
+    announce_type: String,
+    #[clap(long, help = "Avatar image of the main user (--username)")]
+    avatar: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Let clients control MPD playback (Subsonic jukebox mode)"
+    )]
+    jukebox: bool,
+    #[clap(
+        long,
+        help = "Serve a minimal web UI at / to check the server from a browser"
+    )]
+    web_ui: bool,
+    #[clap(
+        long,
+        help = "TLS certificate chain (PEM) to serve HTTPS with (requires --tls-key)"
+    )]
+    tls_cert: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "TLS private key (PEM) to serve HTTPS with (requires --tls-cert)"
+    )]
+    tls_key: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Number of seconds to wait for requests in flight to complete on shutdown",
+        default_value = "30"
+    )]
+    shutdown_timeout: u64,
+}
+
+// parse_user parses name:password pair of an additional user. Neither of them may be empty.
+fn parse_user(user: &str) -> Result<(String, String), String> {
+    match user.split_once(':') {
+        Some((name, password)) if !name.is_empty() && !password.is_empty() => {
+            Ok((name.to_string(), password.to_string()))
+        }
+        _ => Err("expected name:password with non-empty name and password".to_string()),
+    }
+}
+
+// parse_args parses command line arguments. If a config file is given, values from it override
+// the built-in defaults, but not flags or environment variables.
+fn parse_args(args: Vec<OsString>) -> clap::error::Result<Args> {
+    let matches = Args::command()
+        .ignore_errors(true)
+        .get_matches_from(args.clone());
+    let cmd = match matches.get_one::<PathBuf>("config") {
+        Some(path) => config::ConfigFile::load(path)
+            .map_err(|err| Args::command().error(ErrorKind::Io, err))?
+            .apply(Args::command()),
+        None => Args::command(),
+    };
+
+    let args = Args::from_arg_matches(&cmd.try_get_matches_from(args)?)?;
+    // Checked here rather than by clap, as disable_authentication may come from the config file
+    if args.password.is_none() && !args.disable_authentication {
+        return Err(Args::command().error(
+            ErrorKind::MissingRequiredArgument,
+            "--password is required unless --disable-authentication is given",
+        ));
+    }
+    if args.password.as_deref() == Some("") {
+        return Err(Args::command().error(ErrorKind::InvalidValue, "--password must not be empty"));
+    }
+    if args.api_key.as_deref() == Some("") {
+        return Err(Args::command().error(ErrorKind::InvalidValue, "--api-key must not be empty"));
+    }
+    // Users may come from the config file too, a repeated name would silently shadow another user
+    let mut names = std::collections::HashSet::from([args.username.as_str()]);
+    if let Some((name, _)) = args.users.iter().find(|(name, _)| !names.insert(name)) {
+        return Err(Args::command().error(
+            ErrorKind::ValueValidation,
+            format!("user {name} is given more than once"),
+        ));
+    }
+    // Same for the TLS certificate and key, which must come in pairs
+    match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => {
+            for path in [cert, key] {
+                std::fs::File::open(path).map_err(|err| {
+                    Args::command().error(
+                        ErrorKind::Io,
+                        format!("failed to read {}: {err}", path.display()),
+                    )
+                })?;
+            }
+        }
+        (None, None) => (),
+        _ => {
+            return Err(Args::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "--tls-cert and --tls-key must be given together",
+            ))
+        }
+    }
+
+    Ok(args)
 }
 
 async fn print_request(req: Request<Body>, next: Next) -> Response {
@@ -53,6 +275,39 @@ async fn print_request(req: Request<Body>, next: Next) -> Response {
     response
 }
 
+// shutdown_signal resolves when the server is asked to stop with SIGINT (Ctrl+C) or SIGTERM.
+// Requests in flight are served to completion (up to --shutdown-timeout), transcoders of
+// dropped streams are killed.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            warn!("failed to listen for Ctrl+C: {err}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                warn!("failed to listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("shutting down, waiting for requests in flight to complete");
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
@@ -64,28 +319,114 @@ async fn main() {
 }
 
 async fn run_main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let args = parse_args(std::env::args_os().collect()).unwrap_or_else(|err| err.exit());
+
+    let auth = match (args.disable_authentication, &args.password) {
+        (false, Some(password)) => {
+            let auth = args.users.iter().fold(
+                api::Authentication::new(&args.username, password),
+                |auth, (username, password)| auth.with_user(username, password),
+            );
+            match &args.api_key {
+                Some(key) => auth.with_api_key(key),
+                None => auth,
+            }
+        }
+        _ => {
+            warn!(
+                "Subsonic API authentication is DISABLED, anyone who can reach {} has full access \
+                 to the library. This is INSECURE and should only be used on trusted networks!",
+                args.address
+            );
+            api::Authentication::disabled(&args.username)
+        }
+    };
 
-    let manager = mpd::ConnectionManager::new(&args.mpd_address, &args.mpd_password);
+    let manager = mpd::ConnectionManager::new(
+        &args.mpd_address,
+        &args.mpd_password,
+        Duration::from_secs(args.mpd_command_timeout),
+    );
     let pool = bb8::Pool::builder()
-        .max_size(8)
-        .connection_timeout(Duration::from_secs(1))
-        .connection_customizer(Box::new(mpd::ConnectionCustomizer))
+        .max_size(args.mpd_pool_size)
+        .connection_timeout(Duration::from_secs(args.mpd_connect_timeout))
+        .connection_customizer(Box::new(mpd::ConnectionCustomizer::new(
+            args.mpd_binary_limit,
+        )))
         .build(manager)
         .await?;
+    let mpd_library = match args.mpd_library {
+        Some(library) => library,
+        None => mpd::music_directory(&*pool.get().await?).await?,
+    };
 
-    let auth = api::Authentication::new(&args.username, &args.password);
-    let app = api::get_router(
+    let mut app = api::get_router(
         auth,
         pool,
-        library::get_library(&args.mpd_library).await?,
+        library::get_library(&mpd_library).await?,
         args.listenbrainz_token
             .and_then(|t| listenbrainz::Client::new(&t).ok()),
-    )
-    .layer(middleware::from_fn(print_request));
+        args.artist_info_provider
+            .and_then(|p| artistinfo::Client::new(p).ok()),
+        args.fetch_remote_art
+            .then(coverartarchive::Client::new)
+            .and_then(Result::ok),
+        api::Config {
+            artist_image_dir: args.artist_image_dir,
+            hide_paths: args.hide_paths,
+            album_starred_any: args.album_starred_any,
+            default_transcode_format: args.default_transcode_format,
+            read_only: args.read_only,
+            cover_cache_size: args.cover_cache_size,
+            artists_cache_ttl: Duration::from_secs(args.artists_cache_ttl),
+            announce_https: args.announce_https || args.tls_cert.is_some(),
+            announce_type: args.announce_type,
+            avatar: args.avatar,
+            jukebox: args.jukebox,
+        },
+    );
+    if args.web_ui {
+        app = app.merge(webui::get_router());
+    }
+    let app = app.layer(middleware::from_fn(print_request));
+    let shutdown_timeout = Duration::from_secs(args.shutdown_timeout);
 
-    let listener = TcpListener::bind(&args.address).await?;
-    axum::serve(listener, app.into_make_service()).await?;
+    if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+        let config = RustlsConfig::from_pem_file(cert, key)
+            .await
+            .map_err(|err| {
+                format!(
+                    "failed to load TLS certificate {} and key {}: {err}",
+                    cert.display(),
+                    key.display()
+                )
+            })?;
+        let handle = axum_server::Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                shutdown_signal().await;
+                handle.graceful_shutdown(Some(shutdown_timeout));
+            }
+        });
+        axum_server::bind_rustls(args.address, config)
+            .handle(handle)
+            .serve(app.into_make_service())
+            .await?;
+    } else {
+        let listener = TcpListener::bind(&args.address).await?;
+        let shutdown = shutdown_signal().shared();
+        let server = axum::serve(listener, app.into_make_service())
+            .with_graceful_shutdown(shutdown.clone())
+            .into_future();
+        tokio::select! {
+            res = server => res?,
+            _ = async {
+                shutdown.await;
+                tokio::time::sleep(shutdown_timeout).await;
+            } => warn!(timeout = ?shutdown_timeout, "requests in flight didn't complete in time"),
+        }
+    }
 
     Ok(())
 }
@@ -1,23 +1,141 @@
 use axum::async_trait;
+use bytes::{BufMut, BytesMut};
 use mpd_client::{
     client::{CommandError, ConnectWithPasswordError},
-    commands::{Ping, SetBinaryLimit},
+    commands::{Command, CommandList, Count, Find, Ping, SetBinaryLimit},
+    filter::Filter,
+    protocol::{
+        command::{Argument, Command as RawCommand},
+        response::Frame,
+    },
+    responses::{self, Song, TypedResponseError},
     Client,
 };
-use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    ops::Range,
+    path::PathBuf,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tracing::warn;
+
+// Address is where MPD listens, either a TCP address or a path of a local (unix) socket
+#[derive(Clone, Debug, PartialEq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Local(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = std::net::AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.starts_with('/') {
+            true => Ok(Address::Local(PathBuf::from(s))),
+            false => s.parse().map(Address::Tcp),
+        }
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Tcp(address) => write!(f, "{address}"),
+            Address::Local(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct ConnectionManager {
-    address: SocketAddr,
+    address: Address,
     password: Option<String>,
+    command_timeout: Duration,
 }
 
 impl ConnectionManager {
-    pub fn new(address: &SocketAddr, password: &Option<String>) -> ConnectionManager {
+    pub fn new(
+        address: &Address,
+        password: &Option<String>,
+        command_timeout: Duration,
+    ) -> ConnectionManager {
         ConnectionManager {
-            address: *address,
+            address: address.clone(),
             password: password.clone(),
+            command_timeout,
+        }
+    }
+
+    async fn connect_over<S>(&self, connection: S) -> Result<Connection, Error>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (client, _) = Client::connect_with_password_opt(connection, self.password.as_deref())
+            .await
+            .map_err(Error::ConnectWithPassword)?;
+
+        Ok(Connection::new(client, self.command_timeout))
+    }
+}
+
+// Connection is a pooled MPD client that fails commands taking longer than the configured
+// timeout. A connection with a timed out command is considered broken and is not returned to
+// the pool, as MPD may still be busy serving the command.
+pub struct Connection {
+    client: Client,
+    timeout: Duration,
+    timed_out: AtomicBool,
+}
+
+impl Connection {
+    fn new(client: Client, timeout: Duration) -> Connection {
+        Connection {
+            client,
+            timeout,
+            timed_out: AtomicBool::new(false),
+        }
+    }
+
+    pub async fn command<C: Command>(&self, cmd: C) -> Result<C::Response, Error> {
+        self.with_timeout(std::any::type_name::<C>(), self.client.command(cmd))
+            .await
+    }
+
+    pub async fn command_list<L: CommandList>(&self, list: L) -> Result<L::Response, Error> {
+        self.with_timeout(std::any::type_name::<L>(), self.client.command_list(list))
+            .await
+    }
+
+    // protocol_version returns the version of the MPD protocol the server speaks (e.g. 0.23.5)
+    pub fn protocol_version(&self) -> &str {
+        self.client.protocol_version()
+    }
+
+    // command_without_timeout sends a command that is expected to block for a long time
+    // (e.g. idle)
+    pub async fn command_without_timeout<C: Command>(&self, cmd: C) -> Result<C::Response, Error> {
+        self.client.command(cmd).await.map_err(Error::Command)
+    }
+
+    async fn with_timeout<R>(
+        &self,
+        name: &str,
+        fut: impl std::future::Future<Output = Result<R, CommandError>>,
+    ) -> Result<R, Error> {
+        match tokio::time::timeout(self.timeout, fut).await {
+            Ok(res) => res.map_err(Error::Command),
+            Err(_) => {
+                warn!(command = name, timeout = ?self.timeout, "MPD command timed out");
+                self.timed_out.store(true, Ordering::Relaxed);
+                Err(Error::Timeout(self.timeout))
+            }
         }
     }
 }
@@ -27,6 +145,23 @@ pub enum Error {
     Connect(std::io::Error),
     ConnectWithPassword(ConnectWithPasswordError),
     Command(CommandError),
+    Timeout(Duration),
+}
+
+// MPD error code of commands the client has no permission for
+pub const ACK_ERROR_PERMISSION: u64 = 4;
+// MPD error code of commands referring to something which doesn't exist (e.g. a sticker)
+pub const ACK_ERROR_NO_EXIST: u64 = 50;
+
+impl Error {
+    // is_no_exist checks if MPD failed the command because its subject doesn't exist
+    pub fn is_no_exist(&self) -> bool {
+        matches!(
+            self,
+            Error::Command(CommandError::ErrorResponse { error, .. })
+                if error.code == ACK_ERROR_NO_EXIST
+        )
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -35,6 +170,11 @@ impl std::fmt::Display for Error {
             Error::Connect(err) => write!(f, "{err}"),
             Error::ConnectWithPassword(err) => write!(f, "{err}"),
             Error::Command(err) => write!(f, "{err}"),
+            Error::Timeout(timeout) => write!(
+                f,
+                "MPD command timed out after {}ms, try again",
+                timeout.as_millis()
+            ),
         }
     }
 }
@@ -43,36 +183,637 @@ impl std::error::Error for Error {}
 
 #[async_trait]
 impl bb8::ManageConnection for ConnectionManager {
-    type Connection = Client;
+    type Connection = Connection;
     type Error = Error;
     async fn connect(&self) -> Result<Self::Connection, Self::Error> {
-        let connection = TcpStream::connect(self.address)
-            .await
-            .map_err(Error::Connect)?;
-        let (client, _) = Client::connect_with_password_opt(connection, self.password.as_deref())
-            .await
-            .map_err(Error::ConnectWithPassword)?;
-
-        Ok(client)
+        match &self.address {
+            Address::Tcp(address) => {
+                let connection = TcpStream::connect(address).await.map_err(Error::Connect)?;
+                self.connect_over(connection).await
+            }
+            #[cfg(unix)]
+            Address::Local(path) => {
+                let connection = tokio::net::UnixStream::connect(path)
+                    .await
+                    .map_err(Error::Connect)?;
+                self.connect_over(connection).await
+            }
+            #[cfg(not(unix))]
+            Address::Local(_) => Err(Error::Connect(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "local sockets are not supported on this platform",
+            ))),
+        }
     }
 
     async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
-        conn.command(Ping).await.map_err(Error::Command)
+        conn.command(Ping).await
     }
 
     fn has_broken(&self, conn: &mut Self::Connection) -> bool {
-        conn.is_connection_closed()
+        conn.client.is_connection_closed() || conn.timed_out.load(Ordering::Relaxed)
     }
 }
 
+// MPD rejects binary limits below 64 bytes. Responses also have to fit MPD's output buffer
+// (8MiB by default), so the limit is kept well below that.
+const MIN_BINARY_LIMIT: usize = 64;
+const MAX_BINARY_LIMIT: usize = 4 * 1024 * 1024;
+
 #[derive(Debug)]
-pub struct ConnectionCustomizer;
+pub struct ConnectionCustomizer {
+    binary_limit: usize,
+}
+
+impl ConnectionCustomizer {
+    // new returns a customizer setting the maximum size of binary responses (e.g. album art
+    // chunks) of MPD connections. The limit is clamped to the range MPD accepts.
+    pub fn new(binary_limit: usize) -> ConnectionCustomizer {
+        ConnectionCustomizer {
+            binary_limit: binary_limit.clamp(MIN_BINARY_LIMIT, MAX_BINARY_LIMIT),
+        }
+    }
+}
 
 #[async_trait]
-impl bb8::CustomizeConnection<Client, Error> for ConnectionCustomizer {
-    async fn on_acquire(&self, conn: &mut Client) -> Result<(), Error> {
-        conn.command(SetBinaryLimit(128 * 1024))
-            .await
-            .map_err(Error::Command)
+impl bb8::CustomizeConnection<Connection, Error> for ConnectionCustomizer {
+    async fn on_acquire(&self, conn: &mut Connection) -> Result<(), Error> {
+        conn.command(SetBinaryLimit(self.binary_limit)).await
+    }
+}
+
+// Search is the `search` MPD command. It works like `find`, but matches case-insensitively.
+#[derive(Clone, Debug)]
+pub struct Search {
+    filter: Filter,
+    window: Option<Range<usize>>,
+}
+
+impl Search {
+    pub fn new(filter: Filter) -> Self {
+        Search {
+            filter,
+            window: None,
+        }
+    }
+
+    pub fn window(mut self, window: Range<usize>) -> Self {
+        self.window = Some(window);
+        self
+    }
+}
+
+impl Command for Search {
+    type Response = Vec<Song>;
+
+    fn command(&self) -> RawCommand {
+        let mut command = RawCommand::new("search").argument(&self.filter);
+        if let Some(window) = &self.window {
+            command.add_argument("window").unwrap();
+            command
+                .add_argument(format!("{}:{}", window.start, window.end))
+                .unwrap();
+        }
+
+        command
+    }
+
+    fn response(self, frame: Frame) -> Result<Self::Response, TypedResponseError> {
+        Find::new(self.filter).response(frame)
+    }
+}
+
+// SearchCount is the `searchcount` MPD command. It works like `count`, but matches
+// case-insensitively.
+#[derive(Clone, Debug)]
+pub struct SearchCount {
+    filter: Filter,
+}
+
+impl SearchCount {
+    pub fn new(filter: Filter) -> Self {
+        SearchCount { filter }
+    }
+}
+
+impl Command for SearchCount {
+    type Response = responses::Count;
+
+    fn command(&self) -> RawCommand {
+        RawCommand::new("searchcount").argument(&self.filter)
+    }
+
+    fn response(self, frame: Frame) -> Result<Self::Response, TypedResponseError> {
+        Count::new(self.filter).response(frame)
+    }
+}
+
+// LsInfo is the `lsinfo` MPD command. It lists paths of the directories and songs immediately
+// under the directory, playlists are skipped.
+#[derive(Clone, Debug)]
+pub struct LsInfo {
+    directory: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Listing {
+    pub directories: Vec<String>,
+    pub songs: Vec<String>,
+}
+
+impl LsInfo {
+    pub fn new(directory: &str) -> Self {
+        LsInfo {
+            directory: directory.trim_matches('/').to_string(),
+        }
+    }
+}
+
+impl Command for LsInfo {
+    type Response = Listing;
+
+    fn command(&self) -> RawCommand {
+        let mut command = RawCommand::new("lsinfo");
+        if !self.directory.is_empty() {
+            command.add_argument(&self.directory).unwrap();
+        }
+
+        command
+    }
+
+    fn response(self, frame: Frame) -> Result<Self::Response, TypedResponseError> {
+        Ok(frame
+            .fields()
+            .fold(Listing::default(), |mut listing, (key, value)| {
+                match key {
+                    "directory" => listing.directories.push(value.to_string()),
+                    "file" => listing.songs.push(value.to_string()),
+                    _ => (),
+                };
+                listing
+            }))
+    }
+}
+
+// ReadComments is the `readcomments` MPD command. It returns raw tags of the song file,
+// including the ones MPD doesn't know about (e.g. lyrics).
+#[derive(Clone, Debug)]
+pub struct ReadComments {
+    uri: String,
+}
+
+impl ReadComments {
+    pub fn new(uri: &str) -> Self {
+        ReadComments {
+            uri: uri.to_string(),
+        }
+    }
+}
+
+impl Command for ReadComments {
+    type Response = Vec<(String, String)>;
+
+    fn command(&self) -> RawCommand {
+        RawCommand::new("readcomments").argument(self.uri.as_str())
+    }
+
+    fn response(self, frame: Frame) -> Result<Self::Response, TypedResponseError> {
+        Ok(frame
+            .fields()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect())
+    }
+}
+
+// UpdatingDb is the `status` MPD command reduced to the ID of the running database update job.
+// mpd_client looks for the job under a wrong key, so it never reports one.
+#[derive(Clone, Copy, Debug)]
+pub struct UpdatingDb;
+
+impl Command for UpdatingDb {
+    type Response = Option<u64>;
+
+    fn command(&self) -> RawCommand {
+        RawCommand::new("status")
+    }
+
+    fn response(self, frame: Frame) -> Result<Self::Response, TypedResponseError> {
+        frame
+            .find("updating_db")
+            .map(|job| {
+                job.parse()
+                    .map_err(|_| TypedResponseError::invalid_value("updating_db", job.to_string()))
+            })
+            .transpose()
+    }
+}
+
+// IdleUpdate is the `idle update` MPD command. It completes once the state of database update
+// changes.
+#[derive(Clone, Copy, Debug)]
+pub struct IdleUpdate;
+
+impl Command for IdleUpdate {
+    type Response = ();
+
+    fn command(&self) -> RawCommand {
+        RawCommand::new("idle").argument("update")
+    }
+
+    fn response(self, _: Frame) -> Result<Self::Response, TypedResponseError> {
+        Ok(())
+    }
+}
+
+// MusicDirectory is the `config` MPD command reduced to the music directory. MPD answers it only
+// to clients connected over a local socket.
+#[derive(Clone, Copy, Debug)]
+pub struct MusicDirectory;
+
+impl Command for MusicDirectory {
+    type Response = Option<String>;
+
+    fn command(&self) -> RawCommand {
+        RawCommand::new("config")
+    }
+
+    fn response(self, frame: Frame) -> Result<Self::Response, TypedResponseError> {
+        Ok(frame.find("music_directory").map(str::to_string))
+    }
+}
+
+// music_directory returns location of the library MPD is configured with
+pub async fn music_directory(conn: &Connection) -> Result<String, String> {
+    match conn.command(MusicDirectory).await {
+        Ok(Some(dir)) if !dir.is_empty() => Ok(dir),
+        Ok(_) => Err("MPD has no music_directory configured, use --mpd-library".to_string()),
+        Err(err) => Err(format!(
+            "failed to ask MPD for its music_directory ({err}). MPD tells it only to clients \
+             connected over a local socket, connect over one (e.g. --mpd-address \
+             /run/mpd/socket) or use --mpd-library"
+        )),
+    }
+}
+
+// PlaylistStickerSet is the `sticker set playlist` MPD command. Stickers of stored playlists are
+// supported since MPD 0.24.
+#[derive(Clone, Debug)]
+pub struct PlaylistStickerSet {
+    playlist: String,
+    name: String,
+    value: String,
+}
+
+impl PlaylistStickerSet {
+    pub fn new(playlist: &str, name: &str, value: &str) -> Self {
+        PlaylistStickerSet {
+            playlist: playlist.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+impl Command for PlaylistStickerSet {
+    type Response = ();
+
+    fn command(&self) -> RawCommand {
+        RawCommand::new("sticker")
+            .argument("set")
+            .argument("playlist")
+            .argument(self.playlist.as_str())
+            .argument(self.name.as_str())
+            .argument(self.value.as_str())
+    }
+
+    fn response(self, _: Frame) -> Result<Self::Response, TypedResponseError> {
+        Ok(())
+    }
+}
+
+// PlaylistStickerDelete is the `sticker delete playlist` MPD command
+#[derive(Clone, Debug)]
+pub struct PlaylistStickerDelete {
+    playlist: String,
+    name: String,
+}
+
+impl PlaylistStickerDelete {
+    pub fn new(playlist: &str, name: &str) -> Self {
+        PlaylistStickerDelete {
+            playlist: playlist.to_string(),
+            name: name.to_string(),
+        }
+    }
+}
+
+impl Command for PlaylistStickerDelete {
+    type Response = ();
+
+    fn command(&self) -> RawCommand {
+        RawCommand::new("sticker")
+            .argument("delete")
+            .argument("playlist")
+            .argument(self.playlist.as_str())
+            .argument(self.name.as_str())
+    }
+
+    fn response(self, _: Frame) -> Result<Self::Response, TypedResponseError> {
+        Ok(())
+    }
+}
+
+// PlaylistStickerFind is the `sticker find playlist` MPD command. It returns values of the named
+// sticker of all the stored playlists, keyed by playlist name.
+#[derive(Clone, Debug)]
+pub struct PlaylistStickerFind {
+    name: String,
+}
+
+impl PlaylistStickerFind {
+    pub fn new(name: &str) -> Self {
+        PlaylistStickerFind {
+            name: name.to_string(),
+        }
+    }
+}
+
+// EmptyArgument is an empty string argument, which mpd_protocol refuses to render on its own
+struct EmptyArgument;
+
+impl Argument for EmptyArgument {
+    fn render(&self, buf: &mut BytesMut) {
+        buf.put_slice(b"\"\"");
+    }
+}
+
+impl Command for PlaylistStickerFind {
+    type Response = HashMap<String, String>;
+
+    fn command(&self) -> RawCommand {
+        // Playlists are not nested, so they are searched for with an empty URI
+        RawCommand::new("sticker")
+            .argument("find")
+            .argument("playlist")
+            .argument(EmptyArgument)
+            .argument(self.name.as_str())
+    }
+
+    fn response(self, frame: Frame) -> Result<Self::Response, TypedResponseError> {
+        let mut stickers = HashMap::new();
+        let mut playlist = None;
+        for (key, value) in frame.fields() {
+            match (key, playlist.take()) {
+                ("sticker", Some(playlist)) => {
+                    let value = value
+                        .strip_prefix(&self.name)
+                        .and_then(|v| v.strip_prefix('='))
+                        .ok_or_else(|| {
+                            TypedResponseError::invalid_value("sticker", value.to_string())
+                        })?;
+                    stickers.insert(playlist, value.to_string());
+                }
+                ("sticker", None) => {
+                    return Err(TypedResponseError::unexpected_field("playlist", "sticker"))
+                }
+                (_, _) => playlist = Some(value.to_string()),
+            }
+        }
+        Ok(stickers)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::Connection;
+    use mpd_client::Client;
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    // TIMEOUT is the command timeout of connections to fake MPD servers
+    pub(crate) const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // fake_client returns a client connected to a fake MPD server (see serve)
+    pub(crate) async fn fake_client<F>(handler: F) -> Connection
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(serve(server, VERSION, Arc::new(handler)));
+
+        let (client, mut events) = Client::connect(client).await.unwrap();
+        tokio::spawn(async move { while events.next().await.is_some() {} });
+
+        Connection::new(client, TIMEOUT)
+    }
+
+    // VERSION is the MPD protocol version fake MPD servers speak by default
+    const VERSION: &str = "0.23.5";
+
+    // fake_server starts a fake MPD server (see serve) listening on a random local port
+    pub(crate) async fn fake_server<F>(handler: F) -> SocketAddr
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        fake_server_version(VERSION, handler).await
+    }
+
+    // fake_server_version is like fake_server, but the server speaks the given protocol version
+    pub(crate) async fn fake_server_version<F>(version: &'static str, handler: F) -> SocketAddr
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let handler = Arc::new(handler);
+
+        tokio::spawn(async move {
+            while let Ok((conn, _)) = listener.accept().await {
+                tokio::spawn(serve(conn, version, handler.clone()));
+            }
+        });
+
+        address
+    }
+
+    // fake_local_server starts a fake MPD server (see serve) listening on a local socket at path
+    #[cfg(unix)]
+    pub(crate) async fn fake_local_server<F>(path: &std::path::Path, handler: F)
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        let listener = tokio::net::UnixListener::bind(path).unwrap();
+        let handler = Arc::new(handler);
+
+        tokio::spawn(async move {
+            while let Ok((conn, _)) = listener.accept().await {
+                tokio::spawn(serve(conn, VERSION, handler.clone()));
+            }
+        });
+    }
+
+    // serve serves a single connection to a fake MPD server. The server replies to every
+    // command with whatever the handler returns for the command line, followed by OK (unless
+    // the reply is an ACK). Commands of a command list are passed to the handler one by one.
+    async fn serve<S, F>(conn: S, version: &str, handler: Arc<F>)
+    where
+        S: AsyncRead + AsyncWrite,
+        F: Fn(&str) -> String,
+    {
+        let (read, mut write) = tokio::io::split(conn);
+        let mut lines = BufReader::new(read).lines();
+        let mut list: Option<Vec<String>> = None;
+
+        let greeting = format!("OK MPD {version}\n");
+        if write.write_all(greeting.as_bytes()).await.is_err() {
+            return;
+        }
+        while let Ok(Some(line)) = lines.next_line().await {
+            let reply = match (line.as_str(), list.as_mut()) {
+                // Idle never completes on its own
+                ("idle", _) => continue,
+                ("noidle", _) => "OK\n".to_string(),
+                ("command_list_ok_begin", _) => {
+                    list = Some(Vec::new());
+                    continue;
+                }
+                ("command_list_end", Some(_)) => {
+                    let mut reply = String::new();
+                    let mut failed = false;
+                    for command in list.take().unwrap_or_default() {
+                        match handler(&command) {
+                            ack if ack.starts_with("ACK") => {
+                                reply.push_str(&ack);
+                                failed = true;
+                                break;
+                            }
+                            r => reply.push_str(&format!("{r}list_OK\n")),
+                        }
+                    }
+                    if !failed {
+                        reply.push_str("OK\n");
+                    }
+                    reply
+                }
+                (_, Some(list)) => {
+                    list.push(line);
+                    continue;
+                }
+                (command, None) => match handler(command) {
+                    ack if ack.starts_with("ACK") => ack,
+                    r => format!("{r}OK\n"),
+                },
+            };
+            if write.write_all(reply.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use super::testing::{fake_local_server, TIMEOUT};
+    use super::{
+        music_directory, testing::fake_client, Address, Connection, ConnectionCustomizer,
+        ConnectionManager, Error,
+    };
+    use bb8::{CustomizeConnection, ManageConnection};
+    use mpd_client::{commands::Ping, Client};
+    use std::{
+        sync::{atomic::Ordering, Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn music_directory_detection() {
+        let conn = fake_client(|command| match command {
+            "config" => "music_directory: /music\nplaylist_directory: /playlists\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        assert_eq!(music_directory(&conn).await.as_deref(), Ok("/music"));
+
+        // Remote clients are not allowed to ask
+        let conn = fake_client(|command| match command {
+            "config" => "ACK [4@0] {config} Permission denied\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+        assert!(music_directory(&conn).await.is_err());
+    }
+
+    #[test]
+    fn address() {
+        assert_eq!(
+            "127.0.0.1:6600".parse::<Address>(),
+            Ok(Address::Tcp(([127, 0, 0, 1], 6600).into()))
+        );
+        assert_eq!(
+            "/run/mpd/socket".parse::<Address>(),
+            Ok(Address::Local("/run/mpd/socket".into()))
+        );
+        assert!("localhost".parse::<Address>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn local_socket() {
+        let path = std::env::temp_dir().join(format!("mpdsonic-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        fake_local_server(&path, |command| match command {
+            "config" => "music_directory: /music\n".to_string(),
+            _ => String::new(),
+        })
+        .await;
+
+        let manager = ConnectionManager::new(&Address::Local(path.clone()), &None, TIMEOUT);
+        let conn = manager.connect().await.unwrap();
+        assert_eq!(music_directory(&conn).await.as_deref(), Ok("/music"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn binary_limit() {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mut conn = fake_client({
+            let commands = commands.clone();
+            move |command| {
+                commands.lock().unwrap().push(command.to_string());
+                String::new()
+            }
+        })
+        .await;
+
+        for limit in [1024 * 1024, 1, usize::MAX] {
+            let customizer = ConnectionCustomizer::new(limit);
+            assert!(customizer.on_acquire(&mut conn).await.is_ok());
+        }
+        assert_eq!(
+            *commands.lock().unwrap(),
+            [
+                "binarylimit 1048576",
+                "binarylimit 64",
+                "binarylimit 4194304"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn command_timeout() {
+        // The server greets the client, but never replies to commands
+        let (client, mut server) = tokio::io::duplex(1024);
+        server.write_all(b"OK MPD 0.23.5\n").await.unwrap();
+        let (client, _events) = Client::connect(client).await.unwrap();
+        let conn = Connection::new(client, Duration::from_millis(50));
+
+        assert!(matches!(conn.command(Ping).await, Err(Error::Timeout(_))));
+        assert!(conn.timed_out.load(Ordering::Relaxed));
     }
 }
@@ -0,0 +1,40 @@
+use axum::{
+    response::Html,
+    routing::{get, Router},
+};
+
+// Minimal web UI to check that the server works from a browser. It talks to the server through
+// the Subsonic API, just like any other client.
+static INDEX: &str = include_str!("../assets/index.html");
+
+pub(crate) fn get_router() -> Router {
+    Router::new().route("/", get(|| async { Html(INDEX) }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_router;
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, Request, StatusCode},
+    };
+    use tower_service::Service;
+
+    #[tokio::test]
+    async fn index() {
+        let resp = get_router()
+            .call(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("<title>mpdsonic</title>"));
+        assert!(body.contains("getArtists"));
+    }
+}